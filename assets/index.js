@@ -3,7 +3,13 @@
  * @property {"Dir"|"SymlinkDir"|"File"|"SymlinkFile"} path_type
  * @property {string} name
  * @property {number} mtime
+ * @property {string} modified
  * @property {number} size
+ * @property {string} [etag]
+ * @property {string} [type]
+ * @property {string} [owner]
+ * @property {string} [group]
+ * @property {string} [mode]
  */
 
 /**
@@ -12,17 +18,20 @@
  * @property {string} uri_prefix
  * @property {"Index" | "Edit" | "View"} kind
  * @property {PathItem[]} paths
+ * @property {string[]} columns
+ * @property {boolean} utc
  * @property {boolean} allow_upload
  * @property {boolean} allow_delete
  * @property {boolean} allow_search
  * @property {boolean} allow_archive
+ * @property {boolean} separate_upload
  * @property {boolean} auth
  * @property {string} user
  * @property {boolean} dir_exists
  * @property {string} editable
  */
 
-var DUFS_MAX_UPLOADINGS = 1;
+var DUFS_MAX_UPLOADINGS = 3;
 
 /**
  * @type {DATA} DATA
@@ -38,11 +47,56 @@ var DIR_EMPTY_NOTE;
  * @type {PARAMS}
  * @typedef {object} PARAMS
  * @property {string} q
+ * @property {string} mode
+ * @property {string} token
  * @property {string} sort
  * @property {string} order
  */
 const PARAMS = Object.fromEntries(new URLSearchParams(window.location.search).entries());
 
+const SORT_STORAGE_KEY = "dufs:sort";
+
+const VIEW_STORAGE_KEY = "dufs:view";
+
+const COLUMN_NAMES = {
+  mtime: "Last Modified",
+  size: "Size",
+  type: "Type",
+  owner: "Owner",
+  group: "Group",
+  mode: "Permissions",
+};
+
+const SORT_COLUMNS = ["name", "mtime", "size"];
+
+const TIME_STORAGE_KEY = "dufs:time";
+
+const TREE_STORAGE_KEY = "dufs:tree";
+
+const RENAME_STORAGE_KEY = "dufs:rename";
+
+/**
+ * Upload as `name (1).ext` instead of overwriting an existing file
+ * @type {boolean}
+ */
+let RENAME_ON_CONFLICT = false;
+
+/**
+ * Show times like "3 hours ago" instead of dates
+ * @type {boolean}
+ */
+let RELATIVE_TIME = false;
+
+const THEME_STORAGE_KEY = "dufs:theme";
+
+const DARK_SCHEME = window.matchMedia("(prefers-color-scheme: dark)");
+
+const THUMBNAIL_FORMATS = [".jpg", ".jpeg", ".png", ".gif", ".bmp", ".webp", ".avif"];
+
+const AUDIO_FORMATS = [".mp3", ".m4a", ".aac", ".ogg", ".oga", ".opus", ".flac", ".wav"];
+
+const VIDEO_FORMATS = [".mp4", ".m4v", ".webm", ".ogv", ".mov"];
+
 const IFRAME_FORMATS = [
   ".pdf",
   ".jpg", ".jpeg", ".png", ".gif", ".bmp", ".svg",
@@ -57,8 +111,10 @@ const ICONS = {
   file: `<svg height="16" viewBox="0 0 12 16" width="12"><path fill-rule="evenodd" d="M6 5H2V4h4v1zM2 8h7V7H2v1zm0 2h7V9H2v1zm0 2h7v-1H2v1zm10-7.5V14c0 .55-.45 1-1 1H1c-.55 0-1-.45-1-1V2c0-.55.45-1 1-1h7.5L12 4.5zM11 5L8 2H1v12h10V5z"></path></svg>`,
   download: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M.5 9.9a.5.5 0 0 1 .5.5v2.5a1 1 0 0 0 1 1h12a1 1 0 0 0 1-1v-2.5a.5.5 0 0 1 1 0v2.5a2 2 0 0 1-2 2H2a2 2 0 0 1-2-2v-2.5a.5.5 0 0 1 .5-.5z"/><path d="M7.646 11.854a.5.5 0 0 0 .708 0l3-3a.5.5 0 0 0-.708-.708L8.5 10.293V1.5a.5.5 0 0 0-1 0v8.793L5.354 8.146a.5.5 0 1 0-.708.708l3 3z"/></svg>`,
   move: `<svg width="16" height="16" viewBox="0 0 16 16"><path fill-rule="evenodd" d="M1.5 1.5A.5.5 0 0 0 1 2v4.8a2.5 2.5 0 0 0 2.5 2.5h9.793l-3.347 3.346a.5.5 0 0 0 .708.708l4.2-4.2a.5.5 0 0 0 0-.708l-4-4a.5.5 0 0 0-.708.708L13.293 8.3H3.5A1.5 1.5 0 0 1 2 6.8V2a.5.5 0 0 0-.5-.5z"/></svg>`,
+  rename: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M5 2a.5.5 0 0 1 .5-.5c.862 0 1.573.287 2.06.566.174.099.321.198.44.286.119-.088.266-.187.44-.286A4.165 4.165 0 0 1 10.5 1.5a.5.5 0 0 1 0 1c-.638 0-1.177.213-1.564.434a3.49 3.49 0 0 0-.436.294V7.5H9a.5.5 0 0 1 0 1h-.5v4.272c.1.08.248.187.436.294.387.221.926.434 1.564.434a.5.5 0 0 1 0 1 4.165 4.165 0 0 1-2.06-.566A4.561 4.561 0 0 1 8 13.65a4.561 4.561 0 0 1-.44.285 4.165 4.165 0 0 1-2.06.566.5.5 0 0 1 0-1c.638 0 1.177-.213 1.564-.434.188-.107.335-.214.436-.294V8.5H7a.5.5 0 0 1 0-1h.5V3.228a3.49 3.49 0 0 0-.436-.294A3.166 3.166 0 0 0 5.5 2.5.5.5 0 0 1 5 2z"/></svg>`,
   edit: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M12.146.146a.5.5 0 0 1 .708 0l3 3a.5.5 0 0 1 0 .708l-10 10a.5.5 0 0 1-.168.11l-5 2a.5.5 0 0 1-.65-.65l2-5a.5.5 0 0 1 .11-.168l10-10zM11.207 2.5 13.5 4.793 14.793 3.5 12.5 1.207 11.207 2.5zm1.586 3L10.5 3.207 4 9.707V10h.5a.5.5 0 0 1 .5.5v.5h.5a.5.5 0 0 1 .5.5v.5h.293l6.5-6.5zm-9.761 5.175-.106.106-1.528 3.821 3.821-1.528.106-.106A.5.5 0 0 1 5 12.5V12h-.5a.5.5 0 0 1-.5-.5V11h-.5a.5.5 0 0 1-.468-.325z"/></svg>`,
   delete: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M6.854 7.146a.5.5 0 1 0-.708.708L7.293 9l-1.147 1.146a.5.5 0 0 0 .708.708L8 9.707l1.146 1.147a.5.5 0 0 0 .708-.708L8.707 9l1.147-1.146a.5.5 0 0 0-.708-.708L8 8.293 6.854 7.146z"/><path d="M14 14V4.5L9.5 0H4a2 2 0 0 0-2 2v12a2 2 0 0 0 2 2h8a2 2 0 0 0 2-2zM9.5 3A1.5 1.5 0 0 0 11 4.5h2V14a1 1 0 0 1-1 1H4a1 1 0 0 1-1-1V2a1 1 0 0 1 1-1h5.5v2z"/></svg>`,
+  hash: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M8.39 12.648a1.32 1.32 0 0 0-.015.18c0 .305.21.508.5.508.266 0 .492-.172.555-.477l.554-2.703h1.204c.421 0 .617-.234.617-.547 0-.312-.188-.53-.617-.53h-.985l.516-2.524h1.265c.43 0 .618-.227.618-.547 0-.313-.188-.524-.618-.524h-1.046l.476-2.304a1.06 1.06 0 0 0 .016-.164.51.51 0 0 0-.516-.516.54.54 0 0 0-.539.43l-.523 2.554H7.617l.477-2.304c.008-.04.015-.118.015-.164a.512.512 0 0 0-.523-.516.539.539 0 0 0-.531.43L6.53 5.484H5.414c-.43 0-.617.22-.617.532 0 .312.187.539.617.539h.906l-.515 2.523H4.609c-.421 0-.609.219-.609.531 0 .313.188.547.61.547h.976l-.516 2.492c-.008.04-.015.125-.015.18 0 .305.21.508.5.508.265 0 .492-.172.554-.477l.555-2.703h2.242l-.515 2.492zm-1-6.109h2.266l-.515 2.563H6.859l.532-2.563z"/></svg>`,
   view: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M4 0a2 2 0 0 0-2 2v12a2 2 0 0 0 2 2h8a2 2 0 0 0 2-2V2a2 2 0 0 0-2-2zm0 1h8a1 1 0 0 1 1 1v12a1 1 0 0 1-1 1H4a1 1 0 0 1-1-1V2a1 1 0 0 1 1-1"/></svg>`,
 }
 
@@ -104,6 +160,16 @@ let $logoutBtn;
  */
 let $userName;
 
+/**
+ * ETag of the file when it was loaded into the editor
+ * @type {string | null}
+ */
+let editorEtag = null;
+
+// Before the page is rendered, so it doesn't flash in the wrong theme
+applyTheme();
+DARK_SCHEME.addEventListener("change", applyTheme);
+
 // Produce table when window loads
 window.addEventListener("DOMContentLoaded", async () => {
   const $indexData = document.getElementById('index-data');
@@ -129,10 +195,11 @@ async function ready() {
   $logoutBtn = document.querySelector(".logout-btn");
   $userName = document.querySelector(".user-name");
 
-  addBreadcrumb(DATA.href, DATA.uri_prefix);
+  addBreadcrumb(DATA.breadcrumb);
+  setupThemeToggle();
 
   if (DATA.kind === "Index") {
-    document.title = `Index of ${DATA.href} - Dufs`;
+    document.title = indexTitle();
     document.querySelector(".index-page").classList.remove("hidden");
 
     await setupIndexPage();
@@ -160,16 +227,52 @@ class Uploader {
      * @type Element
      */
     this.$uploadStatus = null
+    /**
+     * @type XMLHttpRequest
+     */
+    this.xhr = null;
     this.uploaded = 0;
     this.uploadOffset = 0;
     this.lastUptime = 0;
+    this.canceled = false;
     this.name = [...pathParts, file.name].join("/");
     this.idx = Uploader.globalIdx++;
     this.file = file;
     this.url = newUrl(this.name);
+    this.precondition = uploadPrecondition(this.name, pathParts);
+    this.uploadId = `${Date.now().toString(36)}-${Math.random().toString(36).slice(2)}`;
+    this.rename = RENAME_ON_CONFLICT;
   }
 
   upload() {
+    this.addRow();
+    this.$uploadStatus.innerHTML = `<span class="upload-queued">Queued</span>${this.cancelBtn()}`;
+    this.$uploadStatus.addEventListener("click", e => {
+      const nodeId = e.target.id;
+      const retryMatches = /^retry(\d+)$/.exec(nodeId);
+      if (retryMatches) {
+        const id = parseInt(retryMatches[1]);
+        let uploader = failUploaders.get(id);
+        if (uploader) uploader.retry();
+        return;
+      }
+      if (/^cancel(\d+)$/.test(nodeId)) {
+        this.cancel();
+      }
+    });
+    Uploader.queues.push(this);
+    Uploader.runQueue();
+  }
+
+  /**
+   * Show the file without uploading it, the server already has it unchanged
+   */
+  skip() {
+    this.addRow();
+    this.$uploadStatus.innerHTML = `<span title="Unchanged on server">Unchanged</span>`;
+  }
+
+  addRow() {
     const { idx, name, url } = this;
     const encodedName = encodedStr(name);
     $uploadersTable.insertAdjacentHTML("beforeend", `
@@ -185,18 +288,6 @@ class Uploader {
     $uploadersTable.classList.remove("hidden");
     $emptyFolder.classList.add("hidden");
     this.$uploadStatus = document.getElementById(`uploadStatus${idx}`);
-    this.$uploadStatus.innerHTML = '-';
-    this.$uploadStatus.addEventListener("click", e => {
-      const nodeId = e.target.id;
-      const matches = /^retry(\d+)$/.exec(nodeId);
-      if (matches) {
-        const id = parseInt(matches[1]);
-        let uploader = failUploaders.get(id);
-        if (uploader) uploader.retry();
-      }
-    });
-    Uploader.queues.push(this);
-    Uploader.runQueue();
   }
 
   ajax() {
@@ -206,14 +297,31 @@ class Uploader {
     this.lastUptime = Date.now();
 
     const ajax = new XMLHttpRequest();
+    this.xhr = ajax;
     ajax.upload.addEventListener("progress", e => this.progress(e), false);
     ajax.addEventListener("readystatechange", () => {
       if (ajax.readyState === 4) {
         if (ajax.status >= 200 && ajax.status < 300) {
+          if (this.rename) this.renamed(ajax.responseText);
+          this.complete();
+        } else if (ajax.status === 304) {
+          // The server already has this very file
           this.complete();
+        } else if (ajax.status === 412 && this.precondition) {
+          if (confirm(`File changed on server: ${this.name}\nOverwrite it anyway?`)) {
+            this.precondition = null;
+            this.ajax();
+          } else {
+            this.fail("File changed on server");
+          }
         } else {
           if (ajax.status != 0) {
-            this.fail(`${ajax.status} ${ajax.statusText}`);
+            const reason = errorReason(ajax.responseText);
+            if (reason) {
+              this.fail(reason, true);
+            } else {
+              this.fail(`${ajax.status} ${ajax.statusText}`);
+            }
           }
         }
       }
@@ -224,25 +332,60 @@ class Uploader {
       ajax.open("PATCH", url);
       ajax.setRequestHeader("X-Update-Range", "append");
       ajax.send(this.file.slice(this.uploadOffset));
+    } else if (this.rename) {
+      ajax.open("PUT", `${url}?rename-on-conflict`);
+      ajax.setRequestHeader("X-File-Mtime", this.file.lastModified);
+      ajax.send(this.file);
     } else {
       ajax.open("PUT", url);
+      ajax.setRequestHeader("X-Upload-Id", this.uploadId);
+      // Keeps the mtime, so later folder uploads can tell the file is unchanged
+      ajax.setRequestHeader("X-File-Size", this.file.size);
+      ajax.setRequestHeader("X-File-Mtime", this.file.lastModified);
+      if (this.precondition) {
+        ajax.setRequestHeader(...this.precondition);
+      }
       ajax.send(this.file);
-      // setTimeout(() => ajax.abort(), 3000);
     }
   }
 
   async retry() {
     const { url } = this;
-    let res = await fetch(url, {
+    failUploaders.delete(this.idx);
+    let uploadOffset = 0;
+    // A renamed upload has no name to resume yet, it starts over
+    const res = this.rename ? null : await fetch(url, {
       method: "HEAD",
     });
-    let uploadOffset = 0;
-    if (res.status == 200) {
+    if (res && res.status == 200) {
       let value = res.headers.get("content-length");
       uploadOffset = parseInt(value) || 0;
     }
     this.uploadOffset = uploadOffset;
-    this.ajax();
+    this.$uploadStatus.innerHTML = `<span class="upload-queued">Queued</span>${this.cancelBtn()}`;
+    Uploader.queues.push(this);
+    Uploader.runQueue();
+  }
+
+  async cancel() {
+    if (this.canceled) return;
+    this.canceled = true;
+    failUploaders.delete(this.idx);
+    const queueIdx = Uploader.queues.indexOf(this);
+    if (queueIdx > -1) {
+      Uploader.queues.splice(queueIdx, 1);
+    } else if (this.xhr && this.xhr.readyState !== 4) {
+      this.xhr.abort();
+      Uploader.runnings--;
+      Uploader.runQueue();
+    }
+    this.$uploadStatus.innerHTML = `<span title="Canceled">Canceled</span>`;
+    if (this.xhr && this.uploadOffset == 0 && !this.rename && DATA.allow_delete) {
+      // Remove what the server may have kept of the partial upload
+      try {
+        await fetch(this.url, { method: "DELETE" });
+      } catch { }
+    }
   }
 
   progress(event) {
@@ -250,33 +393,80 @@ class Uploader {
     const speed = (event.loaded - this.uploaded) / (now - this.lastUptime) * 1000;
     const [speedValue, speedUnit] = formatSize(speed);
     const speedText = `${speedValue} ${speedUnit}/s`;
-    const progress = formatPercent(((event.loaded + this.uploadOffset) / this.file.size) * 100);
+    const percent = ((event.loaded + this.uploadOffset) / this.file.size) * 100;
+    const progress = formatPercent(percent);
     const duration = formatDuration((event.total - event.loaded) / speed);
-    this.$uploadStatus.innerHTML = `<span style="width: 80px;">${speedText}</span><span>${progress} ${duration}</span>`;
+    this.$uploadStatus.innerHTML = `<progress max="100" value="${percent}"></progress><span style="width: 80px;">${speedText}</span><span>${progress} ${duration}</span>${this.cancelBtn()}`;
     this.uploaded = event.loaded;
     this.lastUptime = now;
   }
 
+  /**
+   * Point the row at the name the server picked
+   * @param {string} text
+   */
+  renamed(text) {
+    let name;
+    try {
+      name = JSON.parse(text).name;
+    } catch {
+      return;
+    }
+    if (!name) return;
+    this.name = [...this.name.split("/").slice(0, -1), name].join("/");
+    this.url = newUrl(this.name);
+    const $link = document.querySelector(`#upload${this.idx} .cell-name a`);
+    if ($link) {
+      $link.href = this.url;
+      $link.textContent = this.name;
+    }
+  }
+
   complete() {
     const $uploadStatusNew = this.$uploadStatus.cloneNode(true);
     $uploadStatusNew.innerHTML = `✓`;
     this.$uploadStatus.parentNode.replaceChild($uploadStatusNew, this.$uploadStatus);
     this.$uploadStatus = null;
+    this.xhr = null;
     failUploaders.delete(this.idx);
     Uploader.runnings--;
     Uploader.runQueue();
   }
 
-  fail(reason = "") {
-    this.$uploadStatus.innerHTML = `<span style="width: 20px;" title="${reason}">✗</span><span class="retry-btn" id="retry${this.idx}" title="Retry">↻</span>`;
+  fail(reason = "", showReason = false) {
+    if (this.canceled || failUploaders.has(this.idx)) return;
+    const reasonText = showReason ? `<span class="upload-error">${encodedStr(reason)}</span>` : "";
+    this.$uploadStatus.innerHTML = `<span style="width: 20px;" title="${reason}">✗</span>${reasonText}<span class="retry-btn" id="retry${this.idx}" title="Retry">↻</span>${this.cancelBtn()}`;
     failUploaders.set(this.idx, this);
     Uploader.runnings--;
     Uploader.runQueue();
   }
+
+  cancelBtn() {
+    return `<span class="cancel-btn" id="cancel${this.idx}" title="Cancel">✕</span>`;
+  }
 }
 
 Uploader.globalIdx = 0;
 
+/**
+ * Guard an upload against changes made on the server since the listing was loaded
+ * @param {string} name
+ * @param {string[]} pathParts
+ */
+function uploadPrecondition(name, pathParts) {
+  // The listing doesn't show the upload directory
+  if (!DATA.paths || DATA.separate_upload) return null;
+  const item = DATA.paths.find(v => v && v.name === name);
+  if (item) {
+    return item.etag ? ["If-Match", item.etag] : null;
+  }
+  if (pathParts.length === 0 && !new URLSearchParams(location.search).has("q")) {
+    return ["If-None-Match", "*"];
+  }
+  return null;
+}
+
 Uploader.runnings = 0;
 
 Uploader.auth = false;
@@ -288,48 +478,41 @@ Uploader.queues = [];
 
 
 Uploader.runQueue = async () => {
-  if (Uploader.runnings >= DUFS_MAX_UPLOADINGS) return;
-  if (Uploader.queues.length == 0) return;
-  Uploader.runnings++;
-  let uploader = Uploader.queues.shift();
-  if (!Uploader.auth) {
-    Uploader.auth = true;
-    try {
-      await checkAuth();
-    } catch {
-      Uploader.auth = false;
+  while (Uploader.runnings < DUFS_MAX_UPLOADINGS && Uploader.queues.length > 0) {
+    Uploader.runnings++;
+    let uploader = Uploader.queues.shift();
+    if (!Uploader.auth) {
+      Uploader.auth = true;
+      try {
+        await checkAuth();
+      } catch {
+        Uploader.auth = false;
+      }
+    }
+    if (uploader.canceled) {
+      Uploader.runnings--;
+      continue;
     }
+    uploader.ajax();
   }
-  uploader.ajax();
 }
 
 /**
  * Add breadcrumb
- * @param {string} href
- * @param {string} uri_prefix
+ * @param {{name: string, href: string}[]} crumbs the root, every ancestor and the current path
  */
-function addBreadcrumb(href, uri_prefix) {
+function addBreadcrumb(crumbs) {
   const $breadcrumb = document.querySelector(".breadcrumb");
-  let parts = [];
-  if (href === "/") {
-    parts = [""];
-  } else {
-    parts = href.split("/");
-  }
-  const len = parts.length;
-  let path = uri_prefix;
+  $breadcrumb.innerHTML = "";
+  // The searched folder links back to its listing
+  const isSearch = DATA.kind === "Index" && !!PARAMS.q;
+  const len = crumbs.length;
   for (let i = 0; i < len; i++) {
-    const name = parts[i];
-    if (i > 0) {
-      if (!path.endsWith("/")) {
-        path += "/";
-      }
-      path += encodeURIComponent(name);
-    }
+    const { name, href: path } = crumbs[i];
     const encodedName = encodedStr(name);
     if (i === 0) {
       $breadcrumb.insertAdjacentHTML("beforeend", `<a href="${path}" title="Root"><svg width="16" height="16" viewBox="0 0 16 16"><path d="M6.5 14.5v-3.505c0-.245.25-.495.5-.495h2c.25 0 .5.25.5.5v3.5a.5.5 0 0 0 .5.5h4a.5.5 0 0 0 .5-.5v-7a.5.5 0 0 0-.146-.354L13 5.793V2.5a.5.5 0 0 0-.5-.5h-1a.5.5 0 0 0-.5.5v1.293L8.354 1.146a.5.5 0 0 0-.708 0l-6 6A.5.5 0 0 0 1.5 7.5v7a.5.5 0 0 0 .5.5h4a.5.5 0 0 0 .5-.5z"/></svg></a>`);
-    } else if (i === len - 1) {
+    } else if (i === len - 1 && !isSearch) {
       $breadcrumb.insertAdjacentHTML("beforeend", `<b>${encodedName}</b>`);
     } else {
       $breadcrumb.insertAdjacentHTML("beforeend", `<a href="${path}">${encodedName}</a>`);
@@ -338,9 +521,19 @@ function addBreadcrumb(href, uri_prefix) {
       $breadcrumb.insertAdjacentHTML("beforeend", `<span class="separator">/</span>`);
     }
   }
+  if (isSearch) {
+    $breadcrumb.insertAdjacentHTML("beforeend", `<span class="separator">/</span><b class="search-crumb">Search “${encodedStr(PARAMS.q)}”</b>`);
+  }
+}
+
+function indexTitle() {
+  if (PARAMS.q) return `Search “${PARAMS.q}” in ${DATA.href} - Dufs`;
+  return `Index of ${DATA.href} - Dufs`;
 }
 
 async function setupIndexPage() {
+  document.head.insertAdjacentHTML("beforeend", `<link rel="alternate" type="application/atom+xml" title="${encodedStr(DATA.href)}" href="${baseUrl()}?feed">`);
+
   if (DATA.allow_archive) {
     const $download = document.querySelector(".download");
     $download.href = baseUrl() + "?zip";
@@ -350,9 +543,11 @@ async function setupIndexPage() {
 
   if (DATA.allow_upload) {
     setupDropzone();
+    setupPasteUpload();
     setupUploadFile();
     setupNewFolder();
     setupNewFile();
+    setupDiskUsage();
   }
 
   if (DATA.auth) {
@@ -363,8 +558,48 @@ async function setupIndexPage() {
     setupSearch();
   }
 
+  setupSort();
+  setupTree();
+  setupViewToggle();
+  setupMediaPlayer();
+  setupKeyboard();
+  setupFilter();
+  setupTimeFormat();
+  renderReadme();
   renderPathsTableHead();
   renderPathsTableBody();
+
+  if (DATA.allow_delete) {
+    setupSelection();
+  }
+}
+
+/**
+ * Show the README of the folder above the listing, rendered by the server
+ */
+function renderReadme() {
+  if (!DATA.readme) return;
+  const $readme = document.querySelector(".readme");
+  $readme.innerHTML = DATA.readme;
+  $readme.classList.remove("hidden");
+}
+
+/**
+ * Show the free space of the served filesystem in the footer
+ */
+async function setupDiskUsage() {
+  try {
+    const res = await fetch(`${DATA.uri_prefix}__dufs__/df`);
+    await assertResOK(res);
+    const { mounts } = await res.json();
+    if (!mounts || mounts.length === 0) return;
+    const { total, available } = mounts[0];
+    const [availableValue, availableUnit] = formatSize(available);
+    const [totalValue, totalUnit] = formatSize(total);
+    const $diskUsage = document.querySelector(".disk-usage");
+    $diskUsage.textContent = `${availableValue} ${availableUnit} free of ${totalValue} ${totalUnit}`;
+    $diskUsage.classList.remove("hidden");
+  } catch { }
 }
 
 /**
@@ -377,20 +612,22 @@ function renderPathsTableHead() {
       props: `colspan="2"`,
       text: "Name",
     },
-    // {
-    //   name: "mtime",
-    //   props: ``,
-    //   text: "Last Modified",
-    // },
-    {
-      name: "size",
+    ...listColumns().map(name => ({
+      name,
       props: ``,
-      text: "Size",
-    }
+      text: COLUMN_NAMES[name],
+    })),
   ];
+  const selectCell = DATA.allow_delete
+    ? `<th class="cell-select"><input type="checkbox" id="selectAll" title="Select all"></th>`
+    : "";
   $pathsTableHead.insertAdjacentHTML("beforeend", `
     <tr>
+      ${selectCell}
       ${headerItems.map(item => {
+    if (!SORT_COLUMNS.includes(item.name)) {
+      return `<th class="cell-${item.name}" ${item.props}>${item.text}</th>`;
+    }
     let svg = `<svg width="12" height="12" viewBox="0 0 16 16"><path fill-rule="evenodd" d="M11.5 15a.5.5 0 0 0 .5-.5V2.707l3.146 3.147a.5.5 0 0 0 .708-.708l-4-4a.5.5 0 0 0-.708 0l-4 4a.5.5 0 1 0 .708.708L11 2.707V14.5a.5.5 0 0 0 .5.5zm-7-14a.5.5 0 0 1 .5.5v11.793l3.146-3.147a.5.5 0 0 1 .708.708l-4 4a.5.5 0 0 1-.708 0l-4-4a.5.5 0 0 1 .708-.708L4 13.293V1.5a.5.5 0 0 1 .5-.5z"/></svg>`;
     let order = "desc";
     if (PARAMS.sort === item.name) {
@@ -403,13 +640,422 @@ function renderPathsTableHead() {
     }
     const qs = new URLSearchParams({ ...PARAMS, order, sort: item.name }).toString();
     const icon = `<span>${svg}</span>`
-    return `<th class="cell-${item.name}" ${item.props}><a href="?${qs}">${item.text}${icon}</a></th>`
+    return `<th class="cell-${item.name}" ${item.props}><a href="?${qs}" data-sort="${item.name}" data-order="${order}">${item.text}${icon}</a></th>`
   }).join("\n")}
       <th class="cell-actions">Actions</th>
     </tr>
   `);
 }
 
+/**
+ * Sort the listing in the browser when a column header is clicked, and apply
+ * the order chosen last time to listings opened without an explicit sort
+ */
+function setupSort() {
+  $pathsTableHead.addEventListener("click", e => {
+    const $link = e.target.closest("a[data-sort]");
+    if (!$link) return;
+    e.preventDefault();
+    const { sort, order } = $link.dataset;
+    try {
+      localStorage.setItem(SORT_STORAGE_KEY, JSON.stringify({ sort, order }));
+    } catch { }
+    PARAMS.sort = sort;
+    PARAMS.order = order;
+    history.replaceState(null, "", "?" + new URLSearchParams(PARAMS).toString());
+    sortPaths();
+    $pathsTableHead.innerHTML = "";
+    $pathsTableBody.innerHTML = "";
+    $pathsTable.classList.add("hidden");
+    renderPathsTableHead();
+    renderPathsTableBody();
+    updateSelection();
+  });
+
+  // Search results keep their ranking unless sorted explicitly
+  if (PARAMS.sort || PARAMS.q) return;
+  let saved;
+  try {
+    saved = JSON.parse(localStorage.getItem(SORT_STORAGE_KEY));
+  } catch { }
+  if (!saved || !SORT_COLUMNS.includes(saved.sort)) return;
+  PARAMS.sort = saved.sort;
+  PARAMS.order = saved.order === "desc" ? "desc" : "asc";
+  sortPaths();
+}
+
+/**
+ * Collapsible pane with the folders of the share, remembered by the browser.
+ * Subfolders are fetched two levels at a time from `?tree` as they are expanded
+ */
+function setupTree() {
+  const $toggleTree = document.querySelector(".toggle-tree");
+  const $treePane = document.querySelector(".tree-pane");
+  $toggleTree.classList.remove("hidden");
+  let open = false;
+  try {
+    open = localStorage.getItem(TREE_STORAGE_KEY) === "open";
+  } catch { }
+  let loaded = false;
+  const setOpen = value => {
+    open = value;
+    $treePane.classList.toggle("hidden", !open);
+    document.querySelector(".main").classList.toggle("with-tree", open);
+    if (open && !loaded) {
+      loaded = true;
+      loadTree($treePane).catch(err => console.error(err));
+    }
+  };
+  setOpen(open);
+  $toggleTree.addEventListener("click", () => {
+    setOpen(!open);
+    try {
+      localStorage.setItem(TREE_STORAGE_KEY, open ? "open" : "closed");
+    } catch { }
+  });
+  $treePane.addEventListener("click", async e => {
+    const $caret = e.target.closest(".tree-caret");
+    if (!$caret) return;
+    const $node = $caret.parentElement;
+    try {
+      if ($node.classList.contains("expanded")) {
+        $node.classList.remove("expanded");
+      } else {
+        await expandTreeNode($node);
+      }
+    } catch (err) {
+      alert(`Cannot load folders, ${err.message}`);
+    }
+  });
+}
+
+/**
+ * Render the tree from the root and expand it down to the current folder
+ * @param {HTMLElement} $treePane
+ */
+async function loadTree($treePane) {
+  const rootUrl = DATA.uri_prefix;
+  $treePane.innerHTML = `<ul>${treeNodeHtml("/", rootUrl, await fetchTree(rootUrl))}</ul>`;
+  const $root = $treePane.querySelector(".tree-node");
+  $root.classList.add("expanded");
+  let $node = $root;
+  let url = rootUrl;
+  for (const name of DATA.href.split("/").filter(v => v)) {
+    url += encodeURIComponent(name) + "/";
+    $node = [...$node.querySelectorAll(":scope > ul > .tree-node")].find($v => $v.dataset.url === url);
+    if (!$node) return;
+    await expandTreeNode($node);
+  }
+  $node.classList.add("active");
+  $node.scrollIntoView({ block: "nearest" });
+}
+
+/**
+ * @param {HTMLElement} $node
+ */
+async function expandTreeNode($node) {
+  if (!("loaded" in $node.dataset)) {
+    const dirs = await fetchTree($node.dataset.url);
+    $node.insertAdjacentHTML("beforeend", treeChildrenHtml($node.dataset.url, dirs));
+    $node.dataset.loaded = "";
+    $node.classList.toggle("leaf", dirs.length === 0);
+  }
+  $node.classList.add("expanded");
+}
+
+/**
+ * @param {string} url
+ * @returns {Promise<TreeNode[]>}
+ */
+async function fetchTree(url) {
+  const res = await fetch(withToken(`${url}?tree&depth=2`));
+  await assertResOK(res);
+  const { dirs } = await res.json();
+  return dirs;
+}
+
+/**
+ * @typedef {object} TreeNode
+ * @property {string} name
+ * @property {TreeNode[]} [dirs] - missing until fetched
+ */
+
+/**
+ * @param {string} name
+ * @param {string} url
+ * @param {TreeNode[]} [dirs]
+ * @returns {string}
+ */
+function treeNodeHtml(name, url, dirs) {
+  const loaded = dirs ? "data-loaded" : "";
+  const leaf = dirs && dirs.length === 0 ? " leaf" : "";
+  return `<li class="tree-node${leaf}" data-url="${url}" ${loaded}><span class="tree-caret">&#9656;</span><a href="${withToken(url)}">${encodedStr(name)}</a>${dirs ? treeChildrenHtml(url, dirs) : ""}</li>`;
+}
+
+/**
+ * @param {string} url
+ * @param {TreeNode[]} dirs
+ * @returns {string}
+ */
+function treeChildrenHtml(url, dirs) {
+  return `<ul>${dirs.map(v => treeNodeHtml(v.name, url + encodeURIComponent(v.name) + "/", v.dirs)).join("")}</ul>`;
+}
+
+/**
+ * Switch the listing between the table and a grid of tiles, remembered by the browser
+ */
+function setupViewToggle() {
+  const $toggleView = document.querySelector(".toggle-view");
+  $toggleView.classList.remove("hidden");
+  const setView = view => {
+    const isGrid = view === "grid";
+    $pathsTable.classList.toggle("grid-view", isGrid);
+    $toggleView.title = isGrid ? "Show as table" : "Show as grid";
+    $toggleView.querySelector(".icon-grid").classList.toggle("hidden", isGrid);
+    $toggleView.querySelector(".icon-list").classList.toggle("hidden", !isGrid);
+  };
+  let view = "table";
+  try {
+    view = localStorage.getItem(VIEW_STORAGE_KEY) || view;
+  } catch { }
+  setView(view);
+  $toggleView.addEventListener("click", () => {
+    view = view === "grid" ? "table" : "grid";
+    try {
+      localStorage.setItem(VIEW_STORAGE_KEY, view);
+    } catch { }
+    setView(view);
+  });
+}
+
+/**
+ * Clicking a time switches all of them between dates and relative times, remembered by the browser
+ */
+function setupTimeFormat() {
+  try {
+    RELATIVE_TIME = localStorage.getItem(TIME_STORAGE_KEY) === "relative";
+  } catch { }
+  $pathsTableBody.addEventListener("click", e => {
+    if (!e.target.closest("time.mtime")) return;
+    RELATIVE_TIME = !RELATIVE_TIME;
+    try {
+      localStorage.setItem(TIME_STORAGE_KEY, RELATIVE_TIME ? "relative" : "absolute");
+    } catch { }
+    $pathsTableBody.querySelectorAll("tr").forEach($row => {
+      const file = DATA.paths[parseInt($row.id.slice("addPath".length), 10)];
+      const $cell = $row.querySelector(".cell-mtime");
+      if (file && $cell) $cell.innerHTML = formatTime(file);
+    });
+  });
+}
+
+/**
+ * Narrow the loaded listing by name as you type, without asking the server
+ */
+function setupFilter() {
+  const $filter = document.getElementById("filter");
+  $filter.classList.remove("hidden");
+  $filter.addEventListener("input", applyFilter);
+  $filter.addEventListener("keydown", e => {
+    if (e.key !== "Escape") return;
+    $filter.value = "";
+    applyFilter();
+    $filter.blur();
+  });
+}
+
+function applyFilter() {
+  const $filter = document.getElementById("filter");
+  const words = ($filter?.value || "").toLowerCase().split(/\s+/).filter(v => v);
+  let shown = 0;
+  $pathsTableBody.querySelectorAll("tr").forEach($row => {
+    const file = DATA.paths[parseInt($row.id.slice("addPath".length), 10)];
+    const name = (file?.name || "").toLowerCase();
+    const matched = words.every(v => name.includes(v));
+    $row.classList.toggle("hidden", !matched);
+    if (matched) shown++;
+  });
+  if (!DATA.paths.some(v => v)) return;
+  $pathsTable.classList.toggle("hidden", shown === 0);
+  $emptyFolder.textContent = shown === 0 ? "No matches" : DIR_EMPTY_NOTE;
+  $emptyFolder.classList.toggle("hidden", shown !== 0);
+}
+
+/**
+ * Navigate the listing without a mouse: arrow keys move between rows, Enter opens,
+ * Delete deletes, `/` focuses the search and `u` picks files to upload
+ */
+function setupKeyboard() {
+  let $current = null;
+  const select = $row => {
+    $current?.classList.remove("focused");
+    $current = $row;
+    if (!$row) return;
+    $row.classList.add("focused");
+    $row.scrollIntoView({ block: "nearest" });
+  };
+  document.addEventListener("keydown", e => {
+    if (e.ctrlKey || e.metaKey || e.altKey) return;
+    if (e.target.closest("input, textarea, select")) return;
+    if (!document.querySelector(".media-player").classList.contains("hidden")) return;
+    const $rows = Array.from($pathsTableBody.querySelectorAll("tr:not(.hidden)"));
+    if (!$rows.includes($current)) $current = null;
+    switch (e.key) {
+      case "ArrowDown":
+      case "ArrowRight":
+      case "ArrowUp":
+      case "ArrowLeft": {
+        if ($rows.length === 0) return;
+        e.preventDefault();
+        const step = e.key === "ArrowDown" || e.key === "ArrowRight" ? 1 : -1;
+        const index = $current ? $rows.indexOf($current) + step : (step > 0 ? 0 : $rows.length - 1);
+        select($rows[Math.min(Math.max(index, 0), $rows.length - 1)]);
+        break;
+      }
+      case "Enter":
+        if (!$current) return;
+        e.preventDefault();
+        $current.querySelector(".cell-name a")?.click();
+        break;
+      case "Delete":
+        if (!$current || !DATA.allow_delete) return;
+        e.preventDefault();
+        deletePath(parseInt($current.id.slice("addPath".length), 10));
+        break;
+      case "/": {
+        const $search = document.getElementById("search");
+        if (!DATA.allow_search || !$search) return;
+        e.preventDefault();
+        $search.focus();
+        break;
+      }
+      case "u":
+        if (!DATA.allow_upload) return;
+        e.preventDefault();
+        document.getElementById("file")?.click();
+        break;
+    }
+  });
+}
+
+/**
+ * Use the dark theme if toggled in the page, or else if the system prefers it
+ */
+function applyTheme() {
+  let theme = null;
+  try {
+    theme = localStorage.getItem(THEME_STORAGE_KEY);
+  } catch { }
+  const dark = theme ? theme === "dark" : DARK_SCHEME.matches;
+  document.documentElement.classList.toggle("dark", dark);
+}
+
+function setupThemeToggle() {
+  document.querySelector(".theme-btn").addEventListener("click", () => {
+    const dark = !document.documentElement.classList.contains("dark");
+    try {
+      // Follow the system again once the choice matches it
+      if (dark === DARK_SCHEME.matches) {
+        localStorage.removeItem(THEME_STORAGE_KEY);
+      } else {
+        localStorage.setItem(THEME_STORAGE_KEY, dark ? "dark" : "light");
+      }
+    } catch { }
+    document.documentElement.classList.toggle("dark", dark);
+  });
+}
+
+/**
+ * Get whether a path can be played by the media player
+ * @param {PathItem} file
+ * @returns {"audio" | "video" | ""}
+ */
+function mediaKind(file) {
+  if (!file || file.path_type.endsWith("Dir")) return "";
+  const ext = file.name.slice(file.name.lastIndexOf(".")).toLowerCase();
+  if (AUDIO_FORMATS.includes(ext)) return "audio";
+  if (VIDEO_FORMATS.includes(ext)) return "video";
+  return "";
+}
+
+/**
+ * Play audio and video files of the listing in an overlay instead of leaving the page
+ */
+function setupMediaPlayer() {
+  const $player = document.querySelector(".media-player");
+  const $title = $player.querySelector(".media-title");
+  const $body = $player.querySelector(".media-player-body");
+  let current = -1;
+
+  const close = () => {
+    $body.innerHTML = "";
+    $player.classList.add("hidden");
+    current = -1;
+  };
+  const play = index => {
+    const file = DATA.paths[index];
+    const kind = mediaKind(file);
+    if (!kind) return;
+    current = index;
+    // Served by the file endpoint, so seeking uses range requests
+    const $media = document.createElement(kind);
+    $media.src = withToken(newUrl(file.name));
+    $media.controls = true;
+    $media.autoplay = true;
+    $media.addEventListener("ended", () => step(1));
+    $body.innerHTML = "";
+    $body.appendChild($media);
+    $title.textContent = file.name;
+    $player.classList.remove("hidden");
+  };
+  const step = delta => {
+    if (current < 0) return;
+    const len = DATA.paths.length;
+    for (let i = 1; i < len; i++) {
+      const index = (current + delta * i + len) % len;
+      if (mediaKind(DATA.paths[index])) {
+        play(index);
+        return;
+      }
+    }
+  };
+
+  $pathsTableBody.addEventListener("click", e => {
+    const $link = e.target.closest("a[data-media]");
+    if (!$link || e.ctrlKey || e.metaKey || e.shiftKey) return;
+    e.preventDefault();
+    play(parseInt($link.dataset.media, 10));
+  });
+  $player.querySelector(".media-prev").addEventListener("click", () => step(-1));
+  $player.querySelector(".media-next").addEventListener("click", () => step(1));
+  $player.querySelector(".media-close").addEventListener("click", close);
+  $player.addEventListener("click", e => {
+    if (e.target === $player) close();
+  });
+  document.addEventListener("keydown", e => {
+    if (current < 0) return;
+    if (e.key === "Escape") close();
+    else if (e.key === "ArrowLeft") step(-1);
+    else if (e.key === "ArrowRight") step(1);
+  });
+}
+
+/**
+ * Sort `DATA.paths` by `PARAMS.sort` and `PARAMS.order`, the same way the server does
+ */
+function sortPaths() {
+  const collator = new Intl.Collator(undefined, { numeric: true, sensitivity: "base" });
+  const kind = file => file.path_type.endsWith("Dir") ? 0 : 1;
+  DATA.paths = (DATA.paths || []).filter(v => v).sort((a, b) => {
+    const diff = kind(a) - kind(b);
+    if (diff !== 0) return diff;
+    if (PARAMS.sort === "mtime") return (a.mtime || 0) - (b.mtime || 0);
+    if (PARAMS.sort === "size") return (a.size || 0) - (b.size || 0);
+    return collator.compare(a.name, b.name);
+  });
+  if (PARAMS.order === "desc") DATA.paths.reverse();
+}
+
 /**
  * Render path table tbody
  */
@@ -422,6 +1068,7 @@ function renderPathsTableBody() {
     for (let i = 0; i < len; i++) {
       addPath(DATA.paths[i], i);
     }
+    applyFilter();
   } else {
     $emptyFolder.textContent = DIR_EMPTY_NOTE;
     $emptyFolder.classList.remove("hidden");
@@ -439,26 +1086,30 @@ function addPath(file, index) {
   let actionDelete = "";
   let actionDownload = "";
   let actionMove = "";
+  let actionRename = "";
   let actionEdit = "";
   let actionView = "";
+  let actionHash = "";
   let isDir = file.path_type.endsWith("Dir");
   if (isDir) {
     url += "/";
     if (DATA.allow_archive) {
       actionDownload = `
       <div class="action-btn">
-        <a href="${url}?zip" title="Download folder as a .zip file">${ICONS.download}</a>
+        <a href="${withToken(url + "?zip")}" title="Download folder as a .zip file">${ICONS.download}</a>
       </div>`;
     }
   } else {
     actionDownload = `
     <div class="action-btn" >
-      <a href="${url}" title="Download file" download>${ICONS.download}</a>
+      <a href="${withToken(url)}" title="Download file" download>${ICONS.download}</a>
     </div>`;
+    actionHash = `<div onclick="copyHash(${index})" class="action-btn" id="hashBtn${index}" title="Copy SHA-256">${ICONS.hash}</div>`;
   }
   if (DATA.allow_delete) {
     if (DATA.allow_upload) {
       actionMove = `<div onclick="movePath(${index})" class="action-btn" id="moveBtn${index}" title="Move to new path">${ICONS.move}</div>`;
+      actionRename = `<div onclick="renamePath(${index})" class="action-btn" id="renameBtn${index}" title="Rename">${ICONS.rename}</div>`;
       if (!isDir) {
         actionEdit = `<a class="action-btn" title="Edit file" target="_blank" href="${url}?edit">${ICONS.edit}</a>`;
       }
@@ -467,30 +1118,47 @@ function addPath(file, index) {
     <div onclick="deletePath(${index})" class="action-btn" id="deleteBtn${index}" title="Delete">${ICONS.delete}</div>`;
   }
   if (!actionEdit && !isDir) {
-    actionView = `<a class="action-btn" title="View file" target="_blank" href="${url}?view">${ICONS.view}</a>`;
+    actionView = `<a class="action-btn" title="View file" target="_blank" href="${withToken(url + "?view")}">${ICONS.view}</a>`;
   }
   let actionCell = `
   <td class="cell-actions">
     ${actionDownload}
     ${actionView}
+    ${actionHash}
     ${actionMove}
+    ${actionRename}
     ${actionDelete}
     ${actionEdit}
   </td>`;
   
+  const selectCell = DATA.allow_delete
+    ? `<td class="cell-select"><input type="checkbox" class="path-select" data-index="${index}" title="Select"></td>`
+    : "";
+
+  // Only loaded once visible in the grid view
+  const ext = file.name.slice(file.name.lastIndexOf(".")).toLowerCase();
+  let thumbnail = "";
+  if (!isDir && THUMBNAIL_FORMATS.includes(ext)) {
+    thumbnail = `<img class="thumbnail" loading="lazy" alt="" src="${withToken(url)}">`;
+  } else if (!isDir && (DATA.previews || []).includes(ext.slice(1))) {
+    thumbnail = `<img class="thumbnail" loading="lazy" alt="" src="${withToken(url + "?preview")}">`;
+  } else if (!isDir && (DATA.video_posters || []).includes(ext.slice(1))) {
+    thumbnail = `<img class="thumbnail" loading="lazy" alt="" src="${withToken(url + "?thumb")}">`;
+  }
+
   let sizeDisplay = isDir ? `${file.size} ${file.size === 1 ? "item" : "items"}` : formatSize(file.size).join(" ");
 
   $pathsTableBody.insertAdjacentHTML("beforeend", `
 <tr id="addPath${index}">
+  ${selectCell}
   <td class="path cell-icon">
-    ${getPathSvg(file.path_type)}
+    ${getPathSvg(file.path_type)}${thumbnail}
   </td>
   <td class="path cell-name">
-    <a href="${url}" ${isDir ? "" : `target="_blank"`}>${encodedName}</a>
+    <a href="${withToken(url)}" ${isDir ? "" : `target="_blank"`} ${mediaKind(file) ? `data-media="${index}"` : ""}>${encodedName}</a>
   </td>
 
-<!--  <td class="cell-mtime">${formatMtime(file.mtime)}</td>-->
-  <td class="cell-size">${formatSize(file.size).join(" ")}</td>
+  ${listColumns().map(name => `<td class="cell-${name}">${formatColumn(file, name)}</td>`).join("\n  ")}
 
   ${actionCell}
 </tr>`);
@@ -507,7 +1175,7 @@ function setupDropzone() {
     if (!e.dataTransfer.items[0].webkitGetAsEntry) {
       const files = Array.from(e.dataTransfer.files).filter(v => v.size > 0);
       for (const file of files) {
-        new Uploader(file, []).upload();
+        new Uploader(file, relativeDirs(file)).upload();
       }
     } else {
       const entries = [];
@@ -520,6 +1188,96 @@ function setupDropzone() {
   });
 }
 
+function setupPasteUpload() {
+  document.addEventListener("paste", e => {
+    const tagName = e.target.tagName;
+    if (tagName === "INPUT" || tagName === "TEXTAREA") return;
+    const files = Array.from(e.clipboardData.files);
+    if (files.length > 0) {
+      e.preventDefault();
+      for (const file of files) {
+        // Screenshots come without a meaningful name, e.g. `image.png`
+        if (file.type.startsWith("image/") && /^image\.\w+$/.test(file.name)) {
+          const ext = file.name.split(".").pop();
+          new Uploader(new File([file], pasteFileName(ext), { type: file.type }), []).upload();
+        } else {
+          new Uploader(file, []).upload();
+        }
+      }
+      return;
+    }
+    const text = e.clipboardData.getData("text/plain");
+    if (text) {
+      e.preventDefault();
+      new Uploader(new File([text], pasteFileName("txt"), { type: "text/plain" }), []).upload();
+    }
+  });
+}
+
+/**
+ * Generate a timestamped name for pasted content
+ * @param {string} ext
+ * @returns {string}
+ */
+function pasteFileName(ext) {
+  const date = new Date();
+  const pad = v => String(v).padStart(2, "0");
+  const day = `${date.getFullYear()}-${pad(date.getMonth() + 1)}-${pad(date.getDate())}`;
+  const time = `${pad(date.getHours())}${pad(date.getMinutes())}${pad(date.getSeconds())}`;
+  return `paste-${day}-${time}.${ext}`;
+}
+
+/**
+ * Render search results as soon as the server finds them
+ * @param {string} q
+ * @param {string} mode
+ */
+async function streamSearch(q, mode) {
+  PARAMS.q = q;
+  if (mode) {
+    PARAMS.mode = mode;
+  } else {
+    delete PARAMS.mode;
+  }
+  DIR_EMPTY_NOTE = "No results";
+  addBreadcrumb(DATA.breadcrumb);
+  document.title = indexTitle();
+  DATA.paths = [];
+  $pathsTableHead.innerHTML = "";
+  $pathsTableBody.innerHTML = "";
+  $emptyFolder.classList.add("hidden");
+  renderPathsTableHead();
+  updateSelection();
+  try {
+    const params = { q, ndjson: "" };
+    if (mode) params.mode = mode;
+    const res = await fetch(baseUrl() + "?" + new URLSearchParams(params).toString());
+    await assertResOK(res);
+    const reader = res.body.pipeThrough(new TextDecoderStream()).getReader();
+    let buffer = "";
+    while (true) {
+      const { value, done } = await reader.read();
+      if (done) break;
+      buffer += value;
+      const lines = buffer.split("\n");
+      buffer = lines.pop();
+      for (const line of lines) {
+        if (!line) continue;
+        DATA.paths.push(JSON.parse(line));
+        addPath(DATA.paths[DATA.paths.length - 1], DATA.paths.length - 1);
+        $pathsTable.classList.remove("hidden");
+      }
+    }
+  } catch (err) {
+    alert(`Cannot search \`${q}\`, ${err.message}`);
+  }
+  applyFilter();
+  if (DATA.paths.length === 0) {
+    $emptyFolder.textContent = DIR_EMPTY_NOTE;
+    $emptyFolder.classList.remove("hidden");
+  }
+}
+
 async function setupAuth() {
   if (DATA.user) {
     $logoutBtn.classList.remove("hidden");
@@ -528,6 +1286,10 @@ async function setupAuth() {
   } else {
     $loginBtn.classList.remove("hidden");
     $loginBtn.addEventListener("click", async () => {
+      if (DATA.login_page) {
+        location.href = loginUrl();
+        return;
+      }
       try {
         await checkAuth();
       } catch {}
@@ -543,15 +1305,27 @@ function setupSearch() {
     event.preventDefault();
     const formData = new FormData($searchbar);
     const q = formData.get("q");
+    const mode = formData.get("mode");
     let href = baseUrl();
     if (q) {
-      href += "?q=" + q;
+      href += "?q=" + encodeURIComponent(q);
+      if (mode) href += "&mode=" + mode;
+    }
+    // Fuzzy results are ranked by the server, so they can't be shown as they are found
+    if (q && mode !== "fuzzy" && window.TextDecoderStream) {
+      history.pushState(null, "", href);
+      streamSearch(q, mode);
+    } else {
+      location.href = href;
     }
-    location.href = href;
   });
+  window.addEventListener("popstate", () => location.reload());
   if (PARAMS.q) {
     document.getElementById('search').value = PARAMS.q;
   }
+  if (PARAMS.mode) {
+    document.getElementById('search-mode').value = PARAMS.mode;
+  }
 }
 
 function setupUploadFile() {
@@ -562,6 +1336,81 @@ function setupUploadFile() {
       new Uploader(file, []).upload();
     }
   });
+  document.querySelector(".upload-folder").classList.remove("hidden");
+  document.getElementById("folder").addEventListener("change", async e => {
+    await uploadFolder(Array.from(e.target.files));
+  });
+  const $toggleRename = document.querySelector(".toggle-rename");
+  $toggleRename.classList.remove("hidden");
+  const setRename = value => {
+    RENAME_ON_CONFLICT = value;
+    $toggleRename.classList.toggle("active", value);
+    $toggleRename.title = value
+      ? "Keep both files on name conflict: on"
+      : "Keep both files on name conflict: off";
+  };
+  try {
+    setRename(localStorage.getItem(RENAME_STORAGE_KEY) === "on");
+  } catch {
+    setRename(false);
+  }
+  $toggleRename.addEventListener("click", () => {
+    setRename(!RENAME_ON_CONFLICT);
+    try {
+      localStorage.setItem(RENAME_STORAGE_KEY, RENAME_ON_CONFLICT ? "on" : "off");
+    } catch { }
+  });
+}
+
+/**
+ * Upload the files of a picked folder, skipping those the server already has unchanged
+ * @param {File[]} files
+ */
+async function uploadFolder(files) {
+  const items = files.map(file => {
+    const pathParts = relativeDirs(file);
+    return { file, pathParts, path: [...pathParts, file.name].join("/") };
+  });
+  const unchanged = new Set();
+  if (!RENAME_ON_CONFLICT) {
+    try {
+      const manifest = items.map(({ file, path }) => ({
+        path,
+        size: file.size,
+        mtime: file.lastModified,
+      }));
+      const res = await fetch(baseUrl() + "?manifest", {
+        method: "POST",
+        headers: {
+          "content-type": "application/json",
+        },
+        body: JSON.stringify(manifest),
+      });
+      if (res.ok) {
+        for (const item of await res.json()) {
+          if (item.state === "unchanged") unchanged.add(item.path);
+        }
+      }
+    } catch { }
+  }
+  for (const { file, pathParts, path } of items) {
+    const uploader = new Uploader(file, pathParts);
+    if (unchanged.has(path)) {
+      uploader.skip();
+    } else {
+      uploader.upload();
+    }
+  }
+}
+
+/**
+ * Get the directories of a file picked with a folder input or dropped without entry support
+ * @param {File} file
+ * @returns {string[]}
+ */
+function relativeDirs(file) {
+  const relativePath = file.webkitRelativePath || "";
+  return relativePath.split("/").filter(v => v.length > 0).slice(0, -1);
 }
 
 function setupNewFolder() {
@@ -636,6 +1485,7 @@ async function setupEditorPage() {
   try {
     const res = await fetch(baseUrl());
     await assertResOK(res);
+    editorEtag = res.headers.get("etag");
     const encoding = getEncoding(res.headers.get("content-type"));
     if (encoding === "utf-8") {
       $editor.value = await res.text();
@@ -659,14 +1509,127 @@ async function deletePath(index) {
   const file = DATA.paths[index];
   if (!file) return;
   await doDeletePath(file.name, newUrl(file.name), () => {
-    document.getElementById(`addPath${index}`)?.remove();
-    DATA.paths[index] = null;
-    if (!DATA.paths.find(v => !!v)) {
-      $pathsTable.classList.add("hidden");
-      $emptyFolder.textContent = DIR_EMPTY_NOTE;
-      $emptyFolder.classList.remove("hidden");
+    removePath(index);
+    updateSelection();
+  });
+}
+
+/**
+ * Remove path row from the listing
+ * @param {number} index
+ */
+function removePath(index) {
+  document.getElementById(`addPath${index}`)?.remove();
+  DATA.paths[index] = null;
+  if (!DATA.paths.find(v => !!v)) {
+    $pathsTable.classList.add("hidden");
+    $emptyFolder.textContent = DIR_EMPTY_NOTE;
+    $emptyFolder.classList.remove("hidden");
+  }
+}
+
+function setupSelection() {
+  $pathsTableHead.addEventListener("change", e => {
+    if (e.target.id !== "selectAll") return;
+    document.querySelectorAll("tr:not(.hidden) .path-select").forEach($checkbox => {
+      $checkbox.checked = e.target.checked;
+    });
+    updateSelection();
+  });
+  $pathsTableBody.addEventListener("change", e => {
+    if (e.target.classList.contains("path-select")) updateSelection();
+  });
+  document.querySelector(".delete-selected").addEventListener("click", deleteSelected);
+  document.querySelector(".move-selected").addEventListener("click", moveSelected);
+}
+
+/**
+ * Get indexes of the selected paths
+ * @returns {number[]}
+ */
+function selectedIndexes() {
+  return Array.from(document.querySelectorAll(".path-select:checked"))
+    .map($checkbox => parseInt($checkbox.dataset.index, 10));
+}
+
+function updateSelection() {
+  const count = selectedIndexes().length;
+  const $deleteSelected = document.querySelector(".delete-selected");
+  if (!$deleteSelected) return;
+  $deleteSelected.querySelector(".selected-count").textContent = count;
+  $deleteSelected.classList.toggle("hidden", count === 0);
+  document.querySelector(".move-selected").classList.toggle("hidden", count === 0 || !DATA.allow_upload);
+  const $selectAll = document.getElementById("selectAll");
+  if ($selectAll) {
+    $selectAll.checked = count > 0 && count === document.querySelectorAll("tr:not(.hidden) .path-select").length;
+  }
+}
+
+async function moveSelected() {
+  const indexes = selectedIndexes();
+  if (indexes.length === 0) return;
+  const target = prompt("Enter the folder to move the selected items to");
+  if (!target) return;
+  const folder = target.split("/").filter(v => v.length > 0).join("/");
+  const ops = indexes.map(index => {
+    const name = DATA.paths[index].name;
+    return { op: "move", from: name, to: `${folder}/${name.split("/").pop()}` };
+  });
+  try {
+    await checkAuth();
+    const results = await postBatch(ops);
+    const failures = results
+      .filter(result => result.status >= 300)
+      .map(result => `${result.name}: ${result.error || result.status}`);
+    if (failures.length > 0) {
+      alert(`Cannot move some items\n${failures.join("\n")}`);
     }
+    location.reload();
+  } catch (err) {
+    alert(`Cannot move selected items, ${err.message}`);
+  }
+}
+
+/**
+ * Run move/copy/delete operations on the current folder in one request
+ * @param {object[]} ops
+ * @returns {Promise<{name: string, status: number, error?: string}[]>}
+ */
+async function postBatch(ops) {
+  const res = await fetch(baseUrl() + "?batch", {
+    method: "POST",
+    headers: {
+      "content-type": "application/json",
+    },
+    body: JSON.stringify(ops),
   });
+  await assertResOK(res);
+  return res.json();
+}
+
+async function deleteSelected() {
+  const indexes = selectedIndexes();
+  if (indexes.length === 0) return;
+  if (!confirm(`Delete ${indexes.length} selected item(s)?`)) return;
+  try {
+    await checkAuth();
+    const ops = indexes.map(index => ({ op: "delete", path: DATA.paths[index].name }));
+    const results = await postBatch(ops);
+    const failures = [];
+    results.forEach((result, i) => {
+      if (result.status < 300 || result.status === 404) {
+        removePath(indexes[i]);
+      } else {
+        failures.push(`${result.name}: ${result.error || result.status}`);
+      }
+    });
+    if (failures.length > 0) {
+      alert(`Cannot delete some items\n${failures.join("\n")}`);
+    }
+  } catch (err) {
+    alert(`Cannot delete selected items, ${err.message}`);
+  }
+  updateSelection();
 }
 
 async function doDeletePath(name, url, cb) {
@@ -698,6 +1661,73 @@ async function movePath(index) {
   }
 }
 
+/**
+ * Copy the SHA-256 of a file, computed by the server
+ * @param {number} index
+ */
+async function copyHash(index) {
+  const file = DATA.paths[index];
+  if (!file) return;
+  const $hashBtn = document.getElementById(`hashBtn${index}`);
+  try {
+    const res = await fetch(withToken(newUrl(file.name) + "?hash"));
+    await assertResOK(res);
+    const hash = (await res.text()).trim();
+    // The clipboard is only available on https and localhost
+    if (navigator.clipboard) {
+      await navigator.clipboard.writeText(hash);
+      $hashBtn.title = `Copied ${hash}`;
+    } else {
+      prompt("SHA-256", hash);
+    }
+  } catch (err) {
+    alert(`Cannot get the SHA-256 of \`${file.name}\`, ${err.message}`);
+  }
+}
+
+/**
+ * Rename path within the current folder
+ * @param {number} index
+ */
+async function renamePath(index) {
+  const file = DATA.paths[index];
+  if (!file) return;
+  const newName = prompt("Enter new name", file.name);
+  if (!newName || newName === file.name) return;
+  const error = checkNewName(newName);
+  if (error) {
+    alert(`Cannot rename \`${file.name}\` to \`${newName}\`, ${error}`);
+    return;
+  }
+  try {
+    await checkAuth();
+    const res = await fetch(newUrl(file.name), {
+      method: "MOVE",
+      headers: {
+        "Destination": newUrl(newName),
+      }
+    });
+    await assertResOK(res);
+    location.reload();
+  } catch (err) {
+    alert(`Cannot rename \`${file.name}\` to \`${newName}\`, ${err.message}`);
+  }
+}
+
+/**
+ * Check a new name for a path of the current folder
+ * @param {string} name
+ * @returns {string} why the name is invalid, empty if it is valid
+ */
+function checkNewName(name) {
+  if (name.trim() !== name) return "the name has leading or trailing spaces";
+  if (name === "." || name === "..") return "the name is reserved";
+  if (/[\/\\]/.test(name)) return "the name contains a slash";
+  if (/[\x00-\x1f]/.test(name)) return "the name contains control characters";
+  if (DATA.paths.some(v => v && v.name === name)) return "a file or folder with this name already exists";
+  return "";
+}
+
 async function doMovePath(fileUrl) {
   const fileUrlObj = new URL(fileUrl);
 
@@ -740,10 +1770,20 @@ async function doMovePath(fileUrl) {
  */
 async function saveChange() {
   try {
-    await fetch(baseUrl(), {
+    const headers = editorEtag ? { "If-Match": editorEtag } : {};
+    let res = await fetch(baseUrl(), {
       method: "PUT",
       body: $editor.value,
+      headers,
     });
+    if (res.status === 412) {
+      if (!confirm("File changed on server since it was opened.\nOverwrite it with your changes?")) return;
+      res = await fetch(baseUrl(), {
+        method: "PUT",
+        body: $editor.value,
+      });
+    }
+    await assertResOK(res);
     location.reload();
   } catch (err) {
     alert(`Failed to save file, ${err.message}`);
@@ -752,6 +1792,10 @@ async function saveChange() {
 
 async function checkAuth() {
   if (!DATA.auth) return;
+  if (DATA.login_page && !DATA.user) {
+    location.href = loginUrl();
+    throw new Error("Login required");
+  }
   const res = await fetch(baseUrl(), {
     method: "CHECKAUTH",
   });
@@ -763,6 +1807,10 @@ async function checkAuth() {
 
 function logout() {
   if (!DATA.auth) return;
+  if (DATA.login_page) {
+    location.href = `${DATA.uri_prefix}__dufs__/logout`;
+    return;
+  }
   const url = baseUrl();
   const xhr = new XMLHttpRequest();
   xhr.open("LOGOUT", url, true, DATA.user);
@@ -772,6 +1820,11 @@ function logout() {
   xhr.send();
 }
 
+function loginUrl() {
+  const next = location.pathname + location.search;
+  return `${DATA.uri_prefix}__dufs__/login?next=${encodeURIComponent(next)}`;
+}
+
 /**
  * Create a folder
  * @param {string} name
@@ -790,6 +1843,22 @@ async function createFolder(name) {
   }
 }
 
+/**
+ * Create a folder without leaving the current page
+ * @param {string} name
+ */
+async function createEmptyFolder(name) {
+  try {
+    await checkAuth();
+    const res = await fetch(newUrl(name), {
+      method: "MKCOL",
+    });
+    if (res.status !== 405) await assertResOK(res);
+  } catch (err) {
+    alert(`Cannot create folder \`${name}\`, ${err.message}`);
+  }
+}
+
 async function createFile(name) {
   const url = newUrl(name);
   try {
@@ -813,11 +1882,17 @@ async function addFileEntries(entries, dirs) {
       });
     } else if (entry.isDirectory) {
       const dirReader = entry.createReader();
+      const entryDirs = [...dirs, entry.name];
+      let isEmpty = true;
 
       const successCallback = entries => {
         if (entries.length > 0) {
-          addFileEntries(entries, [...dirs, entry.name]);
+          isEmpty = false;
+          addFileEntries(entries, entryDirs);
           dirReader.readEntries(successCallback);
+        } else if (isEmpty) {
+          // Files create their parent folders on upload, only empty folders need an explicit request
+          createEmptyFolder(entryDirs.join("/"));
         }
       };
 
@@ -827,6 +1902,16 @@ async function addFileEntries(entries, dirs) {
 }
 
 
+/**
+ * Keep the guest token of the page on links, so shared folders stay browsable
+ * @param {string} url
+ * @returns {string}
+ */
+function withToken(url) {
+  if (!PARAMS.token) return url;
+  return url + (url.includes("?") ? "&" : "?") + "token=" + encodeURIComponent(PARAMS.token);
+}
+
 function newUrl(name) {
   let url = baseUrl();
   if (!url.endsWith("/")) url += "/";
@@ -865,15 +1950,63 @@ function getPathSvg(path_type) {
   }
 }
 
+/**
+ * Metadata columns of the listing chosen with `--show-columns`
+ * @returns {string[]}
+ */
+function listColumns() {
+  return (DATA.columns || ["mtime", "size"]).filter(v => COLUMN_NAMES[v]);
+}
+
+/**
+ * @param {PathItem} file
+ * @param {string} name
+ * @returns {string}
+ */
+function formatColumn(file, name) {
+  switch (name) {
+    case "mtime":
+      return formatTime(file);
+    case "size":
+      return formatSize(file.size).join(" ");
+    default:
+      return encodedStr(file[name] || "");
+  }
+}
+
+/**
+ * @param {PathItem} file
+ * @returns {string}
+ */
+function formatTime(file) {
+  if (!file.mtime) return "";
+  const date = formatMtime(file.mtime);
+  const relative = formatRelativeTime(file.mtime);
+  const [text, title] = RELATIVE_TIME ? [relative, date] : [date, relative];
+  return `<time class="mtime" datetime="${file.modified || ""}" title="${title}">${text}</time>`;
+}
+
 function formatMtime(mtime) {
   if (!mtime) return "";
   const date = new Date(mtime);
-  const year = date.getFullYear();
-  const month = padZero(date.getMonth() + 1, 2);
-  const day = padZero(date.getDate(), 2);
-  const hours = padZero(date.getHours(), 2);
-  const minutes = padZero(date.getMinutes(), 2);
-  return `${year}-${month}-${day} ${hours}:${minutes}`;
+  // Follow the server with `--utc`, the browser's timezone otherwise
+  const utc = DATA.utc;
+  const year = utc ? date.getUTCFullYear() : date.getFullYear();
+  const month = padZero((utc ? date.getUTCMonth() : date.getMonth()) + 1, 2);
+  const day = padZero(utc ? date.getUTCDate() : date.getDate(), 2);
+  const hours = padZero(utc ? date.getUTCHours() : date.getHours(), 2);
+  const minutes = padZero(utc ? date.getUTCMinutes() : date.getMinutes(), 2);
+  return `${year}-${month}-${day} ${hours}:${minutes}${utc ? "Z" : ""}`;
+}
+
+function formatRelativeTime(mtime) {
+  const seconds = (mtime - Date.now()) / 1000;
+  const units = [["year", 31536000], ["month", 2592000], ["week", 604800], ["day", 86400], ["hour", 3600], ["minute", 60]];
+  const rtf = new Intl.RelativeTimeFormat(undefined, { numeric: "auto" });
+  for (const [unit, size] of units) {
+    if (Math.abs(seconds) >= size) return rtf.format(Math.round(seconds / size), unit);
+  }
+  return rtf.format(0, "second");
 }
 
 function padZero(value, size) {
@@ -916,7 +2049,20 @@ function encodedStr(rawStr) {
 
 async function assertResOK(res) {
   if (!(res.status >= 200 && res.status < 300)) {
-    throw new Error(await res.text() || `Invalid status ${res.status}`);
+    const text = await res.text();
+    throw new Error(errorReason(text) || text || `Invalid status ${res.status}`);
+  }
+}
+
+/**
+ * Get the message of a JSON error body, e.g. `{"code": "insufficient_storage", "message": "No space left on device", "path": "/file"}`
+ * @param {string} text
+ */
+function errorReason(text) {
+  try {
+    return JSON.parse(text).message;
+  } catch {
+    return "";
   }
 }
 
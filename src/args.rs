@@ -3,15 +3,18 @@ use async_zip::Compression;
 use clap::builder::{PossibleValue, PossibleValuesParser};
 use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueEnum};
 use clap_complete::{generate, Generator, Shell};
-use serde::{Deserialize, Deserializer};
+use encoding_rs::Encoding;
+use hyper::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Deserializer, Serialize};
 use smart_default::SmartDefault;
 use std::env;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
 
 use crate::auth::AccessControl;
 use crate::http_logger::HttpLogger;
-use crate::utils::encode_uri;
+use crate::utils::{encode_uri, same_filesystem};
 
 pub fn build_cli() -> Command {
     let app = Command::new(env!("CARGO_CRATE_NAME"))
@@ -60,6 +63,17 @@ pub fn build_cli() -> Command {
                 .help("Specify port to listen on [default: 5000]")
                 .value_name("port"),
         )
+        .arg(
+            Arg::new("bind-readonly")
+                .env("DUFS_BIND_READONLY")
+                .hide_env(true)
+                .long("bind-readonly")
+                .value_parser(value_parser!(SocketAddr))
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("addrs")
+                .help("Also listen on these ip:port addresses, only allowing reads there"),
+        )
         .arg(
             Arg::new("path-prefix")
                 .env("DUFS_PATH_PREFIX")
@@ -68,6 +82,120 @@ pub fn build_cli() -> Command {
                 .value_name("path")
                 .help("Specify a path prefix"),
         )
+        .arg(
+            Arg::new("public-url")
+                .env("DUFS_PUBLIC_URL")
+                .hide_env(true)
+                .long("public-url")
+                .value_name("url")
+                .help("Origin clients reach dufs at, for absolute links in feeds, e.g. https://files.example.com"),
+        )
+        .arg(
+            Arg::new("upload-path")
+                .env("DUFS_UPLOAD_PATH")
+                .hide_env(true)
+                .long("upload-path")
+                .value_parser(value_parser!(PathBuf))
+                .value_name("path")
+                .help("Store uploaded files in this directory instead of the served one"),
+        )
+        .arg(
+            Arg::new("upload-tmp-dir")
+                .env("DUFS_UPLOAD_TMP_DIR")
+                .hide_env(true)
+                .long("upload-tmp-dir")
+                .value_parser(value_parser!(PathBuf))
+                .value_name("dir")
+                .help("Write uploads here and move them into place once complete, on the same filesystem"),
+        )
+        .arg(
+            Arg::new("upload-journal")
+                .env("DUFS_UPLOAD_JOURNAL")
+                .hide_env(true)
+                .long("upload-journal")
+                .value_parser(value_parser!(PathBuf))
+                .value_name("path")
+                .help("Record chunked uploads in progress in this file, so they can be resumed after a restart"),
+        )
+        .arg(
+            Arg::new("route")
+                .env("DUFS_ROUTE")
+                .hide_env(true)
+                .long("route")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("from=to")
+                .help("Rewrite request paths, a trailing `*` in `from` matches a prefix"),
+        )
+        .arg(
+            Arg::new("header")
+                .env("DUFS_HEADER")
+                .hide_env(true)
+                .long("header")
+                .action(ArgAction::Append)
+                .value_name("rule")
+                .help("Add a response header to matching paths, e.g. '/assets/*=Cache-Control: immutable'"),
+        )
+        .arg(
+            Arg::new("preview")
+                .env("DUFS_PREVIEW")
+                .hide_env(true)
+                .long("preview")
+                .action(ArgAction::Append)
+                .value_name("rule")
+                .help("Serve `?preview` of files with a command, e.g. 'heic,heif>jpg=convert {input} -thumbnail 1024x1024 {output}'"),
+        )
+        .arg(
+            Arg::new("preview-cache")
+                .env("DUFS_PREVIEW_CACHE")
+                .hide_env(true)
+                .long("preview-cache")
+                .value_parser(value_parser!(PathBuf))
+                .value_name("dir")
+                .help("Directory keeping generated previews [default: <tmpdir>/dufs-previews]"),
+        )
+        .arg(
+            Arg::new("ffmpeg")
+                .env("DUFS_FFMPEG")
+                .hide_env(true)
+                .long("ffmpeg")
+                .num_args(0..=1)
+                .default_missing_value("ffmpeg")
+                .value_name("path")
+                .help("Make poster frames of videos with ffmpeg, served as `?thumb`"),
+        )
+        .arg(
+            Arg::new("user")
+                .env("DUFS_USER")
+                .hide_env(true)
+                .long("user")
+                .value_name("name")
+                .help("Switch to this user after binding, e.g. when started as root to listen on port 80"),
+        )
+        .arg(
+            Arg::new("group")
+                .env("DUFS_GROUP")
+                .hide_env(true)
+                .long("group")
+                .value_name("name")
+                .help("Switch to this group after binding [default: the group of --user]"),
+        )
+        .arg(
+            Arg::new("sandbox")
+                .env("DUFS_SANDBOX")
+                .hide_env(true)
+                .long("sandbox")
+                .action(ArgAction::SetTrue)
+                .help("Confine the process to the served paths, only supported on Linux with Landlock"),
+        )
+        .arg(
+            Arg::new("pipe")
+                .env("DUFS_PIPE")
+                .hide_env(true)
+                .long("pipe")
+                .value_name("name")
+                .help("Serve data read from stdin as a single file with this name"),
+        )
         .arg(
             Arg::new("hidden")
                 .env("DUFS_HIDDEN")
@@ -78,6 +206,50 @@ pub fn build_cli() -> Command {
                 .help("Hide paths from directory listings, e.g. tmp,*.log,*.lock")
                 .value_name("value"),
         )
+        .arg(
+            Arg::new("gitignore")
+                .env("DUFS_GITIGNORE")
+                .hide_env(true)
+                .long("gitignore")
+                .action(ArgAction::SetTrue)
+                .help("Hide paths matched by .gitignore files from listings, search and archives"),
+        )
+        .arg(
+            Arg::new("ignore-metadata-files")
+                .env("DUFS_IGNORE_METADATA_FILES")
+                .hide_env(true)
+                .long("ignore-metadata-files")
+                .action(ArgAction::SetTrue)
+                .help("Hide .DS_Store, ._* and Thumbs.db files and drop uploads of them"),
+        )
+        .arg(
+            Arg::new("show-columns")
+                .env("DUFS_SHOW_COLUMNS")
+                .hide_env(true)
+                .long("show-columns")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(clap::builder::EnumValueParser::<ListColumn>::new())
+                .value_name("cols")
+                .help("Metadata shown in directory listings [default: mtime,size] [possible values: mtime, size, type, owner, group, mode]")
+                .hide_possible_values(true),
+        )
+        .arg(
+            Arg::new("show-permissions")
+                .env("DUFS_SHOW_PERMISSIONS")
+                .hide_env(true)
+                .long("show-permissions")
+                .action(ArgAction::SetTrue)
+                .help("Show the mode, owner and group of paths in listings, same as adding them to --show-columns"),
+        )
+        .arg(
+            Arg::new("readme")
+                .env("DUFS_README")
+                .hide_env(true)
+                .long("readme")
+                .value_name("file")
+                .help("Render this Markdown file of a directory above its listing, empty to disable [default: README.md]"),
+        )
         .arg(
             Arg::new("auth")
                 .env("DUFS_AUTH")
@@ -88,6 +260,59 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::Append)
                 .value_name("rules"),
         )
+        .arg(
+            Arg::new("user-root")
+                .env("DUFS_USER_ROOT")
+                .hide_env(true)
+                .long("user-root")
+                .action(ArgAction::SetTrue)
+                .help("Confine each user to <root>/<username>, created on first login"),
+        )
+        .arg(
+            Arg::new("login-page")
+                .env("DUFS_LOGIN_PAGE")
+                .hide_env(true)
+                .long("login-page")
+                .action(ArgAction::SetTrue)
+                .help("Log browsers in with a form and a session cookie instead of a popup"),
+        )
+        .arg(
+            Arg::new("session-ttl")
+                .env("DUFS_SESSION_TTL")
+                .hide_env(true)
+                .long("session-ttl")
+                .value_parser(value_parser!(u64))
+                .value_name("secs")
+                .help("Lifetime of login page sessions [default: 86400]"),
+        )
+        .arg(
+            Arg::new("admin-token")
+                .env("DUFS_ADMIN_TOKEN")
+                .hide_env(true)
+                .long("admin-token")
+                .value_name("token")
+                .help("Enable the admin API at /__dufs__/admin/ for requests with this bearer token"),
+        )
+        .arg(
+            Arg::new("s3-bucket")
+                .env("DUFS_S3_BUCKET")
+                .hide_env(true)
+                .long("s3-bucket")
+                .value_name("name")
+                .help("Serve an S3-compatible API at /<name>, a bucket holding the served files"),
+        )
+        .arg(
+            Arg::new("webdav-quirks")
+                .env("DUFS_WEBDAV_QUIRKS")
+                .hide_env(true)
+                .long("webdav-quirks")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(clap::builder::EnumValueParser::<WebdavQuirk>::new())
+                .value_name("clients")
+                .help("Work around known issues of WebDAV clients [possible values: windows, rclone, finder]")
+                .hide_possible_values(true),
+        )
         .arg(
             Arg::new("auth-method")
                 .hide(true)
@@ -148,6 +373,212 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Allow zip archive generation"),
         )
+        .arg(
+            Arg::new("allow-chmod")
+                .env("DUFS_ALLOW_CHMOD")
+                .hide_env(true)
+                .long("allow-chmod")
+                .action(ArgAction::SetTrue)
+                .help("Allow changing permissions of files with PATCH ?chmod=644, not included in --allow-all"),
+        )
+        .arg(
+            Arg::new("no-archive")
+                .env("DUFS_NO_ARCHIVE")
+                .hide_env(true)
+                .long("no-archive")
+                .action(ArgAction::SetTrue)
+                .help("Disable zip archive generation, even if allowed by other options"),
+        )
+        .arg(
+            Arg::new("no-search")
+                .env("DUFS_NO_SEARCH")
+                .hide_env(true)
+                .long("no-search")
+                .action(ArgAction::SetTrue)
+                .help("Disable and reject search requests, even if allowed by other options"),
+        )
+        .arg(
+            Arg::new("no-delete")
+                .env("DUFS_NO_DELETE")
+                .hide_env(true)
+                .long("no-delete")
+                .action(ArgAction::SetTrue)
+                .help("Disable delete/overwrite, even if allowed by other options"),
+        )
+        .arg(
+            Arg::new("write-once")
+                .env("DUFS_WRITE_ONCE")
+                .hide_env(true)
+                .long("write-once")
+                .action(ArgAction::SetTrue)
+                .help("Only allow uploads to create new files, existing files can't be changed or deleted"),
+        )
+        .arg(
+            Arg::new("fsync")
+                .env("DUFS_FSYNC")
+                .hide_env(true)
+                .long("fsync")
+                .action(ArgAction::SetTrue)
+                .help("Flush uploaded files to disk before responding"),
+        )
+        .arg(
+            Arg::new("no-atime-updates")
+                .env("DUFS_NO_ATIME_UPDATES")
+                .hide_env(true)
+                .long("no-atime-updates")
+                .action(ArgAction::SetTrue)
+                .help("Read files without updating their access time (Linux, files owned by the dufs user)"),
+        )
+        .arg(
+            Arg::new("io-uring")
+                .env("DUFS_IO_URING")
+                .hide_env(true)
+                .long("io-uring")
+                .action(ArgAction::SetTrue)
+                .help("Read and write files with io_uring (Linux, built with the io-uring feature)"),
+        )
+        .arg(
+            Arg::new("checksum-sidecar")
+                .env("DUFS_CHECKSUM_SIDECAR")
+                .hide_env(true)
+                .long("checksum-sidecar")
+                .action(ArgAction::SetTrue)
+                .help("Write a .sha256 file next to each uploaded file"),
+        )
+        .arg(
+            Arg::new("dedup-uploads")
+                .env("DUFS_DEDUP_UPLOADS")
+                .hide_env(true)
+                .value_parser(clap::builder::EnumValueParser::<DedupMode>::new())
+                .long("dedup-uploads")
+                .value_name("mode")
+                .help("Store uploads identical to an existing file as a reflink or hardlink to it"),
+        )
+        .arg(
+            Arg::new("allow-upload-types")
+                .env("DUFS_ALLOW_UPLOAD_TYPES")
+                .hide_env(true)
+                .long("allow-upload-types")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("ext")
+                .help("Only allow uploads with these extensions and matching content, e.g. jpg,png,pdf"),
+        )
+        .arg(
+            Arg::new("deny-upload-types")
+                .env("DUFS_DENY_UPLOAD_TYPES")
+                .hide_env(true)
+                .long("deny-upload-types")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("ext")
+                .help("Reject uploads with these extensions, e.g. exe,sh,bat"),
+        )
+        .arg(
+            Arg::new("max-archive-jobs")
+                .env("DUFS_MAX_ARCHIVE_JOBS")
+                .hide_env(true)
+                .long("max-archive-jobs")
+                .value_parser(value_parser!(usize))
+                .value_name("num")
+                .help("Maximum number of concurrent zip archive downloads, 0 for no limit [default: 4]"),
+        )
+        .arg(
+            Arg::new("max-requests")
+                .env("DUFS_MAX_REQUESTS")
+                .hide_env(true)
+                .long("max-requests")
+                .value_parser(value_parser!(usize))
+                .value_name("num")
+                .help("Answer 503 beyond this many requests in flight, zip downloads already past half of it, 0 for no limit [default: 0]"),
+        )
+        .arg(
+            Arg::new("search-max-results")
+                .env("DUFS_SEARCH_MAX_RESULTS")
+                .hide_env(true)
+                .long("search-max-results")
+                .value_parser(value_parser!(usize))
+                .value_name("num")
+                .help("Stop a search after this many matches"),
+        )
+        .arg(
+            Arg::new("search-timeout")
+                .env("DUFS_SEARCH_TIMEOUT")
+                .hide_env(true)
+                .long("search-timeout")
+                .value_parser(value_parser!(u64))
+                .value_name("secs")
+                .help("Stop a search after this many seconds, 0 to disable [default: 60]"),
+        )
+        .arg(
+            Arg::new("depth-max-entries")
+                .env("DUFS_DEPTH_MAX_ENTRIES")
+                .hide_env(true)
+                .long("depth-max-entries")
+                .value_parser(value_parser!(usize))
+                .value_name("num")
+                .help("Maximum entries of a `Depth: infinity` listing, 0 to refuse them [default: 10000]"),
+        )
+        .arg(
+            Arg::new("worker-threads")
+                .env("DUFS_WORKER_THREADS")
+                .hide_env(true)
+                .long("worker-threads")
+                .value_parser(value_parser!(usize))
+                .value_name("num")
+                .help("Number of threads handling requests [default: number of cpus]"),
+        )
+        .arg(
+            Arg::new("blocking-threads")
+                .env("DUFS_BLOCKING_THREADS")
+                .hide_env(true)
+                .long("blocking-threads")
+                .value_parser(value_parser!(usize))
+                .value_name("num")
+                .help("Maximum number of threads for file io, hashing and compression [default: 512]"),
+        )
+        .arg(
+            Arg::new("keep-alive")
+                .env("DUFS_KEEP_ALIVE")
+                .hide_env(true)
+                .long("keep-alive")
+                .value_parser(value_parser!(u64))
+                .value_name("secs")
+                .help("Close connections idle for this many seconds between requests, 0 to disable keep-alive"),
+        )
+        .arg(
+            Arg::new("tcp-nodelay")
+                .env("DUFS_TCP_NODELAY")
+                .hide_env(true)
+                .long("tcp-nodelay")
+                .action(ArgAction::SetTrue)
+                .help("Set TCP_NODELAY on accepted connections"),
+        )
+        .arg(
+            Arg::new("no-reuse-addr")
+                .env("DUFS_NO_REUSE_ADDR")
+                .hide_env(true)
+                .long("no-reuse-addr")
+                .action(ArgAction::SetTrue)
+                .help("Don't set SO_REUSEADDR on listening sockets"),
+        )
+        .arg(
+            Arg::new("reuse-port")
+                .env("DUFS_REUSE_PORT")
+                .hide_env(true)
+                .long("reuse-port")
+                .action(ArgAction::SetTrue)
+                .help("Set SO_REUSEPORT on listening sockets, unix only"),
+        )
+        .arg(
+            Arg::new("backlog")
+                .env("DUFS_BACKLOG")
+                .hide_env(true)
+                .long("backlog")
+                .value_parser(value_parser!(u32))
+                .value_name("num")
+                .help("Maximum length of the queue of pending connections [default: 1024]"),
+        )
         .arg(
             Arg::new("enable-cors")
                 .env("DUFS_ENABLE_CORS")
@@ -180,6 +611,39 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Serve SPA(Single Page Application)"),
         )
+        .arg(
+            Arg::new("precompressed")
+                .env("DUFS_PRECOMPRESSED")
+                .hide_env(true)
+                .long("precompressed")
+                .action(ArgAction::SetTrue)
+                .help("Serve pre-compressed .br/.gz variants of files when the client accepts them"),
+        )
+        .arg(
+            Arg::new("charset")
+                .env("DUFS_CHARSET")
+                .hide_env(true)
+                .long("charset")
+                .value_name("encoding")
+                .help("Charset of non-UTF-8 text files instead of guessing it, e.g. gbk, shift_jis"),
+        )
+        .arg(
+            Arg::new("sniff-content")
+                .env("DUFS_SNIFF_CONTENT")
+                .hide_env(true)
+                .long("sniff-content")
+                .action(ArgAction::SetTrue)
+                .help("Detect the type of files without a known extension from their first bytes"),
+        )
+        .arg(
+            Arg::new("unicode-normalize")
+                .env("DUFS_UNICODE_NORMALIZE")
+                .hide_env(true)
+                .value_parser(clap::builder::EnumValueParser::<UnicodeNormalize>::new())
+                .long("unicode-normalize")
+                .value_name("form")
+                .help("Normalize request paths and uploaded names to this unicode form"),
+        )
         .arg(
             Arg::new("assets")
                 .env("DUFS_ASSETS")
@@ -189,6 +653,42 @@ pub fn build_cli() -> Command {
                 .value_parser(value_parser!(PathBuf))
                 .value_name("path")
         )
+        .arg(
+            Arg::new("robots")
+                .env("DUFS_ROBOTS")
+                .hide_env(true)
+                .value_parser(clap::builder::EnumValueParser::<Robots>::new())
+                .long("robots")
+                .value_name("value")
+                .help("Set the built-in robots.txt policy [default: deny]")
+        )
+        .arg(
+            Arg::new("favicon")
+                .env("DUFS_FAVICON")
+                .hide_env(true)
+                .long("favicon")
+                .help("Set the path to a file served as favicon.ico")
+                .value_parser(value_parser!(PathBuf))
+                .value_name("path")
+        )
+        .arg(
+            Arg::new("header-file")
+                .env("DUFS_HEADER_FILE")
+                .hide_env(true)
+                .long("header-file")
+                .help("Insert the HTML of this file at the top of the web UI")
+                .value_parser(value_parser!(PathBuf))
+                .value_name("path")
+        )
+        .arg(
+            Arg::new("footer-file")
+                .env("DUFS_FOOTER_FILE")
+                .hide_env(true)
+                .long("footer-file")
+                .help("Insert the HTML of this file at the bottom of the web UI")
+                .value_parser(value_parser!(PathBuf))
+                .value_name("path")
+        )
         .arg(
             Arg::new("log-format")
                 .env("DUFS_LOG_FORMAT")
@@ -206,6 +706,25 @@ pub fn build_cli() -> Command {
                 .value_parser(value_parser!(PathBuf))
                 .help("Specify the file to save logs to, other than stdout/stderr"),
         )
+        .arg(
+            Arg::new("trusted-proxies")
+                .env("DUFS_TRUSTED_PROXIES")
+                .hide_env(true)
+                .long("trusted-proxies")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(value_parser!(IpAddr))
+                .value_name("addrs")
+                .help("Keep the X-Request-Id header of requests from these proxies, e.g. 127.0.0.1,::1"),
+        )
+        .arg(
+            Arg::new("utc")
+                .env("DUFS_UTC")
+                .hide_env(true)
+                .long("utc")
+                .action(ArgAction::SetTrue)
+                .help("Show times in UTC instead of the server's timezone"),
+        )
         .arg(
             Arg::new("compress")
                 .env("DUFS_COMPRESS")
@@ -244,7 +763,40 @@ pub fn build_cli() -> Command {
                 .help("Path to the SSL/TLS certificate's private key"),
         );
 
-    app
+    app.args_conflicts_with_subcommands(true)
+        .disable_help_subcommand(true)
+        .subcommand(
+            Command::new("bench")
+                .about("Benchmark the server with small-file, large-file, ranged and zip downloads")
+                .arg(
+                    Arg::new("dir")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("Directory to create the benchmark files in"),
+                )
+                .arg(
+                    Arg::new("requests")
+                        .long("requests")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1000")
+                        .value_name("num")
+                        .help("Number of small-file requests, the other workloads scale from it"),
+                )
+                .arg(
+                    Arg::new("concurrency")
+                        .long("concurrency")
+                        .value_parser(value_parser!(usize))
+                        .default_value("8")
+                        .value_name("num")
+                        .help("Number of concurrent connections"),
+                )
+                .arg(
+                    Arg::new("io-uring")
+                        .long("io-uring")
+                        .action(ArgAction::SetTrue)
+                        .help("Serve with --io-uring, to compare against the default file io"),
+                ),
+        )
 }
 
 pub fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
@@ -266,30 +818,110 @@ pub struct Args {
     #[serde(default = "default_port")]
     #[default(default_port())]
     pub port: u16,
+    pub bind_readonly: Vec<SocketAddr>,
     #[serde(skip)]
     pub path_is_file: bool,
     pub path_prefix: String,
+    pub public_url: Option<String>,
+    pub upload_path: Option<PathBuf>,
+    pub upload_tmp_dir: Option<PathBuf>,
+    pub upload_journal: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_routes")]
+    #[serde(rename = "route")]
+    pub routes: Vec<Route>,
+    #[serde(deserialize_with = "deserialize_headers")]
+    #[serde(rename = "header")]
+    pub headers: Vec<CustomHeader>,
+    #[serde(deserialize_with = "deserialize_previews")]
+    #[serde(rename = "preview")]
+    pub previews: Vec<PreviewRule>,
+    pub preview_cache: Option<PathBuf>,
+    pub ffmpeg: Option<String>,
+    #[serde(skip)]
+    pub video_poster: Option<PreviewRule>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub sandbox: bool,
+    #[serde(skip)]
+    pub pipe: Option<String>,
     #[serde(skip)]
     pub uri_prefix: String,
     #[serde(deserialize_with = "deserialize_string_or_vec")]
     pub hidden: Vec<String>,
+    pub gitignore: bool,
+    pub ignore_metadata_files: bool,
+    #[serde(deserialize_with = "deserialize_columns")]
+    #[default(vec![ListColumn::Mtime, ListColumn::Size])]
+    pub show_columns: Vec<ListColumn>,
+    pub show_permissions: bool,
+    #[default(Some("README.md".to_string()))]
+    pub readme: Option<String>,
     #[serde(deserialize_with = "deserialize_access_control")]
     pub auth: AccessControl,
+    pub user_root: bool,
+    pub login_page: bool,
+    #[default(86400)]
+    pub session_ttl: u64,
+    pub admin_token: Option<String>,
+    pub s3_bucket: Option<String>,
+    #[serde(deserialize_with = "deserialize_webdav_quirks")]
+    pub webdav_quirks: Vec<WebdavQuirk>,
     pub allow_all: bool,
     pub allow_upload: bool,
     pub allow_delete: bool,
     pub allow_search: bool,
     pub allow_symlink: bool,
     pub allow_archive: bool,
+    pub allow_chmod: bool,
+    pub no_archive: bool,
+    pub no_search: bool,
+    pub no_delete: bool,
+    pub write_once: bool,
+    pub fsync: bool,
+    pub no_atime_updates: bool,
+    pub io_uring: bool,
+    pub checksum_sidecar: bool,
+    pub dedup_uploads: Option<DedupMode>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub allow_upload_types: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub deny_upload_types: Vec<String>,
+    #[default(4)]
+    pub max_archive_jobs: usize,
+    pub max_requests: usize,
+    pub search_max_results: Option<usize>,
+    #[default(60)]
+    pub search_timeout: u64,
+    #[default(10000)]
+    pub depth_max_entries: usize,
+    pub worker_threads: Option<usize>,
+    pub blocking_threads: Option<usize>,
+    pub keep_alive: Option<u64>,
+    pub tcp_nodelay: bool,
+    pub no_reuse_addr: bool,
+    pub reuse_port: bool,
+    #[default(1024)]
+    pub backlog: u32,
     pub render_index: bool,
     pub render_spa: bool,
     pub render_try_index: bool,
     pub enable_cors: bool,
+    pub precompressed: bool,
+    #[serde(deserialize_with = "deserialize_charset")]
+    pub charset: Option<&'static Encoding>,
+    pub sniff_content: bool,
+    pub unicode_normalize: Option<UnicodeNormalize>,
     pub assets: Option<PathBuf>,
+    pub robots: Robots,
+    pub favicon: Option<PathBuf>,
+    pub header_file: Option<PathBuf>,
+    pub footer_file: Option<PathBuf>,
     #[serde(deserialize_with = "deserialize_log_http")]
     #[serde(rename = "log-format")]
     pub http_logger: HttpLogger,
     pub log_file: Option<PathBuf>,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub utc: bool,
     pub compress: Compress,
     pub tls_cert: Option<PathBuf>,
     pub tls_key: Option<PathBuf>,
@@ -316,6 +948,45 @@ impl Args {
 
         args.serve_path = Self::sanitize_path(args.serve_path)?;
 
+        if let Some(path) = matches.get_one::<PathBuf>("upload-path") {
+            args.upload_path = Some(path.clone());
+        }
+        if let Some(path) = &args.upload_path {
+            let path = Self::sanitize_path(path)?;
+            if !path.is_dir() {
+                bail!("Upload path `{}` is not a directory", path.display());
+            }
+            args.upload_path = Some(path);
+        }
+        if let Some(dir) = matches.get_one::<PathBuf>("upload-tmp-dir") {
+            args.upload_tmp_dir = Some(dir.clone());
+        }
+        if let Some(dir) = &args.upload_tmp_dir {
+            let dir = Self::sanitize_path(dir)?;
+            if !dir.is_dir() {
+                bail!("Upload tmp dir `{}` is not a directory", dir.display());
+            }
+            let root = args.upload_path.as_ref().unwrap_or(&args.serve_path);
+            if !same_filesystem(&dir, root) {
+                bail!(
+                    "Upload tmp dir `{}` is not on the filesystem of `{}`, moving uploads would copy them",
+                    dir.display(),
+                    root.display()
+                );
+            }
+            args.upload_tmp_dir = Some(dir);
+        }
+        if let Some(path) = matches.get_one::<PathBuf>("upload-journal") {
+            args.upload_journal = Some(path.clone());
+        }
+        if let Some(path) = &args.upload_journal {
+            let path = env::current_dir()?.join(path);
+            if path.is_dir() {
+                bail!("Upload journal `{}` is a directory", path.display());
+            }
+            args.upload_journal = Some(path);
+        }
+
         if let Some(port) = matches.get_one::<u16>("port") {
             args.port = *port
         }
@@ -324,6 +995,74 @@ impl Args {
             let addrs: Vec<_> = addrs.map(|v| v.as_str()).collect();
             args.addrs = BindAddr::parse_addrs(&addrs)?;
         }
+        if let Some(addrs) = matches.get_many::<SocketAddr>("bind-readonly") {
+            args.bind_readonly = addrs.cloned().collect();
+        }
+
+        if let Some(routes) = matches.get_many::<String>("route") {
+            let routes: Vec<_> = routes.map(|v| v.as_str()).collect();
+            args.routes = Route::parse_routes(&routes)?;
+        }
+
+        if let Some(headers) = matches.get_many::<String>("header") {
+            let headers: Vec<_> = headers.map(|v| v.as_str()).collect();
+            args.headers = CustomHeader::parse_headers(&headers)?;
+        }
+
+        if let Some(previews) = matches.get_many::<String>("preview") {
+            let previews: Vec<_> = previews.map(|v| v.as_str()).collect();
+            args.previews = PreviewRule::parse_previews(&previews)?;
+        }
+        if let Some(dir) = matches.get_one::<PathBuf>("preview-cache") {
+            args.preview_cache = Some(dir.clone());
+        }
+        if let Some(ffmpeg) = matches.get_one::<String>("ffmpeg") {
+            args.ffmpeg = Some(ffmpeg.clone());
+        }
+        if let Some(ffmpeg) = &args.ffmpeg {
+            args.video_poster = Some(PreviewRule::video_poster(ffmpeg)?);
+        }
+        if !args.previews.is_empty() || args.video_poster.is_some() {
+            let dir = args
+                .preview_cache
+                .take()
+                .unwrap_or_else(|| env::temp_dir().join("dufs-previews"));
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+            args.preview_cache = Some(Self::sanitize_path(dir)?);
+        }
+
+        if let Some(user) = matches.get_one::<String>("user") {
+            args.user = Some(user.clone());
+        }
+        if let Some(group) = matches.get_one::<String>("group") {
+            args.group = Some(group.clone());
+        }
+
+        if !args.sandbox {
+            args.sandbox = matches.get_flag("sandbox");
+        }
+        // The sandbox never allows running programs
+        if args.sandbox && !args.previews.is_empty() {
+            bail!("--sandbox can't be used with --preview");
+        }
+        if args.sandbox && args.video_poster.is_some() {
+            bail!("--sandbox can't be used with --ffmpeg");
+        }
+
+        if let Some(name) = matches.get_one::<String>("pipe") {
+            if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+                bail!("Invalid pipe name `{name}`");
+            }
+            let dir = std::env::temp_dir().join(format!("dufs-pipe-{}", std::process::id()));
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+            let path = dir.join(name);
+            std::fs::File::create(&path)
+                .with_context(|| format!("Failed to create `{}`", path.display()))?;
+            args.serve_path = Self::sanitize_path(path)?;
+            args.pipe = Some(name.clone());
+        }
 
         args.path_is_file = args.serve_path.metadata()?.is_file();
         if let Some(path_prefix) = matches.get_one::<String>("path-prefix") {
@@ -331,6 +1070,22 @@ impl Args {
         }
         args.path_prefix = args.path_prefix.trim_matches('/').to_string();
 
+        if let Some(url) = matches.get_one::<String>("public-url") {
+            args.public_url = Some(url.clone());
+        }
+        if let Some(url) = args.public_url.take() {
+            let url = url.trim_end_matches('/');
+            let valid = url.parse::<hyper::Uri>().is_ok_and(|v| {
+                matches!(v.scheme_str(), Some("http" | "https"))
+                    && v.authority().is_some()
+                    && v.path_and_query().is_none_or(|v| v.as_str() == "/")
+            });
+            if !valid {
+                bail!("Invalid --public-url `{url}`, expected an origin like https://example.com");
+            }
+            args.public_url = Some(url.to_string());
+        }
+
         args.uri_prefix = if args.path_prefix.is_empty() {
             "/".to_owned()
         } else {
@@ -348,46 +1103,247 @@ impl Args {
                 .collect();
         }
 
-        if !args.enable_cors {
-            args.enable_cors = matches.get_flag("enable-cors");
+        if !args.gitignore {
+            args.gitignore = matches.get_flag("gitignore");
+        }
+        if !args.ignore_metadata_files {
+            args.ignore_metadata_files = matches.get_flag("ignore-metadata-files");
+        }
+
+        if !args.enable_cors {
+            args.enable_cors = matches.get_flag("enable-cors");
+        }
+
+        if let Some(rules) = matches.get_many::<String>("auth") {
+            let rules: Vec<_> = rules.map(|v| v.as_str()).collect();
+            args.auth = AccessControl::new(&rules)?;
+        }
+
+        if !args.user_root {
+            args.user_root = matches.get_flag("user-root");
+        }
+        if args.user_root {
+            args.auth.confine_to_homes()?;
+        }
+
+        if !args.login_page {
+            args.login_page = matches.get_flag("login-page");
+        }
+        if let Some(session_ttl) = matches.get_one::<u64>("session-ttl") {
+            args.session_ttl = *session_ttl;
+        }
+        if let Some(token) = matches.get_one::<String>("admin-token") {
+            args.admin_token = Some(token.clone());
+        }
+        if args.admin_token.as_ref().is_some_and(|v| v.is_empty()) {
+            bail!("--admin-token must not be empty");
+        }
+        if let Some(bucket) = matches.get_one::<String>("s3-bucket") {
+            args.s3_bucket = Some(bucket.clone());
+        }
+        if let Some(bucket) = &args.s3_bucket {
+            if bucket.is_empty() || bucket.contains('/') || bucket == "__dufs__" {
+                bail!("Invalid --s3-bucket `{bucket}`, it must be a single path segment");
+            }
+            if args.path_is_file {
+                bail!("--s3-bucket needs a directory to serve");
+            }
+        }
+        if let Some(quirks) = matches.get_many::<WebdavQuirk>("webdav-quirks") {
+            args.webdav_quirks = quirks.copied().collect();
+        }
+        let mut dropped_files = vec![];
+        if args.webdav_quirks.contains(&WebdavQuirk::Finder) {
+            dropped_files.extend(FINDER_JUNK);
+        }
+        if args.ignore_metadata_files {
+            dropped_files.extend(METADATA_FILES);
+        }
+        for pattern in dropped_files {
+            if !args.hidden.iter().any(|v| v == pattern) {
+                args.hidden.push(pattern.to_string());
+            }
+        }
+
+        if !args.allow_all {
+            args.allow_all = matches.get_flag("allow-all");
+        }
+
+        let allow_all = args.allow_all;
+
+        if !args.allow_upload {
+            args.allow_upload = allow_all || matches.get_flag("allow-upload");
+        }
+        if !args.allow_delete {
+            args.allow_delete = allow_all || matches.get_flag("allow-delete");
+        }
+        if !args.allow_search {
+            args.allow_search = allow_all || matches.get_flag("allow-search");
+        }
+        if !args.allow_symlink {
+            args.allow_symlink = allow_all || matches.get_flag("allow-symlink");
+        }
+        if !args.allow_archive {
+            args.allow_archive = allow_all || matches.get_flag("allow-archive");
+        }
+        if !args.allow_chmod {
+            args.allow_chmod = matches.get_flag("allow-chmod");
+        }
+        if cfg!(not(unix)) && args.allow_chmod {
+            bail!("--allow-chmod is only supported on unix");
+        }
+        if !args.no_archive {
+            args.no_archive = matches.get_flag("no-archive");
+        }
+        if !args.no_search {
+            args.no_search = matches.get_flag("no-search");
+        }
+        if !args.no_delete {
+            args.no_delete = matches.get_flag("no-delete");
+        }
+        if !args.write_once {
+            args.write_once = matches.get_flag("write-once");
+        }
+        if !args.fsync {
+            args.fsync = matches.get_flag("fsync");
+        }
+        if !args.no_atime_updates {
+            args.no_atime_updates = matches.get_flag("no-atime-updates");
+        }
+        if !args.io_uring {
+            args.io_uring = matches.get_flag("io-uring");
+        }
+        if cfg!(not(all(feature = "io-uring", target_os = "linux"))) && args.io_uring {
+            bail!("--io-uring is only supported on Linux builds with the io-uring feature");
+        }
+        if !args.checksum_sidecar {
+            args.checksum_sidecar = matches.get_flag("checksum-sidecar");
+        }
+        if let Some(mode) = matches.get_one::<DedupMode>("dedup-uploads") {
+            args.dedup_uploads = Some(*mode);
+        }
+        if let Some(types) = matches.get_many::<String>("allow-upload-types") {
+            args.allow_upload_types = types.cloned().collect();
+        }
+        args.allow_upload_types = Self::normalize_extensions(&args.allow_upload_types);
+        if let Some(types) = matches.get_many::<String>("deny-upload-types") {
+            args.deny_upload_types = types.cloned().collect();
+        }
+        args.deny_upload_types = Self::normalize_extensions(&args.deny_upload_types);
+        if let Some(max_archive_jobs) = matches.get_one::<usize>("max-archive-jobs") {
+            args.max_archive_jobs = *max_archive_jobs;
+        }
+        if let Some(max_requests) = matches.get_one::<usize>("max-requests") {
+            args.max_requests = *max_requests;
+        }
+        if let Some(search_max_results) = matches.get_one::<usize>("search-max-results") {
+            args.search_max_results = Some(*search_max_results);
+        }
+        if let Some(search_timeout) = matches.get_one::<u64>("search-timeout") {
+            args.search_timeout = *search_timeout;
+        }
+        if let Some(depth_max_entries) = matches.get_one::<usize>("depth-max-entries") {
+            args.depth_max_entries = *depth_max_entries;
+        }
+        if let Some(worker_threads) = matches.get_one::<usize>("worker-threads") {
+            args.worker_threads = Some(*worker_threads);
+        }
+        if let Some(blocking_threads) = matches.get_one::<usize>("blocking-threads") {
+            args.blocking_threads = Some(*blocking_threads);
+        }
+        if let Some(keep_alive) = matches.get_one::<u64>("keep-alive") {
+            args.keep_alive = Some(*keep_alive);
+        }
+        if !args.tcp_nodelay {
+            args.tcp_nodelay = matches.get_flag("tcp-nodelay");
+        }
+        if !args.no_reuse_addr {
+            args.no_reuse_addr = matches.get_flag("no-reuse-addr");
+        }
+        if !args.reuse_port {
+            args.reuse_port = matches.get_flag("reuse-port");
+        }
+        if cfg!(not(unix)) && args.reuse_port {
+            bail!("--reuse-port is only supported on unix");
+        }
+        if let Some(backlog) = matches.get_one::<u32>("backlog") {
+            args.backlog = *backlog;
+        }
+        if args.worker_threads == Some(0) || args.blocking_threads == Some(0) {
+            bail!("--worker-threads and --blocking-threads must be greater than 0");
+        }
+        // Each archive job holds a blocking thread while it waits for file io
+        if let Some(blocking_threads) = args.blocking_threads {
+            if args.max_archive_jobs == 0 || blocking_threads <= args.max_archive_jobs {
+                bail!("--blocking-threads must be greater than --max-archive-jobs");
+            }
+        }
+        if args.no_archive {
+            args.allow_archive = false;
+        }
+        if args.no_search {
+            args.allow_search = false;
+        }
+        if args.no_delete || args.write_once {
+            args.allow_delete = false;
+        }
+        if !args.render_index {
+            args.render_index = matches.get_flag("render-index");
+        }
+
+        if !args.render_try_index {
+            args.render_try_index = matches.get_flag("render-try-index");
         }
 
-        if let Some(rules) = matches.get_many::<String>("auth") {
-            let rules: Vec<_> = rules.map(|v| v.as_str()).collect();
-            args.auth = AccessControl::new(&rules)?;
+        if !args.render_spa {
+            args.render_spa = matches.get_flag("render-spa");
         }
 
-        if !args.allow_all {
-            args.allow_all = matches.get_flag("allow-all");
+        if !args.precompressed {
+            args.precompressed = matches.get_flag("precompressed");
         }
 
-        let allow_all = args.allow_all;
+        if !args.sniff_content {
+            args.sniff_content = matches.get_flag("sniff-content");
+        }
 
-        if !args.allow_upload {
-            args.allow_upload = allow_all || matches.get_flag("allow-upload");
+        if let Some(columns) = matches.get_many::<ListColumn>("show-columns") {
+            args.show_columns = columns.copied().collect();
         }
-        if !args.allow_delete {
-            args.allow_delete = allow_all || matches.get_flag("allow-delete");
+        if !args.show_permissions {
+            args.show_permissions = matches.get_flag("show-permissions");
         }
-        if !args.allow_search {
-            args.allow_search = allow_all || matches.get_flag("allow-search");
+        if args.show_permissions {
+            for column in [ListColumn::Mode, ListColumn::Owner, ListColumn::Group] {
+                if !args.show_columns.contains(&column) {
+                    args.show_columns.push(column);
+                }
+            }
         }
-        if !args.allow_symlink {
-            args.allow_symlink = allow_all || matches.get_flag("allow-symlink");
+        if let Some(name) = matches.get_one::<String>("readme") {
+            args.readme = Some(name.clone());
         }
-        if !args.allow_archive {
-            args.allow_archive = allow_all || matches.get_flag("allow-archive");
+        args.readme = args.readme.filter(|v| !v.is_empty());
+        if let Some(name) = &args.readme {
+            if name.contains(['/', '\\']) {
+                bail!("Invalid --readme `{name}`, it must be a file name");
+            }
         }
-        if !args.render_index {
-            args.render_index = matches.get_flag("render-index");
+        if cfg!(not(unix))
+            && args
+                .show_columns
+                .iter()
+                .any(|v| matches!(v, ListColumn::Owner | ListColumn::Group | ListColumn::Mode))
+        {
+            bail!("The owner, group and mode columns are only supported on unix");
         }
 
-        if !args.render_try_index {
-            args.render_try_index = matches.get_flag("render-try-index");
+        if let Some(form) = matches.get_one::<UnicodeNormalize>("unicode-normalize") {
+            args.unicode_normalize = Some(*form);
         }
 
-        if !args.render_spa {
-            args.render_spa = matches.get_flag("render-spa");
+        if let Some(charset) = matches.get_one::<String>("charset") {
+            args.charset = Some(parse_charset(charset)?);
         }
 
         if let Some(assets_path) = matches.get_one::<PathBuf>("assets") {
@@ -398,6 +1354,31 @@ impl Args {
             args.assets = Some(Args::sanitize_assets_path(assets_path)?);
         }
 
+        if let Some(robots) = matches.get_one::<Robots>("robots") {
+            args.robots = *robots;
+        }
+
+        if let Some(favicon) = matches.get_one::<PathBuf>("favicon") {
+            args.favicon = Some(favicon.clone());
+        }
+
+        if let Some(favicon) = &args.favicon {
+            args.favicon = Some(Args::sanitize_path(favicon)?);
+        }
+
+        if let Some(path) = matches.get_one::<PathBuf>("header-file") {
+            args.header_file = Some(path.clone());
+        }
+        if let Some(path) = &args.header_file {
+            args.header_file = Some(Args::sanitize_path(path)?);
+        }
+        if let Some(path) = matches.get_one::<PathBuf>("footer-file") {
+            args.footer_file = Some(path.clone());
+        }
+        if let Some(path) = &args.footer_file {
+            args.footer_file = Some(Args::sanitize_path(path)?);
+        }
+
         if let Some(log_format) = matches.get_one::<String>("log-format") {
             args.http_logger = log_format.parse()?;
         }
@@ -406,6 +1387,14 @@ impl Args {
             args.log_file = Some(log_file.clone());
         }
 
+        if let Some(proxies) = matches.get_many::<IpAddr>("trusted-proxies") {
+            args.trusted_proxies = proxies.cloned().collect();
+        }
+
+        if !args.utc {
+            args.utc = matches.get_flag("utc");
+        }
+
         if let Some(compress) = matches.get_one::<Compress>("compress") {
             args.compress = *compress;
         }
@@ -436,6 +1425,16 @@ impl Args {
         Ok(args)
     }
 
+    /// Splits comma separated extensions and lowercases them without the leading dot
+    fn normalize_extensions(values: &[String]) -> Vec<String> {
+        values
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().trim_start_matches('.').to_lowercase())
+            .filter(|v| !v.is_empty())
+            .collect()
+    }
+
     fn sanitize_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
         let path = path.as_ref();
         if !path.exists() {
@@ -445,6 +1444,7 @@ impl Args {
         env::current_dir()
             .and_then(|mut p| {
                 p.push(path); // If path is absolute, it replaces the current path.
+                              // An extended-length `\\?\` path on Windows, see `join_root`
                 std::fs::canonicalize(p)
             })
             .with_context(|| format!("Failed to access path `{}`", path.display()))
@@ -492,21 +1492,296 @@ impl BindAddr {
     }
 }
 
+/// A request path pattern, a trailing `*` matches everything under a prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern {
+    path: String,
+    prefix: bool,
+}
+
+impl PathPattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let (path, prefix) = match pattern.strip_suffix('*') {
+            Some(path) => (path, true),
+            None => (pattern, false),
+        };
+        Some(Self {
+            path: normalize_route_path(path)?,
+            prefix,
+        })
+    }
+
+    /// Matches a relative request path (no leading or trailing slash),
+    /// returning the part after the pattern
+    pub fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        if path == self.path {
+            return Some("");
+        }
+        if !self.prefix {
+            return None;
+        }
+        match self.path.is_empty() {
+            true => Some(path),
+            false => path.strip_prefix(&self.path)?.strip_prefix('/'),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    from: PathPattern,
+    to: String,
+}
+
+impl Route {
+    fn parse_routes(routes: &[&str]) -> Result<Vec<Self>> {
+        let mut output = vec![];
+        for route in routes {
+            let Some((from, to)) = route.split_once('=') else {
+                bail!("Invalid route `{route}`, expected `from=to`");
+            };
+            let (Some(from), Some(to)) = (PathPattern::parse(from), normalize_route_path(to))
+            else {
+                bail!("Invalid route `{route}`");
+            };
+            output.push(Route { from, to });
+        }
+        Ok(output)
+    }
+
+    /// Rewrites a relative request path (no leading or trailing slash)
+    pub fn rewrite(&self, path: &str) -> Option<String> {
+        let rest = self.from.strip(path)?;
+        match (self.to.is_empty(), rest.is_empty()) {
+            (true, _) => Some(rest.to_string()),
+            (false, true) => Some(self.to.clone()),
+            (false, false) => Some(format!("{}/{rest}", self.to)),
+        }
+    }
+}
+
+/// A response header added to requests matching `path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomHeader {
+    pub path: PathPattern,
+    pub name: HeaderName,
+    pub value: HeaderValue,
+}
+
+impl CustomHeader {
+    fn parse_headers(headers: &[&str]) -> Result<Vec<Self>> {
+        let mut output = vec![];
+        for header in headers {
+            let parsed = header.split_once('=').and_then(|(path, header)| {
+                let (name, value) = header.split_once(':')?;
+                Some(CustomHeader {
+                    path: PathPattern::parse(path)?,
+                    name: HeaderName::from_bytes(name.trim().as_bytes()).ok()?,
+                    value: HeaderValue::from_str(value.trim()).ok()?,
+                })
+            });
+            match parsed {
+                Some(v) => output.push(v),
+                None => bail!("Invalid header `{header}`, expected `path=name: value`"),
+            }
+        }
+        Ok(output)
+    }
+}
+
+const VIDEO_EXTS: [&str; 8] = ["mp4", "m4v", "webm", "ogv", "mov", "mkv", "avi", "wmv"];
+
+/// A command making the `?preview` of files with one of `exts`, run without a
+/// shell. `{input}` and `{output}` are only replaced as whole arguments, so a
+/// request picks the file and nothing else of the command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewRule {
+    pub exts: Vec<String>,
+    /// Extension of the generated file, tools like ImageMagick pick the format by it
+    pub output_ext: String,
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl PreviewRule {
+    fn parse_previews(previews: &[&str]) -> Result<Vec<Self>> {
+        let mut output = vec![];
+        for preview in previews {
+            let Some(rule) = Self::parse(preview) else {
+                bail!("Invalid preview `{preview}`, expected `ext,ext>output-ext=command {{input}} {{output}}`");
+            };
+            output.push(rule?);
+        }
+        Ok(output)
+    }
+
+    fn parse(preview: &str) -> Option<Result<Self>> {
+        let (types, command) = preview.split_once('=')?;
+        let (exts, output_ext) = types.split_once('>')?;
+        let is_ext = |v: &str| !v.is_empty() && v.chars().all(|c| c.is_ascii_alphanumeric());
+        let exts: Vec<String> = exts
+            .split(',')
+            .map(|v| v.trim().trim_start_matches('.').to_ascii_lowercase())
+            .collect();
+        let output_ext = output_ext
+            .trim()
+            .trim_start_matches('.')
+            .to_ascii_lowercase();
+        if !exts.iter().all(|v| is_ext(v)) || !is_ext(&output_ext) {
+            return None;
+        }
+        let mut words = command.split_whitespace().map(|v| v.to_string());
+        let program = words.next()?;
+        let args: Vec<String> = words.collect();
+        if !args.iter().any(|v| v == "{input}") || !args.iter().any(|v| v == "{output}") {
+            return None;
+        }
+        Some(Self::find_program(&program).map(|program| Self {
+            exts,
+            output_ext,
+            program,
+            args,
+        }))
+    }
+
+    /// Grabs a representative frame of the first seconds of a video, scaled
+    /// down to a thumbnail.
+    fn video_poster(ffmpeg: &str) -> Result<Self> {
+        let args = "-hide_banner -loglevel error -i {input} -vf thumbnail,scale=480:-2 -frames:v 1 -y {output}";
+        Ok(Self {
+            exts: VIDEO_EXTS.iter().map(|v| v.to_string()).collect(),
+            output_ext: "jpg".into(),
+            program: Self::find_program(ffmpeg)?,
+            args: args.split(' ').map(|v| v.to_string()).collect(),
+        })
+    }
+
+    /// Resolves the program at startup, so a typo fails fast instead of on
+    /// every request. Shells are refused, they would run their arguments as code.
+    fn find_program(name: &str) -> Result<PathBuf> {
+        let stem = Path::new(name)
+            .file_stem()
+            .and_then(|v| v.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if matches!(
+            stem.as_str(),
+            "sh" | "bash" | "dash" | "zsh" | "fish" | "ksh" | "csh" | "cmd" | "powershell" | "pwsh"
+        ) {
+            bail!("Preview command `{name}` is a shell, run the tool directly");
+        }
+        let path = Path::new(name);
+        if path.components().count() > 1 {
+            if path.is_file() {
+                return Ok(path.to_path_buf());
+            }
+        } else if let Some(paths) = env::var_os("PATH") {
+            for dir in env::split_paths(&paths) {
+                for candidate in [dir.join(name), dir.join(format!("{name}.exe"))] {
+                    if candidate.is_file() {
+                        return Ok(candidate);
+                    }
+                }
+            }
+        }
+        bail!("Preview command `{name}` not found")
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|v| v.to_str())
+            .is_some_and(|ext| self.exts.iter().any(|v| v.eq_ignore_ascii_case(ext)))
+    }
+}
+
+fn normalize_route_path(path: &str) -> Option<String> {
+    let parts: Vec<&str> = path.split('/').filter(|v| !v.is_empty()).collect();
+    if parts
+        .iter()
+        .any(|v| *v == "." || *v == ".." || v.contains('\\'))
+    {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+/// Metadata of a path shown as a column of directory listings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListColumn {
+    Mtime,
+    Size,
+    /// Mime type guessed from the extension
+    Type,
+    Owner,
+    Group,
+    /// Permissions like `ls -l`
+    Mode,
+}
+
+impl ValueEnum for ListColumn {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Mtime,
+            Self::Size,
+            Self::Type,
+            Self::Owner,
+            Self::Group,
+            Self::Mode,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            ListColumn::Mtime => PossibleValue::new("mtime"),
+            ListColumn::Size => PossibleValue::new("size"),
+            ListColumn::Type => PossibleValue::new("type"),
+            ListColumn::Owner => PossibleValue::new("owner"),
+            ListColumn::Group => PossibleValue::new("group"),
+            ListColumn::Mode => PossibleValue::new("mode"),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 #[serde(rename_all = "lowercase")]
+pub enum UnicodeNormalize {
+    Nfc,
+    Nfd,
+}
+
+impl ValueEnum for UnicodeNormalize {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Nfc, Self::Nfd]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            UnicodeNormalize::Nfc => PossibleValue::new("nfc"),
+            UnicodeNormalize::Nfd => PossibleValue::new("nfd"),
+        })
+    }
+}
+
+impl UnicodeNormalize {
+    pub fn normalize(self, value: &str) -> String {
+        match self {
+            UnicodeNormalize::Nfc => value.nfc().collect(),
+            UnicodeNormalize::Nfd => value.nfd().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
 pub enum Compress {
     None,
+    #[default]
     Low,
     Medium,
     High,
 }
 
-impl Default for Compress {
-    fn default() -> Self {
-        Self::Low
-    }
-}
-
 impl ValueEnum for Compress {
     fn value_variants<'a>() -> &'a [Self] {
         &[Self::None, Self::Low, Self::Medium, Self::High]
@@ -533,6 +1808,87 @@ impl Compress {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Robots {
+    Allow,
+    #[default]
+    Deny,
+}
+
+impl ValueEnum for Robots {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Allow, Self::Deny]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            Robots::Allow => PossibleValue::new("allow"),
+            Robots::Deny => PossibleValue::new("deny"),
+        })
+    }
+}
+
+impl Robots {
+    pub fn to_content(self) -> &'static str {
+        match self {
+            Robots::Allow => "User-agent: *\nDisallow:\n",
+            Robots::Deny => "User-agent: *\nDisallow: /\n",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode {
+    Reflink,
+    Hardlink,
+}
+
+impl ValueEnum for DedupMode {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Reflink, Self::Hardlink]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            DedupMode::Reflink => PossibleValue::new("reflink"),
+            DedupMode::Hardlink => PossibleValue::new("hardlink"),
+        })
+    }
+}
+
+/// Client workarounds enabled with `--webdav-quirks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebdavQuirk {
+    /// Windows WebClient: `MS-Author-Via` in OPTIONS, LOCK creates missing files
+    Windows,
+    /// rclone: the `X-OC-Mtime` header of uploads sets their mtime
+    Rclone,
+    /// macOS Finder: `._*` and `.DS_Store` uploads are dropped
+    Finder,
+}
+
+impl ValueEnum for WebdavQuirk {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Windows, Self::Rclone, Self::Finder]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            WebdavQuirk::Windows => PossibleValue::new("windows"),
+            WebdavQuirk::Rclone => PossibleValue::new("rclone"),
+            WebdavQuirk::Finder => PossibleValue::new("finder"),
+        })
+    }
+}
+
+/// Metadata files Finder writes next to everything it copies
+pub const FINDER_JUNK: [&str; 2] = ["._*", ".DS_Store"];
+
+/// Metadata files of macOS and Windows, dropped with `--ignore-metadata-files`
+pub const METADATA_FILES: [&str; 3] = ["._*", ".DS_Store", "Thumbs.db"];
+
 fn deserialize_bind_addrs<'de, D>(deserializer: D) -> Result<Vec<BindAddr>, D::Error>
 where
     D: Deserializer<'de>,
@@ -597,6 +1953,78 @@ where
     deserializer.deserialize_any(StringOrVec)
 }
 
+fn deserialize_routes<'de, D>(deserializer: D) -> Result<Vec<Route>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let routes = deserialize_string_or_vec(deserializer)?;
+    let routes: Vec<&str> = routes.iter().map(|v| v.as_str()).collect();
+    Route::parse_routes(&routes).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_headers<'de, D>(deserializer: D) -> Result<Vec<CustomHeader>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let headers = deserialize_string_or_vec(deserializer)?;
+    let headers: Vec<&str> = headers.iter().map(|v| v.as_str()).collect();
+    CustomHeader::parse_headers(&headers).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_previews<'de, D>(deserializer: D) -> Result<Vec<PreviewRule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let previews = deserialize_string_or_vec(deserializer)?;
+    let previews: Vec<&str> = previews.iter().map(|v| v.as_str()).collect();
+    PreviewRule::parse_previews(&previews).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_columns<'de, D>(deserializer: D) -> Result<Vec<ListColumn>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_string_or_vec(deserializer)?
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|v| {
+            ListColumn::from_str(v.trim(), true)
+                .map_err(|_| serde::de::Error::custom(format!("Unknown column `{v}`")))
+        })
+        .collect()
+}
+
+fn deserialize_webdav_quirks<'de, D>(deserializer: D) -> Result<Vec<WebdavQuirk>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_string_or_vec(deserializer)?
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|v| {
+            WebdavQuirk::from_str(v.trim(), true)
+                .map_err(|_| serde::de::Error::custom(format!("Unknown webdav quirk `{v}`")))
+        })
+        .collect()
+}
+
+fn deserialize_charset<'de, D>(deserializer: D) -> Result<Option<&'static Encoding>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    parse_charset(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn parse_charset(value: &str) -> Result<&'static Encoding> {
+    match Encoding::for_label(value.as_bytes()) {
+        Some(v) => Ok(v),
+        None => bail!("Unknown charset `{value}`"),
+    }
+}
+
 fn deserialize_access_control<'de, D>(deserializer: D) -> Result<AccessControl, D::Error>
 where
     D: Deserializer<'de>,
@@ -671,6 +2099,51 @@ mod tests {
         assert_eq!(args.hidden, ["tmp", "*.log", "*.lock"]);
     }
 
+    #[test]
+    fn test_args_webdav_quirks_finder() {
+        let cli = build_cli();
+        let matches = cli
+            .try_get_matches_from(vec![
+                "",
+                "--hidden",
+                "tmp",
+                "--webdav-quirks",
+                "rclone,finder",
+            ])
+            .unwrap();
+        let args = Args::parse(matches).unwrap();
+        assert_eq!(
+            args.webdav_quirks,
+            [WebdavQuirk::Rclone, WebdavQuirk::Finder]
+        );
+        assert_eq!(args.hidden, ["tmp", "._*", ".DS_Store"]);
+
+        let cli = build_cli();
+        let matches = cli
+            .try_get_matches_from(vec![
+                "",
+                "--webdav-quirks",
+                "finder",
+                "--ignore-metadata-files",
+            ])
+            .unwrap();
+        let args = Args::parse(matches).unwrap();
+        assert_eq!(args.hidden, ["._*", ".DS_Store", "Thumbs.db"]);
+    }
+
+    #[test]
+    fn test_args_no_overrides_allow_all() {
+        let cli = build_cli();
+        let matches = cli
+            .try_get_matches_from(vec!["", "-A", "--no-archive", "--no-delete"])
+            .unwrap();
+        let args = Args::parse(matches).unwrap();
+        assert!(args.allow_upload);
+        assert!(args.allow_search);
+        assert!(!args.allow_archive);
+        assert!(!args.allow_delete);
+    }
+
     #[test]
     fn test_args_from_empty_config_file() {
         let tmpdir = assert_fs::TempDir::new().unwrap();
@@ -748,4 +2221,56 @@ hidden:
         );
         assert_eq!(args.hidden, ["tmp", "*.log", "*.lock"]);
     }
+
+    #[test]
+    fn test_route() {
+        let routes =
+            Route::parse_routes(&["/latest=/releases/v1.8.0/", "/docs/*=/site/docs"]).unwrap();
+        assert_eq!(routes[0].rewrite("latest"), Some("releases/v1.8.0".into()));
+        assert_eq!(routes[0].rewrite("latest/a.txt"), None);
+        assert_eq!(routes[1].rewrite("docs"), Some("site/docs".into()));
+        assert_eq!(
+            routes[1].rewrite("docs/a/b.html"),
+            Some("site/docs/a/b.html".into())
+        );
+        assert_eq!(routes[1].rewrite("docsx"), None);
+        assert!(Route::parse_routes(&["/a=/../etc"]).is_err());
+        assert!(Route::parse_routes(&["/a"]).is_err());
+    }
+
+    #[test]
+    fn test_custom_header() {
+        let headers = CustomHeader::parse_headers(&[
+            "/assets/*=Cache-Control: public, max-age=31536000, immutable",
+        ])
+        .unwrap();
+        assert_eq!(headers[0].name, "cache-control");
+        assert_eq!(headers[0].value, "public, max-age=31536000, immutable");
+        assert_eq!(headers[0].path.strip("assets/a.js"), Some("a.js"));
+        assert_eq!(headers[0].path.strip("index.html"), None);
+        assert!(CustomHeader::parse_headers(&["/a=Bad Name: 1"]).is_err());
+        assert!(CustomHeader::parse_headers(&["/a=NoValue"]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preview_rule() {
+        let rules = PreviewRule::parse_previews(&["HEIC, .heif>jpg=cp {input} {output}"]).unwrap();
+        assert_eq!(rules[0].exts, ["heic", "heif"]);
+        assert_eq!(rules[0].output_ext, "jpg");
+        assert!(rules[0].program.is_absolute());
+        assert_eq!(rules[0].args, ["{input}", "{output}"]);
+        assert!(rules[0].matches(Path::new("/a/b.Heic")));
+        assert!(!rules[0].matches(Path::new("/a/b.jpg")));
+        for rule in [
+            "heic=cp {input} {output}",
+            "heic>jpg=cp {input}",
+            "heic>jpg=cp {input}x {output}",
+            "he/ic>jpg=cp {input} {output}",
+            "heic>jpg=sh -c {input} {output}",
+            "heic>jpg=dufs-no-such-tool {input} {output}",
+        ] {
+            assert!(PreviewRule::parse_previews(&[rule]).is_err(), "{rule}");
+        }
+    }
 }
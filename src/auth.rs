@@ -1,21 +1,34 @@
-use crate::{args::Args, server::Response, utils::unix_now};
+use crate::{
+    args::Args,
+    server::Response,
+    utils::{hmac_sha256, unix_now},
+};
 
 use anyhow::{anyhow, bail, Result};
-use base64::{engine::general_purpose::STANDARD, Engine as _};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine as _,
+};
 use headers::HeaderValue;
-use hyper::{header::WWW_AUTHENTICATE, Method};
+use hyper::{header::WWW_AUTHENTICATE, Method, StatusCode};
 use indexmap::IndexMap;
 use lazy_static::lazy_static;
 use md5::Context;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
+    sync::Mutex,
 };
 use uuid::Uuid;
 
 const REALM: &str = "DUFS";
 const DIGEST_AUTH_TIMEOUT: u32 = 604800; // 7 days
 
+pub const SESSION_COOKIE: &str = "dufs_session";
+/// Holds the guest token of a `?token=` link once a browser opened it
+pub const GUEST_COOKIE: &str = "dufs_guest";
+
 lazy_static! {
     static ref NONCESTARTHASH: Context = {
         let mut h = Context::new();
@@ -95,6 +108,62 @@ impl AccessControl {
         !self.users.is_empty()
     }
 
+    /// Moves the paths of every user under `/<username>`, so their rules
+    /// apply inside their home directory
+    pub fn confine_to_homes(&mut self) -> Result<()> {
+        for (user, (_, paths)) in self.users.iter_mut() {
+            if user == "." || user == ".." || user.contains(['/', '\\']) {
+                bail!("Invalid user `{user}`, it can't be the name of a home directory");
+            }
+            let home = std::mem::take(paths);
+            paths.children.insert(user.clone(), home);
+        }
+        Ok(())
+    }
+
+    /// Checks the password sent by the login form
+    pub fn check_password(&self, user: &str, pass: &str) -> bool {
+        self.users
+            .get(user)
+            .is_some_and(|(auth_pass, _)| password_matches(pass, auth_pass))
+    }
+
+    /// The password of `user` as configured, unless it is stored hashed. S3
+    /// requests are signed with it.
+    pub fn plain_password(&self, user: &str) -> Option<&str> {
+        self.users
+            .get(user)
+            .map(|(pass, _)| pass.as_str())
+            .filter(|v| !v.starts_with("$6$"))
+    }
+
+    /// Like `guard`, for a user already authenticated by a session cookie
+    pub fn guard_session(
+        &self,
+        path: &str,
+        method: &Method,
+        user: String,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        match self.users.get(&user) {
+            Some((_, paths)) => {
+                let paths = paths.find(path, !is_readonly_method(method));
+                (Some(user), paths)
+            }
+            None => (None, None),
+        }
+    }
+
+    /// Authentication schemes offered to clients, empty if there are no accounts.
+    pub fn schemes(&self) -> Vec<&'static str> {
+        if !self.exist() {
+            vec![]
+        } else if self.use_hashed_password {
+            vec!["basic"]
+        } else {
+            vec!["digest", "basic"]
+        }
+    }
+
     pub fn guard(
         &self,
         path: &str,
@@ -250,6 +319,270 @@ impl AccessPaths {
     }
 }
 
+pub const ACCESS_FILE_NAME: &str = ".dufs-access";
+
+/// Extra requirements a directory owner declares for a subtree in a `.dufs-access` file.
+///
+/// ```text
+/// # comments and blank lines are ignored
+/// require user alice bob   # only these users
+/// require valid-user       # any authenticated user
+/// readonly                 # reject writes
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct AccessFile {
+    users: Option<Vec<String>>,
+    readonly: bool,
+}
+
+impl AccessFile {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut output = Self::default();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["readonly"] => output.readonly = true,
+                ["require", "valid-user"] => {
+                    output.users.get_or_insert_with(Vec::new);
+                }
+                ["require", "user", users @ ..] if !users.is_empty() => {
+                    output
+                        .users
+                        .get_or_insert_with(Vec::new)
+                        .extend(users.iter().map(|v| v.to_string()));
+                }
+                _ => bail!("Invalid directive `{line}`"),
+            }
+        }
+        Ok(output)
+    }
+
+    /// Returns the status to reject the request with, if any.
+    pub fn check(&self, user: Option<&str>, is_write: bool) -> Option<StatusCode> {
+        if let Some(users) = &self.users {
+            match user {
+                None => return Some(StatusCode::UNAUTHORIZED),
+                Some(user) if !users.is_empty() && !users.iter().any(|v| v == user) => {
+                    return Some(StatusCode::FORBIDDEN)
+                }
+                _ => {}
+            }
+        }
+        if self.readonly && is_write {
+            return Some(StatusCode::FORBIDDEN);
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GuestPerm {
+    #[default]
+    Read,
+    Upload,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GuestToken {
+    pub token: String,
+    pub path: String,
+    pub perm: GuestPerm,
+    pub user: String,
+    pub expires: Option<u64>,
+}
+
+impl GuestToken {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|v| v <= now)
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.path.is_empty()
+            || path == self.path
+            || path
+                .strip_prefix(&self.path)
+                .is_some_and(|v| v.starts_with('/'))
+    }
+}
+
+/// Tokens minted by authenticated users to share a directory subtree, kept in memory only.
+#[derive(Debug, Default)]
+pub struct GuestTokens {
+    tokens: Mutex<IndexMap<String, GuestToken>>,
+}
+
+impl GuestTokens {
+    pub fn create(
+        &self,
+        path: &str,
+        perm: GuestPerm,
+        user: &str,
+        expires_in: Option<u64>,
+    ) -> Result<GuestToken> {
+        let now = unix_now()?.as_secs();
+        let token = GuestToken {
+            token: Uuid::new_v4().simple().to_string(),
+            path: path.trim_matches('/').to_string(),
+            perm,
+            user: user.to_string(),
+            expires: expires_in.map(|v| now + v),
+        };
+        let mut tokens = self
+            .tokens
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock tokens"))?;
+        tokens.retain(|_, v| !v.is_expired(now));
+        tokens.insert(token.token.clone(), token.clone());
+        Ok(token)
+    }
+
+    pub fn list(&self, user: &str, path: &str) -> Result<Vec<GuestToken>> {
+        let now = unix_now()?.as_secs();
+        let tokens = self
+            .tokens
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock tokens"))?;
+        Ok(tokens
+            .values()
+            .filter(|v| v.user == user && !v.is_expired(now))
+            .filter(|v| {
+                let path = path.trim_matches('/');
+                path.is_empty() || v.path == path || v.path.starts_with(&format!("{path}/"))
+            })
+            .cloned()
+            .collect())
+    }
+
+    pub fn revoke(&self, user: &str, token: &str) -> Result<bool> {
+        let mut tokens = self
+            .tokens
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock tokens"))?;
+        if tokens.get(token).is_some_and(|v| v.user == user) {
+            tokens.shift_remove(token);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Returns the access granted by `token` for `method` on `path`, if any.
+    pub fn guard(
+        &self,
+        token: &str,
+        path: &str,
+        method: &Method,
+    ) -> Option<(GuestPerm, AccessPaths)> {
+        let now = unix_now().ok()?.as_secs();
+        let tokens = self.tokens.lock().ok()?;
+        let guest_token = tokens.get(token)?;
+        if guest_token.is_expired(now) || !guest_token.contains(path.trim_matches('/')) {
+            return None;
+        }
+        match guest_token.perm {
+            GuestPerm::Read if is_readonly_method(method) => {
+                Some((GuestPerm::Read, AccessPaths::new(AccessPerm::ReadOnly)))
+            }
+            GuestPerm::Upload
+                if method == Method::PUT
+                    || method == Method::OPTIONS
+                    || method.as_str() == "MKCOL" =>
+            {
+                Some((GuestPerm::Upload, AccessPaths::new(AccessPerm::ReadWrite)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Signs the session cookies of the login page. The key is made at startup,
+/// so sessions end when the server restarts.
+#[derive(Debug)]
+pub struct SessionKey {
+    key: Vec<u8>,
+    /// Bumped when a user logs out, which ends all sessions of the user
+    generations: Mutex<HashMap<String, u64>>,
+}
+
+impl Default for SessionKey {
+    fn default() -> Self {
+        let key = [Uuid::new_v4(), Uuid::new_v4()]
+            .iter()
+            .flat_map(|v| *v.as_bytes())
+            .collect();
+        Self {
+            key,
+            generations: Mutex::default(),
+        }
+    }
+}
+
+impl SessionKey {
+    /// Returns the cookie value, `<user>.<generation>.<expires>.<signature>`
+    pub fn sign(&self, user: &str, ttl: u64) -> Result<String> {
+        let expires = unix_now()?.as_secs() + ttl;
+        let generation = self
+            .generations
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock sessions"))?
+            .get(user)
+            .copied()
+            .unwrap_or_default();
+        let payload = format!("{}.{generation}.{expires}", URL_SAFE_NO_PAD.encode(user));
+        let signature = URL_SAFE_NO_PAD.encode(hmac_sha256(&self.key, payload.as_bytes()));
+        Ok(format!("{payload}.{signature}"))
+    }
+
+    /// Ends all sessions of the user of a valid cookie value
+    pub fn revoke(&self, value: &str) -> Result<()> {
+        if let Some(user) = self.verify(value) {
+            *self
+                .generations
+                .lock()
+                .map_err(|_| anyhow!("Failed to lock sessions"))?
+                .entry(user)
+                .or_default() += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the user of a valid, unexpired cookie value
+    pub fn verify(&self, value: &str) -> Option<String> {
+        let (payload, signature) = value.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let expected = hmac_sha256(&self.key, payload.as_bytes());
+        // Compares in constant time
+        if signature.len() != expected.len()
+            || signature
+                .iter()
+                .zip(expected.iter())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                != 0
+        {
+            return None;
+        }
+        let mut parts = payload.splitn(3, '.');
+        let (user, generation, expires) = (parts.next()?, parts.next()?, parts.next()?);
+        if expires.parse::<u64>().ok()? <= unix_now().ok()?.as_secs() {
+            return None;
+        }
+        let user = String::from_utf8(URL_SAFE_NO_PAD.decode(user).ok()?).ok()?;
+        let current = self.generations.lock().ok()?.get(&user).copied();
+        if generation.parse::<u64>().ok()? != current.unwrap_or_default() {
+            return None;
+        }
+        Some(user)
+    }
+}
+
+/// Extracts a guest token sent as `Authorization: Bearer <token>`.
+pub fn get_bearer_token(authorization: &HeaderValue) -> Option<&str> {
+    let value = strip_prefix(authorization.as_bytes(), b"Bearer ")?;
+    std::str::from_utf8(value).ok().map(|v| v.trim())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub enum AccessPerm {
     #[default]
@@ -309,19 +642,11 @@ pub fn check_auth(
         let value: Vec<u8> = STANDARD.decode(value).ok()?;
         let (user, pass) = std::str::from_utf8(&value).ok()?.split_once(':')?;
 
-        if user != auth_user {
+        if user != auth_user || !password_matches(pass, auth_pass) {
             return None;
         }
 
-        if auth_pass.starts_with("$6$") {
-            if let Ok(()) = sha_crypt::sha512_check(pass, auth_pass) {
-                return Some(());
-            }
-        } else if pass == auth_pass {
-            return Some(());
-        }
-
-        None
+        Some(())
     } else if let Some(value) = strip_prefix(authorization.as_bytes(), b"Digest ") {
         let digest_map = to_headermap(value).ok()?;
         if let (Some(username), Some(nonce), Some(user_response)) = (
@@ -396,6 +721,14 @@ pub fn check_auth(
     }
 }
 
+fn password_matches(pass: &str, auth_pass: &str) -> bool {
+    if auth_pass.starts_with("$6$") {
+        sha_crypt::sha512_check(pass, auth_pass).is_ok()
+    } else {
+        pass == auth_pass
+    }
+}
+
 /// Check if a nonce is still valid.
 /// Return an error if it was never valid
 fn validate_nonce(nonce: &[u8]) -> Result<bool> {
@@ -424,7 +757,7 @@ fn validate_nonce(nonce: &[u8]) -> Result<bool> {
     bail!("invalid nonce");
 }
 
-fn is_readonly_method(method: &Method) -> bool {
+pub fn is_readonly_method(method: &Method) -> bool {
     method == Method::GET
         || method == Method::OPTIONS
         || method == Method::HEAD
@@ -532,6 +865,30 @@ fn split_rules(rules: &[&str]) -> Vec<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_access_file() {
+        let access_file =
+            AccessFile::parse("# owners only\nrequire user alice bob\n\nreadonly # no writes\n")
+                .unwrap();
+        assert_eq!(
+            access_file.check(None, false),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            access_file.check(Some("carol"), false),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(access_file.check(Some("bob"), false), None);
+        assert_eq!(
+            access_file.check(Some("bob"), true),
+            Some(StatusCode::FORBIDDEN)
+        );
+        let access_file = AccessFile::parse("require valid-user").unwrap();
+        assert_eq!(access_file.check(Some("carol"), true), None);
+        assert!(AccessFile::parse("require").is_err());
+        assert!(AccessFile::parse("allow all").is_err());
+    }
+
     #[test]
     fn test_split_account_paths() {
         assert_eq!(
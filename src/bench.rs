@@ -0,0 +1,220 @@
+use crate::args::{build_cli, Args};
+use crate::serve;
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use clap::ArgMatches;
+use http_body_util::{BodyExt, Empty};
+use hyper::client::conn::http1::{handshake, SendRequest};
+use hyper::{header::RANGE, Request};
+use hyper_util::rt::TokioIo;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+const SMALL_FILES: usize = 100;
+const SMALL_FILE_SIZE: usize = 4096;
+const LARGE_FILE_SIZE: usize = 64 * 1024 * 1024;
+const RANGE_SIZE: u64 = 65536;
+
+#[derive(Debug, Clone, Copy)]
+enum Workload {
+    SmallFile,
+    LargeFile,
+    Ranged,
+    Zip,
+}
+
+impl Workload {
+    fn name(self) -> &'static str {
+        match self {
+            Workload::SmallFile => "small-file",
+            Workload::LargeFile => "large-file",
+            Workload::Ranged => "ranged",
+            Workload::Zip => "zip",
+        }
+    }
+
+    fn requests(self, small_requests: usize) -> usize {
+        match self {
+            Workload::SmallFile | Workload::Ranged => small_requests,
+            Workload::LargeFile | Workload::Zip => (small_requests / 50).max(1),
+        }
+    }
+
+    fn request(self, index: usize) -> Result<Request<Empty<Bytes>>> {
+        let builder = Request::builder().header("host", "localhost");
+        let request = match self {
+            Workload::SmallFile => builder.uri(format!("/small/{}.txt", index % SMALL_FILES)),
+            Workload::LargeFile => builder.uri("/large.bin"),
+            Workload::Ranged => {
+                let blocks = LARGE_FILE_SIZE as u64 / RANGE_SIZE;
+                let start = pseudo_random(index as u64) % blocks * RANGE_SIZE;
+                builder
+                    .uri("/large.bin")
+                    .header(RANGE, format!("bytes={}-{}", start, start + RANGE_SIZE - 1))
+            }
+            Workload::Zip => builder.uri("/small/?zip"),
+        };
+        Ok(request.body(Empty::new())?)
+    }
+}
+
+struct Report {
+    requests: usize,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+/// Runs `dufs bench <dir>`, serving generated files from a scratch directory
+/// in `dir` and printing the throughput of each workload.
+pub fn run(matches: &ArgMatches) -> Result<()> {
+    let dir = matches
+        .get_one::<PathBuf>("dir")
+        .ok_or_else(|| anyhow!("Missing benchmark directory"))?;
+    let requests = *matches.get_one::<usize>("requests").unwrap_or(&1000);
+    let concurrency = (*matches.get_one::<usize>("concurrency").unwrap_or(&8)).max(1);
+    let io_uring = matches.get_flag("io-uring");
+
+    let root = dir.join(format!("dufs-bench-{}", std::process::id()));
+    create_files(&root)
+        .with_context(|| format!("Failed to create benchmark files in `{}`", dir.display()))?;
+    let ret = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(bench(&root, requests, concurrency, io_uring));
+    let _ = std::fs::remove_dir_all(&root);
+    ret
+}
+
+async fn bench(root: &Path, requests: usize, concurrency: usize, io_uring: bool) -> Result<()> {
+    let port = StdTcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let root_arg = root.to_string_lossy();
+    let port_arg = port.to_string();
+    let mut cli_args = vec![
+        "dufs",
+        root_arg.as_ref(),
+        "-b",
+        "127.0.0.1",
+        "-p",
+        &port_arg,
+        "--allow-archive",
+        // Throughput is measured, not the job limit
+        "--max-archive-jobs",
+        "0",
+        "--log-format",
+        "",
+    ];
+    if io_uring {
+        cli_args.push("--io-uring");
+    }
+    let matches = build_cli().try_get_matches_from(cli_args)?;
+    let args = Args::parse(matches)?;
+    let running = Arc::new(AtomicBool::new(true));
+    let (_, handles) = serve(args, running)?;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+
+    println!(
+        "{:<12} {:>10} {:>12} {:>12} {:>12}",
+        "workload", "requests", "elapsed", "req/s", "MB/s"
+    );
+    for workload in [
+        Workload::SmallFile,
+        Workload::LargeFile,
+        Workload::Ranged,
+        Workload::Zip,
+    ] {
+        let report = run_workload(addr, workload, workload.requests(requests), concurrency).await?;
+        let secs = report.elapsed.as_secs_f64().max(f64::EPSILON);
+        println!(
+            "{:<12} {:>10} {:>11.2}s {:>12.1} {:>12.1}",
+            workload.name(),
+            report.requests,
+            secs,
+            report.requests as f64 / secs,
+            report.bytes as f64 / secs / 1024.0 / 1024.0,
+        );
+    }
+
+    for handle in handles {
+        handle.abort();
+    }
+    Ok(())
+}
+
+async fn run_workload(
+    addr: SocketAddr,
+    workload: Workload,
+    requests: usize,
+    concurrency: usize,
+) -> Result<Report> {
+    let start = Instant::now();
+    let mut tasks = vec![];
+    for worker in 0..concurrency.min(requests) {
+        tasks.push(tokio::spawn(async move {
+            let mut sender = connect(addr).await?;
+            let mut bytes = 0;
+            for index in (worker..requests).step_by(concurrency) {
+                bytes += fetch(&mut sender, workload.request(index)?).await?;
+            }
+            Ok::<u64, anyhow::Error>(bytes)
+        }));
+    }
+    let mut bytes = 0;
+    for task in tasks {
+        bytes += task.await??;
+    }
+    Ok(Report {
+        requests,
+        bytes,
+        elapsed: start.elapsed(),
+    })
+}
+
+async fn connect(addr: SocketAddr) -> Result<SendRequest<Empty<Bytes>>> {
+    let stream = TcpStream::connect(addr).await?;
+    let (sender, conn) = handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(conn);
+    Ok(sender)
+}
+
+async fn fetch(sender: &mut SendRequest<Empty<Bytes>>, req: Request<Empty<Bytes>>) -> Result<u64> {
+    sender.ready().await?;
+    let res = sender.send_request(req).await?;
+    if !res.status().is_success() {
+        return Err(anyhow!("Unexpected status {}", res.status()));
+    }
+    let mut body = res.into_body();
+    let mut bytes = 0;
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            bytes += data.len() as u64;
+        }
+    }
+    Ok(bytes)
+}
+
+fn create_files(root: &Path) -> Result<()> {
+    let small_dir = root.join("small");
+    std::fs::create_dir_all(&small_dir)?;
+    for i in 0..SMALL_FILES {
+        let data: Vec<u8> = (0..SMALL_FILE_SIZE)
+            .map(|j| b'a' + ((i + j) % 26) as u8)
+            .collect();
+        std::fs::write(small_dir.join(format!("{i}.txt")), data)?;
+    }
+    let data: Vec<u8> = (0..LARGE_FILE_SIZE as u64)
+        .map(|i| pseudo_random(i) as u8)
+        .collect();
+    std::fs::write(root.join("large.bin"), data)?;
+    Ok(())
+}
+
+fn pseudo_random(seed: u64) -> u64 {
+    seed.wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407)
+        >> 33
+}
@@ -0,0 +1,340 @@
+//! Dead WebDAV properties set with PROPPATCH. They are kept in an extended
+//! attribute of the file, so they follow renames and go away with it.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use xml::escape::escape_str_attribute;
+use xml::reader::{EventReader, XmlEvent};
+use xml::writer::{EmitterConfig, EventWriter};
+
+pub const DAV_NS: &str = "DAV:";
+
+const XATTR_NAME: &str = "user.dufs.davprops";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadProp {
+    pub ns: String,
+    pub name: String,
+    /// Content of the property element as XML
+    pub value: String,
+}
+
+impl DeadProp {
+    pub fn to_xml(&self) -> String {
+        format!(
+            r#"<{} xmlns="{}">{}</{}>"#,
+            self.name,
+            escape_str_attribute(&self.ns),
+            self.value,
+            self.name
+        )
+    }
+
+    /// The empty element naming the property, as used in PROPPATCH responses
+    pub fn to_name_xml(&self) -> String {
+        format!(
+            r#"<{} xmlns="{}"/>"#,
+            self.name,
+            escape_str_attribute(&self.ns)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropUpdate {
+    Set(DeadProp),
+    Remove(DeadProp),
+}
+
+impl PropUpdate {
+    pub fn prop(&self) -> &DeadProp {
+        match self {
+            PropUpdate::Set(v) | PropUpdate::Remove(v) => v,
+        }
+    }
+}
+
+/// Parses the `propertyupdate` body of a PROPPATCH request, in document order
+pub fn parse_proppatch(body: &[u8]) -> Result<Vec<PropUpdate>> {
+    let mut updates = vec![];
+    if body.iter().all(|v| v.is_ascii_whitespace()) {
+        return Ok(updates);
+    }
+    let mut is_set = None;
+    let mut in_prop = false;
+    // Property being read, its nesting depth and a writer for its content
+    let mut current: Option<(DeadProp, usize, EventWriter<Vec<u8>>)> = None;
+    for event in EventReader::new(body) {
+        let event = event?;
+        if let Some((prop, depth, writer)) = current.as_mut() {
+            match &event {
+                XmlEvent::StartElement { .. } => *depth += 1,
+                XmlEvent::EndElement { .. } if *depth == 0 => {
+                    let (mut prop, _, writer) = current.take().unwrap();
+                    prop.value = String::from_utf8(writer.into_inner())?;
+                    updates.push(match is_set {
+                        Some(true) => PropUpdate::Set(prop),
+                        _ => PropUpdate::Remove(prop),
+                    });
+                    continue;
+                }
+                XmlEvent::EndElement { .. } => *depth -= 1,
+                _ => {}
+            }
+            if let Some(event) = event.as_writer_event() {
+                writer
+                    .write(event)
+                    .map_err(|err| anyhow!("Invalid value of `{}`, {err}", prop.name))?;
+            }
+            continue;
+        }
+        match event {
+            XmlEvent::StartElement { name, .. } => {
+                let is_dav = name.namespace_ref() == Some(DAV_NS);
+                match (is_dav, name.local_name.as_str()) {
+                    (true, "set") => is_set = Some(true),
+                    (true, "remove") => is_set = Some(false),
+                    (true, "prop") if is_set.is_some() => in_prop = true,
+                    _ if in_prop => {
+                        let prop = DeadProp {
+                            ns: name.namespace.unwrap_or_default(),
+                            name: name.local_name,
+                            value: String::new(),
+                        };
+                        let writer = EmitterConfig::new()
+                            .write_document_declaration(false)
+                            .create_writer(vec![]);
+                        current = Some((prop, 0, writer));
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::EndElement { name } if name.namespace_ref() == Some(DAV_NS) => {
+                match name.local_name.as_str() {
+                    "prop" => in_prop = false,
+                    "set" | "remove" => is_set = None,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(updates)
+}
+
+/// Applies the updates in order, later ones win
+pub fn apply(props: &mut Vec<DeadProp>, updates: &[PropUpdate]) {
+    for update in updates {
+        let prop = update.prop();
+        props.retain(|v| v.ns != prop.ns || v.name != prop.name);
+        if let PropUpdate::Set(prop) = update {
+            props.push(prop.clone());
+        }
+    }
+}
+
+/// Dead properties of a path, none if the filesystem can't store them
+pub fn load(path: &Path) -> Result<Vec<DeadProp>> {
+    match imp::get(path, XATTR_NAME) {
+        Ok(Some(data)) => Ok(serde_json::from_slice(&data)?),
+        Ok(None) => Ok(vec![]),
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => Ok(vec![]),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn save(path: &Path, props: &[DeadProp]) -> io::Result<()> {
+    if props.is_empty() {
+        imp::remove(path, XATTR_NAME)
+    } else {
+        imp::set(path, XATTR_NAME, &serde_json::to_vec(props)?)
+    }
+}
+
+/// Gives a copy the dead properties of its source
+pub fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    match load(from) {
+        Ok(props) if !props.is_empty() => save(to, &props),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+mod imp {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[cfg(target_os = "macos")]
+    const ENOATTR: i32 = libc::ENOATTR;
+    #[cfg(not(target_os = "macos"))]
+    const ENOATTR: i32 = libc::ENODATA;
+
+    pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let (path, name) = to_cstrings(path, name)?;
+        let mut buf = vec![];
+        loop {
+            let ret = unsafe { getxattr(&path, &name, &mut buf) };
+            if ret < 0 {
+                let err = io::Error::last_os_error();
+                return match err.raw_os_error() {
+                    Some(ENOATTR) => Ok(None),
+                    // The value grew since its size was read
+                    Some(libc::ERANGE) => {
+                        buf.clear();
+                        continue;
+                    }
+                    _ => Err(to_unsupported(err)),
+                };
+            }
+            let size = ret as usize;
+            if buf.is_empty() && size > 0 {
+                buf.resize(size, 0);
+                continue;
+            }
+            buf.truncate(size);
+            return Ok(Some(buf));
+        }
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let (path, name) = to_cstrings(path, name)?;
+        #[cfg(target_os = "macos")]
+        let ret = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+                0,
+            )
+        };
+        #[cfg(not(target_os = "macos"))]
+        let ret = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(to_unsupported(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    pub fn remove(path: &Path, name: &str) -> io::Result<()> {
+        let (path, name) = to_cstrings(path, name)?;
+        #[cfg(target_os = "macos")]
+        let ret = unsafe { libc::removexattr(path.as_ptr(), name.as_ptr(), 0) };
+        #[cfg(not(target_os = "macos"))]
+        let ret = unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(ENOATTR) {
+                return Err(to_unsupported(err));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the size of the value when `buf` is empty
+    unsafe fn getxattr(path: &CString, name: &CString, buf: &mut [u8]) -> isize {
+        let ptr = match buf.is_empty() {
+            true => std::ptr::null_mut(),
+            false => buf.as_mut_ptr() as *mut libc::c_void,
+        };
+        #[cfg(target_os = "macos")]
+        return libc::getxattr(path.as_ptr(), name.as_ptr(), ptr, buf.len(), 0, 0);
+        #[cfg(not(target_os = "macos"))]
+        return libc::getxattr(path.as_ptr(), name.as_ptr(), ptr, buf.len());
+    }
+
+    fn to_cstrings(path: &Path, name: &str) -> io::Result<(CString, CString)> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let name = CString::new(name)?;
+        Ok((path, name))
+    }
+
+    fn to_unsupported(err: io::Error) -> io::Error {
+        match err.raw_os_error() {
+            Some(libc::ENOTSUP) => io::Error::new(io::ErrorKind::Unsupported, err),
+            _ => err,
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+mod imp {
+    use std::io;
+    use std::path::Path;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Extended attributes are not supported on this platform",
+        )
+    }
+
+    pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Err(unsupported())
+    }
+
+    pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn remove(_path: &Path, _name: &str) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proppatch() {
+        let body = br#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:example">
+  <D:set>
+    <D:prop>
+      <Z:author>Jim &amp; Co</Z:author>
+      <Z:tags><Z:tag>red</Z:tag></Z:tags>
+    </D:prop>
+  </D:set>
+  <D:remove>
+    <D:prop><Z:copyright/></D:prop>
+  </D:remove>
+</D:propertyupdate>"#;
+        let updates = parse_proppatch(body).unwrap();
+        assert_eq!(updates.len(), 3);
+        assert_eq!(
+            updates[0],
+            PropUpdate::Set(DeadProp {
+                ns: "urn:example".into(),
+                name: "author".into(),
+                value: "Jim &amp; Co".into(),
+            })
+        );
+        let PropUpdate::Set(tags) = &updates[1] else {
+            panic!("expected a set");
+        };
+        assert!(tags.value.contains(">red</Z:tag>"));
+        assert!(matches!(&updates[2], PropUpdate::Remove(v) if v.name == "copyright"));
+
+        let mut props = vec![];
+        apply(&mut props, &updates);
+        assert_eq!(props.len(), 2);
+        assert_eq!(
+            props[0].to_xml(),
+            r#"<author xmlns="urn:example">Jim &amp; Co</author>"#
+        );
+    }
+}
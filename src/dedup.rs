@@ -0,0 +1,243 @@
+use crate::args::DedupMode;
+
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+/// Smaller files are stored as they are, linking them saves next to nothing.
+pub const DEDUP_MIN_SIZE: u64 = 65536;
+
+const DEDUP_TMP_PREFIX: &str = ".dufs-dedup-";
+
+/// Files under the serve path by size, the candidates an upload is compared to.
+///
+/// The index is filled by a scan at startup and by uploads, entries that were
+/// deleted or changed since are dropped when they are looked at.
+pub struct DedupIndex {
+    mode: DedupMode,
+    sizes: Arc<Mutex<HashMap<u64, HashSet<PathBuf>>>>,
+    /// Hashes of candidates, valid while their mtime is unchanged
+    hashes: Mutex<HashMap<PathBuf, (SystemTime, String)>>,
+    unsupported_warned: AtomicBool,
+}
+
+impl DedupIndex {
+    /// Starts indexing `root` on a background thread.
+    pub fn new(mode: DedupMode, root: &Path) -> Self {
+        let sizes: Arc<Mutex<HashMap<u64, HashSet<PathBuf>>>> = Arc::default();
+        let sizes_cloned = sizes.clone();
+        let root = root.to_path_buf();
+        std::thread::spawn(move || {
+            for entry in WalkDir::new(&root).into_iter().flatten() {
+                if !entry.file_type().is_file() || is_dufs_tmp(entry.path()) {
+                    continue;
+                }
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                if meta.len() >= DEDUP_MIN_SIZE {
+                    let mut sizes = sizes_cloned.lock().unwrap();
+                    sizes
+                        .entry(meta.len())
+                        .or_default()
+                        .insert(entry.into_path());
+                }
+            }
+        });
+        Self {
+            mode,
+            sizes,
+            hashes: Mutex::default(),
+            unsupported_warned: AtomicBool::default(),
+        }
+    }
+
+    /// Replaces `path`, a complete upload of `size` bytes hashing to `sha256`,
+    /// by a link to an identical file, returning whether there was one.
+    ///
+    /// Blocks, on a miss the candidates of the same size are hashed.
+    pub fn dedup(&self, path: &Path, size: u64, sha256: &str) -> io::Result<bool> {
+        if size < DEDUP_MIN_SIZE {
+            return Ok(false);
+        }
+        let candidates: Vec<PathBuf> = {
+            let sizes = self.sizes.lock().unwrap();
+            match sizes.get(&size) {
+                Some(paths) => paths.iter().filter(|v| *v != path).cloned().collect(),
+                None => vec![],
+            }
+        };
+        let mut found = None;
+        for candidate in candidates {
+            match self.cached_hash(&candidate, size) {
+                Some(hash) if hash == sha256 => {
+                    found = Some(candidate);
+                    break;
+                }
+                Some(_) => {}
+                None => self.forget(&candidate, size),
+            }
+        }
+        let linked = match &found {
+            Some(existing) => match self.link(existing, path) {
+                Ok(()) => true,
+                Err(err) if is_unsupported(&err) => {
+                    if !self.unsupported_warned.swap(true, Ordering::Relaxed) {
+                        warn!("Uploads are not deduplicated, {err}");
+                    }
+                    false
+                }
+                Err(err) => return Err(err),
+            },
+            None => false,
+        };
+        self.insert(path, size, sha256);
+        Ok(linked)
+    }
+
+    /// Gives `path` its own copy of the data before it is changed in place,
+    /// so the files it is hardlinked with stay as they are.
+    pub fn unshare(&self, path: &Path) -> io::Result<()> {
+        if self.mode != DedupMode::Hardlink || !is_hardlinked(path)? {
+            return Ok(());
+        }
+        let meta = std::fs::metadata(path)?;
+        let tmp_path = tmp_path_of(path);
+        let ret = std::fs::copy(path, &tmp_path).and_then(|_| {
+            File::options()
+                .write(true)
+                .open(&tmp_path)?
+                .set_modified(meta.modified()?)?;
+            std::fs::rename(&tmp_path, path)
+        });
+        if ret.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        ret
+    }
+
+    fn insert(&self, path: &Path, size: u64, sha256: &str) {
+        if let Ok(mtime) = std::fs::metadata(path).and_then(|v| v.modified()) {
+            let mut hashes = self.hashes.lock().unwrap();
+            hashes.insert(path.to_path_buf(), (mtime, sha256.to_string()));
+        }
+        let mut sizes = self.sizes.lock().unwrap();
+        sizes.entry(size).or_default().insert(path.to_path_buf());
+    }
+
+    fn forget(&self, path: &Path, size: u64) {
+        self.hashes.lock().unwrap().remove(path);
+        if let Some(paths) = self.sizes.lock().unwrap().get_mut(&size) {
+            paths.remove(path);
+        }
+    }
+
+    /// Hash of a candidate, `None` once it is no longer a file of `size` bytes.
+    fn cached_hash(&self, path: &Path, size: u64) -> Option<String> {
+        let meta = std::fs::symlink_metadata(path).ok()?;
+        if !meta.is_file() || meta.len() != size {
+            return None;
+        }
+        let mtime = meta.modified().ok()?;
+        if let Some((cached_mtime, hash)) = self.hashes.lock().unwrap().get(path) {
+            if *cached_mtime == mtime {
+                return Some(hash.clone());
+            }
+        }
+        let hash = sha256_of(path).ok()?;
+        let mut hashes = self.hashes.lock().unwrap();
+        hashes.insert(path.to_path_buf(), (mtime, hash.clone()));
+        Some(hash)
+    }
+
+    fn link(&self, existing: &Path, path: &Path) -> io::Result<()> {
+        match self.mode {
+            DedupMode::Reflink => reflink(existing, path),
+            DedupMode::Hardlink => {
+                let tmp_path = tmp_path_of(path);
+                std::fs::hard_link(existing, &tmp_path)?;
+                let ret = std::fs::rename(&tmp_path, path);
+                if ret.is_err() {
+                    let _ = std::fs::remove_file(&tmp_path);
+                }
+                ret
+            }
+        }
+    }
+}
+
+/// Shares the data of `existing` with `path` copy-on-write, keeping the inode,
+/// permissions and mtime of `path`.
+#[cfg(target_os = "linux")]
+fn reflink(existing: &Path, path: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let src = File::open(existing)?;
+    let dst = File::options().write(true).open(path)?;
+    let mtime = dst.metadata()?.modified()?;
+    let ret = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE as _, src.as_raw_fd()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    dst.set_modified(mtime)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_existing: &Path, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are only supported on Linux",
+    ))
+}
+
+/// Filesystems without reflinks, or links across filesystems, keep the copy.
+fn is_unsupported(err: &io::Error) -> bool {
+    if err.kind() == io::ErrorKind::Unsupported {
+        return true;
+    }
+    #[cfg(unix)]
+    if let Some(code) = err.raw_os_error() {
+        return [libc::EOPNOTSUPP, libc::EXDEV, libc::EINVAL, libc::ENOTTY].contains(&code);
+    }
+    false
+}
+
+#[cfg(unix)]
+fn is_hardlinked(path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) => Ok(meta.is_file() && meta.nlink() > 1),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_hardlinked(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+fn tmp_path_of(path: &Path) -> PathBuf {
+    let id = Uuid::new_v4().simple();
+    path.with_file_name(format!("{DEDUP_TMP_PREFIX}{id}"))
+}
+
+/// Staged uploads and links in the making are no candidates
+fn is_dufs_tmp(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|v| v.to_str())
+        .is_some_and(|v| v.starts_with(".dufs-"))
+}
+
+fn sha256_of(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
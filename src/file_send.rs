@@ -0,0 +1,103 @@
+use bytes::{Bytes, BytesMut};
+use futures_util::Stream;
+use std::{fs::File, io, sync::Arc};
+
+/// Reads are this large and, after the first one, start at multiples of it.
+pub const SEND_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Streams `len` bytes of `file` from `offset`, stopping early at the end of the file.
+///
+/// Each chunk is read with one positioned read straight into the buffer that
+/// is sent, so the file can be shared by the parts of a multipart range.
+pub fn file_stream(
+    file: Arc<File>,
+    offset: u64,
+    len: u64,
+) -> impl Stream<Item = io::Result<Bytes>> + Send {
+    async_stream::stream! {
+        let end = offset.saturating_add(len);
+        let mut pos = offset;
+        while pos < end {
+            let size = chunk_size(pos, end);
+            let file = file.clone();
+            let ret = tokio::task::spawn_blocking(move || read_chunk(&file, pos, size))
+                .await
+                .unwrap_or_else(|err| Err(io::Error::other(err)));
+            match ret {
+                Ok(chunk) if chunk.is_empty() => break,
+                Ok(chunk) => {
+                    pos += chunk.len() as u64;
+                    yield Ok(chunk);
+                }
+                Err(err) => {
+                    yield Err(err);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Size of the read at `pos`, up to the next chunk boundary or `end`.
+fn chunk_size(pos: u64, end: u64) -> usize {
+    let to_boundary = SEND_CHUNK_SIZE as u64 - pos % SEND_CHUNK_SIZE as u64;
+    to_boundary.min(end - pos) as usize
+}
+
+/// Fills a buffer of `size` bytes from `pos`, shorter only at the end of the file.
+fn read_chunk(file: &File, pos: u64, size: usize) -> io::Result<Bytes> {
+    let mut buf = BytesMut::zeroed(size);
+    let mut filled = 0;
+    while filled < size {
+        match read_at(file, &mut buf[filled..], pos + filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    buf.truncate(filled);
+    Ok(buf.freeze())
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, pos)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    #[test]
+    fn test_chunk_size() {
+        let chunk = SEND_CHUNK_SIZE as u64;
+        assert_eq!(chunk_size(0, u64::MAX), SEND_CHUNK_SIZE);
+        assert_eq!(chunk_size(100, u64::MAX), SEND_CHUNK_SIZE - 100);
+        assert_eq!(chunk_size(chunk, chunk + 10), 10);
+        assert_eq!(chunk_size(chunk - 1, chunk + 10), 1);
+    }
+
+    #[tokio::test]
+    async fn test_file_stream() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path().join("file");
+        let data: Vec<u8> = (0..SEND_CHUNK_SIZE as u32 * 2 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &data).unwrap();
+        let file = Arc::new(File::open(&path).unwrap());
+        let read = |offset, len| file_stream(file.clone(), offset, len).try_collect::<Vec<Bytes>>();
+        assert_eq!(read(0, u64::MAX).await.unwrap().concat(), data);
+        assert_eq!(read(10, 300_000).await.unwrap().concat(), data[10..300_010]);
+        let chunks = read(10, u64::MAX).await.unwrap();
+        let sizes: Vec<usize> = chunks.iter().map(|v| v.len()).collect();
+        assert_eq!(sizes, [SEND_CHUNK_SIZE - 10, SEND_CHUNK_SIZE, 7]);
+    }
+}
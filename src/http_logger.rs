@@ -1,6 +1,9 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{borrow::Cow, collections::HashMap, str::FromStr};
 
-use crate::{auth::get_auth_user, server::Request};
+use crate::{
+    auth::get_auth_user,
+    server::{Request, TlsInfo},
+};
 
 pub const DEFAULT_LOG_FORMAT: &str = r#"$remote_addr "$request" $status"#;
 
@@ -29,7 +32,23 @@ impl HttpLogger {
             match element {
                 LogElement::Variable(name) => match name.as_str() {
                     "request" => {
-                        data.insert(name.to_string(), format!("{} {}", req.method(), req.uri()));
+                        let path = req.uri().path_and_query().map(|v| v.as_str());
+                        data.insert(
+                            name.to_string(),
+                            format!("{} {}", req.method(), redact_token(path.unwrap_or("/"))),
+                        );
+                    }
+                    "server_protocol" => {
+                        data.insert(name.to_string(), format!("{:?}", req.version()));
+                    }
+                    "ssl_protocol" | "ssl_cipher" => {
+                        if let Some(tls) = req.extensions().get::<TlsInfo>() {
+                            let value = match name.as_str() {
+                                "ssl_protocol" => &tls.version,
+                                _ => &tls.cipher,
+                            };
+                            data.insert(name.to_string(), value.clone());
+                        }
                     }
                     "remote_user" => {
                         if let Some(user) =
@@ -51,8 +70,25 @@ impl HttpLogger {
         data
     }
     pub fn log(&self, data: &HashMap<String, String>, err: Option<String>) {
-        if self.elements.is_empty() {
+        let Some(output) = self.format(data) else {
             return;
+        };
+        match err {
+            Some(err) => error!("{} {}", output, err),
+            None => info!("{}", output),
+        }
+    }
+
+    /// Logs a request the client gave up on, which is not a failure of the server.
+    pub fn log_client_abort(&self, data: &HashMap<String, String>, err: String) {
+        if let Some(output) = self.format(data) {
+            warn!("{} {}", output, err);
+        }
+    }
+
+    fn format(&self, data: &HashMap<String, String>) -> Option<String> {
+        if self.elements.is_empty() {
+            return None;
         }
         let mut output = String::new();
         for element in self.elements.iter() {
@@ -63,11 +99,27 @@ impl HttpLogger {
                 }
             }
         }
-        match err {
-            Some(err) => error!("{} {}", output, err),
-            None => info!("{}", output),
-        }
+        Some(output)
+    }
+}
+
+/// Hides the value of `token` query parameters, they grant access as a guest
+fn redact_token(path_and_query: &str) -> Cow<'_, str> {
+    let is_token = |v: &&str| v.split('=').next() == Some("token");
+    let Some((path, query)) = path_and_query.split_once('?') else {
+        return Cow::Borrowed(path_and_query);
+    };
+    if !query.split('&').any(|v| is_token(&v)) {
+        return Cow::Borrowed(path_and_query);
     }
+    let query: Vec<&str> = query
+        .split('&')
+        .map(|v| match is_token(&v) {
+            true => "token=REDACTED",
+            false => v,
+        })
+        .collect();
+    Cow::Owned(format!("{path}?{}", query.join("&")))
 }
 
 impl FromStr for HttpLogger {
@@ -1,105 +1,319 @@
-use bytes::{Bytes, BytesMut};
+use crate::s3::AwsChunkedDecoder;
+
+use bytes::Bytes;
 use futures_util::Stream;
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::body::{Body, Incoming};
+use hyper::header::{HeaderMap, HeaderValue, VARY};
+use hyper::StatusCode;
+use sha2::{Digest, Sha256};
 use std::{
+    fmt,
+    io::ErrorKind,
     pin::Pin,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
-use tokio::io::AsyncRead;
-use tokio_util::io::poll_read_buf;
 
 #[derive(Debug)]
 pub struct IncomingStream {
     inner: Incoming,
+    hasher: Option<Sha256>,
+    aws_chunked: Option<AwsChunkedDecoder>,
+    end: Arc<Mutex<BodyEnd>>,
+}
+
+/// What is only known once a request body has been read to the end
+#[derive(Debug, Default)]
+pub struct BodyEnd {
+    pub trailers: Option<HeaderMap>,
+    /// Hex SHA-256 of the data, if it was asked for
+    pub sha256: Option<String>,
 }
 
 impl IncomingStream {
-    pub fn new(inner: Incoming) -> Self {
-        Self { inner }
+    /// Keeps the trailers, and hashes the data if `hash`, into the returned
+    /// handle once the stream is done. Bodies of S3 streaming uploads are
+    /// decoded if `aws_chunked`.
+    pub fn new(inner: Incoming, hash: bool, aws_chunked: bool) -> (Self, Arc<Mutex<BodyEnd>>) {
+        let end = Arc::new(Mutex::new(BodyEnd::default()));
+        let stream = Self {
+            inner,
+            hasher: hash.then(Sha256::new),
+            aws_chunked: aws_chunked.then(AwsChunkedDecoder::default),
+            end: end.clone(),
+        };
+        (stream, end)
     }
 }
 
 impl Stream for IncomingStream {
-    type Item = Result<Bytes, anyhow::Error>;
+    type Item = std::io::Result<Bytes>;
 
     #[inline]
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         loop {
-            match futures_util::ready!(Pin::new(&mut self.inner).poll_frame(cx)?) {
+            let frame = Pin::new(&mut self.inner)
+                .poll_frame(cx)
+                .map_err(std::io::Error::other);
+            match futures_util::ready!(frame?) {
                 Some(frame) => match frame.into_data() {
-                    Ok(data) => return Poll::Ready(Some(Ok(data))),
-                    Err(_frame) => {}
+                    Ok(data) => {
+                        let data = match self.aws_chunked.as_mut() {
+                            Some(decoder) => decoder.decode(&data)?,
+                            None => data,
+                        };
+                        if data.is_empty() {
+                            continue;
+                        }
+                        if let Some(hasher) = self.hasher.as_mut() {
+                            hasher.update(&data);
+                        }
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            self.end.lock().unwrap().trailers = Some(trailers);
+                        }
+                    }
                 },
-                None => return Poll::Ready(None),
+                None => {
+                    if let Some(decoder) = self.aws_chunked.take() {
+                        decoder.finish()?;
+                    }
+                    if let Some(hasher) = self.hasher.take() {
+                        self.end.lock().unwrap().sha256 = Some(format!("{:x}", hasher.finalize()));
+                    }
+                    return Poll::Ready(None);
+                }
             }
         }
     }
 }
 
-pin_project_lite::pin_project! {
-    pub struct LengthLimitedStream<R> {
-        #[pin]
-        reader: Option<R>,
-        remaining: usize,
-        buf: BytesMut,
-        capacity: usize,
-    }
+pub fn body_full(content: impl Into<hyper::body::Bytes>) -> BoxBody<Bytes, anyhow::Error> {
+    Full::new(content.into())
+        .map_err(anyhow::Error::new)
+        .boxed()
 }
 
-impl<R> LengthLimitedStream<R> {
-    pub fn new(reader: R, limit: usize) -> Self {
-        Self {
-            reader: Some(reader),
-            remaining: limit,
-            buf: BytesMut::new(),
-            capacity: 4096,
+/// Builds a `Content-Disposition` value per RFC 6266, non-ASCII filenames are
+/// sent as RFC 5987 `filename*` with an ASCII `filename` fallback.
+pub fn content_disposition(inline: bool, filename: &str) -> anyhow::Result<HeaderValue> {
+    let kind = if inline { "inline" } else { "attachment" };
+    let mut fallback = String::with_capacity(filename.len());
+    for ch in filename.chars() {
+        match ch {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(ch);
+            }
+            '\t' => fallback.push(ch),
+            _ if ch.is_ascii_control() => fallback.push(' '),
+            _ if !ch.is_ascii() => fallback.push('_'),
+            _ => fallback.push(ch),
         }
     }
+    let value = if filename.is_ascii() {
+        format!("{kind}; filename=\"{fallback}\"")
+    } else {
+        format!(
+            "{kind}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+            urlencoding::encode(filename)
+        )
+    };
+    Ok(HeaderValue::from_str(&value)?)
 }
 
-impl<R: AsyncRead> Stream for LengthLimitedStream<R> {
-    type Item = std::io::Result<Bytes>;
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut this = self.as_mut().project();
-
-        if *this.remaining == 0 {
-            self.project().reader.set(None);
-            return Poll::Ready(None);
-        }
-
-        let reader = match this.reader.as_pin_mut() {
-            Some(r) => r,
-            None => return Poll::Ready(None),
-        };
+/// A failed request, answered with its status and a JSON body of
+/// `code`, `message`, `path` and `request_id`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpError {
+    pub status: StatusCode,
+    pub message: String,
+}
 
-        if this.buf.capacity() == 0 {
-            this.buf.reserve(*this.capacity);
+impl HttpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
         }
+    }
 
-        match poll_read_buf(reader, cx, &mut this.buf) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(Err(err)) => {
-                self.project().reader.set(None);
-                Poll::Ready(Some(Err(err)))
+    /// Finds the status of an error returned by a handler. Filesystem errors
+    /// that clients can act on keep their meaning, everything else is a 500
+    /// whose details stay in the log.
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<HttpError>() {
+                return err.clone();
             }
-            Poll::Ready(Ok(0)) => {
-                self.project().reader.set(None);
-                Poll::Ready(None)
-            }
-            Poll::Ready(Ok(_)) => {
-                let mut chunk = this.buf.split();
-                let chunk_size = (*this.remaining).min(chunk.len());
-                chunk.truncate(chunk_size);
-                *this.remaining -= chunk_size;
-                Poll::Ready(Some(Ok(chunk.freeze())))
+            if is_body_error(cause) {
+                return Self::new(StatusCode::BAD_REQUEST, "Incomplete request body");
             }
+            let Some(err) = cause.downcast_ref::<std::io::Error>() else {
+                continue;
+            };
+            let (status, message) = match err.kind() {
+                ErrorKind::NotFound => (StatusCode::NOT_FOUND, "Not Found"),
+                ErrorKind::PermissionDenied => (StatusCode::FORBIDDEN, "Permission denied"),
+                ErrorKind::AlreadyExists => (StatusCode::CONFLICT, "Already exists"),
+                ErrorKind::ReadOnlyFilesystem => (StatusCode::FORBIDDEN, "Read-only filesystem"),
+                ErrorKind::FileTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "File too large"),
+                ErrorKind::StorageFull => {
+                    (StatusCode::INSUFFICIENT_STORAGE, "No space left on device")
+                }
+                ErrorKind::QuotaExceeded => {
+                    (StatusCode::INSUFFICIENT_STORAGE, "Disk quota exceeded")
+                }
+                _ => continue,
+            };
+            return Self::new(status, message);
         }
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+    }
+
+    /// Snake case reason of the status, e.g. `not_found`
+    pub fn code(&self) -> String {
+        self.status
+            .canonical_reason()
+            .unwrap_or("error")
+            .to_lowercase()
+            .replace([' ', '-'], "_")
+    }
+
+    pub fn to_json(&self, path: &str, request_id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "code": self.code(),
+            "message": self.message,
+            "path": path,
+            "request_id": request_id,
+        })
     }
 }
 
-pub fn body_full(content: impl Into<hyper::body::Bytes>) -> BoxBody<Bytes, anyhow::Error> {
-    Full::new(content.into())
-        .map_err(anyhow::Error::new)
-        .boxed()
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status.as_u16(), self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Adds `names` to the `Vary` of a response, merging any values a handler
+/// already set into one header without duplicates.
+pub fn merge_vary(headers: &mut HeaderMap, names: &[&str]) {
+    let mut values: Vec<String> = vec![];
+    let existing = headers.get_all(VARY).iter().filter_map(|v| v.to_str().ok());
+    for name in existing
+        .flat_map(|v| v.split(','))
+        .chain(names.iter().copied())
+        .map(str::trim)
+    {
+        if !name.is_empty() && !values.iter().any(|v| v.eq_ignore_ascii_case(name)) {
+            values.push(name.to_string());
+        }
+    }
+    if values.is_empty() {
+        return;
+    }
+    let value = match values.iter().any(|v| v == "*") {
+        true => "*".to_string(),
+        false => values.join(", "),
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(VARY, value);
+    }
+}
+
+/// Whether reading the request body failed, e.g. because the client went away mid-upload.
+pub fn is_client_abort(err: &anyhow::Error) -> bool {
+    err.chain().any(is_body_error)
+}
+
+/// Body errors of hyper reach handlers wrapped in the `io::Error` of a `StreamReader`.
+fn is_body_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    if err.is::<hyper::Error>() {
+        return true;
+    }
+    err.downcast_ref::<std::io::Error>()
+        .and_then(|v| v.get_ref())
+        .is_some_and(|v| v.is::<hyper::Error>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_disposition() {
+        let value = |inline, filename| content_disposition(inline, filename).unwrap();
+        assert_eq!(value(true, "a.txt"), r#"inline; filename="a.txt""#);
+        assert_eq!(
+            value(false, r#"a "b\c".zip"#),
+            r#"attachment; filename="a \"b\\c\".zip""#
+        );
+        assert_eq!(value(true, "a\r\nb"), r#"inline; filename="a  b""#);
+        assert_eq!(
+            value(false, "中文.zip"),
+            r#"attachment; filename="__.zip"; filename*=UTF-8''%E4%B8%AD%E6%96%87.zip"#
+        );
+        assert_eq!(
+            value(true, "😀 a.bin"),
+            r#"inline; filename="_ a.bin"; filename*=UTF-8''%F0%9F%98%80%20a.bin"#
+        );
+    }
+
+    #[test]
+    fn test_http_error_from_error() {
+        let from_io = |kind| HttpError::from_error(&std::io::Error::from(kind).into()).status;
+        assert_eq!(from_io(ErrorKind::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(from_io(ErrorKind::AlreadyExists), StatusCode::CONFLICT);
+        assert_eq!(
+            from_io(ErrorKind::ReadOnlyFilesystem),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            from_io(ErrorKind::StorageFull),
+            StatusCode::INSUFFICIENT_STORAGE
+        );
+        assert_eq!(
+            from_io(ErrorKind::QuotaExceeded),
+            StatusCode::INSUFFICIENT_STORAGE
+        );
+        assert_eq!(from_io(ErrorKind::Other), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let err = anyhow::Error::new(HttpError::new(StatusCode::CONFLICT, "Busy"))
+            .context("Failed to upload");
+        let err = HttpError::from_error(&err);
+        assert_eq!(err.message, "Busy");
+        assert_eq!(err.code(), "conflict");
+        assert_eq!(
+            err.to_json("/a", "1"),
+            serde_json::json!({"code": "conflict", "message": "Busy", "path": "/a", "request_id": "1"})
+        );
+    }
+
+    #[test]
+    fn test_merge_vary() {
+        let vary = |existing: &[&str], names: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for v in existing {
+                headers.append(VARY, HeaderValue::from_str(v).unwrap());
+            }
+            merge_vary(&mut headers, names);
+            headers.get(VARY).map(|v| v.to_str().unwrap().to_string())
+        };
+        assert_eq!(vary(&[], &[]), None);
+        assert_eq!(
+            vary(&["Accept-Encoding"], &["Authorization", "accept-encoding"]),
+            Some("Accept-Encoding, Authorization".into())
+        );
+        assert_eq!(
+            vary(&["Origin, Accept", "Cookie"], &["Accept"]),
+            Some("Origin, Accept, Cookie".into())
+        );
+        assert_eq!(vary(&["*"], &["Accept"]), Some("*".into()));
+    }
 }
@@ -0,0 +1,124 @@
+use crate::auth::{AccessFile, ACCESS_FILE_NAME};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const DUFS_IGNORE_NAME: &str = ".dufsignore";
+pub const GIT_IGNORE_NAME: &str = ".gitignore";
+
+/// Matches paths against the ignore files (gitignore syntax) found in the
+/// directories between the serve root and the path. `.dufs-access` files and
+/// directories whose `.dufs-access` file rejects the user are ignored as well.
+///
+/// The files are read on demand and cached for the lifetime of the value,
+/// so create one per listing/walk to pick up edits.
+#[derive(Debug)]
+pub struct IgnoreFiles {
+    root: PathBuf,
+    names: Vec<&'static str>,
+    user: Option<String>,
+    cache: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl IgnoreFiles {
+    pub fn new(root: &Path, names: Vec<&'static str>, user: Option<&str>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            names,
+            user: user.map(|v| v.to_string()),
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        if !is_dir && path.file_name().is_some_and(|v| v == ACCESS_FILE_NAME) {
+            return true;
+        }
+        let Ok(relative_path) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let Some(parent) = relative_path.parent() else {
+            return false;
+        };
+        let mut ignored = false;
+        let mut dir = self.root.clone();
+        let mut dirs = vec![dir.clone()];
+        for component in parent.components() {
+            dir.push(component);
+            dirs.push(dir.clone());
+        }
+        // Rules of deeper directories take precedence
+        for dir in dirs {
+            if let Some(gitignore) = self.load(&dir) {
+                match gitignore.matched(path, is_dir) {
+                    Match::None => {}
+                    Match::Ignore(_) => ignored = true,
+                    Match::Whitelist(_) => ignored = false,
+                }
+            }
+        }
+        if !ignored && is_dir {
+            ignored = self.is_access_denied(path);
+        }
+        ignored
+    }
+
+    fn is_access_denied(&self, dir: &Path) -> bool {
+        match std::fs::read_to_string(dir.join(ACCESS_FILE_NAME)) {
+            Ok(content) => AccessFile::parse(&content)
+                .map(|v| v.check(self.user.as_deref(), false).is_some())
+                .unwrap_or(true),
+            Err(_) => false,
+        }
+    }
+
+    fn load(&mut self, dir: &Path) -> Option<&Gitignore> {
+        let names = &self.names;
+        self.cache
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let mut builder = GitignoreBuilder::new(dir);
+                let mut found = false;
+                for name in names {
+                    let file = dir.join(name);
+                    if file.is_file() && builder.add(file).is_none() {
+                        found = true;
+                    }
+                }
+                if !found {
+                    return None;
+                }
+                builder.build().ok()
+            })
+            .as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn test_ignore_files() {
+        let tmpdir = assert_fs::TempDir::new().unwrap();
+        tmpdir
+            .child(".dufsignore")
+            .write_str("*.log\nsecret/\n")
+            .unwrap();
+        tmpdir
+            .child("dir1/.dufsignore")
+            .write_str("!keep.log\n")
+            .unwrap();
+        let root = tmpdir.path();
+        let mut ignore_files = IgnoreFiles::new(root, vec![DUFS_IGNORE_NAME], None);
+        assert!(ignore_files.is_ignored(&root.join("a.log"), false));
+        assert!(ignore_files.is_ignored(&root.join("dir1/a.log"), false));
+        assert!(!ignore_files.is_ignored(&root.join("dir1/keep.log"), false));
+        assert!(ignore_files.is_ignored(&root.join("secret"), true));
+        assert!(!ignore_files.is_ignored(&root.join("secret"), false));
+        assert!(!ignore_files.is_ignored(&root.join("a.txt"), false));
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use chrono::{Local, SecondsFormat};
+use chrono::{Local, SecondsFormat, Utc};
 use log::{Level, LevelFilter, Metadata, Record};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
@@ -8,6 +8,7 @@ use std::sync::Mutex;
 
 struct SimpleLogger {
     file: Option<Mutex<File>>,
+    utc: bool,
 }
 
 impl log::Log for SimpleLogger {
@@ -17,7 +18,10 @@ impl log::Log for SimpleLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let timestamp = Local::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+            let timestamp = match self.utc {
+                true => Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+                false => Local::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            };
             let text = format!("{} {} - {}", timestamp, record.level(), record.args());
             match &self.file {
                 Some(file) => {
@@ -39,7 +43,7 @@ impl log::Log for SimpleLogger {
     fn flush(&self) {}
 }
 
-pub fn init(log_file: Option<PathBuf>) -> Result<()> {
+pub fn init(log_file: Option<PathBuf>, utc: bool) -> Result<()> {
     let file = match log_file {
         None => None,
         Some(log_file) => {
@@ -53,7 +57,7 @@ pub fn init(log_file: Option<PathBuf>) -> Result<()> {
             Some(Mutex::new(file))
         }
     };
-    let logger = SimpleLogger { file };
+    let logger = SimpleLogger { file, utc };
     log::set_boxed_logger(Box::new(logger))
         .map(|_| log::set_max_level(LevelFilter::Info))
         .with_context(|| "Failed to init logger")?;
@@ -1,16 +1,29 @@
 mod args;
 mod auth;
+mod bench;
+mod dav_props;
+mod dedup;
+mod file_send;
 mod http_logger;
 mod http_utils;
+mod ignore_files;
 mod logger;
+mod markdown;
+mod s3;
+mod sandbox;
 mod server;
+#[cfg(test)]
+mod tests;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
 mod utils;
 
 #[macro_use]
 extern crate log;
 
 use crate::args::{build_cli, print_completions, Args};
-use crate::server::Server;
+use crate::server::{ReadonlyListener, Server, TlsInfo};
+use crate::utils::{drop_privileges, lookup_ids};
 #[cfg(feature = "tls")]
 use crate::utils::{load_certs, load_private_key};
 
@@ -25,18 +38,20 @@ use hyper_util::{
     server::conn::auto::Builder,
 };
 use std::net::{IpAddr, SocketAddr, TcpListener as StdTcpListener};
+use std::pin::Pin;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
-use std::time::Duration;
+use std::task::{self, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::time::timeout;
 use tokio::{net::TcpListener, task::JoinHandle};
 #[cfg(feature = "tls")]
 use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let cmd = build_cli();
     let matches = cmd.get_matches();
     if let Some(generator) = matches.get_one::<Shell>("completions") {
@@ -44,16 +59,47 @@ async fn main() -> Result<()> {
         print_completions(*generator, &mut cmd);
         return Ok(());
     }
-    let mut args = Args::parse(matches)?;
-    logger::init(args.log_file.clone()).map_err(|e| anyhow!("Failed to init logger, {e}"))?;
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        return bench::run(matches);
+    }
+    let args = Args::parse(matches)?;
+    logger::init(args.log_file.clone(), args.utc)
+        .map_err(|e| anyhow!("Failed to init logger, {e}"))?;
+    let ids = match args.user.is_some() || args.group.is_some() {
+        true => Some(lookup_ids(args.user.as_deref(), args.group.as_deref())?),
+        false => None,
+    };
+    if args.sandbox {
+        // Before the runtime starts any threads, so all of them are confined
+        sandbox::apply(&args)?;
+    }
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(worker_threads) = args.worker_threads {
+        runtime.worker_threads(worker_threads);
+    }
+    if let Some(blocking_threads) = args.blocking_threads {
+        runtime.max_blocking_threads(blocking_threads);
+    }
+    runtime.build()?.block_on(run(args, ids))
+}
+
+async fn run(mut args: Args, ids: Option<(Option<u32>, Option<u32>)>) -> Result<()> {
     let (new_addrs, print_addrs) = check_addrs(&args)?;
     args.addrs = new_addrs;
     let running = Arc::new(AtomicBool::new(true));
     let listening = print_listening(&args, &print_addrs)?;
-    let handles = serve(args, running.clone())?;
+    let pipe_dir = match args.pipe {
+        Some(_) => args.serve_path.parent().map(|v| v.to_path_buf()),
+        None => None,
+    };
+    let (server, handles) = serve(args, running.clone())?;
+    if let Some((uid, gid)) = ids {
+        drop_privileges(uid, gid)?;
+    }
     println!("{listening}");
 
-    tokio::select! {
+    let ret = tokio::select! {
         ret = join_all(handles) => {
             for r in ret {
                 if let Err(e) = r {
@@ -66,21 +112,41 @@ async fn main() -> Result<()> {
             running.store(false, Ordering::SeqCst);
             Ok(())
         },
+        _ = server.drained() => {
+            running.store(false, Ordering::SeqCst);
+            Ok(())
+        },
+    };
+    if let Some(dir) = pipe_dir {
+        let _ = std::fs::remove_dir_all(dir);
     }
+    ret
 }
 
-fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
-    let addrs = args.addrs.clone();
-    let port = args.port;
+fn serve(args: Args, running: Arc<AtomicBool>) -> Result<(Arc<Server>, Vec<JoinHandle<()>>)> {
+    // The read-only listeners come after the regular ones
+    let listen_addrs: Vec<(BindAddr, u16, bool)> = args
+        .addrs
+        .iter()
+        .map(|v| (v.clone(), args.port, false))
+        .chain(
+            args.bind_readonly
+                .iter()
+                .map(|v| (BindAddr::IpAddr(v.ip()), v.port(), true)),
+        )
+        .collect();
     let tls_config = (args.tls_cert.clone(), args.tls_key.clone());
+    let (backlog, reuse_addr, reuse_port) = (args.backlog, !args.no_reuse_addr, args.reuse_port);
+    let tcp_nodelay = args.tcp_nodelay;
     let server_handle = Arc::new(Server::init(args, running)?);
     let mut handles = vec![];
-    for bind_addr in addrs.iter() {
+    for (bind_addr, port, readonly) in listen_addrs {
         let server_handle = server_handle.clone();
-        match bind_addr {
+        match &bind_addr {
             BindAddr::IpAddr(ip) => {
-                let listener = create_listener(SocketAddr::new(*ip, port))
-                    .with_context(|| format!("Failed to bind `{ip}:{port}`"))?;
+                let listener =
+                    create_listener(SocketAddr::new(*ip, port), backlog, reuse_addr, reuse_port)
+                        .with_context(|| format!("Failed to bind `{ip}:{port}`"))?;
 
                 match &tls_config {
                     #[cfg(feature = "tls")]
@@ -100,6 +166,9 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
                                 let Ok((stream, addr)) = listener.accept().await else {
                                     continue;
                                 };
+                                if tcp_nodelay {
+                                    let _ = stream.set_nodelay(true);
+                                }
                                 let Some(stream) =
                                     timeout(handshake_timeout, tls_accepter.accept(stream))
                                         .await
@@ -108,11 +177,15 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
                                 else {
                                     continue;
                                 };
+                                let tls = tls_info(stream.get_ref().1);
+                                server_handle.record_tls(&tls);
                                 let stream = TokioIo::new(stream);
                                 tokio::spawn(handle_stream(
                                     server_handle.clone(),
                                     stream,
                                     Some(addr),
+                                    Some(tls),
+                                    readonly,
                                 ));
                             }
                         });
@@ -125,11 +198,16 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
                                 let Ok((stream, addr)) = listener.accept().await else {
                                     continue;
                                 };
+                                if tcp_nodelay {
+                                    let _ = stream.set_nodelay(true);
+                                }
                                 let stream = TokioIo::new(stream);
                                 tokio::spawn(handle_stream(
                                     server_handle.clone(),
                                     stream,
                                     Some(addr),
+                                    None,
+                                    readonly,
                                 ));
                             }
                         });
@@ -161,7 +239,13 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
                             continue;
                         };
                         let stream = TokioIo::new(stream);
-                        tokio::spawn(handle_stream(server_handle.clone(), stream, None));
+                        tokio::spawn(handle_stream(
+                            server_handle.clone(),
+                            stream,
+                            None,
+                            None,
+                            readonly,
+                        ));
                     }
                 });
 
@@ -169,20 +253,53 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
             }
         }
     }
-    Ok(handles)
+    Ok((server_handle, handles))
 }
 
-async fn handle_stream<T>(handle: Arc<Server>, stream: TokioIo<T>, addr: Option<SocketAddr>)
-where
-    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+async fn handle_stream<T>(
+    handle: Arc<Server>,
+    stream: TokioIo<T>,
+    addr: Option<SocketAddr>,
+    tls: Option<TlsInfo>,
+    readonly: bool,
+) where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    let hyper_service =
-        service_fn(move |request: Request<Incoming>| handle.clone().call(request, addr));
+    let mut builder = Builder::new(TokioExecutor::new());
+    let keep_alive = handle.keep_alive();
+    if keep_alive.is_some_and(|v| v.is_zero()) {
+        builder.http1().keep_alive(false);
+    }
+    let hyper_service = service_fn(move |mut request: Request<Incoming>| {
+        if let Some(tls) = &tls {
+            request.extensions_mut().insert(tls.clone());
+        }
+        if readonly {
+            request.extensions_mut().insert(ReadonlyListener);
+        }
+        handle.clone().call(request, addr)
+    });
 
-    match Builder::new(TokioExecutor::new())
-        .serve_connection_with_upgrades(stream, hyper_service)
-        .await
-    {
+    let (stream, last_active) = IdleStream::new(stream.into_inner());
+    let conn = builder.serve_connection_with_upgrades(TokioIo::new(stream), hyper_service);
+    tokio::pin!(conn);
+    let ret = match keep_alive.filter(|v| !v.is_zero()) {
+        Some(keep_alive) => loop {
+            let idle = last_active.elapsed();
+            tokio::select! {
+                ret = conn.as_mut() => break ret,
+                _ = tokio::time::sleep(keep_alive.saturating_sub(idle)) => {
+                    if last_active.elapsed() >= keep_alive {
+                        // Lets an in-flight request finish, then closes the connection
+                        conn.as_mut().graceful_shutdown();
+                        break conn.await;
+                    }
+                }
+            }
+        },
+        None => conn.await,
+    };
+    match ret {
         Ok(()) => {}
         Err(_err) => {
             // This error only appears when the client doesn't send a request and terminate the connection.
@@ -192,15 +309,135 @@ where
     }
 }
 
-fn create_listener(addr: SocketAddr) -> Result<TcpListener> {
+/// Time of the last read or write on a connection.
+struct LastActive {
+    start: Instant,
+    millis: AtomicU64,
+}
+
+impl LastActive {
+    fn touch(&self) {
+        let millis = self.start.elapsed().as_millis() as u64;
+        self.millis.store(millis, Ordering::Relaxed);
+    }
+
+    fn elapsed(&self) -> Duration {
+        let millis = self.start.elapsed().as_millis() as u64;
+        Duration::from_millis(millis.saturating_sub(self.millis.load(Ordering::Relaxed)))
+    }
+}
+
+/// Wraps a connection to track when it was last active, for `--keep-alive`.
+struct IdleStream<T> {
+    inner: T,
+    last_active: Arc<LastActive>,
+}
+
+impl<T> IdleStream<T> {
+    fn new(inner: T) -> (Self, Arc<LastActive>) {
+        let last_active = Arc::new(LastActive {
+            start: Instant::now(),
+            millis: AtomicU64::new(0),
+        });
+        let stream = Self {
+            inner,
+            last_active: last_active.clone(),
+        };
+        (stream, last_active)
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for IdleStream<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let ret = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if ret.is_ready() {
+            self.last_active.touch();
+        }
+        ret
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for IdleStream<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let ret = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if ret.is_ready() {
+            self.last_active.touch();
+        }
+        ret
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let ret = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if ret.is_ready() {
+            self.last_active.touch();
+        }
+        ret
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls_info(conn: &tokio_rustls::rustls::ServerConnection) -> TlsInfo {
+    let version = conn.protocol_version().and_then(|v| v.as_str());
+    let cipher = conn
+        .negotiated_cipher_suite()
+        .and_then(|v| v.suite().as_str());
+    TlsInfo {
+        version: version.unwrap_or("unknown").replace('_', "."),
+        cipher: cipher.unwrap_or("unknown").to_string(),
+        alpn: conn
+            .alpn_protocol()
+            .map(|v| String::from_utf8_lossy(v).to_string()),
+    }
+}
+
+fn create_listener(
+    addr: SocketAddr,
+    backlog: u32,
+    reuse_addr: bool,
+    reuse_port: bool,
+) -> Result<TcpListener> {
     use socket2::{Domain, Protocol, Socket, Type};
     let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
     if addr.is_ipv6() {
         socket.set_only_v6(true)?;
     }
-    socket.set_reuse_address(true)?;
+    socket.set_reuse_address(reuse_addr)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(reuse_port)?;
+    #[cfg(not(unix))]
+    let _ = reuse_port;
     socket.bind(&addr.into())?;
-    socket.listen(1024 /* Default backlog */)?;
+    socket.listen(backlog.min(i32::MAX as u32) as i32)?;
     let std_listener = StdTcpListener::from(socket);
     std_listener.set_nonblocking(true)?;
     let listener = TcpListener::from_std(std_listener)?;
@@ -264,7 +501,12 @@ fn interface_addrs() -> Result<(Vec<BindAddr>, Vec<BindAddr>)> {
 
 fn print_listening(args: &Args, print_addrs: &[BindAddr]) -> Result<String> {
     let mut output = String::new();
-    let urls = print_addrs
+    let protocol = if args.tls_cert.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let mut urls = print_addrs
         .iter()
         .map(|bind_addr| match bind_addr {
             BindAddr::IpAddr(addr) => {
@@ -272,17 +514,17 @@ fn print_listening(args: &Args, print_addrs: &[BindAddr]) -> Result<String> {
                     IpAddr::V4(_) => format!("{}:{}", addr, args.port),
                     IpAddr::V6(_) => format!("[{}]:{}", addr, args.port),
                 };
-                let protocol = if args.tls_cert.is_some() {
-                    "https"
-                } else {
-                    "http"
-                };
                 format!("{}://{}{}", protocol, addr, args.uri_prefix)
             }
             #[cfg(unix)]
             BindAddr::SocketPath(path) => path.to_string(),
         })
         .collect::<Vec<_>>();
+    urls.extend(
+        args.bind_readonly
+            .iter()
+            .map(|addr| format!("{}://{}{} (read-only)", protocol, addr, args.uri_prefix)),
+    );
 
     if urls.len() == 1 {
         output.push_str(&format!("Listening on {}", urls[0]))
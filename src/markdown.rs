@@ -0,0 +1,81 @@
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+
+/// Renders the Markdown of a directory README to HTML for the index page.
+///
+/// Raw HTML is shown as text and links with schemes other than http(s) and
+/// mailto are dropped, so a README can't run scripts in the page. Relative
+/// links and images resolve against `base`, the URL of the directory.
+pub fn render(text: &str, base: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let events = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: resolve_url(dest_url, base),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: resolve_url(dest_url, base),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut output = String::new();
+    pulldown_cmark::html::push_html(&mut output, events);
+    output
+}
+
+fn resolve_url<'a>(url: CowStr<'a>, base: &str) -> CowStr<'a> {
+    let scheme_end = url.find(|c: char| !c.is_ascii_alphanumeric() && !"+-.".contains(c));
+    if let Some(index) = scheme_end.filter(|v| *v > 0 && url[*v..].starts_with(':')) {
+        let scheme = url[..index].to_ascii_lowercase();
+        return match ["http", "https", "mailto"].contains(&scheme.as_str()) {
+            true => url,
+            false => CowStr::Borrowed(""),
+        };
+    }
+    if url.is_empty() || url.starts_with(['/', '#', '?']) {
+        return url;
+    }
+    CowStr::from(format!("{base}{url}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let html = render("# Title\n\nSee [docs](docs/a.md) and `code`.\n", "/dir1/");
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains(r#"<a href="/dir1/docs/a.md">docs</a>"#));
+        assert!(html.contains("<code>code</code>"));
+    }
+
+    #[test]
+    fn test_render_unsafe() {
+        let html = render("<script>alert(1)</script>\n\n[x](javascript:alert(1))", "/");
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("javascript:"));
+        let html = render("[x](https://example.com) ![y](#top)", "/dir1/");
+        assert!(html.contains(r#"href="https://example.com""#));
+        assert!(html.contains(r##"src="#top""##));
+    }
+}
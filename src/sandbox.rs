@@ -0,0 +1,223 @@
+use crate::args::Args;
+#[cfg(unix)]
+use crate::args::BindAddr;
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    ReadWrite,
+    /// Create and replace unix sockets in a directory
+    Socket,
+}
+
+/// Confines the process to the paths dufs needs, so a path handling bug
+/// can't reach files outside the share.
+///
+/// Must be called before any other thread is spawned, restrictions are only
+/// inherited by threads created afterwards.
+pub fn apply(args: &Args) -> Result<()> {
+    let rules = rules(args);
+    imp::restrict(&rules)
+}
+
+fn rules(args: &Args) -> Vec<(PathBuf, Access)> {
+    let mut rules = vec![];
+    let serve_path = match (&args.pipe, args.serve_path.parent()) {
+        // The pipe directory is removed on exit
+        (Some(_), Some(dir)) => dir.to_path_buf(),
+        _ => args.serve_path.clone(),
+    };
+    let serve_access = match args.path_is_file && args.pipe.is_none() {
+        true => Access::Read,
+        false => Access::ReadWrite,
+    };
+    rules.push((serve_path, serve_access));
+    let write_paths = [args.upload_path.as_ref(), args.upload_tmp_dir.as_ref()];
+    for path in write_paths.into_iter().flatten() {
+        rules.push((path.clone(), Access::ReadWrite));
+    }
+    // Replaced through a temporary file next to it
+    if let Some(parent) = args.upload_journal.as_deref().and_then(|v| v.parent()) {
+        rules.push((parent.to_path_buf(), Access::ReadWrite));
+    }
+    let read_paths = [
+        args.assets.as_deref(),
+        args.favicon.as_deref(),
+        args.header_file.as_deref(),
+        args.footer_file.as_deref(),
+        args.tls_cert.as_deref(),
+        args.tls_key.as_deref(),
+        Some(Path::new("/etc/localtime")),
+        // Owner and group names of the listing
+        Some(Path::new("/etc/passwd")),
+        Some(Path::new("/etc/group")),
+        Some(Path::new("/etc/nsswitch.conf")),
+    ];
+    for path in read_paths.into_iter().flatten() {
+        rules.push((path.to_path_buf(), Access::Read));
+    }
+    #[cfg(unix)]
+    for addr in &args.addrs {
+        if let BindAddr::SocketPath(path) = addr {
+            if path.starts_with('@') {
+                continue;
+            }
+            if let Some(parent) = Path::new(path).parent() {
+                let parent = match parent.as_os_str().is_empty() {
+                    true => Path::new("."),
+                    false => parent,
+                };
+                rules.push((parent.to_path_buf(), Access::Socket));
+            }
+        }
+    }
+    rules.retain(|(path, _)| path.exists());
+    rules
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::Access;
+
+    use anyhow::{anyhow, bail, Context, Result};
+    use std::fs::OpenOptions;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::PathBuf;
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+    /// All rights of the first ABI version
+    const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+    /// Rights that apply to files, other rights are only valid on directories
+    const ACCESS_FILE: u64 = ACCESS_FS_EXECUTE
+        | ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_READ_FILE
+        | ACCESS_FS_TRUNCATE
+        | ACCESS_FS_IOCTL_DEV;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    pub fn restrict(rules: &[(PathBuf, Access)]) -> Result<()> {
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0usize,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            bail!("Sandbox is not supported, Landlock is unavailable in this kernel");
+        }
+        let mut handled = ACCESS_FS_V1;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        if abi >= 5 {
+            handled |= ACCESS_FS_IOCTL_DEV;
+        }
+
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(last_error("Failed to create sandbox ruleset"));
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        for (path, access) in rules {
+            let file = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+                .open(path)
+                .with_context(|| format!("Failed to open `{}`", path.display()))?;
+            let mut allowed = match access {
+                Access::Read => ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR,
+                Access::ReadWrite => handled & !ACCESS_FS_EXECUTE,
+                Access::Socket => ACCESS_FS_MAKE_SOCK | ACCESS_FS_REMOVE_FILE,
+            } & handled;
+            if !file.metadata()?.is_dir() {
+                allowed &= ACCESS_FILE;
+            }
+            let attr = PathBeneathAttr {
+                allowed_access: allowed,
+                parent_fd: file.as_raw_fd(),
+            };
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &attr as *const PathBeneathAttr,
+                    0,
+                )
+            };
+            if ret < 0 {
+                return Err(last_error(&format!(
+                    "Failed to add sandbox rule for `{}`",
+                    path.display()
+                )));
+            }
+        }
+
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(last_error("Failed to restrict privileges"));
+        }
+        let ret =
+            unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) };
+        if ret < 0 {
+            return Err(last_error("Failed to enable sandbox"));
+        }
+        Ok(())
+    }
+
+    fn last_error(message: &str) -> anyhow::Error {
+        anyhow!("{message}, {}", std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::Access;
+
+    use anyhow::{bail, Result};
+    use std::path::PathBuf;
+
+    pub fn restrict(_rules: &[(PathBuf, Access)]) -> Result<()> {
+        bail!("Sandbox is not supported on this platform")
+    }
+}
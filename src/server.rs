@@ -1,55 +1,75 @@
 #![allow(clippy::too_many_arguments)]
 
-use crate::auth::{www_authenticate, AccessPaths, AccessPerm};
-use crate::http_utils::{body_full, IncomingStream, LengthLimitedStream};
+use crate::args::{ListColumn, PreviewRule, WebdavQuirk, FINDER_JUNK, METADATA_FILES};
+use crate::auth::{
+    get_bearer_token, is_readonly_method, www_authenticate, AccessFile, AccessPaths, AccessPerm,
+    GuestPerm, GuestTokens, SessionKey, ACCESS_FILE_NAME, GUEST_COOKIE, SESSION_COOKIE,
+};
+use crate::dav_props::{self, DeadProp, PropUpdate, DAV_NS};
+use crate::dedup::{DedupIndex, DEDUP_MIN_SIZE};
+use crate::file_send::file_stream;
+use crate::http_utils::{
+    body_full, content_disposition, is_client_abort, merge_vary, BodyEnd, HttpError, IncomingStream,
+};
+use crate::ignore_files::{IgnoreFiles, DUFS_IGNORE_NAME, GIT_IGNORE_NAME};
+use crate::markdown;
+use crate::s3::{self, ListEntry, ListObjects, SigV4Auth};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::uring::Uring;
 use crate::utils::{
-    decode_uri, encode_uri, get_file_mtime_and_mode, get_file_name, glob, parse_range,
-    try_get_file_name,
+    decode_uri, download_name, encode_uri, fuzzy_score, get_disk_usage, get_file_mtime_and_mode,
+    get_file_name, glob, is_windows_reserved_name, numbered_name, open_read, open_read_blocking,
+    parse_range, parse_ranges, same_filesystem, sync_parent_dir, try_get_file_name, unix_now,
 };
+#[cfg(unix)]
+use crate::utils::{format_mode, group_name, user_name};
 use crate::Args;
 
 use anyhow::{anyhow, Result};
 use async_zip::{tokio::write::ZipFileWriter, Compression, ZipDateTime, ZipEntryBuilder};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 use bytes::Bytes;
-use chrono::{LocalResult, TimeZone, Utc};
-use futures_util::{pin_mut, TryStreamExt};
+use chrono::{Local, LocalResult, SecondsFormat, TimeZone, Utc};
+use encoding_rs::{Encoding, UTF_8};
+use futures_util::{future::BoxFuture, pin_mut, TryStreamExt};
 use headers::{
     AcceptRanges, AccessControlAllowCredentials, AccessControlAllowOrigin, CacheControl,
     ContentLength, ContentType, ETag, HeaderMap, HeaderMapExt, IfMatch, IfModifiedSince,
-    IfNoneMatch, IfRange, IfUnmodifiedSince, LastModified, Range,
+    IfNoneMatch, IfUnmodifiedSince, LastModified, Range,
 };
-use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+use http_body_util::{combinators::BoxBody, BodyExt, Limited, StreamBody};
 use hyper::body::Frame;
 use hyper::{
     body::Incoming,
     header::{
-        HeaderValue, AUTHORIZATION, CONNECTION, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE,
-        CONTENT_TYPE, RANGE,
+        HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONNECTION, CONTENT_DISPOSITION,
+        CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, COOKIE, HOST, IF_RANGE,
+        LOCATION, RANGE, RETRY_AFTER, SET_COOKIE, TRAILER, VARY,
     },
     Method, StatusCode, Uri,
 };
-use serde::Serialize;
+use indexmap::IndexMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs::Metadata;
 use std::io::SeekFrom;
 use std::net::SocketAddr;
 use std::path::{Component, Path, PathBuf};
-use std::sync::atomic::{self, AtomicBool};
-use std::sync::Arc;
-use std::time::SystemTime;
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use std::sync::atomic::{self, AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Notify, Semaphore};
 use tokio::{fs, io};
 
 use tokio_util::compat::FuturesAsyncWriteCompatExt;
 use tokio_util::io::{ReaderStream, StreamReader};
 use uuid::Uuid;
 use walkdir::WalkDir;
-use xml::escape::escape_str_pcdata;
+use xml::escape::{escape_str_attribute, escape_str_pcdata};
 
 pub type Request = hyper::Request<Incoming>;
 pub type Response = hyper::Response<BoxBody<Bytes, anyhow::Error>>;
@@ -57,11 +77,41 @@ pub type Response = hyper::Response<BoxBody<Bytes, anyhow::Error>>;
 const INDEX_HTML: &str = include_str!("../assets/index.html");
 const INDEX_CSS: &str = include_str!("../assets/index.css");
 const INDEX_JS: &str = include_str!("../assets/index.js");
+const OPENAPI_JSON: &str = include_str!("../assets/openapi.json");
+const LOGIN_HTML: &str = include_str!("../assets/login.html");
 const FAVICON_ICO: &[u8] = include_bytes!("../assets/favicon.ico");
 const INDEX_NAME: &str = "index.html";
+const ROBOTS_NAME: &str = "robots.txt";
+const FAVICON_NAME: &str = "favicon.ico";
+const INTERNAL_PREFIX: &str = "__dufs__/";
 const BUF_SIZE: usize = 65536;
 const EDITABLE_TEXT_MAX_SIZE: u64 = 4194304; // 4M
+const README_MAX_SIZE: u64 = 1048576; // 1M
 const RESUMABLE_UPLOAD_MIN_SIZE: u64 = 20971520; // 20M
+const FEED_MAX_ENTRIES: usize = 50;
+/// Files a feed looks at for the newest ones, bigger trees are cut short
+const FEED_MAX_WALK: usize = 10000;
+const TREE_DEFAULT_DEPTH: usize = 2;
+const CHECKSUM_SIDECAR_EXT: &str = ".sha256";
+const TREE_MAX_DEPTH: usize = 8;
+const BATCH_BODY_MAX_SIZE: usize = 1048576; // 1M
+const UPLOAD_ID_HEADER: &str = "x-upload-id";
+const FILE_SIZE_HEADER: &str = "x-file-size";
+const FILE_MTIME_HEADER: &str = "x-file-mtime";
+const FILE_SHA256_HEADER: &str = "x-file-sha256";
+const CHECKSUM_HEADER: &str = "x-checksum-sha256";
+const ARCHIVE_TOTAL_BYTES_HEADER: &str = "x-archive-total-bytes";
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const UPLOAD_IDS_MAX: usize = 1024;
+const DELETED_PATHS_MAX: usize = 10000;
+const CHUNKED_UPLOADS_MAX: usize = 64;
+const CHUNKED_UPLOAD_CHUNKS_MAX: u64 = 100_000;
+const CHUNKED_UPLOAD_PREFIX: &str = ".dufs-upload-";
+const MAX_RENAME_ATTEMPTS: usize = 1000;
+const PREVIEW_JOBS_MAX: usize = 2;
+const PREVIEW_TIMEOUT: Duration = Duration::from_secs(60);
+const PIPE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const S3_MAX_KEYS: usize = 1000;
 
 pub struct Server {
     args: Args,
@@ -69,6 +119,25 @@ pub struct Server {
     html: Cow<'static, str>,
     single_file_req_paths: Vec<String>,
     running: Arc<AtomicBool>,
+    guest_tokens: GuestTokens,
+    session_key: SessionKey,
+    upload_ids: UploadIds,
+    deleted_paths: DeletedPaths,
+    chunked_uploads: ChunkedUploads,
+    dedup: Option<Arc<DedupIndex>>,
+    archive_jobs: Option<Arc<Semaphore>>,
+    preview_jobs: Semaphore,
+    pipe_done: Option<Arc<AtomicBool>>,
+    tls_metrics: Mutex<TlsMetrics>,
+    in_flight: Arc<AtomicUsize>,
+    refused_requests: AtomicU64,
+    refused_archives: AtomicU64,
+    active_jobs: Arc<ActiveJobs>,
+    readonly: AtomicBool,
+    draining: AtomicBool,
+    drained: Arc<Notify>,
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    uring: Option<Uring>,
 }
 
 impl Server {
@@ -87,24 +156,437 @@ impl Server {
         } else {
             vec![]
         };
+        let args_max_archive_jobs = args.max_archive_jobs;
+        let args_upload_journal = args.upload_journal.clone();
+        let dedup = args
+            .dedup_uploads
+            .map(|mode| Arc::new(DedupIndex::new(mode, &args.serve_path)));
+        let pipe_done = match args.pipe.is_some() {
+            true => Some(spawn_pipe_reader(&args.serve_path)?),
+            false => None,
+        };
         let html = match args.assets.as_ref() {
             Some(path) => Cow::Owned(std::fs::read_to_string(path.join("index.html"))?),
             None => Cow::Borrowed(INDEX_HTML),
         };
+        // Trusted HTML of the operator, inserted as it is
+        let read_html = |path: Option<&PathBuf>| match path {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|err| anyhow!("Failed to read `{}`, {err}", path.display())),
+            None => Ok(String::new()),
+        };
+        let header_html = read_html(args.header_file.as_ref())?;
+        let footer_html = read_html(args.footer_file.as_ref())?;
+        let html = Cow::Owned(
+            html.replace("__HEADER_HTML__", &header_html)
+                .replace("__FOOTER_HTML__", &footer_html),
+        );
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let uring = match args.io_uring {
+            true => {
+                Some(Uring::start().map_err(|err| anyhow!("Failed to set up io_uring, {err}"))?)
+            }
+            false => None,
+        };
         Ok(Self {
             args,
             running,
             single_file_req_paths,
             assets_prefix,
             html,
+            guest_tokens: GuestTokens::default(),
+            session_key: SessionKey::default(),
+            upload_ids: UploadIds::default(),
+            deleted_paths: DeletedPaths::default(),
+            chunked_uploads: ChunkedUploads::load(args_upload_journal),
+            dedup,
+            archive_jobs: match args_max_archive_jobs {
+                0 => None,
+                n => Some(Arc::new(Semaphore::new(n))),
+            },
+            preview_jobs: Semaphore::new(PREVIEW_JOBS_MAX),
+            pipe_done,
+            tls_metrics: Mutex::default(),
+            in_flight: Arc::default(),
+            refused_requests: AtomicU64::default(),
+            refused_archives: AtomicU64::default(),
+            active_jobs: Arc::default(),
+            readonly: AtomicBool::default(),
+            draining: AtomicBool::default(),
+            drained: Arc::default(),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            uring,
         })
     }
 
+    /// Counts a TLS handshake for `/__dufs__/metrics`.
+    pub fn record_tls(&self, info: &TlsInfo) {
+        let mut metrics = self.tls_metrics.lock().unwrap();
+        *metrics.versions.entry(info.version.clone()).or_default() += 1;
+        *metrics.ciphers.entry(info.cipher.clone()).or_default() += 1;
+        let alpn = info.alpn.clone().unwrap_or_else(|| "-".into());
+        *metrics.alpn.entry(alpn).or_default() += 1;
+    }
+
+    /// A fresh name in `--upload-tmp-dir` for an upload to `path`, unless the
+    /// directory is on another filesystem than `path`, e.g. a mount below the
+    /// upload root. Such uploads are written in place.
+    fn upload_tmp_path(&self, path: &Path) -> Option<PathBuf> {
+        let dir = self.args.upload_tmp_dir.as_ref()?;
+        if !same_filesystem(dir, path.parent()?) {
+            return None;
+        }
+        let id = Uuid::new_v4().simple();
+        Some(dir.join(format!("{CHUNKED_UPLOAD_PREFIX}{id}")))
+    }
+
+    /// With `--dedup-uploads`, stores a complete upload as a link to an
+    /// identical file if there is one. The hash is computed unless given.
+    async fn dedup_upload(&self, path: &Path, sha256: Option<String>) -> Result<()> {
+        let Some(dedup) = self.dedup.clone() else {
+            return Ok(());
+        };
+        let size = fs::metadata(path).await?.len();
+        if size < DEDUP_MIN_SIZE {
+            return Ok(());
+        }
+        let sha256 = match sha256 {
+            Some(v) => v,
+            None => sha256_file(path, self.args.no_atime_updates).await?,
+        };
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || dedup.dedup(&path, size, &sha256)).await??;
+        Ok(())
+    }
+
+    /// Breaks the hardlinks of a deduplicated file before it is written in place.
+    async fn unshare(&self, path: &Path) -> Result<()> {
+        if let Some(dedup) = self.dedup.clone() {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || dedup.unshare(&path)).await??;
+        }
+        Ok(())
+    }
+
+    /// Checks `path` as the identity `handle` authenticated the request with
+    fn guard_identity(
+        &self,
+        identity: &Identity,
+        path: &str,
+        method: &Method,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        match identity {
+            Identity::Header {
+                authorization,
+                guard_options,
+            } => self
+                .args
+                .auth
+                .guard(path, method, authorization.as_ref(), *guard_options),
+            Identity::Session(user) => self.args.auth.guard_session(path, method, user.clone()),
+            Identity::Guest(token) => match self.guest_tokens.guard(token, path, method) {
+                Some((_, access_paths)) => (None, Some(access_paths)),
+                None => (None, None),
+            },
+        }
+    }
+
+    fn has_quirk(&self, quirk: WebdavQuirk) -> bool {
+        self.args.webdav_quirks.contains(&quirk)
+    }
+
+    /// Metadata files that are not kept, see `--ignore-metadata-files`
+    fn is_dropped_upload(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|v| v.to_str()) else {
+            return false;
+        };
+        let matches = |patterns: &[&str]| patterns.iter().any(|v| glob(v, name));
+        (self.has_quirk(WebdavQuirk::Finder) && matches(&FINDER_JUNK))
+            || (self.args.ignore_metadata_files && matches(&METADATA_FILES))
+    }
+
+    /// The object key of a request to the `--s3-bucket` bucket, empty for the
+    /// bucket itself.
+    fn s3_key<'a>(&self, relative_path: &'a str) -> Option<&'a str> {
+        let bucket = self.args.s3_bucket.as_deref()?;
+        match relative_path.strip_prefix(bucket)? {
+            "" => Some(""),
+            v => v.strip_prefix('/'),
+        }
+    }
+
+    /// The user whose password, as secret key, signed an S3 request, `None`
+    /// for unsigned requests. Fails with an S3 error code and message.
+    fn s3_user(&self, req: &Request) -> Result<Option<String>, (&'static str, &'static str)> {
+        let Some(authorization) = req.headers().get(AUTHORIZATION) else {
+            return Ok(None);
+        };
+        let auth = authorization
+            .to_str()
+            .ok()
+            .and_then(SigV4Auth::parse)
+            .ok_or((
+                "AuthorizationHeaderMalformed",
+                "Expected AWS Signature Version 4",
+            ))?;
+        let secret = self.args.auth.plain_password(&auth.access_key).ok_or((
+            "InvalidAccessKeyId",
+            "Unknown access key, or its password is hashed",
+        ))?;
+        if !auth.verify(req.method(), req.uri(), req.headers(), secret) {
+            return Err((
+                "SignatureDoesNotMatch",
+                "The signature is wrong or the request is older than 15 minutes",
+            ));
+        }
+        Ok(Some(auth.access_key))
+    }
+
+    /// Serves `--s3-bucket`, `key` is the path of an object, empty for requests
+    /// to the bucket itself. Signed requests act as the user of the access key.
+    async fn handle_s3(&self, key: &str, mut req: Request, res: &mut Response) -> Result<()> {
+        let method = req.method().clone();
+        let user = match self.s3_user(&req) {
+            Ok(v) => v,
+            Err((code, message)) => {
+                res_s3_error(res, StatusCode::FORBIDDEN, code, message);
+                return Ok(());
+            }
+        };
+        let (user, access_paths) = match user {
+            Some(user) => self.args.auth.guard_session(key, &method, user),
+            None => self.args.auth.guard(key, &method, None, false),
+        };
+        let query = req.uri().query().unwrap_or_default();
+        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let is_write = !is_readonly_method(&method);
+        let path = match is_write {
+            true => self.join_upload_path(key),
+            false => self.join_path(key),
+        };
+        let (Some(access_paths), Some(path)) = (access_paths, path) else {
+            status_forbid(res);
+            return Ok(());
+        };
+        let not_implemented = |res: &mut Response| {
+            status_error(res, StatusCode::NOT_IMPLEMENTED, "Not supported by dufs");
+        };
+
+        if key.is_empty() {
+            match method {
+                Method::HEAD => {}
+                Method::GET if query_params.contains_key("location") => {
+                    res_xml(res, s3::location_xml());
+                }
+                Method::GET if query_params.get("list-type").is_some_and(|v| v == "2") => {
+                    self.handle_s3_list(&query_params, user.as_deref(), access_paths, res)
+                        .await?;
+                }
+                _ => not_implemented(res),
+            }
+        } else if !self.args.allow_symlink && !self.is_root_contained(&path).await {
+            status_not_found(res);
+        } else if self
+            .check_access_files(&path, user.as_deref(), is_write)
+            .await
+            .is_some()
+        {
+            status_forbid(res);
+        } else {
+            let meta = fs::metadata(&path).await.ok();
+            let is_dir = meta.as_ref().is_some_and(|v| v.is_dir());
+            let size = meta.as_ref().map(|v| v.len()).unwrap_or_default();
+            match method {
+                Method::GET | Method::HEAD if meta.is_some() && !is_dir => {
+                    self.handle_send_file(&path, req.headers(), method == Method::HEAD, res)
+                        .await?;
+                }
+                Method::GET | Method::HEAD => status_not_found(res),
+                Method::PUT if req.headers().contains_key("x-amz-copy-source") => {
+                    not_implemented(res)
+                }
+                Method::PUT if !self.args.allow_upload => status_forbid(res),
+                Method::PUT if req.uri().path().ends_with('/') => {
+                    // The folder markers of S3 clients
+                    fs::create_dir_all(&path).await?;
+                }
+                Method::PUT if is_dir => status_conflict(res),
+                Method::PUT if meta.is_some() && self.args.write_once => status_conflict(res),
+                Method::PUT if size > 0 && !self.args.allow_delete => status_forbid(res),
+                Method::PUT if self.is_dropped_upload(&path) => {}
+                Method::PUT => {
+                    let content_sha256 = req.headers().get(s3::CONTENT_SHA256_HEADER).cloned();
+                    if let Some(value) = content_sha256.filter(|v| v.len() == 64) {
+                        req.headers_mut().insert(CHECKSUM_HEADER, value);
+                    }
+                    self.handle_upload(&path, None, None, size, self.args.write_once, req, res)
+                        .await?;
+                    if res.status().is_success() {
+                        *res.status_mut() = StatusCode::OK;
+                        let meta = fs::metadata(&path).await?;
+                        if let Some((etag, _)) = extract_cache_headers(&meta) {
+                            res.headers_mut().typed_insert(etag);
+                        }
+                    }
+                }
+                Method::DELETE if !self.args.allow_delete => status_forbid(res),
+                Method::DELETE => {
+                    // Folders only go away once empty, like prefixes do
+                    if is_dir {
+                        let _ = fs::remove_dir(&path).await;
+                    } else if meta.is_some() {
+                        self.handle_delete(&path, false, res).await?;
+                    }
+                    status_no_content(res);
+                }
+                _ => not_implemented(res),
+            }
+        }
+        Ok(())
+    }
+
+    /// `ListObjectsV2` of the bucket, keys are paths relative to the serve
+    /// path and listed in byte order.
+    async fn handle_s3_list(
+        &self,
+        query_params: &HashMap<String, String>,
+        user: Option<&str>,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let get = |name: &str| query_params.get(name).filter(|v| !v.is_empty()).cloned();
+        let mut page = ListObjects {
+            bucket: self.args.s3_bucket.clone().unwrap_or_default(),
+            prefix: get("prefix").unwrap_or_default(),
+            delimiter: get("delimiter"),
+            max_keys: get("max-keys")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(S3_MAX_KEYS)
+                .min(S3_MAX_KEYS),
+            start_after: get("start-after"),
+            continuation_token: get("continuation-token"),
+            url_encode: get("encoding-type").is_some_and(|v| v == "url"),
+            ..Default::default()
+        };
+        let mut last_key = match &page.continuation_token {
+            Some(token) => match STANDARD.decode(token).ok().map(String::from_utf8) {
+                Some(Ok(v)) => Some(v),
+                _ => {
+                    status_bad_request(res, "Invalid continuation token");
+                    return Ok(());
+                }
+            },
+            None => page.start_after.clone(),
+        };
+
+        // Only the directory the prefix ends in is walked
+        let base = match page.prefix.rsplit_once('/') {
+            Some((v, _)) => v,
+            None => "",
+        };
+        let is_plain = base.is_empty()
+            || base
+                .split('/')
+                .all(|v| !v.is_empty() && v != "." && v != "..");
+        let entries = match (is_plain, access_paths.find(base, false)) {
+            (true, Some(access_paths)) => {
+                walk_s3_dir(
+                    &join_root(&self.args.serve_path, base),
+                    access_paths,
+                    &self.args.hidden,
+                    self.ignore_files(user),
+                    self.running.clone(),
+                    page.delimiter.as_deref() == Some("/"),
+                )
+                .await?
+            }
+            _ => vec![],
+        };
+        let mut keys: BTreeMap<String, Option<Metadata>> = BTreeMap::new();
+        for (path, meta) in entries {
+            let Ok(mut key) = relative_to_root(&self.args.serve_path, &path) else {
+                continue;
+            };
+            if meta.is_dir() {
+                key.push('/');
+            }
+            let Some(rest) = key.strip_prefix(page.prefix.as_str()) else {
+                continue;
+            };
+            // Keys with the delimiter after the prefix are rolled up
+            let common_end = page
+                .delimiter
+                .as_deref()
+                .and_then(|v| rest.find(v).map(|i| page.prefix.len() + i + v.len()));
+            match common_end {
+                Some(end) => {
+                    keys.insert(key[..end].to_string(), None);
+                }
+                None if meta.is_dir() => {}
+                None => {
+                    keys.insert(key, Some(meta));
+                }
+            }
+        }
+
+        for (key, meta) in keys {
+            if last_key.as_ref().is_some_and(|v| &key <= v) {
+                continue;
+            }
+            if page.contents.len() + page.common_prefixes.len() >= page.max_keys {
+                page.next_continuation_token = last_key.map(|v| STANDARD.encode(v));
+                break;
+            }
+            last_key = Some(key.clone());
+            match meta {
+                Some(meta) => page.contents.push(ListEntry {
+                    size: meta.len(),
+                    mtime: meta.modified()?,
+                    etag: etag_text(&meta).unwrap_or_default(),
+                    key,
+                }),
+                None => page.common_prefixes.push(key),
+            }
+        }
+        res_xml(res, page.to_xml());
+        Ok(())
+    }
+
+    /// Url path of a file under the serve path, for logs and listings.
+    fn href_of(&self, path: &Path) -> String {
+        match relative_to_root(&self.args.serve_path, path) {
+            Ok(v) => format!("{}{}", self.args.uri_prefix, v),
+            Err(_) => path.display().to_string(),
+        }
+    }
+
+    /// Resolves once a drain requested through the admin API has finished.
+    pub async fn drained(&self) {
+        self.drained.notified().await
+    }
+
+    /// Idle timeout of keep-alive connections, zero disables keep-alive.
+    pub fn keep_alive(&self) -> Option<Duration> {
+        self.args.keep_alive.map(Duration::from_secs)
+    }
+
     pub async fn call(
         self: Arc<Self>,
-        req: Request,
+        mut req: Request,
         addr: Option<SocketAddr>,
     ) -> Result<Response, hyper::Error> {
+        // HTTP/2 carries the host in the `:authority` pseudo-header instead
+        if !req.headers().contains_key(HOST) {
+            if let Some(value) = req
+                .uri()
+                .authority()
+                .and_then(|v| HeaderValue::from_str(v.as_str()).ok())
+            {
+                req.headers_mut().insert(HOST, value);
+            }
+        }
         let uri = req.uri().clone();
         let assets_prefix = &self.assets_prefix;
         let enable_cors = self.args.enable_cors;
@@ -114,12 +596,28 @@ impl Server {
             .and_then(|v| v.to_str().ok())
             .map(|v| v.starts_with("Microsoft-WebDAV-MiniRedir/"))
             .unwrap_or_default();
+        let request_id = self.request_id(req.headers(), addr);
         let mut http_log_data = self.args.http_logger.data(&req);
         if let Some(addr) = addr {
             http_log_data.insert("remote_addr".to_string(), addr.ip().to_string());
         }
+        http_log_data.insert("request_id".to_string(), request_id.clone());
 
-        let mut res = match self.clone().handle(req, is_microsoft_webdav).await {
+        let (in_flight, requests) = InFlight::enter(&self.in_flight);
+        let ret = if self.args.max_requests > 0 && requests > self.args.max_requests {
+            self.refused_requests
+                .fetch_add(1, atomic::Ordering::Relaxed);
+            let mut res = Response::default();
+            status_overloaded(&mut res);
+            Ok(res)
+        } else if self.draining.load(atomic::Ordering::Relaxed) {
+            let mut res = Response::default();
+            status_overloaded(&mut res);
+            Ok(res)
+        } else {
+            self.clone().handle(req, is_microsoft_webdav).await
+        };
+        let mut res = match ret {
             Ok(res) => {
                 http_log_data.insert("status".to_string(), res.status().as_u16().to_string());
                 if !uri.path().starts_with(assets_prefix) {
@@ -129,16 +627,33 @@ impl Server {
             }
             Err(err) => {
                 let mut res = Response::default();
-                let status = StatusCode::INTERNAL_SERVER_ERROR;
-                *res.status_mut() = status;
-                http_log_data.insert("status".to_string(), status.as_u16().to_string());
-                self.args
-                    .http_logger
-                    .log(&http_log_data, Some(err.to_string()));
+                let error = HttpError::from_error(&err);
+                *res.status_mut() = error.status;
+                if is_client_abort(&err) {
+                    // Logged as nginx's "client closed request", nobody reads the response
+                    http_log_data.insert("status".to_string(), "499".to_string());
+                    self.args
+                        .http_logger
+                        .log_client_abort(&http_log_data, format!("[{request_id}] {err}"));
+                } else {
+                    http_log_data.insert("status".to_string(), error.status.as_u16().to_string());
+                    self.args
+                        .http_logger
+                        .log(&http_log_data, Some(format!("[{request_id}] {err}")));
+                }
+                res.extensions_mut().insert(error);
                 res
             }
         };
 
+        if let Some(error) = res.extensions_mut().remove::<HttpError>() {
+            let path = decode_uri(uri.path()).unwrap_or(Cow::Borrowed(uri.path()));
+            res_error(&mut res, &error.to_json(&path, &request_id));
+        }
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            res.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+
         if is_microsoft_webdav {
             // microsoft webdav requires this.
             res.headers_mut()
@@ -147,9 +662,72 @@ impl Server {
         if enable_cors {
             add_cors(&mut res);
         }
+        self.add_vary(&mut res);
+        self.add_custom_headers(uri.path(), &mut res);
+        // Still in flight until the body is sent
+        let res = res.map(|body| {
+            body.map_frame(move |frame| {
+                let _ = &in_flight;
+                frame
+            })
+            .boxed()
+        });
         Ok(res)
     }
 
+    /// Past half of `--max-requests`, so heavy jobs are refused before plain requests are.
+    fn is_busy(&self) -> bool {
+        let max = self.args.max_requests;
+        max > 0 && self.in_flight.load(atomic::Ordering::Relaxed) * 2 > max
+    }
+
+    /// Keeps the `X-Request-Id` of requests from trusted proxies, otherwise makes a new one.
+    fn request_id(&self, headers: &HeaderMap<HeaderValue>, addr: Option<SocketAddr>) -> String {
+        let trusted =
+            addr.is_some_and(|v| self.args.trusted_proxies.contains(&v.ip().to_canonical()));
+        let forwarded = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty() && v.len() <= 128 && v.bytes().all(|b| b.is_ascii_graphic()));
+        match forwarded {
+            Some(id) if trusted => id.to_string(),
+            _ => Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Declares the request headers responses are negotiated on, so shared
+    /// caches don't serve one user's listing or encoding to another.
+    fn add_vary(&self, res: &mut Response) {
+        let mut names = vec![];
+        if self.args.auth.exist() {
+            names.push("Authorization");
+            if self.args.login_page {
+                // Sessions are cookies, and browsers are told apart by `Accept`
+                names.extend(["Cookie", "Accept"]);
+            }
+        }
+        merge_vary(res.headers_mut(), &names);
+    }
+
+    fn add_custom_headers(&self, req_path: &str, res: &mut Response) {
+        let status = res.status();
+        if self.args.headers.is_empty()
+            || !(status.is_success() || status == StatusCode::NOT_MODIFIED)
+        {
+            return;
+        }
+        let Some(relative_path) = self.resolve_path(req_path) else {
+            return;
+        };
+        for header in &self.args.headers {
+            if header.path.strip(&relative_path).is_none() {
+                continue;
+            }
+            res.headers_mut()
+                .insert(header.name.clone(), header.value.clone());
+        }
+    }
+
     pub async fn handle(
         self: Arc<Self>,
         req: Request,
@@ -168,6 +746,12 @@ impl Server {
                 return Ok(res);
             }
         };
+        let relative_path = self
+            .args
+            .routes
+            .iter()
+            .find_map(|route| route.rewrite(&relative_path))
+            .unwrap_or(relative_path);
 
         if method == Method::GET
             && self
@@ -177,15 +761,130 @@ impl Server {
             return Ok(res);
         }
 
+        if (method == Method::GET || method == Method::HEAD)
+            && self
+                .handle_well_known(&relative_path, method == Method::HEAD, &mut res)
+                .await?
+        {
+            return Ok(res);
+        }
+
+        if self.args.login_page {
+            match relative_path.strip_prefix(INTERNAL_PREFIX) {
+                Some("login") => {
+                    self.handle_login(req, &mut res).await?;
+                    return Ok(res);
+                }
+                Some("logout") => {
+                    self.handle_logout(headers, &mut res)?;
+                    return Ok(res);
+                }
+                _ => {}
+            }
+        }
+
+        let readonly_listener = req.extensions().get::<ReadonlyListener>().is_some();
+        if readonly_listener && !is_readonly_method(&method) {
+            status_forbid(&mut res);
+            return Ok(res);
+        }
+
+        // Handled before the read-only mode it can switch off
+        if let Some(action) = relative_path
+            .strip_prefix(INTERNAL_PREFIX)
+            .and_then(|v| v.strip_prefix("admin/"))
+        {
+            self.handle_admin(action, req, &mut res).await?;
+            return Ok(res);
+        }
+        let readonly = readonly_listener || self.readonly.load(atomic::Ordering::Relaxed);
+        if readonly && !is_readonly_method(&method) {
+            status_forbid(&mut res);
+            return Ok(res);
+        }
+
+        if let Some(key) = self.s3_key(&relative_path) {
+            self.handle_s3(key, req, &mut res).await?;
+            // Errors are answered in XML, also those of the shared handlers
+            if let Some(error) = res.extensions_mut().remove::<HttpError>() {
+                let code = s3::error_code(error.status);
+                res_s3_error(&mut res, error.status, code, &error.message);
+            }
+            return Ok(res);
+        }
+
         let authorization = headers.get(AUTHORIZATION);
-        let guard =
-            self.args
-                .auth
-                .guard(&relative_path, &method, authorization, is_microsoft_webdav);
 
-        let (user, access_paths) = match guard {
+        let query = req.uri().query().unwrap_or_default();
+        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        // Internal endpoints are guarded like the root path
+        let internal_name = relative_path
+            .strip_prefix(INTERNAL_PREFIX)
+            .map(|v| v.to_string());
+        let guard_path = match internal_name {
+            Some(_) => "",
+            None => relative_path.as_str(),
+        };
+        let is_browser = method == Method::GET
+            && headers
+                .get(ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.contains("text/html"));
+        let query_token = query_params.get("token").map(|v| v.as_str());
+        // A stale cookie falls back to the other credentials instead of failing
+        let cookie_token = get_cookie(headers, GUEST_COOKIE)
+            .filter(|_| authorization.is_none())
+            .filter(|v| self.guest_tokens.guard(v, guard_path, &method).is_some());
+        let guest_token = query_token
+            .or_else(|| authorization.and_then(get_bearer_token))
+            .or(cookie_token);
+        let mut guest_perm = None;
+        let identity = match guest_token {
+            Some(token) => Identity::Guest(token.to_string()),
+            None => match self.session_user(headers, authorization) {
+                Some(user) => Identity::Session(user),
+                None => Identity::Header {
+                    authorization: authorization.cloned(),
+                    guard_options: is_microsoft_webdav,
+                },
+            },
+        };
+        let guard = match &identity {
+            Identity::Guest(token) => match self.guest_tokens.guard(token, guard_path, &method) {
+                // Browsers keep the token in a cookie, out of the address bar, history and logs
+                Some(_) if query_token.is_some() && is_browser => {
+                    self.set_guest_cookie(&mut res, token)?;
+                    status_see_other(&mut res, &strip_token_param(req.uri()))?;
+                    return Ok(res);
+                }
+                Some((perm, access_paths)) => {
+                    guest_perm = Some(perm);
+                    (None, Some(access_paths))
+                }
+                None => {
+                    status_forbid(&mut res);
+                    return Ok(res);
+                }
+            },
+            identity => self.guard_identity(identity, guard_path, &method),
+        };
+
+        let (user, mut access_paths) = match guard {
             (None, None) => {
-                self.auth_reject(&mut res)?;
+                if self.args.login_page && authorization.is_none() && is_browser {
+                    let next = req.uri().path_and_query().map(|v| v.as_str());
+                    let location = format!(
+                        "{}{INTERNAL_PREFIX}login?next={}",
+                        self.args.uri_prefix,
+                        encode_uri(next.unwrap_or_default())
+                    );
+                    status_see_other(&mut res, &location)?;
+                } else {
+                    self.auth_reject(&mut res)?;
+                }
                 return Ok(res);
             }
             (Some(_), None) => {
@@ -194,11 +893,16 @@ impl Server {
             }
             (x, Some(y)) => (x, y),
         };
+        if readonly && access_paths.perm().readwrite() {
+            access_paths.set_perm(AccessPerm::ReadOnly);
+        }
 
-        let query = req.uri().query().unwrap_or_default();
-        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
+        if let (true, Some(user)) = (self.args.user_root, &user) {
+            let home = self.args.serve_path.join(user);
+            if fs::symlink_metadata(&home).await.is_err() {
+                fs::create_dir_all(&home).await?;
+            }
+        }
 
         if method.as_str() == "CHECKAUTH" {
             match user.clone() {
@@ -213,6 +917,11 @@ impl Server {
             return Ok(res);
         }
 
+        if let Some(name) = internal_name {
+            self.handle_internal(&name, &method, &mut res).await?;
+            return Ok(res);
+        }
+
         let head_only = method == Method::HEAD;
 
         if self.args.path_is_file {
@@ -221,14 +930,37 @@ impl Server {
                 .iter()
                 .any(|v| v.as_str() == req_path)
             {
-                self.handle_send_file(&self.args.serve_path, headers, head_only, &mut res)
-                    .await?;
+                match &self.pipe_done {
+                    Some(done) if !done.load(atomic::Ordering::SeqCst) => {
+                        self.handle_send_pipe(&self.args.serve_path, done, head_only, &mut res)
+                            .await?;
+                    }
+                    _ => {
+                        self.handle_send_file(&self.args.serve_path, headers, head_only, &mut res)
+                            .await?;
+                    }
+                }
             } else {
                 status_not_found(&mut res);
             }
             return Ok(res);
         }
-        let path = match self.join_path(&relative_path) {
+        let is_chunked = matches!(method, Method::PUT | Method::POST | Method::DELETE)
+            && (query_params.contains_key("upload") || has_query_flag(&query_params, "chunked"));
+        let is_manifest = method == Method::POST && has_query_flag(&query_params, "manifest");
+        let is_chmod = method == Method::PATCH && query_params.contains_key("chmod");
+        // With `--upload-path` writes change the upload tree like S3 writes do,
+        // only a chmod is about the served files
+        let is_upload = (matches!(method, Method::PUT | Method::PATCH | Method::DELETE)
+            && !is_chmod)
+            || matches!(method.as_str(), "MKCOL" | "MOVE")
+            || is_chunked
+            || is_manifest;
+        let path = match is_upload {
+            true => self.join_upload_path(&relative_path),
+            false => self.join_path(&relative_path),
+        };
+        let path = match path {
             Some(v) => v,
             None => {
                 status_forbid(&mut res);
@@ -238,7 +970,8 @@ impl Server {
 
         let path = path.as_path();
 
-        let (is_miss, is_dir, is_file, size) = match fs::metadata(path).await.ok() {
+        let meta = fs::metadata(path).await.ok();
+        let (is_miss, is_dir, is_file, size) = match &meta {
             Some(meta) => (false, meta.is_dir(), meta.is_file(), meta.len()),
             None => (true, false, false, 0),
         };
@@ -251,49 +984,176 @@ impl Server {
         let render_spa = self.args.render_spa;
         let render_try_index = self.args.render_try_index;
 
-        if !self.args.allow_symlink && !is_miss && !self.is_root_contained(path).await {
+        if !self.args.allow_symlink && !self.is_root_contained(path).await {
             status_not_found(&mut res);
             return Ok(res);
         }
 
-        match method {
-            Method::GET | Method::HEAD => {
-                if is_dir {
-                    if render_try_index {
-                        if allow_archive && has_query_flag(&query_params, "zip") {
-                            if !allow_archive {
-                                status_not_found(&mut res);
-                                return Ok(res);
-                            }
-                            self.handle_zip_dir(path, head_only, access_paths, &mut res)
-                                .await?;
-                        } else if allow_search && query_params.contains_key("q") {
-                            self.handle_search_dir(
-                                path,
-                                &query_params,
-                                head_only,
-                                user,
-                                access_paths,
-                                &mut res,
-                            )
-                            .await?;
-                        } else {
-                            self.handle_render_index(
-                                path,
-                                &query_params,
-                                headers,
-                                head_only,
-                                user,
-                                access_paths,
-                                &mut res,
-                            )
-                            .await?;
-                        }
-                    } else if render_index || render_spa {
-                        self.handle_render_index(
-                            path,
-                            &query_params,
-                            headers,
+        if let Some(status) = self
+            .check_access_files(path, user.as_deref(), !is_readonly_method(&method))
+            .await
+        {
+            if status == StatusCode::UNAUTHORIZED && guest_perm.is_none() {
+                self.auth_reject(&mut res)?;
+            } else {
+                status_forbid(&mut res);
+            }
+            return Ok(res);
+        }
+
+        if self.args.no_search && is_dir && query_params.contains_key("q") {
+            status_forbid(&mut res);
+            return Ok(res);
+        }
+
+        let upload_id = match method {
+            Method::PUT => headers
+                .get(UPLOAD_ID_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty() && v.len() <= 128)
+                .map(|v| v.to_string()),
+            _ => None,
+        };
+        if let Some(upload_id) = &upload_id {
+            // A retried upload that already succeeded gets its final state back
+            if let Some((etag, _)) = meta.as_ref().and_then(extract_cache_headers) {
+                if self.upload_ids.is_done(upload_id, path, &etag) {
+                    *res.status_mut() = StatusCode::CREATED;
+                    res.headers_mut().typed_insert(etag);
+                    return Ok(res);
+                }
+            }
+        }
+
+        let is_write = matches!(method, Method::PUT | Method::PATCH | Method::DELETE)
+            || method.as_str() == "MOVE";
+        if is_write && !write_preconditions_pass(headers, meta.as_ref()) {
+            status_error(
+                &mut res,
+                StatusCode::PRECONDITION_FAILED,
+                "Precondition Failed",
+            );
+            return Ok(res);
+        }
+
+        // Upload tokens may only add new paths
+        let is_rename_upload =
+            method == Method::PUT && has_query_flag(&query_params, "rename-on-conflict");
+        if guest_perm == Some(GuestPerm::Upload)
+            && !is_miss
+            && method != Method::OPTIONS
+            && !is_rename_upload
+        {
+            status_forbid(&mut res);
+            return Ok(res);
+        }
+
+        if is_dir
+            && has_query_flag(&query_params, "tokens")
+            && (method == Method::GET || method == Method::POST)
+        {
+            self.handle_guest_tokens(&relative_path, user, req, &mut res)
+                .await?;
+            return Ok(res);
+        }
+
+        // Clients copy these next to every file, they are accepted and dropped
+        if self.is_dropped_upload(path) {
+            match method.as_str() {
+                "PUT" if allow_upload && !is_chunked => {
+                    *res.status_mut() = StatusCode::CREATED;
+                    return Ok(res);
+                }
+                "LOCK" if allow_upload => {
+                    let has_auth = authorization.is_some();
+                    self.handle_lock(req_path, has_auth, &mut res).await?;
+                    return Ok(res);
+                }
+                "UNLOCK" => {
+                    *res.status_mut() = StatusCode::NO_CONTENT;
+                    return Ok(res);
+                }
+                _ => {}
+            }
+        }
+
+        match method {
+            Method::GET | Method::HEAD => {
+                if is_dir {
+                    if render_try_index {
+                        if allow_archive && has_query_flag(&query_params, "zip") {
+                            if !allow_archive {
+                                status_not_found(&mut res);
+                                return Ok(res);
+                            }
+                            self.handle_zip_dir(
+                                path,
+                                query_params.get("name").map(|v| v.as_str()),
+                                user.as_deref(),
+                                head_only,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
+                        } else if allow_search && query_params.contains_key("q") {
+                            self.clone()
+                                .handle_search_dir(
+                                    path,
+                                    &query_params,
+                                    headers,
+                                    head_only,
+                                    user,
+                                    access_paths,
+                                    &mut res,
+                                )
+                                .await?;
+                        } else if has_query_flag(&query_params, "changes") {
+                            self.handle_changes_dir(
+                                path,
+                                &query_params,
+                                user.as_deref(),
+                                head_only,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
+                        } else if has_query_flag(&query_params, "tree") {
+                            self.handle_tree_dir(
+                                path,
+                                &query_params,
+                                user.as_deref(),
+                                head_only,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
+                        } else if has_query_flag(&query_params, "feed") {
+                            self.handle_feed_dir(
+                                path,
+                                user.as_deref(),
+                                headers,
+                                head_only,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
+                        } else {
+                            self.handle_render_index(
+                                path,
+                                &query_params,
+                                headers,
+                                head_only,
+                                user,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
+                        }
+                    } else if render_index || render_spa {
+                        self.handle_render_index(
+                            path,
+                            &query_params,
+                            headers,
                             head_only,
                             user,
                             access_paths,
@@ -305,10 +1165,61 @@ impl Server {
                             status_not_found(&mut res);
                             return Ok(res);
                         }
-                        self.handle_zip_dir(path, head_only, access_paths, &mut res)
-                            .await?;
+                        self.handle_zip_dir(
+                            path,
+                            query_params.get("name").map(|v| v.as_str()),
+                            user.as_deref(),
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
                     } else if allow_search && query_params.contains_key("q") {
-                        self.handle_search_dir(
+                        self.clone()
+                            .handle_search_dir(
+                                path,
+                                &query_params,
+                                headers,
+                                head_only,
+                                user,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
+                    } else if has_query_flag(&query_params, "changes") {
+                        self.handle_changes_dir(
+                            path,
+                            &query_params,
+                            user.as_deref(),
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
+                    } else if has_query_flag(&query_params, "tree") {
+                        self.handle_tree_dir(
+                            path,
+                            &query_params,
+                            user.as_deref(),
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
+                    } else if has_query_flag(&query_params, "feed") {
+                        self.handle_feed_dir(
+                            path,
+                            user.as_deref(),
+                            headers,
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
+                    } else if has_query_flag(&query_params, "json")
+                        && headers.get("depth").is_some_and(is_depth_infinity)
+                    {
+                        self.handle_deep_ls_dir(
                             path,
                             &query_params,
                             head_only,
@@ -322,6 +1233,7 @@ impl Server {
                             path,
                             true,
                             &query_params,
+                            headers,
                             head_only,
                             user,
                             access_paths,
@@ -338,6 +1250,17 @@ impl Server {
                             .await?;
                     } else if has_query_flag(&query_params, "hash") {
                         self.handle_hash_file(path, head_only, &mut res).await?;
+                    } else if has_query_flag(&query_params, "preview") {
+                        match self.args.previews.iter().find(|v| v.matches(path)) {
+                            Some(rule) => {
+                                self.handle_preview_file(path, rule, headers, head_only, &mut res)
+                                    .await?
+                            }
+                            None => status_not_found(&mut res),
+                        }
+                    } else if has_query_flag(&query_params, "thumb") {
+                        self.handle_thumb_file(path, headers, head_only, &mut res)
+                            .await?;
                     } else {
                         self.handle_send_file(path, headers, head_only, &mut res)
                             .await?;
@@ -350,6 +1273,7 @@ impl Server {
                         path,
                         false,
                         &query_params,
+                        headers,
                         head_only,
                         user,
                         access_paths,
@@ -362,12 +1286,124 @@ impl Server {
             }
             Method::OPTIONS => {
                 set_webdav_headers(&mut res);
+                if self.has_quirk(WebdavQuirk::Windows) {
+                    // Office and WebClient only open files for writing with it
+                    res.headers_mut()
+                        .insert("MS-Author-Via", HeaderValue::from_static("DAV"));
+                }
+            }
+            Method::PUT if is_chunked => {
+                if is_dir || !allow_upload {
+                    status_forbid(&mut res);
+                } else {
+                    self.handle_chunked_part(path, &query_params, req, &mut res)
+                        .await?;
+                }
+            }
+            Method::PUT if has_query_flag(&query_params, "rename-on-conflict") => {
+                let file_info = match FileInfo::from_headers(headers) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        status_bad_request(&mut res, &err.to_string());
+                        return Ok(res);
+                    }
+                };
+                if !allow_upload {
+                    status_forbid(&mut res);
+                } else {
+                    self.handle_upload_renamed(path, file_info.mtime, req, &mut res)
+                        .await?;
+                }
             }
             Method::PUT => {
-                if is_dir || !allow_upload || (!allow_delete && size > 0) {
+                let file_info = match FileInfo::from_headers(headers) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        status_bad_request(&mut res, &err.to_string());
+                        return Ok(res);
+                    }
+                };
+                // rclone sends the mtime in seconds and sets it with PROPPATCH unless accepted
+                let oc_mtime =
+                    match self.has_quirk(WebdavQuirk::Rclone) && file_info.mtime.is_none() {
+                        true => parse_oc_mtime(headers),
+                        false => None,
+                    };
+                let mtime = file_info.mtime.or(oc_mtime);
+                if is_dir || !allow_upload {
+                    status_forbid(&mut res);
+                } else if file_info
+                    .matches(path, meta.as_ref(), self.args.no_atime_updates)
+                    .await?
+                {
+                    // The client already has this file here, skip the body
+                    *res.status_mut() = StatusCode::NOT_MODIFIED;
+                } else {
+                    // A failed attempt of the same upload may be replaced
+                    let is_retry = upload_id
+                        .as_ref()
+                        .is_some_and(|id| self.upload_ids.is_failed(id, path));
+                    if self.args.write_once && !is_miss {
+                        status_conflict(&mut res);
+                    } else if !allow_delete && size > 0 && !is_retry {
+                        status_forbid(&mut res);
+                    } else if let Some(upload_id) = upload_id {
+                        if !self.upload_ids.start(&upload_id, path) {
+                            status_error(&mut res, StatusCode::CONFLICT, "Upload in progress");
+                            return Ok(res);
+                        }
+                        let ret = self
+                            .handle_upload(
+                                path,
+                                None,
+                                mtime,
+                                size,
+                                self.args.write_once,
+                                req,
+                                &mut res,
+                            )
+                            .await;
+                        let etag = match (&ret, res.status().is_success()) {
+                            (Ok(_), true) => fs::metadata(path)
+                                .await
+                                .ok()
+                                .and_then(|meta| extract_cache_headers(&meta))
+                                .map(|(etag, _)| etag),
+                            _ => None,
+                        };
+                        if let Some(etag) = &etag {
+                            res.headers_mut().typed_insert(etag.clone());
+                        }
+                        self.upload_ids.finish(&upload_id, path, etag);
+                        ret?;
+                    } else {
+                        self.handle_upload(
+                            path,
+                            None,
+                            mtime,
+                            size,
+                            self.args.write_once,
+                            req,
+                            &mut res,
+                        )
+                        .await?;
+                    }
+                }
+                if oc_mtime.is_some() && res.status().is_success() {
+                    res.headers_mut()
+                        .insert("X-OC-Mtime", HeaderValue::from_static("accepted"));
+                }
+            }
+            Method::PATCH if is_chmod => {
+                if !self.args.allow_chmod {
                     status_forbid(&mut res);
+                } else if is_miss {
+                    status_not_found(&mut res);
+                } else if self.args.write_once {
+                    status_conflict(&mut res);
                 } else {
-                    self.handle_upload(path, None, size, req, &mut res).await?;
+                    self.handle_chmod(path, &query_params["chmod"], &mut res)
+                        .await?;
                 }
             }
             Method::PATCH => {
@@ -375,6 +1411,8 @@ impl Server {
                     status_not_found(&mut res);
                 } else if !allow_upload {
                     status_forbid(&mut res);
+                } else if self.args.write_once {
+                    status_conflict(&mut res);
                 } else {
                     let offset = match parse_upload_offset(headers, size) {
                         Ok(v) => v,
@@ -384,12 +1422,20 @@ impl Server {
                         }
                     };
                     match offset {
+                        Some(offset) if offset < size && !allow_delete => {
+                            status_forbid(&mut res);
+                        }
                         Some(offset) => {
-                            if offset < size && !allow_delete {
-                                status_forbid(&mut res);
-                            }
-                            self.handle_upload(path, Some(offset), size, req, &mut res)
-                                .await?;
+                            self.handle_upload(
+                                path,
+                                Some(offset),
+                                None,
+                                size,
+                                false,
+                                req,
+                                &mut res,
+                            )
+                            .await?;
                         }
                         None => {
                             *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
@@ -397,8 +1443,74 @@ impl Server {
                     }
                 }
             }
+            Method::POST if is_chunked => {
+                if is_dir || !allow_upload {
+                    status_forbid(&mut res);
+                } else if let Some(id) = query_params.get("upload") {
+                    self.handle_chunked_finish(path, id, &mut res).await?;
+                } else if self.args.write_once && !is_miss {
+                    status_conflict(&mut res);
+                } else if !allow_delete && size > 0 {
+                    status_forbid(&mut res);
+                } else {
+                    self.handle_chunked_start(path, &query_params, &mut res)
+                        .await?;
+                }
+            }
+            Method::POST if is_manifest => {
+                if !allow_upload {
+                    status_forbid(&mut res);
+                } else if is_file {
+                    *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                } else {
+                    if let Some(entries) =
+                        read_json_body::<Vec<ManifestEntry>>(req, &mut res).await?
+                    {
+                        self.handle_manifest(&relative_path, entries, &method, &identity, &mut res)
+                            .await?;
+                    }
+                }
+            }
+            Method::POST => {
+                let is_delete = has_query_flag(&query_params, "delete");
+                let is_batch = has_query_flag(&query_params, "batch");
+                if !is_dir || !(is_delete || is_batch) {
+                    *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                } else if (is_delete && !allow_delete)
+                    || (is_batch && !allow_upload && !allow_delete)
+                {
+                    status_forbid(&mut res);
+                } else {
+                    let ops = if is_delete {
+                        read_json_body::<Vec<String>>(req, &mut res)
+                            .await?
+                            .map(|names| {
+                                names
+                                    .into_iter()
+                                    .map(|path| BatchOp::Delete { path })
+                                    .collect()
+                            })
+                    } else {
+                        read_json_body::<Vec<BatchOp>>(req, &mut res).await?
+                    };
+                    if let Some(ops) = ops {
+                        self.handle_batch(&relative_path, ops, &method, &identity, &mut res)
+                            .await?;
+                    }
+                }
+            }
+            Method::DELETE if is_chunked => match query_params.get("upload") {
+                Some(id) if allow_upload => self.handle_chunked_abort(path, id, &mut res).await?,
+                Some(_) => status_forbid(&mut res),
+                None => *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED,
+            },
             Method::DELETE => {
-                if !allow_delete {
+                let denied = is_dir
+                    && self
+                        .check_descendant_access_files(path, user.as_deref())
+                        .await
+                        .is_some();
+                if !allow_delete || denied {
                     status_forbid(&mut res);
                 } else if !is_miss {
                     self.handle_delete(path, is_dir, &mut res).await?
@@ -416,8 +1528,14 @@ impl Server {
                             } else {
                                 access_paths
                             };
-                        self.handle_propfind_dir(path, headers, access_paths, &mut res)
-                            .await?;
+                        self.handle_propfind_dir(
+                            path,
+                            user.as_deref(),
+                            headers,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
                     } else if is_file {
                         self.handle_propfind_file(path, &mut res).await?;
                     } else {
@@ -425,8 +1543,10 @@ impl Server {
                     }
                 }
                 "PROPPATCH" => {
-                    if is_file {
-                        self.handle_proppatch(req_path, &mut res).await?;
+                    if is_file || is_dir {
+                        let href = req_path.to_string();
+                        self.handle_proppatch(path, &href, allow_upload, req, &mut res)
+                            .await?;
                     } else {
                         status_not_found(&mut res);
                     }
@@ -435,8 +1555,7 @@ impl Server {
                     if !allow_upload {
                         status_forbid(&mut res);
                     } else if !is_miss {
-                        *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
-                        *res.body_mut() = body_full("Already exists");
+                        status_error(&mut res, StatusCode::METHOD_NOT_ALLOWED, "Already exists");
                     } else {
                         self.handle_mkcol(path, &mut res).await?;
                     }
@@ -447,7 +1566,8 @@ impl Server {
                     } else if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_copy(path, &req, &mut res).await?
+                        self.handle_copy(path, user.as_deref(), &identity, &req, &mut res)
+                            .await?
                     }
                 }
                 "MOVE" => {
@@ -456,7 +1576,8 @@ impl Server {
                     } else if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_move(path, &req, &mut res).await?
+                        self.handle_move(path, user.as_deref(), &identity, &req, &mut res)
+                            .await?
                     }
                 }
                 "LOCK" => {
@@ -464,6 +1585,16 @@ impl Server {
                     if is_file {
                         let has_auth = authorization.is_some();
                         self.handle_lock(req_path, has_auth, &mut res).await?;
+                    } else if is_miss && allow_upload && self.has_quirk(WebdavQuirk::Windows) {
+                        let has_auth = authorization.is_some();
+                        self.handle_lock_new(
+                            &relative_path,
+                            req_path,
+                            user.as_deref(),
+                            has_auth,
+                            &mut res,
+                        )
+                        .await?;
                     } else {
                         status_not_found(&mut res);
                     }
@@ -482,17 +1613,70 @@ impl Server {
         Ok(res)
     }
 
+    /// Writes the body to `path`. With `exclusive` a new file never replaces
+    /// one that shows up while the body is received, the upload gets a 409.
     async fn handle_upload(
         &self,
         path: &Path,
         upload_offset: Option<u64>,
+        mtime: Option<u64>,
         size: u64,
+        exclusive: bool,
         req: Request,
         res: &mut Response,
     ) -> Result<()> {
+        let _job = self.active_jobs.start("upload", self.href_of(path));
+        let checksum_header = req.headers().get(CHECKSUM_HEADER).cloned();
+        let hash = checksum_header.is_some()
+            || has_checksum_trailer(req.headers())
+            || (self.dedup.is_some() && upload_offset.is_none());
+        let aws_chunked = s3::is_aws_chunked(req.headers());
+        let (stream, body_end) = IncomingStream::new(req.into_body(), hash, aws_chunked);
+
+        let body_reader = StreamReader::new(stream);
+
+        pin_mut!(body_reader);
+
+        // The start of the content is checked before anything is written
+        let mut head = vec![];
+        let has_head = upload_offset.unwrap_or_default() == 0;
+        if has_head && self.has_upload_types() {
+            (&mut body_reader)
+                .take(UPLOAD_SNIFF_SIZE)
+                .read_to_end(&mut head)
+                .await?;
+        }
+        if let Some(reason) = self.check_upload_type(path, has_head.then_some(&head)) {
+            status_unsupported_type(res, &reason);
+            return Ok(());
+        }
+
         ensure_path_parent(path).await?;
+        // A new file is only moved into place once complete
+        let tmp_path = match upload_offset {
+            None => self.upload_tmp_path(path),
+            Some(_) => None,
+        };
+        let write_path = tmp_path.as_deref().unwrap_or(path);
+        if tmp_path.is_none() && !exclusive {
+            self.unshare(path).await?;
+        }
         let (mut file, status) = match upload_offset {
-            None => (fs::File::create(path).await?, StatusCode::CREATED),
+            None if exclusive && tmp_path.is_none() => {
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(path)
+                    .await;
+                match file {
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                        status_conflict(res);
+                        return Ok(());
+                    }
+                    file => (file?, StatusCode::CREATED),
+                }
+            }
+            None => (fs::File::create(write_path).await?, StatusCode::CREATED),
             Some(offset) if offset == size => (
                 fs::OpenOptions::new().append(true).open(path).await?,
                 StatusCode::NO_CONTENT,
@@ -503,23 +1687,64 @@ impl Server {
                 (file, StatusCode::NO_CONTENT)
             }
         };
-        let stream = IncomingStream::new(req.into_body());
-
-        let body_with_io_error = stream.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
-        let body_reader = StreamReader::new(body_with_io_error);
-
-        pin_mut!(body_reader);
+        // Removes the partial file if the upload fails or is cancelled by the client,
+        // unless it is large enough to be worth resuming.
+        let mut partial_guard = match tmp_path {
+            Some(_) => PartialUploadGuard::temporary(write_path),
+            None => PartialUploadGuard::new(path, upload_offset.is_none()),
+        };
 
-        let ret = io::copy(&mut body_reader, &mut file).await;
-        let size = fs::metadata(path)
-            .await
-            .map(|v| v.len())
-            .unwrap_or_default();
-        if ret.is_err() {
-            if upload_offset.is_none() && size < RESUMABLE_UPLOAD_MIN_SIZE {
-                let _ = tokio::fs::remove_file(&path).await;
+        file.write_all(&head).await?;
+        let offset = upload_offset.unwrap_or_default() + head.len() as u64;
+        self.copy_to_file(&mut body_reader, &mut file, offset)
+            .await?;
+        if let Err(err) = verify_body_checksum(checksum_header.as_ref(), &body_end) {
+            // Leaves no trace of the bad data
+            match upload_offset {
+                None => {
+                    drop(file);
+                    partial_guard.disarm();
+                    fs::remove_file(write_path).await?;
+                }
+                Some(offset) => file.set_len(offset).await?,
+            }
+            status_bad_request(res, &err);
+            return Ok(());
+        }
+        if self.args.fsync {
+            file.flush().await?;
+            file.sync_all().await?;
+        }
+        if tmp_path.is_some() && exclusive {
+            // Linking fails instead of replacing a file created during the upload
+            let linked = fs::hard_link(write_path, path).await;
+            partial_guard.disarm();
+            let _ = fs::remove_file(write_path).await;
+            match linked {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    status_conflict(res);
+                    return Ok(());
+                }
+                ret => ret?,
             }
-            ret?;
+        } else if tmp_path.is_some() {
+            fs::rename(write_path, path).await?;
+        }
+        if self.args.fsync {
+            sync_parent_dir(path).await?;
+        }
+        partial_guard.disarm();
+        if let Some(mtime) = mtime {
+            // Keeps the client's time, so the next `X-File-Mtime` matches
+            let mtime = SystemTime::UNIX_EPOCH + Duration::from_millis(mtime);
+            file.into_std().await.set_modified(mtime)?;
+        }
+        if self.args.checksum_sidecar && !is_checksum_sidecar(path) {
+            self.write_checksum_sidecar(path).await?;
+        }
+        if upload_offset.is_none() {
+            let sha256 = body_end.lock().unwrap().sha256.clone();
+            self.dedup_upload(path, sha256).await?;
         }
 
         *res.status_mut() = status;
@@ -527,21 +1752,499 @@ impl Server {
         Ok(())
     }
 
-    async fn handle_delete(&self, path: &Path, is_dir: bool, res: &mut Response) -> Result<()> {
-        match is_dir {
-            true => fs::remove_dir_all(path).await?,
-            false => fs::remove_file(path).await?,
-        }
-
-        status_no_content(res);
-        Ok(())
-    }
-
-    async fn handle_ls_dir(
+    /// Uploads next to an existing entry as `name (1).ext`, `name (2).ext`...
+    /// instead of replacing it, the final name is returned as JSON.
+    async fn handle_upload_renamed(
+        &self,
+        path: &Path,
+        mtime: Option<u64>,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        ensure_path_parent(path).await?;
+        let Some(path) = reserve_free_path(path).await? else {
+            status_conflict(res);
+            return Ok(());
+        };
+        // The reserved name is ours to replace, even under `--write-once`
+        let ret = self
+            .handle_upload(&path, None, mtime, 0, false, req, res)
+            .await;
+        if ret.is_err() || !res.status().is_success() {
+            // Frees the reserved name again
+            let _ = fs::remove_file(&path).await;
+            return ret;
+        }
+        let output = serde_json::json!({ "name": get_file_name(&path) }).to_string();
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        *res.body_mut() = body_full(output);
+        Ok(())
+    }
+
+    /// Sets the permission bits of a path, only plain `rwx` bits are accepted
+    /// so setuid, setgid and sticky bits can't be granted remotely.
+    async fn handle_chmod(&self, path: &Path, mode: &str, res: &mut Response) -> Result<()> {
+        let mode = match u32::from_str_radix(mode, 8) {
+            Ok(v) if (3..=4).contains(&mode.len()) && v <= 0o777 => v,
+            _ => {
+                status_bad_request(res, "Invalid chmod, expected octal bits like 644");
+                return Ok(());
+            }
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+        }
+        #[cfg(not(unix))]
+        let _ = (path, mode);
+        status_no_content(res);
+        Ok(())
+    }
+
+    /// Starts a chunked upload of `size` bytes, sent as `chunk-size` sized
+    /// parts that may arrive in any order and in parallel.
+    async fn handle_chunked_start(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        res: &mut Response,
+    ) -> Result<()> {
+        let get = |name: &str| query_params.get(name).and_then(|v| v.parse::<u64>().ok());
+        let (Some(size), Some(chunk_size)) = (get("size"), get("chunk-size")) else {
+            status_bad_request(res, "Missing or invalid size and chunk-size");
+            return Ok(());
+        };
+        if chunk_size == 0 || size.div_ceil(chunk_size) > CHUNKED_UPLOAD_CHUNKS_MAX {
+            status_bad_request(
+                res,
+                &format!(
+                    "Invalid chunk-size, at most {CHUNKED_UPLOAD_CHUNKS_MAX} chunks are allowed"
+                ),
+            );
+            return Ok(());
+        }
+        let id = Uuid::new_v4().simple().to_string();
+        ensure_path_parent(path).await?;
+        let staging_path = self
+            .upload_tmp_path(path)
+            .unwrap_or_else(|| path.with_file_name(format!("{CHUNKED_UPLOAD_PREFIX}{id}")));
+        let file = fs::File::create(&staging_path).await?;
+        file.set_len(size).await?;
+        let chunks = size.div_ceil(chunk_size);
+        let evicted = self
+            .chunked_uploads
+            .start(
+                &id,
+                ChunkedUpload {
+                    path: path.to_path_buf(),
+                    staging_path,
+                    size,
+                    chunk_size,
+                    received: vec![false; chunks as usize],
+                },
+            )
+            .await;
+        if let Some(evicted) = evicted {
+            let _ = fs::remove_file(&evicted.staging_path).await;
+        }
+        let output = serde_json::to_string_pretty(&serde_json::json!({
+            "id": id,
+            "chunks": chunks,
+        }))?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        *res.status_mut() = StatusCode::CREATED;
+        *res.body_mut() = body_full(output);
+        Ok(())
+    }
+
+    /// Writes a chunk at its place in the staging file, a failed chunk can
+    /// simply be sent again.
+    async fn handle_chunked_part(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let id = query_params
+            .get("upload")
+            .map(|v| v.as_str())
+            .unwrap_or_default();
+        let Some(index) = query_params
+            .get("chunk")
+            .and_then(|v| v.parse::<u64>().ok())
+        else {
+            status_bad_request(res, "Missing or invalid chunk");
+            return Ok(());
+        };
+        let Some((staging_path, offset, len)) = self.chunked_uploads.chunk(id, path, index) else {
+            status_not_found(res);
+            return Ok(());
+        };
+        let _job = self.active_jobs.start("upload", self.href_of(path));
+        let checksum_header = req.headers().get(CHECKSUM_HEADER).cloned();
+        let hash = checksum_header.is_some() || has_checksum_trailer(req.headers());
+        let (stream, body_end) = IncomingStream::new(req.into_body(), hash, false);
+        let body_reader = StreamReader::new(stream);
+        pin_mut!(body_reader);
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(&staging_path)
+            .await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        // One byte more than expected tells an oversized chunk apart
+        let written = self
+            .copy_to_file(&mut (&mut body_reader).take(len + 1), &mut file, offset)
+            .await?;
+        if written != len {
+            status_bad_request(res, &format!("Chunk {index} must be {len} bytes"));
+            return Ok(());
+        }
+        if let Err(err) = verify_body_checksum(checksum_header.as_ref(), &body_end) {
+            status_bad_request(res, &err);
+            return Ok(());
+        }
+        if self.args.fsync {
+            file.sync_data().await?;
+        }
+        self.chunked_uploads.received(id, index).await;
+        *res.status_mut() = StatusCode::NO_CONTENT;
+        Ok(())
+    }
+
+    /// Moves the assembled file into place once every chunk has arrived
+    async fn handle_chunked_finish(&self, path: &Path, id: &str, res: &mut Response) -> Result<()> {
+        let upload = match self.chunked_uploads.finish(id, path).await {
+            Ok(v) => v,
+            Err(Some(missing)) => {
+                let message = format!(
+                    "Missing {} chunks, starting with chunk {}",
+                    missing.len(),
+                    missing[0]
+                );
+                status_error(res, StatusCode::CONFLICT, message);
+                return Ok(());
+            }
+            Err(None) => {
+                status_not_found(res);
+                return Ok(());
+            }
+        };
+        // The target may have been created since the upload started
+        let target_size = fs::symlink_metadata(path).await.ok().map(|v| v.len());
+        if self.args.write_once && target_size.is_some() {
+            let _ = fs::remove_file(&upload.staging_path).await;
+            status_conflict(res);
+            return Ok(());
+        }
+        if !self.args.allow_delete && target_size.is_some_and(|v| v > 0) {
+            let _ = fs::remove_file(&upload.staging_path).await;
+            status_forbid(res);
+            return Ok(());
+        }
+        let mut head = vec![];
+        if self.has_upload_types() {
+            fs::File::open(&upload.staging_path)
+                .await?
+                .take(UPLOAD_SNIFF_SIZE)
+                .read_to_end(&mut head)
+                .await?;
+        }
+        if let Some(reason) = self.check_upload_type(path, Some(&head)) {
+            let _ = fs::remove_file(&upload.staging_path).await;
+            status_unsupported_type(res, &reason);
+            return Ok(());
+        }
+        if self.is_dropped_upload(path) {
+            let _ = fs::remove_file(&upload.staging_path).await;
+            *res.status_mut() = StatusCode::CREATED;
+            return Ok(());
+        }
+        if self.args.fsync {
+            fs::File::open(&upload.staging_path)
+                .await?
+                .sync_all()
+                .await?;
+        }
+        if self.args.write_once {
+            // Linking fails instead of replacing a file created since the check above
+            let linked = fs::hard_link(&upload.staging_path, path).await;
+            let _ = fs::remove_file(&upload.staging_path).await;
+            match linked {
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    status_conflict(res);
+                    return Ok(());
+                }
+                ret => ret?,
+            }
+        } else {
+            fs::rename(&upload.staging_path, path).await?;
+        }
+        if self.args.fsync {
+            sync_parent_dir(path).await?;
+        }
+        if self.args.checksum_sidecar && !is_checksum_sidecar(path) {
+            self.write_checksum_sidecar(path).await?;
+        }
+        self.dedup_upload(path, None).await?;
+        *res.status_mut() = StatusCode::CREATED;
+        Ok(())
+    }
+
+    async fn handle_chunked_abort(&self, path: &Path, id: &str, res: &mut Response) -> Result<()> {
+        match self.chunked_uploads.abort(id, path).await {
+            Some(upload) => {
+                let _ = fs::remove_file(&upload.staging_path).await;
+                *res.status_mut() = StatusCode::NO_CONTENT;
+            }
+            None => status_not_found(res),
+        }
+        Ok(())
+    }
+
+    /// Writes `<file>.sha256` in the `sha256sum` format, so `sha256sum -c` can
+    /// verify downloads
+    async fn write_checksum_sidecar(&self, path: &Path) -> Result<()> {
+        let hash = sha256_file(path, false).await?;
+        let sidecar_path = checksum_sidecar_path(path);
+        let content = format!("{hash}  {}\n", get_file_name(path));
+        fs::write(&sidecar_path, content).await?;
+        if self.args.fsync {
+            fs::File::open(&sidecar_path).await?.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_delete(&self, path: &Path, is_dir: bool, res: &mut Response) -> Result<()> {
+        match is_dir {
+            true => fs::remove_dir_all(path).await?,
+            false => fs::remove_file(path).await?,
+        }
+        self.deleted_paths.record(path, is_dir);
+        if self.args.checksum_sidecar && !is_dir {
+            let _ = fs::remove_file(checksum_sidecar_path(path)).await;
+        }
+
+        status_no_content(res);
+        Ok(())
+    }
+
+    async fn handle_guest_tokens(
+        &self,
+        relative_path: &str,
+        user: Option<String>,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let user = match user {
+            Some(user) if self.args.auth.exist() => user,
+            _ => {
+                status_forbid(res);
+                return Ok(());
+            }
+        };
+        if req.method() == Method::GET {
+            let tokens = self.guest_tokens.list(&user, relative_path)?;
+            return res_json(res, &tokens);
+        }
+        let data: GuestTokenRequest = match read_json_body(req, res).await? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        if let Some(token) = data.revoke {
+            if self.guest_tokens.revoke(&user, &token)? {
+                status_no_content(res);
+            } else {
+                status_not_found(res);
+            }
+            return Ok(());
+        }
+        if data.perm == GuestPerm::Upload && !self.args.allow_upload {
+            status_forbid(res);
+            return Ok(());
+        }
+        let token = self
+            .guest_tokens
+            .create(relative_path, data.perm, &user, data.expires_in)?;
+        *res.status_mut() = StatusCode::CREATED;
+        res_json(res, &token)
+    }
+
+    async fn handle_batch(
+        &self,
+        relative_path: &str,
+        ops: Vec<BatchOp>,
+        method: &Method,
+        identity: &Identity,
+        res: &mut Response,
+    ) -> Result<()> {
+        let mut results = vec![];
+        for op in ops {
+            let result = match self.batch_item(relative_path, &op, method, identity).await {
+                Ok(status) => BatchResult::new(op.name(), status, None),
+                Err(err) => {
+                    BatchResult::new(op.name(), StatusCode::INTERNAL_SERVER_ERROR, Some(err))
+                }
+            };
+            results.push(result);
+        }
+        res_json(res, &results)
+    }
+
+    async fn batch_item(
+        &self,
+        relative_path: &str,
+        op: &BatchOp,
+        method: &Method,
+        identity: &Identity,
+    ) -> Result<StatusCode> {
+        let allowed = match op {
+            BatchOp::Move { .. } => self.args.allow_upload && self.args.allow_delete,
+            BatchOp::Copy { .. } => self.args.allow_upload,
+            BatchOp::Delete { .. } => self.args.allow_delete,
+        };
+        if !allowed {
+            return Ok(StatusCode::FORBIDDEN);
+        }
+        let resolve = |name, is_upload| {
+            self.resolve_batch_path(relative_path, name, method, identity, is_upload)
+        };
+        match op {
+            BatchOp::Delete { path } => {
+                let path = match resolve(path, true).await {
+                    Ok(v) => v,
+                    Err(status) => return Ok(status),
+                };
+                let is_dir = match fs::symlink_metadata(&path).await {
+                    Ok(meta) => meta.is_dir(),
+                    Err(_) => return Ok(StatusCode::NOT_FOUND),
+                };
+                let mut res = Response::default();
+                self.handle_delete(&path, is_dir, &mut res).await?;
+                Ok(res.status())
+            }
+            BatchOp::Move { from, to } | BatchOp::Copy { from, to } => {
+                let is_move = matches!(op, BatchOp::Move { .. });
+                let (from, to) = match (resolve(from, is_move).await, resolve(to, true).await) {
+                    (Ok(from), Ok(to)) => (from, to),
+                    (Err(status), _) | (_, Err(status)) => return Ok(status),
+                };
+                let meta = match fs::symlink_metadata(&from).await {
+                    Ok(v) => v,
+                    Err(_) => return Ok(StatusCode::NOT_FOUND),
+                };
+                if !is_move && meta.is_dir() {
+                    return Ok(StatusCode::FORBIDDEN);
+                }
+                if fs::symlink_metadata(&to).await.is_ok() {
+                    return Ok(StatusCode::CONFLICT);
+                }
+                let mut res = Response::default();
+                if is_move {
+                    self.move_path(&from, &to, meta.is_dir(), &mut res).await?;
+                } else {
+                    self.copy_file(&from, &to, &mut res).await?;
+                }
+                Ok(res.status())
+            }
+        }
+    }
+
+    /// Tells bulk uploaders which files of a manifest are already on the server,
+    /// judged like the `X-File-*` headers of a PUT, so they can be skipped.
+    async fn handle_manifest(
+        &self,
+        relative_path: &str,
+        entries: Vec<ManifestEntry>,
+        method: &Method,
+        identity: &Identity,
+        res: &mut Response,
+    ) -> Result<()> {
+        let mut results = vec![];
+        for mut entry in entries {
+            let resolved = self
+                .resolve_batch_path(relative_path, &entry.path, method, identity, true)
+                .await;
+            let state = match resolved {
+                Ok(path) => match fs::metadata(&path).await.ok() {
+                    None => ManifestState::Missing,
+                    Some(meta) => {
+                        entry.info.sha256 = entry.info.sha256.map(|v| v.to_ascii_lowercase());
+                        match entry
+                            .info
+                            .matches(&path, Some(&meta), self.args.no_atime_updates)
+                            .await?
+                        {
+                            true => ManifestState::Unchanged,
+                            false => ManifestState::Changed,
+                        }
+                    }
+                },
+                Err(StatusCode::BAD_REQUEST) => ManifestState::Invalid,
+                Err(_) => ManifestState::Forbidden,
+            };
+            results.push(ManifestResult {
+                path: entry.path,
+                state,
+            });
+        }
+        res_json(res, &results)
+    }
+
+    /// Resolves a path of a batch operation relative to the requested directory and checks
+    /// that the user is allowed to write it. Uploads may go to `--upload-path` instead.
+    async fn resolve_batch_path(
+        &self,
+        relative_path: &str,
+        name: &str,
+        method: &Method,
+        identity: &Identity,
+        is_upload: bool,
+    ) -> std::result::Result<PathBuf, StatusCode> {
+        let relative_path = resolve_child_path(relative_path, &self.normalize_unicode(name))
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        let (user, access_paths) = self.guard_identity(identity, &relative_path, method);
+        if access_paths.is_none() {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let path = match is_upload {
+            true => self.join_upload_path(&relative_path),
+            false => self.join_path(&relative_path),
+        }
+        .ok_or(StatusCode::FORBIDDEN)?;
+        if self
+            .check_access_files(&path, user.as_deref(), true)
+            .await
+            .is_some()
+        {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        if !self.args.allow_symlink && !self.is_root_contained(&path).await {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        let is_dir = fs::symlink_metadata(&path).await.is_ok_and(|v| v.is_dir());
+        if is_dir
+            && self
+                .check_descendant_access_files(&path, user.as_deref())
+                .await
+                .is_some()
+        {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(path)
+    }
+
+    async fn handle_ls_dir(
         &self,
         path: &Path,
         exist: bool,
         query_params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
         head_only: bool,
         user: Option<String>,
         access_paths: AccessPaths,
@@ -549,7 +2252,10 @@ impl Server {
     ) -> Result<()> {
         let mut paths = vec![];
         if exist {
-            paths = match self.list_dir(path, path, access_paths.clone()).await {
+            paths = match self
+                .list_dir(path, path, user.as_deref(), access_paths.clone())
+                .await
+            {
                 Ok(paths) => paths,
                 Err(_) => {
                     status_forbid(res);
@@ -557,10 +2263,28 @@ impl Server {
                 }
             }
         };
+        let etag =
+            self.listing_etag(&paths, exist, query_params, user.as_deref(), &access_paths)?;
+        if headers
+            .typed_get::<IfNoneMatch>()
+            .is_some_and(|v| !v.precondition_passes(&etag))
+        {
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            res.headers_mut().typed_insert(etag);
+            res.headers_mut()
+                .typed_insert(CacheControl::new().with_no_cache());
+            return Ok(());
+        }
+        res.headers_mut().typed_insert(etag);
+        let readme = match exist {
+            true => self.render_readme(path, &paths).await,
+            false => None,
+        };
         self.send_index(
             path,
             paths,
             exist,
+            readme,
             query_params,
             head_only,
             user,
@@ -569,7 +2293,54 @@ impl Server {
         )
     }
 
-    async fn handle_search_dir(
+    /// The `--readme` file listed in `dir` rendered to HTML, the listing
+    /// already leaves out hidden and forbidden paths
+    async fn render_readme(&self, dir: &Path, paths: &[PathItem]) -> Option<String> {
+        let name = self.args.readme.as_deref()?;
+        let item = paths.iter().find(|v| v.name == name && !v.is_dir())?;
+        if item.size > README_MAX_SIZE {
+            return None;
+        }
+        let text = fs::read_to_string(dir.join(name)).await.ok()?;
+        let relative = relative_to_root(&self.args.serve_path, dir).ok()?;
+        let base = match relative.is_empty() {
+            true => self.args.uri_prefix.clone(),
+            false => format!("{}{}/", self.args.uri_prefix, encode_uri(&relative)),
+        };
+        Some(markdown::render(&text, &base))
+    }
+
+    /// Weak ETag of a listing, a hash of its entries and of everything else
+    /// the rendered page depends on, so pollers can revalidate for a 304
+    fn listing_etag(
+        &self,
+        paths: &[PathItem],
+        exist: bool,
+        query_params: &HashMap<String, String>,
+        user: Option<&str>,
+        access_paths: &AccessPaths,
+    ) -> Result<ETag> {
+        // Sorted, as directories are not guaranteed to be read in the same order
+        let mut items = paths
+            .iter()
+            .map(serde_json::to_vec)
+            .collect::<Result<Vec<_>, _>>()?;
+        items.sort_unstable();
+        let mut hasher = Sha256::new();
+        for item in items {
+            hasher.update(item);
+        }
+        let query: BTreeMap<_, _> = query_params.iter().collect();
+        let variant = serde_json::json!([query, user, exist, access_paths.perm().readwrite(),]);
+        hasher.update(serde_json::to_vec(&variant)?);
+        hasher.update(self.html.as_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        Ok(format!(r#"W/"{}""#, &hash[..32]).parse::<ETag>()?)
+    }
+
+    /// Lists the whole tree below `path` for `Depth: infinity`, names are
+    /// relative to `path` like search results
+    async fn handle_deep_ls_dir(
         &self,
         path: &Path,
         query_params: &HashMap<String, String>,
@@ -578,69 +2349,18 @@ impl Server {
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        let mut paths: Vec<PathItem> = vec![];
-        let search = query_params
-            .get("q")
-            .ok_or_else(|| anyhow!("invalid q"))?
-            .to_lowercase();
-        if search.is_empty() {
-            return self
-                .handle_ls_dir(path, true, query_params, head_only, user, access_paths, res)
-                .await;
-        } else {
-            let path_buf = path.to_path_buf();
-            let hidden = Arc::new(self.args.hidden.to_vec());
-            let hidden = hidden.clone();
-            let running = self.running.clone();
-            let access_paths = access_paths.clone();
-            let search_paths = tokio::task::spawn_blocking(move || {
-                let mut paths: Vec<PathBuf> = vec![];
-                for dir in access_paths.child_paths(&path_buf) {
-                    let mut it = WalkDir::new(&dir).into_iter();
-                    it.next();
-                    while let Some(Ok(entry)) = it.next() {
-                        if !running.load(atomic::Ordering::SeqCst) {
-                            break;
-                        }
-                        let entry_path = entry.path();
-                        let base_name = get_file_name(entry_path);
-                        let file_type = entry.file_type();
-                        let mut is_dir_type: bool = file_type.is_dir();
-                        if file_type.is_symlink() {
-                            match std::fs::symlink_metadata(entry_path) {
-                                Ok(meta) => {
-                                    is_dir_type = meta.is_dir();
-                                }
-                                Err(_) => {
-                                    continue;
-                                }
-                            }
-                        }
-                        if is_hidden(&hidden, base_name, is_dir_type) {
-                            if file_type.is_dir() {
-                                it.skip_current_dir();
-                            }
-                            continue;
-                        }
-                        if !base_name.to_lowercase().contains(&search) {
-                            continue;
-                        }
-                        paths.push(entry_path.to_path_buf());
-                    }
-                }
-                paths
-            })
-            .await?;
-            for search_path in search_paths.into_iter() {
-                if let Ok(Some(item)) = self.to_pathitem(search_path, path.to_path_buf()).await {
-                    paths.push(item);
-                }
-            }
-        }
+        let Some(paths) = self
+            .walk_pathitems(path, path, user.as_deref(), access_paths.clone())
+            .await?
+        else {
+            status_finite_depth(res);
+            return Ok(());
+        };
         self.send_index(
             path,
             paths,
             true,
+            None,
             query_params,
             head_only,
             user,
@@ -649,38 +2369,239 @@ impl Server {
         )
     }
 
-    async fn handle_zip_dir(
+    /// Every path below `dir`, `None` if there are more than
+    /// `--depth-max-entries` of them
+    async fn walk_pathitems(
         &self,
+        dir: &Path,
+        base_path: &Path,
+        user: Option<&str>,
+        access_paths: AccessPaths,
+    ) -> Result<Option<Vec<PathItem>>> {
+        let limit = self.args.depth_max_entries;
+        if limit == 0 {
+            return Ok(None);
+        }
+        let entry_paths = walk_dir(
+            dir,
+            access_paths,
+            &self.args.hidden,
+            self.ignore_files(user),
+            self.running.clone(),
+            true,
+            Some(limit),
+        )
+        .await?;
+        if entry_paths.len() > limit {
+            return Ok(None);
+        }
+        let mut paths = vec![];
+        for entry_path in entry_paths {
+            if let Ok(Some(item)) = self.to_pathitem(entry_path.as_path(), base_path).await {
+                paths.push(item);
+            }
+        }
+        Ok(Some(paths))
+    }
+
+    async fn handle_search_dir(
+        self: Arc<Self>,
         path: &Path,
+        query_params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
         head_only: bool,
+        user: Option<String>,
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        let (mut writer, reader) = tokio::io::duplex(BUF_SIZE);
-        let filename = try_get_file_name(path)?;
-        set_content_disposition(res, false, &format!("{}.zip", filename))?;
+        let mut paths: Vec<PathItem> = vec![];
+        let search = query_params.get("q").ok_or_else(|| anyhow!("invalid q"))?;
+        if search.is_empty() {
+            return self
+                .handle_ls_dir(
+                    path,
+                    true,
+                    query_params,
+                    headers,
+                    head_only,
+                    user,
+                    access_paths,
+                    res,
+                )
+                .await;
+        }
+        let options = match SearchOptions::new(path, query_params, &self.args) {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, err);
+                return Ok(());
+            }
+        };
+        let mode = options.mode;
+        let limit = options.limit;
+        let dirs = access_paths.child_paths(path);
+        let hidden = self.args.hidden.to_vec();
+        let mut ignore_files = self.ignore_files(user.as_deref());
+        let running = self.running.clone();
+        if has_query_flag(query_params, "ndjson") {
+            res.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/x-ndjson"),
+            );
+            res.headers_mut()
+                .typed_insert(CacheControl::new().with_no_cache());
+            if head_only {
+                return Ok(());
+            }
+            let (tx, mut rx) = mpsc::channel::<PathBuf>(64);
+            tokio::task::spawn_blocking(move || {
+                let mut count = 0;
+                search_walk(
+                    &dirs,
+                    &options,
+                    &hidden,
+                    &mut ignore_files,
+                    &running,
+                    |entry_path, _| {
+                        count += 1;
+                        tx.blocking_send(entry_path).is_ok() && limit.is_none_or(|v| count < v)
+                    },
+                )
+            });
+            let base_path = path.to_path_buf();
+            let stream = async_stream::stream! {
+                while let Some(search_path) = rx.recv().await {
+                    if let Ok(Some(item)) = self.to_pathitem(search_path.as_path(), &base_path).await {
+                        let mut line = serde_json::to_vec(&item)?;
+                        line.push(b'\n');
+                        yield Ok(Bytes::from(line));
+                    }
+                }
+            };
+            let stream_body = StreamBody::new(stream.map_ok(Frame::data));
+            *res.body_mut() = stream_body.boxed();
+            return Ok(());
+        }
+        let search_paths = tokio::task::spawn_blocking(move || {
+            let mut paths: Vec<(PathBuf, u32)> = vec![];
+            search_walk(
+                &dirs,
+                &options,
+                &hidden,
+                &mut ignore_files,
+                &running,
+                |entry_path, score| {
+                    paths.push((entry_path, score));
+                    limit.is_none_or(|v| paths.len() < v)
+                },
+            );
+            paths
+        })
+        .await?;
+        let mut scored_paths = vec![];
+        for (search_path, score) in search_paths.into_iter() {
+            if let Ok(Some(item)) = self.to_pathitem(search_path, path.to_path_buf()).await {
+                scored_paths.push((item, score));
+            }
+        }
+        if mode == SearchMode::Fuzzy {
+            scored_paths.sort_by(|(v1, s1), (v2, s2)| s2.cmp(s1).then_with(|| v1.sort_by_name(v2)));
+        }
+        paths.extend(scored_paths.into_iter().map(|(item, _)| item));
+        self.send_index(
+            path,
+            paths,
+            true,
+            None,
+            query_params,
+            head_only,
+            user,
+            access_paths,
+            res,
+        )
+    }
+
+    async fn handle_zip_dir(
+        &self,
+        path: &Path,
+        name: Option<&str>,
+        user: Option<&str>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        if !head_only && self.is_busy() {
+            self.refused_archives
+                .fetch_add(1, atomic::Ordering::Relaxed);
+            status_overloaded(res);
+            return Ok(());
+        }
+        let permit = match (&self.archive_jobs, head_only) {
+            (Some(archive_jobs), false) => match archive_jobs.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    self.refused_archives
+                        .fetch_add(1, atomic::Ordering::Relaxed);
+                    let message = "Too many archive downloads, try again later";
+                    status_error(res, StatusCode::TOO_MANY_REQUESTS, message);
+                    res.headers_mut()
+                        .insert(RETRY_AFTER, HeaderValue::from_static("5"));
+                    return Ok(());
+                }
+            },
+            _ => None,
+        };
+        let (mut writer, reader) = tokio::io::duplex(BUF_SIZE);
+        // `?name=` overrides the folder name, e.g. for the root which has none in the url
+        let filename = match name.and_then(|v| download_name(v, "zip")) {
+            Some(v) => v,
+            None => format!("{}.zip", try_get_file_name(path)?),
+        };
+        set_content_disposition(res, false, &filename)?;
         res.headers_mut()
             .insert("content-type", HeaderValue::from_static("application/zip"));
+        let zip_paths = walk_files(
+            path,
+            access_paths,
+            &self.args.hidden,
+            self.ignore_files(user),
+            self.running.clone(),
+        )
+        .await?;
+        // The size of the zip depends on compression, so clients get the size
+        // of its content to show progress
+        let (zip_paths, total) = tokio::task::spawn_blocking(move || {
+            let total: u64 = zip_paths
+                .iter()
+                .filter_map(|v| std::fs::metadata(v).ok())
+                .map(|v| v.len())
+                .sum();
+            (zip_paths, total)
+        })
+        .await?;
+        res.headers_mut()
+            .insert(ARCHIVE_TOTAL_BYTES_HEADER, HeaderValue::from(total));
         if head_only {
             return Ok(());
         }
+        let job = self.active_jobs.start("archive", self.href_of(path));
         let path = path.to_owned();
-        let hidden = self.args.hidden.clone();
-        let running = self.running.clone();
         let compression = self.args.compress.to_compression();
-        tokio::spawn(async move {
-            if let Err(e) = zip_dir(
-                &mut writer,
-                &path,
-                access_paths,
-                &hidden,
-                compression,
-                running,
-            )
-            .await
-            {
-                error!("Failed to zip {}, {}", path.display(), e);
-            }
+        let utc = self.args.utc;
+        let no_atime = self.args.no_atime_updates;
+        // Compression is cpu heavy, keep it off the threads handling requests
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            runtime.block_on(async move {
+                if let Err(e) =
+                    zip_dir(&mut writer, &path, zip_paths, compression, utc, no_atime).await
+                {
+                    error!("Failed to zip {}, {}", path.display(), e);
+                }
+                // Free the slot before the writer is dropped and the client sees the end
+                drop(permit);
+                drop(job);
+            })
         });
         let reader_stream = ReaderStream::with_capacity(reader, BUF_SIZE);
         let stream_body = StreamBody::new(
@@ -693,6 +2614,283 @@ impl Server {
         Ok(())
     }
 
+    async fn handle_feed_dir(
+        &self,
+        path: &Path,
+        user: Option<&str>,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let file_paths = walk_dir(
+            path,
+            access_paths,
+            &self.args.hidden,
+            self.ignore_files(user),
+            self.running.clone(),
+            false,
+            Some(FEED_MAX_WALK - 1),
+        )
+        .await?;
+        let mut paths = vec![];
+        for file_path in file_paths {
+            if let Ok(Some(item)) = self.to_pathitem(file_path, path.to_path_buf()).await {
+                paths.push(item);
+            }
+        }
+        paths.sort_by_key(|v| std::cmp::Reverse(v.mtime));
+        paths.truncate(FEED_MAX_ENTRIES);
+
+        let origin = match &self.args.public_url {
+            Some(url) => url.clone(),
+            None => {
+                // Only a plain `host[:port]`, anything else is left out of the links
+                let host = headers
+                    .get(HOST)
+                    .and_then(|v| v.to_str().ok())
+                    .filter(|v| !v.contains('@'))
+                    .filter(|v| v.parse::<hyper::http::uri::Authority>().is_ok());
+                match host {
+                    Some(host) => {
+                        let scheme = if self.args.tls_cert.is_some() {
+                            "https"
+                        } else {
+                            "http"
+                        };
+                        format!("{scheme}://{host}")
+                    }
+                    None => String::new(),
+                }
+            }
+        };
+        let href = format!(
+            "{}{}",
+            self.args.uri_prefix,
+            relative_to_root(&self.args.serve_path, path)?
+        );
+        let mut dir_url = format!("{origin}{}", encode_uri(&href));
+        if !dir_url.ends_with('/') {
+            dir_url.push('/');
+        }
+        let updated = paths.first().map(|v| v.mtime).unwrap_or_default();
+        let entries = paths
+            .iter()
+            .map(|v| v.to_atom_entry(&dir_url))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let output = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>Index of {}</title>
+<id>{}</id>
+<link href="{}"/>
+<updated>{}</updated>
+{entries}
+</feed>"#,
+            escape_str_pcdata(&href),
+            escape_str_pcdata(&dir_url),
+            escape_str_attribute(&dir_url),
+            format_rfc3339(updated),
+        );
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/atom+xml; charset=utf-8"),
+        );
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = body_full(output);
+        Ok(())
+    }
+
+    async fn handle_changes_dir(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        user: Option<&str>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(since) = query_params.get("since").and_then(|v| parse_since(v)) else {
+            status_bad_request(
+                res,
+                "Invalid since, expected milliseconds since the epoch or an RFC 3339 time",
+            );
+            return Ok(());
+        };
+        // Taken before the walk, so the next poll from here misses nothing
+        let until = to_timestamp(&SystemTime::now());
+        let file_paths = walk_files(
+            path,
+            access_paths.clone(),
+            &self.args.hidden,
+            self.ignore_files(user),
+            self.running.clone(),
+        )
+        .await?;
+        let mut changes = vec![];
+        for file_path in file_paths {
+            let Ok(meta) = fs::metadata(&file_path).await else {
+                continue;
+            };
+            let mtime = to_timestamp(&meta.modified()?);
+            if changed_time(&meta, mtime) < since {
+                continue;
+            }
+            let created = meta.created().is_ok_and(|v| to_timestamp(&v) >= since);
+            changes.push(Change {
+                name: normalize_path(file_path.strip_prefix(path)?),
+                change: match created {
+                    true => ChangeKind::Created,
+                    false => ChangeKind::Modified,
+                },
+                mtime: Some(mtime),
+                size: Some(meta.len()),
+            });
+        }
+        let (deleted, complete) = self.deleted_paths.since(path, since);
+        for (deleted_path, is_dir) in deleted {
+            let relative_path = deleted_path.strip_prefix(path)?;
+            let mut name = normalize_path(relative_path);
+            let visible = access_paths.find(&name, false).is_some()
+                && !relative_path
+                    .iter()
+                    .any(|v| is_hidden(&self.args.hidden, &v.to_string_lossy(), true));
+            if !visible || changes.iter().any(|v| v.name == name) {
+                continue;
+            }
+            if is_dir {
+                name.push('/');
+            }
+            changes.push(Change {
+                name,
+                change: ChangeKind::Deleted,
+                mtime: None,
+                size: None,
+            });
+        }
+        changes.sort_by(|v1, v2| v1.name.cmp(&v2.name));
+        let output = serde_json::to_string_pretty(&ChangesData {
+            since,
+            until,
+            complete,
+            changes,
+        })?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = body_full(output);
+        Ok(())
+    }
+
+    async fn handle_tree_dir(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        user: Option<&str>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let depth = match query_params.get("depth").map(|v| v.parse::<usize>()) {
+            None => TREE_DEFAULT_DEPTH,
+            Some(Ok(v)) if (1..=TREE_MAX_DEPTH).contains(&v) => v,
+            Some(_) => {
+                status_bad_request(
+                    res,
+                    &format!("Invalid depth: must be between 1 and {TREE_MAX_DEPTH}."),
+                );
+                return Ok(());
+            }
+        };
+        let mut ignore_files = self.ignore_files(user);
+        let dirs = self
+            .tree_dir(path.to_path_buf(), access_paths, depth, &mut ignore_files)
+            .await;
+        let output = serde_json::to_string(&TreeNode {
+            name: String::new(),
+            dirs: Some(dirs),
+        })?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = body_full(output);
+        Ok(())
+    }
+
+    /// Subdirectories of `dir` down to `depth` levels, deeper levels are left
+    /// for the client to fetch when expanded
+    fn tree_dir<'a>(
+        &'a self,
+        dir: PathBuf,
+        access_paths: AccessPaths,
+        depth: usize,
+        ignore_files: &'a mut IgnoreFiles,
+    ) -> BoxFuture<'a, Vec<TreeNode>> {
+        Box::pin(async move {
+            let names: Vec<String> = if access_paths.perm().indexonly() {
+                access_paths.child_names().into_iter().cloned().collect()
+            } else {
+                let mut names = vec![];
+                if let Ok(mut rd) = fs::read_dir(&dir).await {
+                    while let Ok(Some(entry)) = rd.next_entry().await {
+                        names.push(entry.file_name().to_string_lossy().to_string());
+                    }
+                }
+                names
+            };
+            let mut nodes = vec![];
+            for name in names {
+                let entry_path = dir.join(&name);
+                if !fs::metadata(&entry_path).await.is_ok_and(|v| v.is_dir())
+                    || is_hidden(&self.args.hidden, &name, true)
+                    || ignore_files.is_ignored(&entry_path, true)
+                {
+                    continue;
+                }
+                let is_symlink = fs::symlink_metadata(&entry_path)
+                    .await
+                    .is_ok_and(|v| v.is_symlink());
+                if !self.args.allow_symlink
+                    && is_symlink
+                    && !self.is_root_contained(&entry_path).await
+                {
+                    continue;
+                }
+                let dirs = match (depth > 1, access_paths.find(&name, false)) {
+                    (true, Some(access_paths)) => Some(
+                        self.tree_dir(entry_path, access_paths, depth - 1, ignore_files)
+                            .await,
+                    ),
+                    _ => None,
+                };
+                nodes.push(TreeNode { name, dirs });
+            }
+            nodes.sort_by(|v1, v2| {
+                alphanumeric_sort::compare_str(v1.name.to_lowercase(), v2.name.to_lowercase())
+            });
+            nodes
+        })
+    }
+
     async fn handle_render_index(
         &self,
         path: &Path,
@@ -713,14 +2911,101 @@ impl Server {
             self.handle_send_file(&index_path, headers, head_only, res)
                 .await?;
         } else if self.args.render_try_index {
-            self.handle_ls_dir(path, true, query_params, head_only, user, access_paths, res)
-                .await?;
+            self.handle_ls_dir(
+                path,
+                true,
+                query_params,
+                headers,
+                head_only,
+                user,
+                access_paths,
+                res,
+            )
+            .await?;
         } else {
             status_not_found(res)
         }
         Ok(())
     }
 
+    /// Sends the output of the `--preview` command for the file's extension.
+    /// It is made once per version of the file and kept in `--preview-cache`.
+    async fn handle_preview_file(
+        &self,
+        path: &Path,
+        rule: &PreviewRule,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(cache_dir) = &self.args.preview_cache else {
+            status_not_found(res);
+            return Ok(());
+        };
+        let meta = fs::metadata(path).await?;
+        let mut hasher = Sha256::new();
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update(format!(
+            "\0{}\0{}\0{}\0{}",
+            to_timestamp(&meta.modified()?),
+            meta.len(),
+            rule.program.display(),
+            rule.args.join(" ")
+        ));
+        let key = format!("{:x}", hasher.finalize());
+        let output = cache_dir.join(format!("{key}.{}", rule.output_ext));
+        if fs::metadata(&output).await.is_err() {
+            let _permit = self.preview_jobs.acquire().await?;
+            // Another request may have made it while this one waited
+            if fs::metadata(&output).await.is_err() {
+                run_preview(rule, path, &output).await?;
+            }
+        }
+        self.handle_send_file(&output, headers, head_only, res)
+            .await?;
+        let stem = path
+            .file_stem()
+            .and_then(|v| v.to_str())
+            .unwrap_or_default();
+        set_content_disposition(res, true, &format!("{stem}.{}", rule.output_ext))?;
+        Ok(())
+    }
+
+    /// Sends a small image standing for the file: the `--ffmpeg` poster of a
+    /// video, an image `--preview`, or the file itself if it is an image.
+    async fn handle_thumb_file(
+        &self,
+        path: &Path,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let rule = self
+            .args
+            .video_poster
+            .iter()
+            .chain(
+                self.args
+                    .previews
+                    .iter()
+                    .filter(|v| is_image_ext(&v.output_ext)),
+            )
+            .find(|v| v.matches(path));
+        if let Some(rule) = rule {
+            self.handle_preview_file(path, rule, headers, head_only, res)
+                .await?;
+        } else if path
+            .extension()
+            .and_then(|v| v.to_str())
+            .is_some_and(is_image_ext)
+        {
+            self.handle_send_file(path, headers, head_only, res).await?;
+        } else {
+            status_not_found(res);
+        }
+        Ok(())
+    }
+
     async fn handle_render_spa(
         &self,
         path: &Path,
@@ -747,7 +3032,7 @@ impl Server {
         if let Some(name) = req_path.strip_prefix(&self.assets_prefix) {
             match self.args.assets.as_ref() {
                 Some(assets_path) => {
-                    let path = assets_path.join(name);
+                    let path = join_root(assets_path, name);
                     if path.exists() {
                         self.handle_send_file(&path, headers, false, res).await?;
                     } else {
@@ -771,9 +3056,7 @@ impl Server {
                         );
                     }
                     "favicon.ico" => {
-                        *res.body_mut() = body_full(FAVICON_ICO);
-                        res.headers_mut()
-                            .insert("content-type", HeaderValue::from_static("image/x-icon"));
+                        self.send_favicon(false, res).await?;
                     }
                     _ => {
                         status_not_found(res);
@@ -794,32 +3077,306 @@ impl Server {
         }
     }
 
-    async fn handle_send_file(
+    async fn handle_well_known(
         &self,
-        path: &Path,
-        headers: &HeaderMap<HeaderValue>,
+        relative_path: &str,
         head_only: bool,
         res: &mut Response,
-    ) -> Result<()> {
-        let (file, meta) = tokio::join!(fs::File::open(path), fs::metadata(path),);
-        let (mut file, meta) = (file?, meta?);
-        let size = meta.len();
-        let mut use_range = true;
-        if let Some((etag, last_modified)) = extract_cache_headers(&meta) {
-            if let Some(if_unmodified_since) = headers.typed_get::<IfUnmodifiedSince>() {
-                if !if_unmodified_since.precondition_passes(last_modified.into()) {
-                    *res.status_mut() = StatusCode::PRECONDITION_FAILED;
-                    return Ok(());
-                }
+    ) -> Result<bool> {
+        if relative_path != ROBOTS_NAME && relative_path != FAVICON_NAME {
+            return Ok(false);
+        }
+        if !self.args.path_is_file
+            && fs::metadata(self.args.serve_path.join(relative_path))
+                .await
+                .is_ok()
+        {
+            return Ok(false);
+        }
+        if relative_path == ROBOTS_NAME {
+            let output = self.args.robots.to_content();
+            res.headers_mut()
+                .typed_insert(ContentType::from(mime_guess::mime::TEXT_PLAIN_UTF_8));
+            res.headers_mut()
+                .typed_insert(ContentLength(output.len() as u64));
+            if !head_only {
+                *res.body_mut() = body_full(output);
             }
-            if let Some(if_match) = headers.typed_get::<IfMatch>() {
-                if !if_match.precondition_passes(&etag) {
-                    *res.status_mut() = StatusCode::PRECONDITION_FAILED;
-                    return Ok(());
-                }
+        } else {
+            self.send_favicon(head_only, res).await?;
+        }
+        Ok(true)
+    }
+
+    async fn handle_internal(&self, name: &str, method: &Method, res: &mut Response) -> Result<()> {
+        if method != Method::GET {
+            *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return Ok(());
+        }
+        match name {
+            "df" => {
+                let serve_path = self.args.serve_path.clone();
+                let (total, used, available) =
+                    tokio::task::spawn_blocking(move || get_disk_usage(&serve_path)).await??;
+                let mounts = vec![DiskUsage {
+                    path: self.args.uri_prefix.clone(),
+                    total,
+                    used,
+                    available,
+                }];
+                res_json(res, &serde_json::json!({ "mounts": mounts }))?;
             }
-            if let Some(if_modified_since) = headers.typed_get::<IfModifiedSince>() {
-                if !if_modified_since.is_modified(last_modified.into()) {
+            "info" => {
+                let args = &self.args;
+                let info = serde_json::json!({
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "build": {
+                        "os": std::env::consts::OS,
+                        "arch": std::env::consts::ARCH,
+                        "debug": cfg!(debug_assertions),
+                        "tls": cfg!(feature = "tls"),
+                    },
+                    "features": {
+                        "tls": args.tls_cert.is_some() && args.tls_key.is_some(),
+                        "webdav": true,
+                        "auth": args.auth.schemes(),
+                        "cors": args.enable_cors,
+                    },
+                    "mounts": [{ "path": args.uri_prefix }],
+                    "permissions": {
+                        "allow_upload": args.allow_upload,
+                        "allow_delete": args.allow_delete,
+                        "allow_search": args.allow_search,
+                        "allow_symlink": args.allow_symlink,
+                        "allow_archive": args.allow_archive,
+                        "write_once": args.write_once,
+                    },
+                });
+                res_json(res, &info)?;
+            }
+            "metrics" => {
+                let max_archive_jobs = self.args.max_archive_jobs;
+                let archive_jobs = self
+                    .archive_jobs
+                    .as_ref()
+                    .map(|v| max_archive_jobs - v.available_permits());
+                let load = serde_json::json!({
+                    "requests": self.in_flight.load(atomic::Ordering::Relaxed),
+                    "max_requests": self.args.max_requests,
+                    "archive_jobs": archive_jobs,
+                    "max_archive_jobs": max_archive_jobs,
+                    "preview_jobs": PREVIEW_JOBS_MAX - self.preview_jobs.available_permits(),
+                    "max_preview_jobs": PREVIEW_JOBS_MAX,
+                    "refused_requests": self.refused_requests.load(atomic::Ordering::Relaxed),
+                    "refused_archives": self.refused_archives.load(atomic::Ordering::Relaxed),
+                });
+                let tls = self.tls_metrics.lock().unwrap().clone();
+                res_json(res, &serde_json::json!({ "load": load, "tls": tls }))?;
+            }
+            "openapi.json" => {
+                let output = OPENAPI_JSON
+                    .replace("__VERSION__", env!("CARGO_PKG_VERSION"))
+                    .replace("__URI_PREFIX__", &self.args.uri_prefix);
+                res.headers_mut()
+                    .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+                res.headers_mut()
+                    .typed_insert(ContentLength(output.len() as u64));
+                *res.body_mut() = body_full(output);
+            }
+            _ => status_not_found(res),
+        }
+        Ok(())
+    }
+
+    /// `/__dufs__/admin/<action>`, for requests with the `--admin-token` bearer token.
+    async fn handle_admin(
+        self: &Arc<Self>,
+        action: &str,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(admin_token) = &self.args.admin_token else {
+            status_not_found(res);
+            return Ok(());
+        };
+        let token = req.headers().get(AUTHORIZATION).and_then(get_bearer_token);
+        // Compares digests, so the time taken doesn't depend on a matching prefix
+        let authorized = token.is_some_and(|v| Sha256::digest(v) == Sha256::digest(admin_token));
+        if !authorized {
+            status_error(res, StatusCode::UNAUTHORIZED, "Invalid admin token");
+            return Ok(());
+        }
+        let method = req.method().clone();
+        match (action, method) {
+            ("status", Method::GET) => {}
+            ("readonly", Method::POST) => {
+                let Some(body) = read_json_body::<AdminReadonly>(req, res).await? else {
+                    return Ok(());
+                };
+                self.readonly
+                    .store(body.readonly, atomic::Ordering::Relaxed);
+                info!("Admin API set read-only mode to {}", body.readonly);
+            }
+            ("invalidate-caches", Method::POST) => {
+                let previews = match self.args.preview_cache.clone() {
+                    Some(dir) => {
+                        tokio::task::spawn_blocking(move || clear_preview_cache(&dir)).await??
+                    }
+                    None => 0,
+                };
+                let upload_ids = self.upload_ids.clear_finished();
+                info!("Admin API removed {previews} previews and {upload_ids} upload ids");
+                res_json(
+                    res,
+                    &serde_json::json!({ "previews": previews, "upload_ids": upload_ids }),
+                )?;
+                return Ok(());
+            }
+            ("drain", Method::POST) => {
+                if !self.draining.swap(true, atomic::Ordering::Relaxed) {
+                    info!("Admin API started draining");
+                    let server = self.clone();
+                    tokio::spawn(async move {
+                        // The drain request itself is done once its response is sent
+                        while server.in_flight.load(atomic::Ordering::Relaxed) > 0 {
+                            tokio::time::sleep(PIPE_POLL_INTERVAL).await;
+                        }
+                        server.drained.notify_one();
+                    });
+                }
+                *res.status_mut() = StatusCode::ACCEPTED;
+            }
+            ("status" | "readonly" | "invalidate-caches" | "drain", _) => {
+                *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                return Ok(());
+            }
+            _ => {
+                status_not_found(res);
+                return Ok(());
+            }
+        }
+        let status = serde_json::json!({
+            "readonly": self.readonly.load(atomic::Ordering::Relaxed),
+            "draining": self.draining.load(atomic::Ordering::Relaxed),
+            "requests": self.in_flight.load(atomic::Ordering::Relaxed),
+            "jobs": self.active_jobs.list(),
+        });
+        res_json(res, &status)
+    }
+
+    async fn send_favicon(&self, head_only: bool, res: &mut Response) -> Result<()> {
+        let content = match &self.args.favicon {
+            Some(path) => Cow::Owned(fs::read(path).await?),
+            None => Cow::Borrowed(FAVICON_ICO),
+        };
+        let content_type = match &self.args.favicon {
+            Some(path) => mime_guess::from_path(path)
+                .first()
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "image/x-icon".into()),
+            None => "image/x-icon".into(),
+        };
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+        res.headers_mut()
+            .typed_insert(ContentLength(content.len() as u64));
+        if !head_only {
+            *res.body_mut() = body_full(content.into_owned());
+        }
+        Ok(())
+    }
+
+    /// Streams the pipe file while stdin is still being read, following its
+    /// growth until the reader thread is done.
+    async fn handle_send_pipe(
+        &self,
+        path: &Path,
+        done: &Arc<AtomicBool>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let content_type = get_content_type(
+            path,
+            self.args.charset,
+            self.args.sniff_content,
+            self.args.no_atime_updates,
+        )
+        .await?;
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+        let filename = try_get_file_name(path)?;
+        set_content_disposition(res, true, filename)?;
+        if head_only {
+            return Ok(());
+        }
+        let mut file = fs::File::open(path).await?;
+        let done = done.clone();
+        let stream = async_stream::stream! {
+            let mut buf = vec![0; 65536];
+            let mut finished = false;
+            loop {
+                let n = file.read(&mut buf).await?;
+                if n > 0 {
+                    yield Ok(Bytes::copy_from_slice(&buf[..n]));
+                } else if finished {
+                    break;
+                } else if done.load(atomic::Ordering::SeqCst) {
+                    // Drain whatever was written before the flag was set
+                    finished = true;
+                } else {
+                    tokio::time::sleep(PIPE_POLL_INTERVAL).await;
+                }
+            }
+        };
+        let stream_body = StreamBody::new(stream.map_ok(Frame::data));
+        *res.body_mut() = stream_body.boxed();
+        Ok(())
+    }
+
+    async fn handle_send_file(
+        &self,
+        path: &Path,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let precompressed = match self.args.precompressed {
+            true => {
+                // Also on 304s, they stand in for the negotiated response
+                res.headers_mut()
+                    .insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+                find_precompressed(path, headers).await
+            }
+            false => None,
+        };
+        let file_path = match &precompressed {
+            Some((variant_path, _)) => variant_path.as_path(),
+            None => path,
+        };
+        let (file, meta) = tokio::join!(
+            open_read(file_path, self.args.no_atime_updates),
+            fs::metadata(file_path),
+        );
+        let (file, meta) = (file?, meta?);
+        let size = meta.len();
+        let validators = extract_cache_headers(&meta);
+        let use_range =
+            headers.typed_get::<Range>().is_some() && if_range_passes(headers, validators.as_ref());
+        if let Some((etag, last_modified)) = validators {
+            if let Some(if_unmodified_since) = headers.typed_get::<IfUnmodifiedSince>() {
+                if !if_unmodified_since.precondition_passes(last_modified.into()) {
+                    *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+                    return Ok(());
+                }
+            }
+            if let Some(if_match) = headers.typed_get::<IfMatch>() {
+                if !if_match.precondition_passes(&etag) {
+                    *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+                    return Ok(());
+                }
+            }
+            if let Some(if_modified_since) = headers.typed_get::<IfModifiedSince>() {
+                if !if_modified_since.is_modified(last_modified.into()) {
                     *res.status_mut() = StatusCode::NOT_MODIFIED;
                     return Ok(());
                 }
@@ -832,17 +3389,7 @@ impl Server {
             }
 
             res.headers_mut().typed_insert(last_modified);
-            res.headers_mut().typed_insert(etag.clone());
-
-            if headers.typed_get::<Range>().is_some() {
-                use_range = headers
-                    .typed_get::<IfRange>()
-                    .map(|if_range| !if_range.is_modified(Some(&etag), Some(&last_modified)))
-                    // Always be fresh if there is no validators
-                    .unwrap_or(true);
-            } else {
-                use_range = false;
-            }
+            res.headers_mut().typed_insert(etag);
         }
 
         let range = if use_range {
@@ -850,47 +3397,94 @@ impl Server {
                 range
                     .to_str()
                     .ok()
-                    .and_then(|range| parse_range(range, size))
+                    .and_then(|range| parse_ranges(range, size))
             })
         } else {
             None
         };
 
-        res.headers_mut().insert(
-            CONTENT_TYPE,
-            HeaderValue::from_str(&get_content_type(path).await?)?,
-        );
+        let content_type = get_content_type(
+            path,
+            self.args.charset,
+            self.args.sniff_content,
+            self.args.no_atime_updates,
+        )
+        .await?;
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
 
         let filename = try_get_file_name(path)?;
         set_content_disposition(res, true, filename)?;
 
         res.headers_mut().typed_insert(AcceptRanges::bytes());
+        if let Some((_, encoding)) = precompressed {
+            res.headers_mut()
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
 
         if let Some(range) = range {
-            if let Some((start, end)) = range {
-                file.seek(SeekFrom::Start(start)).await?;
-                let range_size = end - start + 1;
-                *res.status_mut() = StatusCode::PARTIAL_CONTENT;
-                let content_range = format!("bytes {}-{}/{}", start, end, size);
-                res.headers_mut()
-                    .insert(CONTENT_RANGE, content_range.parse()?);
-                res.headers_mut()
-                    .insert(CONTENT_LENGTH, format!("{range_size}").parse()?);
-                if head_only {
-                    return Ok(());
+            match range {
+                Some(ranges) if ranges.len() == 1 => {
+                    let (start, end) = ranges[0];
+                    let range_size = end - start + 1;
+                    *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                    let content_range = format!("bytes {}-{}/{}", start, end, size);
+                    res.headers_mut()
+                        .insert(CONTENT_RANGE, content_range.parse()?);
+                    res.headers_mut()
+                        .insert(CONTENT_LENGTH, format!("{range_size}").parse()?);
+                    if head_only {
+                        return Ok(());
+                    }
+
+                    *res.body_mut() = self.file_body(file, Some((start, range_size))).await;
                 }
+                Some(ranges) => {
+                    let boundary = Uuid::new_v4().simple().to_string();
+                    let mut parts = vec![];
+                    let mut content_length = 0;
+                    for (start, end) in ranges {
+                        let part_header = format!(
+                            "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {start}-{end}/{size}\r\n\r\n"
+                        );
+                        content_length += part_header.len() as u64 + end - start + 1;
+                        parts.push((part_header, start, end));
+                    }
+                    let tail = format!("\r\n--{boundary}--\r\n");
+                    content_length += tail.len() as u64;
+                    *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                    res.headers_mut().insert(
+                        CONTENT_TYPE,
+                        format!("multipart/byteranges; boundary={boundary}").parse()?,
+                    );
+                    res.headers_mut()
+                        .insert(CONTENT_LENGTH, format!("{content_length}").parse()?);
+                    if head_only {
+                        return Ok(());
+                    }
 
-                let stream_body = StreamBody::new(
-                    LengthLimitedStream::new(file, range_size as usize)
-                        .map_ok(Frame::data)
-                        .map_err(|err| anyhow!("{err}")),
-                );
-                let boxed_body = stream_body.boxed();
-                *res.body_mut() = boxed_body;
-            } else {
-                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
-                res.headers_mut()
-                    .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                    let file = Arc::new(file.into_std().await);
+                    let stream = async_stream::stream! {
+                        for (part_header, start, end) in parts {
+                            yield Ok(Bytes::from(part_header));
+                            let reader = file_stream(file.clone(), start, end - start + 1);
+                            pin_mut!(reader);
+                            while let Some(chunk) = futures_util::StreamExt::next(&mut reader).await {
+                                yield chunk;
+                            }
+                        }
+                        yield Ok(Bytes::from(tail));
+                    };
+                    let stream_body =
+                        StreamBody::new(stream.map_ok(Frame::data).map_err(|err| anyhow!("{err}")));
+                    let boxed_body = stream_body.boxed();
+                    *res.body_mut() = boxed_body;
+                }
+                None => {
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    res.headers_mut()
+                        .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                }
             }
         } else {
             res.headers_mut()
@@ -899,18 +3493,55 @@ impl Server {
                 return Ok(());
             }
 
-            let reader_stream = ReaderStream::with_capacity(file, BUF_SIZE);
-            let stream_body = StreamBody::new(
-                reader_stream
-                    .map_ok(Frame::data)
-                    .map_err(|err| anyhow!("{err}")),
-            );
-            let boxed_body = stream_body.boxed();
-            *res.body_mut() = boxed_body;
+            *res.body_mut() = self.file_body(file, None).await;
         }
         Ok(())
     }
 
+    /// Streams `file`, or `(offset, len)` of it.
+    async fn file_body(
+        &self,
+        file: fs::File,
+        range: Option<(u64, u64)>,
+    ) -> BoxBody<Bytes, anyhow::Error> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(uring) = &self.uring {
+            let (offset, len) = range.unwrap_or((0, u64::MAX));
+            let stream = uring.read(
+                file.into_std().await,
+                offset,
+                len,
+                crate::file_send::SEND_CHUNK_SIZE,
+            );
+            return StreamBody::new(stream.map_ok(Frame::data).map_err(|err| anyhow!("{err}")))
+                .boxed();
+        }
+        let (offset, len) = range.unwrap_or((0, u64::MAX));
+        let stream = file_stream(Arc::new(file.into_std().await), offset, len);
+        StreamBody::new(stream.map_ok(Frame::data).map_err(|err| anyhow!("{err}"))).boxed()
+    }
+
+    /// Copies an upload body into `file`, which is positioned at `offset`.
+    #[cfg_attr(
+        not(all(feature = "io-uring", target_os = "linux")),
+        allow(unused_variables)
+    )]
+    async fn copy_to_file<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        file: &mut fs::File,
+        offset: u64,
+    ) -> std::io::Result<u64> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(uring) = &self.uring {
+            // Writes buffered by `file` have to land before the ring writes after them
+            file.flush().await?;
+            let file = file.try_clone().await?.into_std().await;
+            return uring.copy(reader, file, offset, BUF_SIZE).await;
+        }
+        io::copy(reader, file).await
+    }
+
     async fn handle_edit_file(
         &self,
         path: &Path,
@@ -919,23 +3550,27 @@ impl Server {
         user: Option<String>,
         res: &mut Response,
     ) -> Result<()> {
-        let (file, meta) = tokio::join!(fs::File::open(path), fs::metadata(path),);
-        let (file, meta) = (file?, meta?);
-        let href = format!(
-            "/{}",
-            normalize_path(path.strip_prefix(&self.args.serve_path)?)
+        let (file, meta) = tokio::join!(
+            open_read(path, self.args.no_atime_updates),
+            fs::metadata(path),
         );
+        let (file, meta) = (file?, meta?);
+        let relative = relative_to_root(&self.args.serve_path, path)?;
+        let href = format!("/{relative}");
+        let breadcrumb = breadcrumb(&self.args.uri_prefix, &relative, false);
         let mut buffer: Vec<u8> = vec![];
         file.take(1024).read_to_end(&mut buffer).await?;
         let editable =
             meta.len() <= EDITABLE_TEXT_MAX_SIZE && content_inspector::inspect(&buffer).is_text();
         let data = EditData {
             href,
+            breadcrumb,
             kind,
             uri_prefix: self.args.uri_prefix.clone(),
             allow_upload: self.args.allow_upload,
             allow_delete: self.args.allow_delete,
             auth: self.args.auth.exist(),
+            login_page: self.args.login_page,
             user,
             editable,
         };
@@ -950,7 +3585,7 @@ impl Server {
             )
             .replace("__INDEX_DATA__", &index_data);
         res.headers_mut()
-            .typed_insert(ContentLength(output.as_bytes().len() as u64));
+            .typed_insert(ContentLength(output.len() as u64));
         if head_only {
             return Ok(());
         }
@@ -964,11 +3599,11 @@ impl Server {
         head_only: bool,
         res: &mut Response,
     ) -> Result<()> {
-        let output = sha256_file(path).await?;
+        let output = sha256_file(path, self.args.no_atime_updates).await?;
         res.headers_mut()
             .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
         res.headers_mut()
-            .typed_insert(ContentLength(output.as_bytes().len() as u64));
+            .typed_insert(ContentLength(output.len() as u64));
         if head_only {
             return Ok(());
         }
@@ -979,28 +3614,42 @@ impl Server {
     async fn handle_propfind_dir(
         &self,
         path: &Path,
+        user: Option<&str>,
         headers: &HeaderMap<HeaderValue>,
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        let depth: u32 = match headers.get("depth") {
+        // `None` is infinity
+        let depth: Option<u32> = match headers.get("depth") {
+            Some(v) if is_depth_infinity(v) => None,
             Some(v) => match v.to_str().ok().and_then(|v| v.parse().ok()) {
-                Some(0) => 0,
-                Some(1) => 1,
+                Some(0) => Some(0),
+                Some(1) => Some(1),
                 _ => {
-                    status_bad_request(res, "Invalid depth: only 0 and 1 are allowed.");
+                    status_bad_request(res, "Invalid depth: only 0, 1 and infinity are allowed.");
                     return Ok(());
                 }
             },
-            None => 1,
+            None => Some(1),
         };
         let mut paths = match self.to_pathitem(path, &self.args.serve_path).await? {
             Some(v) => vec![v],
             None => vec![],
         };
-        if depth == 1 {
+        if depth.is_none() {
+            match self
+                .walk_pathitems(path, &self.args.serve_path, user, access_paths)
+                .await?
+            {
+                Some(child) => paths.extend(child),
+                None => {
+                    status_finite_depth(res);
+                    return Ok(());
+                }
+            }
+        } else if depth == Some(1) {
             match self
-                .list_dir(path, &self.args.serve_path, access_paths)
+                .list_dir(path, &self.args.serve_path, user, access_paths)
                 .await
             {
                 Ok(child) => paths.extend(child),
@@ -1010,9 +3659,11 @@ impl Server {
                 }
             }
         }
+        let dead_props = self.load_dead_props(&paths).await?;
         let output = paths
             .iter()
-            .map(|v| v.to_dav_xml(self.args.uri_prefix.as_str()))
+            .zip(dead_props)
+            .map(|(v, props)| v.to_dav_xml(self.args.uri_prefix.as_str(), &props))
             .fold(String::new(), |mut acc, v| {
                 acc.push_str(&v);
                 acc
@@ -1023,57 +3674,209 @@ impl Server {
 
     async fn handle_propfind_file(&self, path: &Path, res: &mut Response) -> Result<()> {
         if let Some(pathitem) = self.to_pathitem(path, &self.args.serve_path).await? {
-            res_multistatus(res, &pathitem.to_dav_xml(self.args.uri_prefix.as_str()));
+            let props = self.load_dead_props(&[&pathitem]).await?.remove(0);
+            res_multistatus(
+                res,
+                &pathitem.to_dav_xml(self.args.uri_prefix.as_str(), &props),
+            );
         } else {
             status_not_found(res);
         }
         Ok(())
     }
 
+    /// Dead properties of each item, in the same order
+    async fn load_dead_props<T: std::borrow::Borrow<PathItem>>(
+        &self,
+        paths: &[T],
+    ) -> Result<Vec<Vec<DeadProp>>> {
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|v| join_root(&self.args.serve_path, &v.borrow().name))
+            .collect();
+        let props = tokio::task::spawn_blocking(move || {
+            paths
+                .iter()
+                .map(|v| dav_props::load(v).unwrap_or_default())
+                .collect()
+        })
+        .await?;
+        Ok(props)
+    }
+
     async fn handle_mkcol(&self, path: &Path, res: &mut Response) -> Result<()> {
         fs::create_dir_all(path).await?;
         *res.status_mut() = StatusCode::CREATED;
         Ok(())
     }
 
-    async fn handle_copy(&self, path: &Path, req: &Request, res: &mut Response) -> Result<()> {
-        let dest = match self.extract_dest(req, res) {
+    async fn handle_copy(
+        &self,
+        path: &Path,
+        user: Option<&str>,
+        identity: &Identity,
+        req: &Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let dest = match self.extract_dest(identity, req, res).await {
             Some(dest) => dest,
             None => {
                 return Ok(());
             }
         };
 
+        if self.check_access_files(&dest, user, true).await.is_some() {
+            status_forbid(res);
+            return Ok(());
+        }
+
         let meta = fs::symlink_metadata(path).await?;
         if meta.is_dir() {
             status_forbid(res);
             return Ok(());
         }
 
-        ensure_path_parent(&dest).await?;
+        self.copy_file(path, &dest, res).await
+    }
+
+    /// Copies the file `path` to `dest`, for COPY and batch copies.
+    async fn copy_file(&self, path: &Path, dest: &Path, res: &mut Response) -> Result<()> {
+        if self.args.write_once && fs::symlink_metadata(dest).await.is_ok() {
+            status_conflict(res);
+            return Ok(());
+        }
+
+        if let Some(reason) = self.check_copy_type(path, dest).await? {
+            status_unsupported_type(res, &reason);
+            return Ok(());
+        }
+
+        ensure_path_parent(dest).await?;
 
-        fs::copy(path, &dest).await?;
+        self.unshare(dest).await?;
+        fs::copy(path, dest).await?;
+        copy_dead_props(path, dest).await;
 
         status_no_content(res);
         Ok(())
     }
 
-    async fn handle_move(&self, path: &Path, req: &Request, res: &mut Response) -> Result<()> {
-        let dest = match self.extract_dest(req, res) {
+    async fn handle_move(
+        &self,
+        path: &Path,
+        user: Option<&str>,
+        identity: &Identity,
+        req: &Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let dest = match self.extract_dest(identity, req, res).await {
             Some(dest) => dest,
             None => {
                 return Ok(());
             }
         };
 
-        ensure_path_parent(&dest).await?;
+        if self.check_access_files(&dest, user, true).await.is_some() {
+            status_forbid(res);
+            return Ok(());
+        }
+
+        let is_dir = fs::symlink_metadata(path).await?.is_dir();
+        if is_dir
+            && self
+                .check_descendant_access_files(path, user)
+                .await
+                .is_some()
+        {
+            status_forbid(res);
+            return Ok(());
+        }
+
+        self.move_path(path, &dest, is_dir, res).await
+    }
+
+    /// Renames `path` to `dest`, for MOVE and batch moves.
+    async fn move_path(
+        &self,
+        path: &Path,
+        dest: &Path,
+        is_dir: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        if !is_dir {
+            if let Some(reason) = self.check_copy_type(path, dest).await? {
+                status_unsupported_type(res, &reason);
+                return Ok(());
+            }
+        }
+
+        ensure_path_parent(dest).await?;
 
-        fs::rename(path, &dest).await?;
+        fs::rename(path, dest).await?;
+        self.deleted_paths.record(path, is_dir);
 
         status_no_content(res);
         Ok(())
     }
 
+    /// Windows locks a new file before writing it, the lock creates it empty.
+    /// The path goes through the checks of a PUT, and nothing is created under
+    /// `--write-once` since the placeholder would make the real PUT fail.
+    async fn handle_lock_new(
+        &self,
+        relative_path: &str,
+        req_path: &str,
+        user: Option<&str>,
+        auth: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(path) = self.join_upload_path(relative_path) else {
+            status_forbid(res);
+            return Ok(());
+        };
+        if !self.args.allow_symlink && !self.is_root_contained(&path).await {
+            status_not_found(res);
+            return Ok(());
+        }
+        if self.check_access_files(&path, user, true).await.is_some() {
+            status_forbid(res);
+            return Ok(());
+        }
+        if let Some(reason) = self.check_upload_type(&path, None) {
+            status_unsupported_type(res, &reason);
+            return Ok(());
+        }
+        let has_parent = match path.parent() {
+            Some(parent) => fs::metadata(parent).await.is_ok_and(|v| v.is_dir()),
+            None => false,
+        };
+        if !has_parent {
+            status_error(res, StatusCode::CONFLICT, "Parent not found");
+            return Ok(());
+        }
+        let created = match self.args.write_once {
+            true => false,
+            false => {
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .await;
+                match file {
+                    Ok(_) => true,
+                    // Already uploaded, the lock applies to it
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => false,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        };
+        self.handle_lock(req_path, auth, res).await?;
+        if created {
+            *res.status_mut() = StatusCode::CREATED;
+        }
+        Ok(())
+    }
+
     async fn handle_lock(&self, req_path: &str, auth: bool, res: &mut Response) -> Result<()> {
         let token = if auth {
             format!("opaquelocktoken:{}", Uuid::new_v4())
@@ -1098,15 +3901,84 @@ impl Server {
         Ok(())
     }
 
-    async fn handle_proppatch(&self, req_path: &str, res: &mut Response) -> Result<()> {
+    async fn handle_proppatch(
+        &self,
+        path: &Path,
+        req_path: &str,
+        allow_upload: bool,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(body) = read_body(req, res).await? else {
+            return Ok(());
+        };
+        let updates = match dav_props::parse_proppatch(&body) {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, &format!("Invalid XML body, {err}"));
+                return Ok(());
+            }
+        };
+        // Updates are atomic, one rejected property fails all of them
+        let is_protected = |v: &PropUpdate| v.prop().ns == DAV_NS;
+        let statuses: Vec<StatusCode> = if !allow_upload {
+            vec![StatusCode::FORBIDDEN; updates.len()]
+        } else if updates.iter().any(is_protected) {
+            updates
+                .iter()
+                .map(|v| match is_protected(v) {
+                    true => StatusCode::FORBIDDEN,
+                    false => StatusCode::FAILED_DEPENDENCY,
+                })
+                .collect()
+        } else {
+            let ret = {
+                let (path, updates) = (path.to_path_buf(), updates.clone());
+                tokio::task::spawn_blocking(move || {
+                    let mut props = dav_props::load(&path)?;
+                    dav_props::apply(&mut props, &updates);
+                    dav_props::save(&path, &props).map_err(anyhow::Error::from)
+                })
+                .await?
+            };
+            let status = match ret {
+                Ok(_) => StatusCode::OK,
+                Err(err) => {
+                    warn!("Failed to store properties of `{}`, {err}", path.display());
+                    StatusCode::FORBIDDEN
+                }
+            };
+            vec![status; updates.len()]
+        };
+
+        let mut propstats = String::new();
+        let mut groups: Vec<(StatusCode, String)> = vec![];
+        for (update, status) in updates.iter().zip(statuses) {
+            let name = update.prop().to_name_xml();
+            match groups.iter_mut().find(|(v, _)| *v == status) {
+                Some((_, names)) => names.push_str(&name),
+                None => groups.push((status, name)),
+            }
+        }
+        if groups.is_empty() {
+            let status = match allow_upload {
+                true => StatusCode::OK,
+                false => StatusCode::FORBIDDEN,
+            };
+            groups.push((status, String::new()));
+        }
+        for (status, names) in groups {
+            propstats.push_str(&format!(
+                r#"
+<D:propstat>
+<D:prop>{names}</D:prop>
+<D:status>HTTP/1.1 {status}</D:status>
+</D:propstat>"#
+            ));
+        }
         let output = format!(
             r#"<D:response>
-<D:href>{req_path}</D:href>
-<D:propstat>
-<D:prop>
-</D:prop>
-<D:status>HTTP/1.1 403 Forbidden</D:status>
-</D:propstat>
+<D:href>{req_path}</D:href>{propstats}
 </D:response>"#
         );
         res_multistatus(res, &output);
@@ -1119,6 +3991,7 @@ impl Server {
         path: &Path,
         mut paths: Vec<PathItem>,
         exist: bool,
+        readme: Option<String>,
         query_params: &HashMap<String, String>,
         head_only: bool,
         user: Option<String>,
@@ -1140,47 +4013,76 @@ impl Server {
             {
                 paths.reverse()
             }
-        } else {
+        } else if !is_ranked_search(query_params) {
             paths.sort_by(|v1, v2| v1.sort_by_name(v2))
         }
         if has_query_flag(query_params, "simple") {
             let output = paths
                 .into_iter()
-                .map(|v| {
-                    if v.is_dir() {
-                        format!("{}/\n", v.name)
-                    } else {
-                        format!("{}\n", v.name)
-                    }
-                })
+                .map(|v| format!("{}\n", v.display_name()))
                 .collect::<Vec<String>>()
                 .join("");
             res.headers_mut()
-                .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
+                .typed_insert(ContentType::from(mime_guess::mime::TEXT_PLAIN_UTF_8));
             res.headers_mut()
-                .typed_insert(ContentLength(output.as_bytes().len() as u64));
-            *res.body_mut() = body_full(output);
+                .typed_insert(ContentLength(output.len() as u64));
             if head_only {
                 return Ok(());
             }
+            *res.body_mut() = body_full(output);
             return Ok(());
         }
-        let href = format!(
-            "/{}",
-            normalize_path(path.strip_prefix(&self.args.serve_path)?)
-        );
-        let readwrite = access_paths.perm().readwrite();
-        let data = IndexData {
-            kind: DataKind::Index,
-            href,
+        if has_query_flag(query_params, "csv") {
+            let mut output = String::from("name,size,mtime\n");
+            for item in paths {
+                output.push_str(&item.to_csv_row());
+            }
+            res.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/csv; charset=utf-8"),
+            );
+            res.headers_mut()
+                .typed_insert(ContentLength(output.len() as u64));
+            if head_only {
+                return Ok(());
+            }
+            *res.body_mut() = body_full(output);
+            return Ok(());
+        }
+        let relative = relative_to_root(&self.args.serve_path, path)?;
+        let href = format!("/{relative}");
+        let breadcrumb = breadcrumb(&self.args.uri_prefix, &relative, true);
+        let readwrite = access_paths.perm().readwrite();
+        let data = IndexData {
+            kind: DataKind::Index,
+            href,
+            breadcrumb,
             uri_prefix: self.args.uri_prefix.clone(),
             allow_upload: self.args.allow_upload && readwrite,
             allow_delete: self.args.allow_delete && readwrite,
             allow_search: self.args.allow_search,
             allow_archive: self.args.allow_archive,
+            separate_upload: self.args.upload_path.is_some(),
             dir_exists: exist,
             auth: self.args.auth.exist(),
+            login_page: self.args.login_page,
             user,
+            columns: self.args.show_columns.clone(),
+            utc: self.args.utc,
+            previews: self
+                .args
+                .previews
+                .iter()
+                .filter(|v| is_image_ext(&v.output_ext))
+                .flat_map(|v| v.exts.clone())
+                .collect(),
+            video_posters: self
+                .args
+                .video_poster
+                .iter()
+                .flat_map(|v| v.exts.clone())
+                .collect(),
+            readme,
             paths,
         };
         let output = if has_query_flag(query_params, "json") {
@@ -1200,7 +4102,7 @@ impl Server {
                 .replace("__INDEX_DATA__", &index_data)
         };
         res.headers_mut()
-            .typed_insert(ContentLength(output.as_bytes().len() as u64));
+            .typed_insert(ContentLength(output.len() as u64));
         res.headers_mut()
             .typed_insert(CacheControl::new().with_no_cache());
         res.headers_mut().insert(
@@ -1214,6 +4116,128 @@ impl Server {
         Ok(())
     }
 
+    /// The user of a valid login page session, API clients sending
+    /// `Authorization` are never authenticated by cookie
+    fn session_user(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        authorization: Option<&HeaderValue>,
+    ) -> Option<String> {
+        if !self.args.login_page || authorization.is_some() {
+            return None;
+        }
+        let value = get_cookie(headers, SESSION_COOKIE)?;
+        self.session_key.verify(value)
+    }
+
+    async fn handle_login(&self, req: Request, res: &mut Response) -> Result<()> {
+        let login_url = format!("{}{INTERNAL_PREFIX}login", self.args.uri_prefix);
+        let query_params: HashMap<String, String> =
+            form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+        match *req.method() {
+            Method::GET | Method::HEAD => {
+                let next = self.login_next(query_params.get("next"));
+                let error_class = match query_params.contains_key("error") {
+                    true => "",
+                    false => "hidden",
+                };
+                let output = LOGIN_HTML
+                    .replace(
+                        "__ASSETS_PREFIX__",
+                        &format!("{}{}", self.args.uri_prefix, self.assets_prefix),
+                    )
+                    .replace("__LOGIN_URL__", &escape_str_attribute(&login_url))
+                    .replace("__NEXT__", &escape_str_attribute(&next))
+                    .replace("__ERROR_CLASS__", error_class);
+                res.headers_mut()
+                    .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
+                res.headers_mut()
+                    .typed_insert(ContentLength(output.len() as u64));
+                res.headers_mut()
+                    .typed_insert(CacheControl::new().with_no_store());
+                if *req.method() == Method::GET {
+                    *res.body_mut() = body_full(output);
+                }
+            }
+            Method::POST => {
+                let Some(body) = read_body(req, res).await? else {
+                    return Ok(());
+                };
+                let form: HashMap<String, String> = form_urlencoded::parse(&body)
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                let next = self.login_next(form.get("next"));
+                let user = form.get("user").map(|v| v.as_str()).unwrap_or_default();
+                let pass = form.get("pass").map(|v| v.as_str()).unwrap_or_default();
+                if self.args.auth.check_password(user, pass) {
+                    let value = self.session_key.sign(user, self.args.session_ttl)?;
+                    self.set_session_cookie(res, &value, self.args.session_ttl)?;
+                    status_see_other(res, &next)?;
+                } else {
+                    warn!("Failed login of user `{user}`");
+                    let location = format!("{login_url}?error&next={}", encode_uri(&next));
+                    status_see_other(res, &location)?;
+                }
+            }
+            _ => *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED,
+        }
+        Ok(())
+    }
+
+    fn handle_logout(&self, headers: &HeaderMap<HeaderValue>, res: &mut Response) -> Result<()> {
+        if let Some(value) = get_cookie(headers, SESSION_COOKIE) {
+            self.session_key.revoke(value)?;
+        }
+        self.set_session_cookie(res, "", 0)?;
+        status_see_other(res, &self.args.uri_prefix)
+    }
+
+    /// Where to go after the login, only paths of this server are allowed.
+    /// Browsers read `/\` like `//`, so backslashes and control characters
+    /// are refused along with anything that has more than one leading slash.
+    fn login_next(&self, next: Option<&String>) -> String {
+        match next {
+            Some(v)
+                if v.starts_with(&self.args.uri_prefix)
+                    && !v.starts_with("//")
+                    && !v.chars().any(|c| c == '\\' || c.is_control()) =>
+            {
+                v.clone()
+            }
+            _ => self.args.uri_prefix.clone(),
+        }
+    }
+
+    fn set_guest_cookie(&self, res: &mut Response, token: &str) -> Result<()> {
+        let secure = match self.args.tls_cert.is_some() {
+            true => "; Secure",
+            false => "",
+        };
+        let cookie = format!(
+            "{GUEST_COOKIE}={token}; Path={}; HttpOnly; SameSite=Lax{secure}",
+            self.args.uri_prefix
+        );
+        res.headers_mut()
+            .append(SET_COOKIE, HeaderValue::from_str(&cookie)?);
+        Ok(())
+    }
+
+    fn set_session_cookie(&self, res: &mut Response, value: &str, max_age: u64) -> Result<()> {
+        let secure = match self.args.tls_cert.is_some() {
+            true => "; Secure",
+            false => "",
+        };
+        let cookie = format!(
+            "{SESSION_COOKIE}={value}; Path={}; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}",
+            self.args.uri_prefix
+        );
+        res.headers_mut()
+            .append(SET_COOKIE, HeaderValue::from_str(&cookie)?);
+        Ok(())
+    }
+
     fn auth_reject(&self, res: &mut Response) -> Result<()> {
         set_webdav_headers(res);
 
@@ -1222,15 +4246,110 @@ impl Server {
         Ok(())
     }
 
+    /// Applies the `.dufs-access` files of the directories from the root holding `path` down
+    /// to it, returning the status to reject the request with. The files themselves are
+    /// neither readable nor writable.
+    async fn check_access_files(
+        &self,
+        path: &Path,
+        user: Option<&str>,
+        is_write: bool,
+    ) -> Option<StatusCode> {
+        if get_file_name(path) == ACCESS_FILE_NAME {
+            return Some(StatusCode::FORBIDDEN);
+        }
+        let root = [Some(&self.args.serve_path), self.args.upload_path.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|root| path.starts_with(root));
+        let Some(root) = root else {
+            return Some(StatusCode::FORBIDDEN);
+        };
+        let relative_path = path.strip_prefix(root).ok()?;
+        let mut dir = root.clone();
+        let mut dirs = vec![dir.clone()];
+        for component in relative_path.components() {
+            dir.push(component);
+            dirs.push(dir.clone());
+        }
+        for dir in dirs {
+            let access_file_path = dir.join(ACCESS_FILE_NAME);
+            let Ok(content) = fs::read_to_string(&access_file_path).await else {
+                continue;
+            };
+            let status = match AccessFile::parse(&content) {
+                Ok(access_file) => access_file.check(user, is_write),
+                Err(err) => {
+                    warn!("Invalid {}, {err}", access_file_path.display());
+                    Some(StatusCode::FORBIDDEN)
+                }
+            };
+            if status.is_some() {
+                return status;
+            }
+        }
+        None
+    }
+
+    /// Applies the `.dufs-access` files below `dir` too, a recursive delete or move writes
+    /// to the whole subtree.
+    async fn check_descendant_access_files(
+        &self,
+        dir: &Path,
+        user: Option<&str>,
+    ) -> Option<StatusCode> {
+        let dir = dir.to_path_buf();
+        let user = user.map(|v| v.to_string());
+        tokio::task::spawn_blocking(move || {
+            let entries = WalkDir::new(&dir).min_depth(2).into_iter().flatten();
+            for entry in entries.filter(|v| v.file_name() == ACCESS_FILE_NAME) {
+                let status = match std::fs::read_to_string(entry.path()) {
+                    Ok(content) => AccessFile::parse(&content)
+                        .map(|v| v.check(user.as_deref(), true))
+                        .unwrap_or(Some(StatusCode::FORBIDDEN)),
+                    Err(_) => Some(StatusCode::FORBIDDEN),
+                };
+                if status.is_some() {
+                    return status;
+                }
+            }
+            None
+        })
+        .await
+        .unwrap_or(Some(StatusCode::FORBIDDEN))
+    }
+
+    fn ignore_files(&self, user: Option<&str>) -> IgnoreFiles {
+        let mut names = vec![DUFS_IGNORE_NAME];
+        if self.args.gitignore {
+            names.insert(0, GIT_IGNORE_NAME);
+        }
+        IgnoreFiles::new(&self.args.serve_path, names, user)
+    }
+
+    /// Checks that `path` stays inside the served roots once symlinks are
+    /// resolved. Missing trailing components are allowed, so targets of
+    /// uploads and moves can be checked before they are created.
     async fn is_root_contained(&self, path: &Path) -> bool {
-        fs::canonicalize(path)
+        canonicalize_lenient(path)
             .await
-            .ok()
-            .map(|v| v.starts_with(&self.args.serve_path))
+            .map(|v| {
+                v.starts_with(&self.args.serve_path)
+                    || self
+                        .args
+                        .upload_path
+                        .as_ref()
+                        .is_some_and(|upload_path| v.starts_with(upload_path))
+            })
             .unwrap_or_default()
     }
 
-    fn extract_dest(&self, req: &Request, res: &mut Response) -> Option<PathBuf> {
+    async fn extract_dest(
+        &self,
+        identity: &Identity,
+        req: &Request,
+        res: &mut Response,
+    ) -> Option<PathBuf> {
         let headers = req.headers();
         let dest_path = match self
             .extract_destination_header(headers)
@@ -1243,13 +4362,7 @@ impl Server {
             }
         };
 
-        let authorization = headers.get(AUTHORIZATION);
-        let guard = self
-            .args
-            .auth
-            .guard(&dest_path, req.method(), authorization, false);
-
-        match guard {
+        match self.guard_identity(identity, &dest_path, req.method()) {
             (_, Some(_)) => {}
             _ => {
                 status_forbid(res);
@@ -1257,14 +4370,19 @@ impl Server {
             }
         };
 
-        let dest = match self.join_path(&dest_path) {
+        let dest = match self.join_upload_path(&dest_path) {
             Some(dest) => dest,
             None => {
-                *res.status_mut() = StatusCode::BAD_REQUEST;
+                status_bad_request(res, "Invalid Destination");
                 return None;
             }
         };
 
+        if !self.args.allow_symlink && !self.is_root_contained(&dest).await {
+            status_forbid(res);
+            return None;
+        }
+
         Some(dest)
     }
 
@@ -1274,113 +4392,946 @@ impl Server {
         Some(uri.path().to_string())
     }
 
-    fn resolve_path(&self, path: &str) -> Option<String> {
-        let path = decode_uri(path)?;
-        let path = path.trim_matches('/');
-        let mut parts = vec![];
-        for comp in Path::new(path).components() {
-            if let Component::Normal(v) = comp {
-                let v = v.to_string_lossy();
-                if cfg!(windows) {
-                    let chars: Vec<char> = v.chars().collect();
-                    if chars.len() == 2 && chars[1] == ':' && chars[0].is_ascii_alphabetic() {
-                        return None;
-                    }
-                }
-                parts.push(v);
-            } else {
-                return None;
-            }
+    fn has_upload_types(&self) -> bool {
+        !self.args.allow_upload_types.is_empty() || !self.args.deny_upload_types.is_empty()
+    }
+
+    /// Checks the name of an uploaded file, and the start of its content if given,
+    /// against `--allow-upload-types` and `--deny-upload-types`.
+    /// Returns why the upload is rejected.
+    fn check_upload_type(&self, path: &Path, head: Option<&[u8]>) -> Option<String> {
+        if !self.has_upload_types() {
+            return None;
+        }
+        let ext = path
+            .extension()
+            .map(|v| v.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let allow = &self.args.allow_upload_types;
+        if self.args.deny_upload_types.contains(&ext)
+            || (!allow.is_empty() && !allow.contains(&ext))
+        {
+            return Some(match ext.is_empty() {
+                true => "Files without an extension are not allowed".into(),
+                false => format!("Files of type `{ext}` are not allowed"),
+            });
+        }
+        let head = head?;
+        let matched = match sniff_upload_type(head) {
+            Some(exts) => exts.contains(&ext.as_str()),
+            None => !UPLOAD_SIGNATURES
+                .iter()
+                .any(|(_, _, exts, required)| *required && exts.contains(&ext.as_str())),
+        };
+        match matched {
+            true => None,
+            false => Some(format!("File content doesn't match type `{ext}`")),
+        }
+    }
+
+    /// Checks the destination of a copy or move like an upload of the source file.
+    async fn check_copy_type(&self, from: &Path, to: &Path) -> Result<Option<String>> {
+        if !self.has_upload_types() {
+            return Ok(None);
+        }
+        let mut head = vec![];
+        fs::File::open(from)
+            .await?
+            .take(UPLOAD_SNIFF_SIZE)
+            .read_to_end(&mut head)
+            .await?;
+        Ok(self.check_upload_type(to, Some(&head)))
+    }
+
+    fn normalize_unicode<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        match self.args.unicode_normalize {
+            Some(form) => Cow::Owned(form.normalize(value)),
+            None => Cow::Borrowed(value),
+        }
+    }
+
+    fn resolve_path(&self, path: &str) -> Option<String> {
+        let path = decode_uri(path)?;
+        let path = self.normalize_unicode(&path);
+        let path = path.trim_matches('/');
+        let mut parts = vec![];
+        for comp in Path::new(path).components() {
+            if let Component::Normal(v) = comp {
+                let v = v.to_string_lossy();
+                if cfg!(windows) && is_windows_reserved_name(&v) {
+                    return None;
+                }
+                parts.push(v);
+            } else {
+                return None;
+            }
+        }
+        let new_path = parts.join("/");
+        let path_prefix = self.args.path_prefix.as_str();
+        if path_prefix.is_empty() {
+            return Some(new_path);
+        }
+        new_path
+            .strip_prefix(path_prefix.trim_start_matches('/'))
+            .map(|v| v.trim_matches('/').to_string())
+    }
+
+    fn join_path(&self, path: &str) -> Option<PathBuf> {
+        Some(join_root(&self.args.serve_path, path))
+    }
+
+    /// Like `join_path`, but for the paths uploads are written to.
+    fn join_upload_path(&self, path: &str) -> Option<PathBuf> {
+        match &self.args.upload_path {
+            Some(upload_path) => Some(join_root(upload_path, path)),
+            None => self.join_path(path),
+        }
+    }
+
+    async fn list_dir(
+        &self,
+        entry_path: &Path,
+        base_path: &Path,
+        user: Option<&str>,
+        access_paths: AccessPaths,
+    ) -> Result<Vec<PathItem>> {
+        let mut paths: Vec<PathItem> = vec![];
+        let mut ignore_files = self.ignore_files(user);
+        if access_paths.perm().indexonly() {
+            for name in access_paths.child_names() {
+                let entry_path = entry_path.join(name);
+                self.add_pathitem(&mut paths, &mut ignore_files, base_path, &entry_path)
+                    .await;
+            }
+        } else {
+            let mut rd = fs::read_dir(entry_path).await?;
+            while let Ok(Some(entry)) = rd.next_entry().await {
+                let entry_path = entry.path();
+                self.add_pathitem(&mut paths, &mut ignore_files, base_path, &entry_path)
+                    .await;
+            }
+        }
+        Ok(paths)
+    }
+
+    async fn add_pathitem(
+        &self,
+        paths: &mut Vec<PathItem>,
+        ignore_files: &mut IgnoreFiles,
+        base_path: &Path,
+        entry_path: &Path,
+    ) {
+        let base_name = get_file_name(entry_path);
+        if let Ok(Some(item)) = self.to_pathitem(entry_path, base_path).await {
+            if is_hidden(&self.args.hidden, base_name, item.is_dir())
+                || ignore_files.is_ignored(entry_path, item.is_dir())
+            {
+                return;
+            }
+            paths.push(item);
+        }
+    }
+
+    async fn to_pathitem<P: AsRef<Path>>(&self, path: P, base_path: P) -> Result<Option<PathItem>> {
+        let path = path.as_ref();
+        let (meta, meta2) = tokio::join!(fs::metadata(&path), fs::symlink_metadata(&path));
+        let (meta, meta2) = (meta?, meta2?);
+        let is_symlink = meta2.is_symlink();
+        if !self.args.allow_symlink && is_symlink && !self.is_root_contained(path).await {
+            return Ok(None);
+        }
+        let is_dir = meta.is_dir();
+        let path_type = match (is_symlink, is_dir) {
+            (true, true) => PathType::SymlinkDir,
+            (false, true) => PathType::Dir,
+            (true, false) => PathType::SymlinkFile,
+            (false, false) => PathType::File,
+        };
+        let mtime = to_timestamp(&meta.modified()?);
+        let size = match path_type {
+            PathType::Dir | PathType::SymlinkDir => {
+                let mut count = 0;
+                let mut entries = tokio::fs::read_dir(&path).await?;
+                while entries.next_entry().await?.is_some() {
+                    count += 1;
+                }
+                count
+            }
+            PathType::File | PathType::SymlinkFile => meta.len(),
+        };
+        let etag = match path_type {
+            PathType::File | PathType::SymlinkFile => Some(format!(r#""{mtime}-{size}""#)),
+            _ => None,
+        };
+        let name = relative_to_root(base_path.as_ref(), path)?;
+        let columns = &self.args.show_columns;
+        let mime = match path_type {
+            PathType::File | PathType::SymlinkFile if columns.contains(&ListColumn::Type) => Some(
+                mime_guess::from_path(path)
+                    .first_or_octet_stream()
+                    .essence_str()
+                    .to_string(),
+            ),
+            _ => None,
+        };
+        #[cfg(unix)]
+        let (owner, group, mode) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                columns
+                    .contains(&ListColumn::Owner)
+                    .then(|| user_name(meta2.uid())),
+                columns
+                    .contains(&ListColumn::Group)
+                    .then(|| group_name(meta2.gid())),
+                columns
+                    .contains(&ListColumn::Mode)
+                    .then(|| format_mode(meta2.mode())),
+            )
+        };
+        #[cfg(not(unix))]
+        let (owner, group, mode) = (None, None, None);
+        Ok(Some(PathItem {
+            path_type,
+            name,
+            mtime,
+            modified: format_time(mtime, self.args.utc),
+            size,
+            etag,
+            mime,
+            owner,
+            group,
+            mode,
+        }))
+    }
+}
+
+#[cfg(test)]
+impl Server {
+    /// Serves `args` on an ephemeral local port inside the current runtime,
+    /// returning the bound address and a handle that stops the server.
+    pub async fn spawn_for_tests(args: Args) -> Result<(SocketAddr, TestShutdown)> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = Arc::new(Server::init(args, Arc::new(AtomicBool::new(true)))?);
+        let (tx, mut rx) = tokio::sync::oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    ret = listener.accept() => {
+                        let Ok((stream, addr)) = ret else {
+                            continue;
+                        };
+                        let stream = hyper_util::rt::TokioIo::new(stream);
+                        tokio::spawn(crate::handle_stream(server.clone(), stream, Some(addr), None, false));
+                    }
+                    _ = &mut rx => break,
+                }
+            }
+        });
+        Ok((addr, TestShutdown { tx, handle }))
+    }
+}
+
+#[cfg(test)]
+pub struct TestShutdown {
+    tx: tokio::sync::oneshot::Sender<()>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(test)]
+impl TestShutdown {
+    /// Stops accepting connections and waits for the accept loop to end.
+    pub async fn shutdown(self) {
+        let _ = self.tx.send(());
+        let _ = self.handle.await;
+    }
+}
+
+/// Tracks uploads by the client supplied `X-Upload-Id` so that retried PUTs
+/// replay the final state instead of producing duplicate or partial files.
+#[derive(Debug, Default)]
+struct UploadIds {
+    states: Mutex<IndexMap<String, (PathBuf, UploadState)>>,
+}
+
+#[derive(Debug)]
+enum UploadState {
+    Running,
+    Failed,
+    Done(ETag),
+}
+
+impl UploadIds {
+    fn is_done(&self, id: &str, path: &Path, etag: &ETag) -> bool {
+        let states = self.states.lock().unwrap();
+        matches!(states.get(id), Some((p, UploadState::Done(v))) if p == path && *v == *etag)
+    }
+
+    fn is_failed(&self, id: &str, path: &Path) -> bool {
+        let states = self.states.lock().unwrap();
+        matches!(states.get(id), Some((p, UploadState::Failed)) if p == path)
+    }
+
+    /// Returns false if an upload with the same id is still running.
+    fn start(&self, id: &str, path: &Path) -> bool {
+        let mut states = self.states.lock().unwrap();
+        if let Some((_, UploadState::Running)) = states.get(id) {
+            return false;
+        }
+        states.shift_remove(id);
+        if states.len() >= UPLOAD_IDS_MAX {
+            states.shift_remove_index(0);
+        }
+        states.insert(id.to_string(), (path.to_path_buf(), UploadState::Running));
+        true
+    }
+
+    /// Forgets finished uploads, returns how many were forgotten.
+    fn clear_finished(&self) -> usize {
+        let mut states = self.states.lock().unwrap();
+        let len = states.len();
+        states.retain(|_, (_, state)| matches!(state, UploadState::Running));
+        len - states.len()
+    }
+
+    fn finish(&self, id: &str, path: &Path, etag: Option<ETag>) {
+        let state = match etag {
+            Some(etag) => UploadState::Done(etag),
+            None => UploadState::Failed,
+        };
+        let mut states = self.states.lock().unwrap();
+        states.insert(id.to_string(), (path.to_path_buf(), state));
+    }
+}
+
+/// Uploads and zip downloads in progress, listed by the admin API.
+#[derive(Debug, Default)]
+struct ActiveJobs {
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, ActiveJob>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ActiveJob {
+    id: u64,
+    kind: &'static str,
+    path: String,
+    started: u64,
+}
+
+impl ActiveJobs {
+    /// The job is listed until the returned guard is dropped.
+    fn start(self: &Arc<Self>, kind: &'static str, path: String) -> ActiveJobGuard {
+        let id = self.next_id.fetch_add(1, atomic::Ordering::Relaxed);
+        let started = unix_now().map(|v| v.as_secs()).unwrap_or_default();
+        let job = ActiveJob {
+            id,
+            kind,
+            path,
+            started,
+        };
+        self.jobs.lock().unwrap().insert(id, job);
+        ActiveJobGuard {
+            jobs: self.clone(),
+            id,
+        }
+    }
+
+    fn list(&self) -> Vec<ActiveJob> {
+        self.jobs.lock().unwrap().values().cloned().collect()
+    }
+}
+
+struct ActiveJobGuard {
+    jobs: Arc<ActiveJobs>,
+    id: u64,
+}
+
+impl Drop for ActiveJobGuard {
+    fn drop(&mut self) {
+        self.jobs.jobs.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AdminReadonly {
+    readonly: bool,
+}
+
+/// Removes the files in `--preview-cache`, returns how many were removed.
+fn clear_preview_cache(dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && std::fs::remove_file(entry.path()).is_ok() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Chunked uploads in progress. Chunks are written straight into a hidden
+/// staging file next to the target, which is renamed over it at the end.
+#[derive(Debug, Default)]
+struct ChunkedUploads {
+    uploads: Mutex<IndexMap<String, ChunkedUpload>>,
+    /// `--upload-journal`, rewritten after every change
+    journal: Option<PathBuf>,
+    /// Counts the changes to `uploads`
+    changes: AtomicU64,
+    /// The last change in the journal, held while it is written
+    saved: tokio::sync::Mutex<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChunkedUpload {
+    path: PathBuf,
+    staging_path: PathBuf,
+    size: u64,
+    chunk_size: u64,
+    #[serde(
+        serialize_with = "serialize_received",
+        deserialize_with = "deserialize_received"
+    )]
+    received: Vec<bool>,
+}
+
+impl ChunkedUploads {
+    /// Picks up the uploads in the journal whose staging files are still there
+    fn load(journal: Option<PathBuf>) -> Self {
+        let mut uploads = IndexMap::new();
+        if let Some(path) = &journal {
+            match std::fs::read(path) {
+                Ok(data) => {
+                    match serde_json::from_slice::<IndexMap<String, ChunkedUpload>>(&data) {
+                        Ok(saved) => {
+                            uploads = saved
+                                .into_iter()
+                                .filter(|(_, v)| v.is_resumable())
+                                .collect();
+                            info!(
+                                "Resuming {} chunked uploads from the journal",
+                                uploads.len()
+                            );
+                        }
+                        Err(err) => {
+                            warn!("Ignoring upload journal `{}`, {err}", path.display());
+                        }
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => warn!("Failed to read upload journal `{}`, {err}", path.display()),
+            }
+        }
+        Self {
+            uploads: Mutex::new(uploads),
+            journal,
+            ..Default::default()
+        }
+    }
+
+    /// Writes the journal on the blocking pool once the change is made. Changes
+    /// made while a write is running are saved together by the next one.
+    async fn save(&self) {
+        let Some(path) = &self.journal else {
+            return;
+        };
+        let change = self.changes.load(atomic::Ordering::SeqCst);
+        let mut saved = self.saved.lock().await;
+        if *saved >= change {
+            return;
+        }
+        match self.write(path).await {
+            Ok(change) => *saved = change,
+            Err(err) => warn!("Failed to write upload journal `{}`, {err}", path.display()),
+        }
+    }
+
+    /// Returns the change that was written
+    async fn write(&self, path: &Path) -> Result<u64> {
+        let (change, data) = {
+            let uploads = self.uploads.lock().unwrap();
+            let change = self.changes.load(atomic::Ordering::SeqCst);
+            (change, serde_json::to_vec(&*uploads)?)
+        };
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || write_journal(&path, &data)).await??;
+        Ok(change)
+    }
+
+    /// Returns the oldest upload if it had to make room
+    async fn start(&self, id: &str, upload: ChunkedUpload) -> Option<ChunkedUpload> {
+        let evicted = {
+            let mut uploads = self.uploads.lock().unwrap();
+            let evicted = match uploads.len() >= CHUNKED_UPLOADS_MAX {
+                true => uploads.shift_remove_index(0).map(|(_, v)| v),
+                false => None,
+            };
+            uploads.insert(id.to_string(), upload);
+            self.changes.fetch_add(1, atomic::Ordering::SeqCst);
+            evicted
+        };
+        self.save().await;
+        evicted
+    }
+
+    /// The staging file, offset and length of a chunk
+    fn chunk(&self, id: &str, path: &Path, index: u64) -> Option<(PathBuf, u64, u64)> {
+        let uploads = self.uploads.lock().unwrap();
+        let upload = uploads.get(id).filter(|v| v.path == path)?;
+        if index >= upload.received.len() as u64 {
+            return None;
+        }
+        let offset = index * upload.chunk_size;
+        let len = upload.chunk_size.min(upload.size - offset);
+        Some((upload.staging_path.clone(), offset, len))
+    }
+
+    async fn received(&self, id: &str, index: u64) {
+        {
+            let mut uploads = self.uploads.lock().unwrap();
+            let Some(v) = uploads
+                .get_mut(id)
+                .and_then(|v| v.received.get_mut(index as usize))
+            else {
+                return;
+            };
+            *v = true;
+            self.changes.fetch_add(1, atomic::Ordering::SeqCst);
+        }
+        self.save().await;
+    }
+
+    /// Takes a complete upload, or fails with the missing chunks
+    async fn finish(&self, id: &str, path: &Path) -> Result<ChunkedUpload, Option<Vec<usize>>> {
+        let upload = {
+            let mut uploads = self.uploads.lock().unwrap();
+            let upload = uploads.get(id).filter(|v| v.path == path).ok_or(None)?;
+            let missing: Vec<usize> = upload
+                .received
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| !**v)
+                .map(|(i, _)| i)
+                .collect();
+            if !missing.is_empty() {
+                return Err(Some(missing));
+            }
+            self.changes.fetch_add(1, atomic::Ordering::SeqCst);
+            uploads.shift_remove(id).unwrap()
+        };
+        self.save().await;
+        Ok(upload)
+    }
+
+    async fn abort(&self, id: &str, path: &Path) -> Option<ChunkedUpload> {
+        let upload = {
+            let mut uploads = self.uploads.lock().unwrap();
+            uploads.get(id).filter(|v| v.path == path)?;
+            self.changes.fetch_add(1, atomic::Ordering::SeqCst);
+            uploads.shift_remove(id)
+        };
+        self.save().await;
+        upload
+    }
+}
+
+impl ChunkedUpload {
+    /// The staging file still has the size it was created with
+    fn is_resumable(&self) -> bool {
+        self.chunk_size > 0
+            && self.received.len() as u64 == self.size.div_ceil(self.chunk_size)
+            && std::fs::metadata(&self.staging_path).is_ok_and(|v| v.len() == self.size)
+    }
+}
+
+/// Replaces the journal through a temporary file, so a crash leaves either version.
+fn write_journal(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    std::fs::write(&tmp_path, data)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Received chunks as a string like `0110`, compact even for many chunks
+fn serialize_received<S: serde::Serializer>(
+    received: &[bool],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let value: String = received
+        .iter()
+        .map(|v| if *v { '1' } else { '0' })
+        .collect();
+    serializer.serialize_str(&value)
+}
+
+fn deserialize_received<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<bool>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value
+        .chars()
+        .map(|v| match v {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(serde::de::Error::custom("expected 0 or 1")),
+        })
+        .collect()
+}
+
+/// Counts a request in flight until dropped, along with its response body.
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// Returns the guard and the count including this request
+    fn enter(counter: &Arc<AtomicUsize>) -> (Self, usize) {
+        let count = counter.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+        (Self(counter.clone()), count)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, atomic::Ordering::Relaxed);
+    }
+}
+
+/// Marks requests that arrived on a `--bind-readonly` listener.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadonlyListener;
+
+/// Negotiated parameters of a TLS connection, attached to each of its requests.
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    /// e.g. `TLSv1.3`
+    pub version: String,
+    pub cipher: String,
+    pub alpn: Option<String>,
+}
+
+/// Handshakes since startup, counted by negotiated parameter
+#[derive(Debug, Default, Clone, Serialize)]
+struct TlsMetrics {
+    versions: BTreeMap<String, u64>,
+    ciphers: BTreeMap<String, u64>,
+    alpn: BTreeMap<String, u64>,
+}
+
+/// Paths deleted or moved away through dufs, for `?changes`. Deletions made
+/// by other programs can't be seen without watching the filesystem.
+#[derive(Debug)]
+struct DeletedPaths {
+    state: Mutex<DeletedPathsState>,
+}
+
+#[derive(Debug)]
+struct DeletedPathsState {
+    entries: VecDeque<(u64, PathBuf, bool)>,
+    /// Deletions after this time are all known
+    complete_since: u64,
+}
+
+impl Default for DeletedPaths {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(DeletedPathsState {
+                entries: VecDeque::new(),
+                complete_since: to_timestamp(&SystemTime::now()),
+            }),
+        }
+    }
+}
+
+impl DeletedPaths {
+    fn record(&self, path: &Path, is_dir: bool) {
+        let mut state = self.state.lock().unwrap();
+        let now = to_timestamp(&SystemTime::now());
+        state.entries.push_back((now, path.to_path_buf(), is_dir));
+        if state.entries.len() > DELETED_PATHS_MAX {
+            if let Some((time, _, _)) = state.entries.pop_front() {
+                state.complete_since = time;
+            }
+        }
+    }
+
+    /// Paths deleted below `dir` since the time, and whether the list is complete
+    fn since(&self, dir: &Path, since: u64) -> (Vec<(PathBuf, bool)>, bool) {
+        let state = self.state.lock().unwrap();
+        let mut paths: Vec<(PathBuf, bool)> = vec![];
+        for (time, path, is_dir) in state.entries.iter() {
+            if *time >= since && path.starts_with(dir) && path != dir {
+                paths.retain(|(v, _)| v != path);
+                paths.push((path.clone(), *is_dir));
+            }
+        }
+        (paths, since >= state.complete_since)
+    }
+}
+
+/// What a client knows about the file it is about to upload, sent in the
+/// `X-File-Size`, `X-File-Mtime` (ms since the epoch) and `X-File-Sha256`
+/// headers of a PUT, or the same fields of a manifest entry.
+#[derive(Debug, Default, Deserialize)]
+struct FileInfo {
+    size: Option<u64>,
+    mtime: Option<u64>,
+    sha256: Option<String>,
+}
+
+impl FileInfo {
+    fn from_headers(headers: &HeaderMap<HeaderValue>) -> Result<Self> {
+        let get = |name: &str| -> Result<Option<&str>> {
+            match headers.get(name) {
+                Some(v) => Ok(Some(
+                    v.to_str()
+                        .map_err(|_| anyhow!("Invalid {name} header"))?
+                        .trim(),
+                )),
+                None => Ok(None),
+            }
+        };
+        let parse_u64 = |name: &str| -> Result<Option<u64>> {
+            get(name)?
+                .map(|v| v.parse().map_err(|_| anyhow!("Invalid {name} header")))
+                .transpose()
+        };
+        let sha256 = match get(FILE_SHA256_HEADER)? {
+            Some(v) if v.len() == 64 && v.chars().all(|c| c.is_ascii_hexdigit()) => {
+                Some(v.to_ascii_lowercase())
+            }
+            Some(_) => return Err(anyhow!("Invalid {FILE_SHA256_HEADER} header")),
+            None => None,
+        };
+        Ok(Self {
+            size: parse_u64(FILE_SIZE_HEADER)?,
+            mtime: parse_u64(FILE_MTIME_HEADER)?,
+            sha256,
+        })
+    }
+
+    /// Whether the existing file is the same, judged by every field given.
+    /// Mtimes are compared to the second, many filesystems store no more.
+    async fn matches(&self, path: &Path, meta: Option<&Metadata>, no_atime: bool) -> Result<bool> {
+        let Some(meta) = meta.filter(|v| v.is_file()) else {
+            return Ok(false);
+        };
+        if self.size.is_none() && self.mtime.is_none() && self.sha256.is_none() {
+            return Ok(false);
+        }
+        if self.size.is_some_and(|v| v != meta.len()) {
+            return Ok(false);
+        }
+        if let Some(mtime) = self.mtime {
+            if to_timestamp(&meta.modified()?) / 1000 != mtime / 1000 {
+                return Ok(false);
+            }
+        }
+        if let Some(sha256) = &self.sha256 {
+            if &sha256_file(path, no_atime).await? != sha256 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+struct PartialUploadGuard {
+    path: PathBuf,
+    armed: bool,
+    resumable: bool,
+}
+
+impl PartialUploadGuard {
+    fn new(path: &Path, armed: bool) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            armed,
+            resumable: true,
+        }
+    }
+
+    /// Removes the file whatever its size, resuming only targets the final path
+    fn temporary(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            armed: true,
+            resumable: false,
+        }
+    }
+
+    fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for PartialUploadGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
         }
-        let new_path = parts.join("/");
-        let path_prefix = self.args.path_prefix.as_str();
-        if path_prefix.is_empty() {
-            return Some(new_path);
+        let size = std::fs::metadata(&self.path)
+            .map(|v| v.len())
+            .unwrap_or_default();
+        if !self.resumable || size < RESUMABLE_UPLOAD_MIN_SIZE {
+            let _ = std::fs::remove_file(&self.path);
         }
-        new_path
-            .strip_prefix(path_prefix.trim_start_matches('/'))
-            .map(|v| v.trim_matches('/').to_string())
     }
+}
 
-    fn join_path(&self, path: &str) -> Option<PathBuf> {
-        if path.is_empty() {
-            return Some(self.args.serve_path.clone());
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SearchMode {
+    Contains,
+    Prefix,
+    Exact,
+    Fuzzy,
+}
+
+impl SearchMode {
+    fn parse(value: Option<&String>) -> Option<Self> {
+        match value.map(|v| v.as_str()) {
+            None | Some("") | Some("contains") => Some(Self::Contains),
+            Some("prefix") => Some(Self::Prefix),
+            Some("exact") => Some(Self::Exact),
+            Some("fuzzy") => Some(Self::Fuzzy),
+            Some(_) => None,
         }
-        let path = if cfg!(windows) {
-            path.replace('/', "\\")
-        } else {
-            path.to_string()
+    }
+
+    fn score(&self, name: &str, search: &str) -> Option<u32> {
+        let matched = match self {
+            Self::Contains => name.contains(search),
+            Self::Prefix => name.starts_with(search),
+            Self::Exact => name == search,
+            Self::Fuzzy => return fuzzy_score(name, search),
         };
-        Some(self.args.serve_path.join(path))
+        matched.then_some(0)
     }
+}
 
-    async fn list_dir(
-        &self,
-        entry_path: &Path,
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct GuestTokenRequest {
+    perm: GuestPerm,
+    expires_in: Option<u64>,
+    revoke: Option<String>,
+}
+
+#[derive(Debug)]
+struct SearchOptions {
+    search: String,
+    base_path: PathBuf,
+    mode: SearchMode,
+    limit: Option<usize>,
+    max_depth: Option<usize>,
+    dirs_only: bool,
+    files_only: bool,
+    deadline: Option<Instant>,
+}
+
+impl SearchOptions {
+    fn new(
         base_path: &Path,
-        access_paths: AccessPaths,
-    ) -> Result<Vec<PathItem>> {
-        let mut paths: Vec<PathItem> = vec![];
-        if access_paths.perm().indexonly() {
-            for name in access_paths.child_names() {
-                let entry_path = entry_path.join(name);
-                self.add_pathitem(&mut paths, base_path, &entry_path).await;
-            }
-        } else {
-            let mut rd = fs::read_dir(entry_path).await?;
-            while let Ok(Some(entry)) = rd.next_entry().await {
-                let entry_path = entry.path();
-                self.add_pathitem(&mut paths, base_path, &entry_path).await;
-            }
+        query_params: &HashMap<String, String>,
+        args: &Args,
+    ) -> std::result::Result<Self, &'static str> {
+        let search = query_params
+            .get("q")
+            .map(|v| v.to_lowercase())
+            .unwrap_or_default();
+        let mode = SearchMode::parse(query_params.get("mode")).ok_or("Invalid search mode")?;
+        let parse_positive = |name: &str, err: &'static str| match query_params
+            .get(name)
+            .map(|v| v.parse::<usize>())
+        {
+            None => Ok(None),
+            Some(Ok(v)) if v > 0 => Ok(Some(v)),
+            Some(_) => Err(err),
+        };
+        let limit = parse_positive("limit", "Invalid limit")?;
+        let limit = match (limit, args.search_max_results.filter(|v| *v > 0)) {
+            (Some(v), Some(max)) => Some(v.min(max)),
+            (v, max) => v.or(max),
+        };
+        let max_depth = parse_positive("max-depth", "Invalid max-depth")?;
+        let dirs_only = has_query_flag(query_params, "dirs-only");
+        let files_only = has_query_flag(query_params, "files-only");
+        if dirs_only && files_only {
+            return Err("Cannot combine dirs-only and files-only");
         }
-        Ok(paths)
+        let deadline = match args.search_timeout {
+            0 => None,
+            v => Some(Instant::now() + Duration::from_secs(v)),
+        };
+        Ok(Self {
+            search,
+            base_path: base_path.to_path_buf(),
+            mode,
+            limit,
+            max_depth,
+            dirs_only,
+            files_only,
+            deadline,
+        })
     }
+}
 
-    async fn add_pathitem(&self, paths: &mut Vec<PathItem>, base_path: &Path, entry_path: &Path) {
-        let base_name = get_file_name(entry_path);
-        if let Ok(Some(item)) = self.to_pathitem(entry_path, base_path).await {
-            if is_hidden(&self.args.hidden, base_name, item.is_dir()) {
-                return;
-            }
-            paths.push(item);
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BatchOp {
+    Move { from: String, to: String },
+    Copy { from: String, to: String },
+    Delete { path: String },
+}
+
+impl BatchOp {
+    fn name(&self) -> &str {
+        match self {
+            BatchOp::Move { from, .. } | BatchOp::Copy { from, .. } => from,
+            BatchOp::Delete { path } => path,
         }
     }
+}
 
-    async fn to_pathitem<P: AsRef<Path>>(&self, path: P, base_path: P) -> Result<Option<PathItem>> {
-        let path = path.as_ref();
-        let (meta, meta2) = tokio::join!(fs::metadata(&path), fs::symlink_metadata(&path));
-        let (meta, meta2) = (meta?, meta2?);
-        let is_symlink = meta2.is_symlink();
-        if !self.args.allow_symlink && is_symlink && !self.is_root_contained(path).await {
-            return Ok(None);
+#[derive(Debug, Deserialize)]
+struct ManifestEntry {
+    path: String,
+    #[serde(flatten)]
+    info: FileInfo,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestResult {
+    path: String,
+    state: ManifestState,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ManifestState {
+    Missing,
+    Changed,
+    Unchanged,
+    Forbidden,
+    Invalid,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchResult {
+    name: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl BatchResult {
+    fn new(name: &str, status: StatusCode, error: Option<anyhow::Error>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: status.as_u16(),
+            error: error.map(|v| v.to_string()),
         }
-        let is_dir = meta.is_dir();
-        let path_type = match (is_symlink, is_dir) {
-            (true, true) => PathType::SymlinkDir,
-            (false, true) => PathType::Dir,
-            (true, false) => PathType::SymlinkFile,
-            (false, false) => PathType::File,
-        };
-        let mtime = to_timestamp(&meta.modified()?);
-        let size = match path_type {
-            PathType::Dir | PathType::SymlinkDir => {
-                let mut count = 0;
-                let mut entries = tokio::fs::read_dir(&path).await?;
-                while entries.next_entry().await?.is_some() {
-                    count += 1;
-                }
-                count
-            }
-            PathType::File | PathType::SymlinkFile => meta.len(),
-        };
-        let rel_path = path.strip_prefix(base_path)?;
-        let name = normalize_path(rel_path);
-        Ok(Some(PathItem {
-            path_type,
-            name,
-            mtime,
-            size,
-        }))
     }
 }
 
@@ -1394,36 +5345,108 @@ enum DataKind {
 #[derive(Debug, Serialize)]
 struct IndexData {
     href: String,
+    /// Links to the root and each ancestor, ending with this directory
+    breadcrumb: Vec<Crumb>,
     kind: DataKind,
     uri_prefix: String,
     allow_upload: bool,
     allow_delete: bool,
     allow_search: bool,
     allow_archive: bool,
+    separate_upload: bool,
     dir_exists: bool,
     auth: bool,
+    login_page: bool,
     user: Option<String>,
+    columns: Vec<ListColumn>,
+    utc: bool,
+    /// Extensions with an image `?preview`, shown as thumbnails
+    previews: Vec<String>,
+    /// Extensions with a `--ffmpeg` poster as `?thumb`
+    video_posters: Vec<String>,
+    /// The `--readme` of the directory as HTML
+    #[serde(skip_serializing_if = "Option::is_none")]
+    readme: Option<String>,
     paths: Vec<PathItem>,
 }
 
 #[derive(Debug, Serialize)]
 struct EditData {
     href: String,
+    breadcrumb: Vec<Crumb>,
     kind: DataKind,
     uri_prefix: String,
     allow_upload: bool,
     allow_delete: bool,
     auth: bool,
+    login_page: bool,
     user: Option<String>,
     editable: bool,
 }
 
+#[derive(Debug, Serialize)]
+struct ChangesData {
+    since: u64,
+    /// Pass as `since` to get the next changes
+    until: u64,
+    /// False if deletions before the server started may be missing
+    complete: bool,
+    changes: Vec<Change>,
+}
+
+#[derive(Debug, Serialize)]
+struct Change {
+    name: String,
+    change: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, Serialize)]
+struct TreeNode {
+    name: String,
+    /// Missing below the requested depth
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dirs: Option<Vec<TreeNode>>,
+}
+
+#[derive(Debug, Serialize)]
+struct DiskUsage {
+    path: String,
+    total: u64,
+    used: u64,
+    available: u64,
+}
+
 #[derive(Debug, Serialize, Eq, PartialEq, Ord, PartialOrd)]
 struct PathItem {
     path_type: PathType,
     name: String,
     mtime: u64,
+    /// `mtime` as an RFC 3339 string, in UTC with `--utc`
+    modified: String,
     size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    /// Extra columns of `--show-columns`
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    mime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
 }
 
 impl PathItem {
@@ -1431,7 +5454,7 @@ impl PathItem {
         self.path_type == PathType::Dir || self.path_type == PathType::SymlinkDir
     }
 
-    pub fn to_dav_xml(&self, prefix: &str) -> String {
+    pub fn to_dav_xml(&self, prefix: &str, dead_props: &[DeadProp]) -> String {
         let mtime = match Utc.timestamp_millis_opt(self.mtime as i64) {
             LocalResult::Single(v) => format!("{}", v.format("%a, %d %b %Y %H:%M:%S GMT")),
             _ => String::new(),
@@ -1441,6 +5464,7 @@ impl PathItem {
             href.push('/');
         }
         let displayname = escape_str_pcdata(self.base_name());
+        let dead_props: String = dead_props.iter().map(|v| v.to_xml()).collect();
         match self.path_type {
             PathType::Dir | PathType::SymlinkDir => format!(
                 r#"<D:response>
@@ -1449,7 +5473,7 @@ impl PathItem {
 <D:prop>
 <D:displayname>{displayname}</D:displayname>
 <D:getlastmodified>{mtime}</D:getlastmodified>
-<D:resourcetype><D:collection/></D:resourcetype>
+<D:resourcetype><D:collection/></D:resourcetype>{dead_props}
 </D:prop>
 <D:status>HTTP/1.1 200 OK</D:status>
 </D:propstat>
@@ -1463,18 +5487,53 @@ impl PathItem {
 <D:displayname>{displayname}</D:displayname>
 <D:getcontentlength>{}</D:getcontentlength>
 <D:getlastmodified>{mtime}</D:getlastmodified>
-<D:resourcetype></D:resourcetype>
+<D:resourcetype></D:resourcetype>{}
 </D:prop>
 <D:status>HTTP/1.1 200 OK</D:status>
 </D:propstat>
 </D:response>"#,
-                self.size
+                self.size, dead_props
             ),
         }
     }
 
+    pub fn to_csv_row(&self) -> String {
+        format!(
+            "{},{},{}\n",
+            escape_csv_field(&self.display_name()),
+            self.size,
+            format_rfc3339(self.mtime)
+        )
+    }
+
+    pub fn to_atom_entry(&self, dir_url: &str) -> String {
+        let url = format!("{dir_url}{}", encode_uri(&self.name));
+        format!(
+            r#"<entry>
+<title>{}</title>
+<id>{}</id>
+<link href="{}"/>
+<updated>{}</updated>
+<summary>{} bytes</summary>
+</entry>"#,
+            escape_str_pcdata(&self.name),
+            escape_str_pcdata(&url),
+            escape_str_attribute(&url),
+            format_rfc3339(self.mtime),
+            self.size,
+        )
+    }
+
+    pub fn display_name(&self) -> String {
+        if self.is_dir() {
+            format!("{}/", self.name)
+        } else {
+            self.name.clone()
+        }
+    }
+
     pub fn base_name(&self) -> &str {
-        self.name.split('/').last().unwrap_or_default()
+        self.name.split('/').next_back().unwrap_or_default()
     }
 
     pub fn sort_by_name(&self, other: &Self) -> Ordering {
@@ -1527,6 +5586,69 @@ impl PartialOrd for PathType {
     }
 }
 
+/// Copies stdin into the pipe file on a background thread, returning a flag
+/// that is set once stdin is exhausted.
+fn spawn_pipe_reader(path: &Path) -> Result<Arc<AtomicBool>> {
+    let mut file = std::fs::OpenOptions::new().append(true).open(path)?;
+    let done = Arc::new(AtomicBool::new(false));
+    let done_cloned = done.clone();
+    std::thread::spawn(move || {
+        if let Err(err) = std::io::copy(&mut std::io::stdin().lock(), &mut file) {
+            error!("Failed to read stdin, {err}");
+        }
+        done_cloned.store(true, atomic::Ordering::SeqCst);
+    });
+    Ok(done)
+}
+
+fn format_rfc3339(mtime: u64) -> String {
+    match Utc.timestamp_millis_opt(mtime as i64) {
+        LocalResult::Single(v) => v.to_rfc3339_opts(SecondsFormat::Secs, true),
+        _ => String::new(),
+    }
+}
+
+fn format_time(mtime: u64, utc: bool) -> String {
+    if utc {
+        return format_rfc3339(mtime);
+    }
+    match Local.timestamp_millis_opt(mtime as i64) {
+        LocalResult::Single(v) => v.to_rfc3339_opts(SecondsFormat::Secs, true),
+        _ => String::new(),
+    }
+}
+
+fn escape_csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Renames and permission changes only touch the ctime on unix
+fn changed_time(meta: &Metadata, mtime: u64) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let ctime = (meta.ctime() * 1000 + meta.ctime_nsec() / 1_000_000).max(0) as u64;
+        mtime.max(ctime)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        mtime
+    }
+}
+
+fn parse_since(value: &str) -> Option<u64> {
+    if let Ok(v) = value.parse::<u64>() {
+        return Some(v);
+    }
+    let datetime = chrono::DateTime::parse_from_rfc3339(value).ok()?;
+    u64::try_from(datetime.timestamp_millis()).ok()
+}
+
 fn to_timestamp(time: &SystemTime) -> u64 {
     time.duration_since(SystemTime::UNIX_EPOCH)
         .unwrap_or_default()
@@ -1548,62 +5670,225 @@ async fn ensure_path_parent(path: &Path) -> Result<()> {
             fs::create_dir_all(&parent).await?;
         }
     }
-    Ok(())
-}
-
-fn add_cors(res: &mut Response) {
-    res.headers_mut()
-        .typed_insert(AccessControlAllowOrigin::ANY);
-    res.headers_mut()
-        .typed_insert(AccessControlAllowCredentials);
-    res.headers_mut().insert(
-        "Access-Control-Allow-Methods",
-        HeaderValue::from_static("*"),
-    );
-    res.headers_mut().insert(
-        "Access-Control-Allow-Headers",
-        HeaderValue::from_static("Authorization,*"),
-    );
-    res.headers_mut().insert(
-        "Access-Control-Expose-Headers",
-        HeaderValue::from_static("Authorization,*"),
-    );
+    Ok(())
+}
+
+fn add_cors(res: &mut Response) {
+    res.headers_mut()
+        .typed_insert(AccessControlAllowOrigin::ANY);
+    res.headers_mut()
+        .typed_insert(AccessControlAllowCredentials);
+    res.headers_mut().insert(
+        "Access-Control-Allow-Methods",
+        HeaderValue::from_static("*"),
+    );
+    res.headers_mut().insert(
+        "Access-Control-Allow-Headers",
+        HeaderValue::from_static("Authorization,*"),
+    );
+    res.headers_mut().insert(
+        "Access-Control-Expose-Headers",
+        HeaderValue::from_static("Authorization,*"),
+    );
+}
+
+fn is_depth_infinity(value: &HeaderValue) -> bool {
+    value
+        .to_str()
+        .is_ok_and(|v| v.eq_ignore_ascii_case("infinity"))
+}
+
+/// Tells WebDAV clients to list one level at a time instead, see RFC 4918 9.1
+fn status_finite_depth(res: &mut Response) {
+    *res.status_mut() = StatusCode::FORBIDDEN;
+    res.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("application/xml; charset=utf-8"),
+    );
+    *res.body_mut() = body_full(
+        r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:error xmlns:D="DAV:"><D:propfind-finite-depth/></D:error>"#,
+    );
+}
+
+fn res_xml(res: &mut Response, content: String) {
+    res.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("application/xml; charset=utf-8"),
+    );
+    *res.body_mut() = body_full(content);
+}
+
+fn res_s3_error(res: &mut Response, status: StatusCode, code: &str, message: &str) {
+    *res.status_mut() = status;
+    res_xml(res, s3::error_xml(code, message));
+}
+
+fn res_multistatus(res: &mut Response, content: &str) {
+    *res.status_mut() = StatusCode::MULTI_STATUS;
+    res.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("application/xml; charset=utf-8"),
+    );
+    *res.body_mut() = body_full(format!(
+        r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:multistatus xmlns:D="DAV:">
+{content}
+</D:multistatus>"#,
+    ));
+}
+
+async fn zip_dir<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    dir: &Path,
+    zip_paths: Vec<PathBuf>,
+    compression: Compression,
+    utc: bool,
+    no_atime: bool,
+) -> Result<()> {
+    let mut writer = ZipFileWriter::with_tokio(writer);
+    for zip_path in zip_paths.into_iter() {
+        let filename = match zip_path.strip_prefix(dir).ok().map(normalize_path) {
+            Some(v) if !v.is_empty() => v,
+            _ => continue,
+        };
+        let (datetime, mode) = get_file_mtime_and_mode(&zip_path).await?;
+        // Zip times have no timezone, extractors read them as local time
+        let datetime = match utc {
+            true => datetime,
+            false => datetime.with_timezone(&Local).naive_local().and_utc(),
+        };
+        let builder = ZipEntryBuilder::new(filename.into(), compression)
+            .unix_permissions(mode)
+            .last_modification_date(ZipDateTime::from_chrono(&datetime));
+        let mut file = open_read(&zip_path, no_atime).await?;
+        let mut file_writer = writer.write_entry_stream(builder).await?.compat_write();
+        io::copy(&mut file, &mut file_writer).await?;
+        file_writer.into_inner().close().await?;
+    }
+    writer.close().await?;
+    Ok(())
+}
+
+/// Walks `dirs` for entries matching the search options, passing them with their score to
+/// `on_match` until it returns false or the deadline is reached.
+fn search_walk(
+    dirs: &[PathBuf],
+    options: &SearchOptions,
+    hidden: &[String],
+    ignore_files: &mut IgnoreFiles,
+    running: &AtomicBool,
+    mut on_match: impl FnMut(PathBuf, u32) -> bool,
+) {
+    for dir in dirs {
+        let mut walker = WalkDir::new(dir);
+        if let Some(max_depth) = options.max_depth {
+            let offset = dir
+                .strip_prefix(&options.base_path)
+                .map(|v| v.components().count())
+                .unwrap_or_default();
+            if max_depth <= offset {
+                continue;
+            }
+            walker = walker.max_depth(max_depth - offset);
+        }
+        let mut it = walker.into_iter();
+        it.next();
+        while let Some(Ok(entry)) = it.next() {
+            if !running.load(atomic::Ordering::SeqCst)
+                || options.deadline.is_some_and(|v| Instant::now() >= v)
+            {
+                return;
+            }
+            let entry_path = entry.path();
+            let base_name = get_file_name(entry_path);
+            let file_type = entry.file_type();
+            let mut is_dir_type: bool = file_type.is_dir();
+            if file_type.is_symlink() {
+                match std::fs::symlink_metadata(entry_path) {
+                    Ok(meta) => {
+                        is_dir_type = meta.is_dir();
+                    }
+                    Err(_) => {
+                        continue;
+                    }
+                }
+            }
+            if is_hidden(hidden, base_name, is_dir_type)
+                || ignore_files.is_ignored(entry_path, is_dir_type)
+            {
+                if file_type.is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+            if options.dirs_only || options.files_only {
+                let is_dir = match file_type.is_symlink() {
+                    true => entry_path.is_dir(),
+                    false => file_type.is_dir(),
+                };
+                if (options.dirs_only && !is_dir) || (options.files_only && is_dir) {
+                    continue;
+                }
+            }
+            let score = match options
+                .mode
+                .score(&base_name.to_lowercase(), &options.search)
+            {
+                Some(v) => v,
+                None => continue,
+            };
+            if !on_match(entry_path.to_path_buf(), score) {
+                return;
+            }
+        }
+    }
 }
 
-fn res_multistatus(res: &mut Response, content: &str) {
-    *res.status_mut() = StatusCode::MULTI_STATUS;
-    res.headers_mut().insert(
-        "content-type",
-        HeaderValue::from_static("application/xml; charset=utf-8"),
-    );
-    *res.body_mut() = body_full(format!(
-        r#"<?xml version="1.0" encoding="utf-8" ?>
-<D:multistatus xmlns:D="DAV:">
-{content}
-</D:multistatus>"#,
-    ));
+async fn walk_files(
+    dir: &Path,
+    access_paths: AccessPaths,
+    hidden: &[String],
+    ignore_files: IgnoreFiles,
+    running: Arc<AtomicBool>,
+) -> Result<Vec<PathBuf>> {
+    walk_dir(
+        dir,
+        access_paths,
+        hidden,
+        ignore_files,
+        running,
+        false,
+        None,
+    )
+    .await
 }
 
-async fn zip_dir<W: AsyncWrite + Unpin>(
-    writer: &mut W,
+/// Like `walk_files`, optionally with the directories too. Stops after one
+/// path more than `limit`, so callers can tell the listing is incomplete.
+async fn walk_dir(
     dir: &Path,
     access_paths: AccessPaths,
     hidden: &[String],
-    compression: Compression,
+    mut ignore_files: IgnoreFiles,
     running: Arc<AtomicBool>,
-) -> Result<()> {
-    let mut writer = ZipFileWriter::with_tokio(writer);
+    with_dirs: bool,
+    limit: Option<usize>,
+) -> Result<Vec<PathBuf>> {
     let hidden = Arc::new(hidden.to_vec());
     let dir_clone = dir.to_path_buf();
-    let zip_paths = tokio::task::spawn_blocking(move || {
+    let paths = tokio::task::spawn_blocking(move || {
         let mut paths: Vec<PathBuf> = vec![];
-        for dir in access_paths.child_paths(&dir_clone) {
+        'outer: for dir in access_paths.child_paths(&dir_clone) {
             let mut it = WalkDir::new(&dir).into_iter();
             it.next();
             while let Some(Ok(entry)) = it.next() {
                 if !running.load(atomic::Ordering::SeqCst) {
                     break;
                 }
+                if limit.is_some_and(|v| paths.len() > v) {
+                    break 'outer;
+                }
                 let entry_path = entry.path();
                 let base_name = get_file_name(entry_path);
                 let file_type = entry.file_type();
@@ -1618,7 +5903,9 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
                         }
                     }
                 }
-                if is_hidden(&hidden, base_name, is_dir_type) {
+                if is_hidden(&hidden, base_name, is_dir_type)
+                    || ignore_files.is_ignored(entry_path, is_dir_type)
+                {
                     if file_type.is_dir() {
                         it.skip_current_dir();
                     }
@@ -1627,7 +5914,7 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
                 if entry.path().symlink_metadata().is_err() {
                     continue;
                 }
-                if !file_type.is_file() {
+                if !(file_type.is_file() || with_dirs && file_type.is_dir()) {
                     continue;
                 }
                 paths.push(entry_path.to_path_buf());
@@ -1636,41 +5923,401 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
         paths
     })
     .await?;
-    for zip_path in zip_paths.into_iter() {
-        let filename = match zip_path.strip_prefix(dir).ok().and_then(|v| v.to_str()) {
-            Some(v) => v,
-            None => continue,
-        };
-        let (datetime, mode) = get_file_mtime_and_mode(&zip_path).await?;
-        let builder = ZipEntryBuilder::new(filename.into(), compression)
-            .unix_permissions(mode)
-            .last_modification_date(ZipDateTime::from_chrono(&datetime));
-        let mut file = File::open(&zip_path).await?;
-        let mut file_writer = writer.write_entry_stream(builder).await?.compat_write();
-        io::copy(&mut file, &mut file_writer).await?;
-        file_writer.into_inner().close().await?;
+    Ok(paths)
+}
+
+/// Entries below `dir` for an S3 listing: all files, or with `shallow` only
+/// the files and directories directly in it.
+async fn walk_s3_dir(
+    dir: &Path,
+    access_paths: AccessPaths,
+    hidden: &[String],
+    mut ignore_files: IgnoreFiles,
+    running: Arc<AtomicBool>,
+    shallow: bool,
+) -> Result<Vec<(PathBuf, Metadata)>> {
+    let hidden = hidden.to_vec();
+    let dir = dir.to_path_buf();
+    let entries = tokio::task::spawn_blocking(move || {
+        let mut entries = vec![];
+        for root in access_paths.child_paths(&dir) {
+            // Paths allowed below an index-only directory are listed as themselves
+            let is_dir = root == dir;
+            let max_depth = match (shallow, is_dir) {
+                (true, true) => 1,
+                (true, false) => 0,
+                (false, _) => usize::MAX,
+            };
+            let mut it = WalkDir::new(&root)
+                .min_depth(is_dir as usize)
+                .max_depth(max_depth)
+                .into_iter();
+            while let Some(Ok(entry)) = it.next() {
+                if !running.load(atomic::Ordering::SeqCst) {
+                    break;
+                }
+                let file_type = entry.file_type();
+                if is_hidden(&hidden, get_file_name(entry.path()), file_type.is_dir())
+                    || ignore_files.is_ignored(entry.path(), file_type.is_dir())
+                {
+                    if file_type.is_dir() {
+                        it.skip_current_dir();
+                    }
+                    continue;
+                }
+                if !(file_type.is_file() || shallow && file_type.is_dir()) {
+                    continue;
+                }
+                if let Ok(meta) = entry.metadata() {
+                    entries.push((entry.into_path(), meta));
+                }
+            }
+        }
+        entries
+    })
+    .await?;
+    Ok(entries)
+}
+
+/// How `handle` authenticated a request. Paths beyond the request path, like
+/// MOVE destinations and batch items, are checked the same way.
+#[derive(Debug, Clone)]
+enum Identity {
+    /// The `Authorization` header, anonymous without one
+    Header {
+        authorization: Option<HeaderValue>,
+        guard_options: bool,
+    },
+    /// A login page session
+    Session(String),
+    /// A guest token
+    Guest(String),
+}
+
+/// A link of the breadcrumb of a page
+#[derive(Debug, Serialize)]
+struct Crumb {
+    name: String,
+    href: String,
+}
+
+/// Links to the root and every ancestor of `relative`, ending with the path
+/// itself. Folder links end with a slash.
+fn breadcrumb(uri_prefix: &str, relative: &str, is_dir: bool) -> Vec<Crumb> {
+    let mut crumbs = vec![Crumb {
+        name: String::new(),
+        href: uri_prefix.to_string(),
+    }];
+    let names: Vec<&str> = relative.split('/').filter(|v| !v.is_empty()).collect();
+    let mut href = uri_prefix.to_string();
+    for (i, name) in names.iter().enumerate() {
+        href.push_str(&encode_uri(name));
+        if is_dir || i + 1 < names.len() {
+            href.push('/');
+        }
+        crumbs.push(Crumb {
+            name: name.to_string(),
+            href: href.clone(),
+        });
     }
-    writer.close().await?;
+    crumbs
+}
+
+/// `path` relative to `root`, `/` separated. When the two are spelled
+/// differently, e.g. through a symlink or a relative root, they are compared
+/// by their canonical parents so that a symlinked entry keeps its own name.
+fn relative_to_root(root: &Path, path: &Path) -> Result<String> {
+    if let Ok(v) = path.strip_prefix(root) {
+        return Ok(normalize_path(v));
+    }
+    let root = std::fs::canonicalize(root)?;
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => std::fs::canonicalize(parent)?.join(name),
+        _ => std::fs::canonicalize(path)?,
+    };
+    let path = match path.strip_prefix(&root) {
+        Ok(_) => path,
+        Err(_) => std::fs::canonicalize(&path)?,
+    };
+    let v = path
+        .strip_prefix(&root)
+        .map_err(|_| anyhow!("`{}` is outside of `{}`", path.display(), root.display()))?;
+    Ok(normalize_path(v))
+}
+
+fn is_image_ext(ext: &str) -> bool {
+    mime_guess::from_ext(ext)
+        .first()
+        .is_some_and(|mime| mime.type_() == mime_guess::mime::IMAGE)
+}
+
+/// Runs a `--preview` command, writing to a temporary name that is only moved
+/// to `output` once the command succeeded.
+async fn run_preview(rule: &PreviewRule, input: &Path, output: &Path) -> Result<()> {
+    let tmp = output.with_file_name(format!(
+        ".tmp-{}.{}",
+        Uuid::new_v4().simple(),
+        rule.output_ext
+    ));
+    let args = rule.args.iter().map(|v| match v.as_str() {
+        "{input}" => input.as_os_str(),
+        "{output}" => tmp.as_os_str(),
+        v => std::ffi::OsStr::new(v),
+    });
+    let child = tokio::process::Command::new(&rule.program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let ret = match tokio::time::timeout(PREVIEW_TIMEOUT, child).await {
+        Ok(Ok(v)) if v.status.success() => match fs::metadata(&tmp).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(anyhow!("Preview command wrote no output")),
+        },
+        Ok(Ok(v)) => Err(anyhow!(
+            "Preview command failed with {}, {}",
+            v.status,
+            String::from_utf8_lossy(&v.stderr).trim()
+        )),
+        Ok(Err(err)) => Err(anyhow!("Failed to run preview command, {err}")),
+        Err(_) => Err(anyhow!("Preview command timed out")),
+    };
+    if let Err(err) = ret {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(err);
+    }
+    fs::rename(&tmp, output).await?;
     Ok(())
 }
 
+/// Creates the first free one of `path` and its numbered names, so concurrent
+/// uploads of the same name can't both pick it.
+async fn reserve_free_path(path: &Path) -> Result<Option<PathBuf>> {
+    let name = get_file_name(path);
+    for n in 0..MAX_RENAME_ATTEMPTS {
+        let candidate = match n {
+            0 => path.to_path_buf(),
+            _ => path.with_file_name(numbered_name(name, n)),
+        };
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+            .await
+        {
+            Ok(_) => return Ok(Some(candidate)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(None)
+}
+
+/// Canonicalizes the deepest existing ancestor of `path` and appends the
+/// missing components. Fails on entries that exist but can't be resolved,
+/// like dangling symlinks, since writing through them could escape the root.
+async fn canonicalize_lenient(path: &Path) -> Option<PathBuf> {
+    let mut tail = vec![];
+    let mut current = path;
+    let mut resolved = loop {
+        match fs::canonicalize(current).await {
+            Ok(v) => break v,
+            Err(_) => {
+                if fs::symlink_metadata(current).await.is_ok() {
+                    return None;
+                }
+                tail.push(current.file_name()?);
+                current = current.parent()?;
+            }
+        }
+    };
+    for name in tail.into_iter().rev() {
+        resolved.push(name);
+    }
+    Some(resolved)
+}
+
+/// Joins a `/` separated relative path onto a root. On Windows roots are `\\?\`
+/// extended-length paths, which keep deep trees clear of `MAX_PATH` but are not
+/// normalized, so the separators must be converted.
+fn join_root(root: &Path, path: &str) -> PathBuf {
+    if path.is_empty() {
+        return root.to_path_buf();
+    }
+    let path = if cfg!(windows) {
+        path.replace('/', "\\")
+    } else {
+        path.to_string()
+    };
+    root.join(path)
+}
+
 fn extract_cache_headers(meta: &Metadata) -> Option<(ETag, LastModified)> {
     let mtime = meta.modified().ok()?;
-    let timestamp = to_timestamp(&mtime);
-    let size = meta.len();
-    let etag = format!(r#""{timestamp}-{size}""#).parse::<ETag>().ok()?;
+    let etag = etag_text(meta)?.parse::<ETag>().ok()?;
     let last_modified = LastModified::from(mtime);
     Some((etag, last_modified))
 }
 
+/// The strong ETag of a file, made of its mtime in ms and its size
+fn etag_text(meta: &Metadata) -> Option<String> {
+    let mtime = meta.modified().ok()?;
+    Some(format!(r#""{}-{}""#, to_timestamp(&mtime), meta.len()))
+}
+
+/// A range is only served when `If-Range` is absent or exactly matches the
+/// current file: a strong ETag match or the identical `Last-Modified` date.
+/// Anything else (weak or stale ETag, another date, garbage) means the client
+/// holds a different version, so it gets the full body instead of a splice.
+fn if_range_passes(
+    headers: &HeaderMap<HeaderValue>,
+    validators: Option<&(ETag, LastModified)>,
+) -> bool {
+    let Some(value) = headers.get(IF_RANGE) else {
+        return true;
+    };
+    let (Some((etag, last_modified)), Ok(text)) = (validators, value.to_str()) else {
+        return false;
+    };
+    if text.starts_with('"') || text.starts_with("W/") {
+        text.parse::<ETag>().is_ok_and(|v| &v == etag)
+    } else {
+        <LastModified as headers::Header>::decode(&mut std::iter::once(value))
+            .is_ok_and(|v| &v == last_modified)
+    }
+}
+
+/// Evaluates `If-Match`, `If-None-Match` and `If-Unmodified-Since` against
+/// the current state of the target before it gets modified.
+fn write_preconditions_pass(headers: &HeaderMap<HeaderValue>, meta: Option<&Metadata>) -> bool {
+    let validators = meta.and_then(extract_cache_headers);
+    if let Some(if_match) = headers.typed_get::<IfMatch>() {
+        match &validators {
+            Some((etag, _)) => {
+                if !if_match.precondition_passes(etag) {
+                    return false;
+                }
+            }
+            None => return false,
+        }
+    } else if let Some(if_unmodified_since) = headers.typed_get::<IfUnmodifiedSince>() {
+        if let Some((_, last_modified)) = &validators {
+            if !if_unmodified_since.precondition_passes((*last_modified).into()) {
+                return false;
+            }
+        }
+    }
+    if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+        if let Some((etag, _)) = &validators {
+            if !if_none_match.precondition_passes(etag) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Whether the `Trailer` header announces `X-Checksum-Sha256`
+fn has_checksum_trailer(headers: &HeaderMap<HeaderValue>) -> bool {
+    headers.get_all(TRAILER).iter().any(|v| {
+        v.to_str().is_ok_and(|v| {
+            v.split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case(CHECKSUM_HEADER))
+        })
+    })
+}
+
+/// Checks the body against `X-Checksum-Sha256`, sent as a header or, by
+/// clients that hash while sending, as a trailer
+fn verify_body_checksum(
+    header: Option<&HeaderValue>,
+    body_end: &Mutex<BodyEnd>,
+) -> std::result::Result<(), String> {
+    let body_end = body_end.lock().unwrap();
+    let expected = body_end
+        .trailers
+        .as_ref()
+        .and_then(|v| v.get(CHECKSUM_HEADER))
+        .or(header);
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let Some(actual) = &body_end.sha256 else {
+        return Err("Announce X-Checksum-Sha256 in the Trailer header".into());
+    };
+    let expected = expected.to_str().unwrap_or_default().trim();
+    if !expected.eq_ignore_ascii_case(actual) {
+        return Err(format!("Checksum mismatch, the body has sha256 {actual}"));
+    }
+    Ok(())
+}
+
+fn status_see_other(res: &mut Response, location: &str) -> Result<()> {
+    *res.status_mut() = StatusCode::SEE_OTHER;
+    res.headers_mut()
+        .insert(LOCATION, HeaderValue::from_str(location)?);
+    Ok(())
+}
+
+/// The path and query of `uri` without its `token` parameters
+fn strip_token_param(uri: &Uri) -> String {
+    let query: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|v| !v.is_empty() && v.split('=').next() != Some("token"))
+        .collect();
+    match query.is_empty() {
+        true => uri.path().to_string(),
+        false => format!("{}?{}", uri.path(), query.join("&")),
+    }
+}
+
+fn get_cookie<'a>(headers: &'a HeaderMap<HeaderValue>, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|v| {
+            let (key, value) = v.trim().split_once('=')?;
+            (key == name).then_some(value)
+        })
+}
+
+/// Fails the request, the JSON body is rendered by `call` which knows the request path.
+fn status_error(res: &mut Response, status: StatusCode, message: impl Into<String>) {
+    *res.status_mut() = status;
+    res.extensions_mut().insert(HttpError::new(status, message));
+}
+
+/// Refused for load, clients may retry shortly.
+fn status_overloaded(res: &mut Response) {
+    status_error(
+        res,
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Server is busy, try again later",
+    );
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+}
+
 fn status_forbid(res: &mut Response) {
-    *res.status_mut() = StatusCode::FORBIDDEN;
-    *res.body_mut() = body_full("Forbidden");
+    status_error(res, StatusCode::FORBIDDEN, "Forbidden");
+}
+
+fn status_conflict(res: &mut Response) {
+    status_error(res, StatusCode::CONFLICT, "Already exists");
 }
 
 fn status_not_found(res: &mut Response) {
-    *res.status_mut() = StatusCode::NOT_FOUND;
-    *res.body_mut() = body_full("Not Found");
+    status_error(res, StatusCode::NOT_FOUND, "Not Found");
+}
+
+fn status_unsupported_type(res: &mut Response, body: &str) {
+    status_error(res, StatusCode::UNSUPPORTED_MEDIA_TYPE, body);
 }
 
 fn status_no_content(res: &mut Response) {
@@ -1678,38 +6325,107 @@ fn status_no_content(res: &mut Response) {
 }
 
 fn status_bad_request(res: &mut Response, body: &str) {
-    *res.status_mut() = StatusCode::BAD_REQUEST;
-    if !body.is_empty() {
-        *res.body_mut() = body_full(body.to_string());
-    }
+    let message = match body.is_empty() {
+        true => "Bad Request",
+        false => body,
+    };
+    status_error(res, StatusCode::BAD_REQUEST, message);
 }
 
-fn set_content_disposition(res: &mut Response, inline: bool, filename: &str) -> Result<()> {
-    let kind = if inline { "inline" } else { "attachment" };
-    let filename: String = filename
-        .chars()
-        .map(|ch| {
-            if ch.is_ascii_control() && ch != '\t' {
-                ' '
-            } else {
-                ch
+fn res_error(res: &mut Response, data: &serde_json::Value) {
+    let output = data.to_string();
+    res.headers_mut()
+        .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+    res.headers_mut()
+        .typed_insert(ContentLength(output.len() as u64));
+    *res.body_mut() = body_full(output);
+}
+
+fn res_json<T: Serialize>(res: &mut Response, data: &T) -> Result<()> {
+    let output = serde_json::to_string_pretty(data)?;
+    res.headers_mut()
+        .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+    res.headers_mut()
+        .typed_insert(ContentLength(output.len() as u64));
+    *res.body_mut() = body_full(output);
+    Ok(())
+}
+
+/// Reads a small JSON request body, answers 413/400 and returns `None` if it is unusable.
+async fn read_body(req: Request, res: &mut Response) -> Result<Option<Bytes>> {
+    match Limited::new(req.into_body(), BATCH_BODY_MAX_SIZE)
+        .collect()
+        .await
+    {
+        Ok(v) => Ok(Some(v.to_bytes())),
+        Err(err) => {
+            if err.is::<http_body_util::LengthLimitError>() {
+                status_error(res, StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large");
+                return Ok(None);
             }
-        })
-        .collect();
-    let value = if filename.is_ascii() {
-        HeaderValue::from_str(&format!("{kind}; filename=\"{}\"", filename,))?
+            Err(anyhow!(err))
+        }
+    }
+}
+
+async fn read_json_body<T: DeserializeOwned>(
+    req: Request,
+    res: &mut Response,
+) -> Result<Option<T>> {
+    let Some(body) = read_body(req, res).await? else {
+        return Ok(None);
+    };
+    let body = if body.is_empty() {
+        Bytes::from_static(b"{}")
     } else {
-        HeaderValue::from_str(&format!(
-            "{kind}; filename=\"{}\"; filename*=UTF-8''{}",
-            filename,
-            encode_uri(&filename),
-        ))?
+        body
     };
-    res.headers_mut().insert(CONTENT_DISPOSITION, value);
+    match serde_json::from_slice(&body) {
+        Ok(v) => Ok(Some(v)),
+        Err(err) => {
+            status_bad_request(res, &format!("Invalid JSON body, {err}"));
+            Ok(None)
+        }
+    }
+}
+
+/// Joins a client supplied name onto a relative path, rejecting anything but plain components.
+fn resolve_child_path(base: &str, name: &str) -> Option<String> {
+    let mut parts = vec![];
+    for comp in Path::new(name.trim_matches('/')).components() {
+        match comp {
+            Component::Normal(v) => {
+                let v = v.to_string_lossy();
+                if cfg!(windows) && is_windows_reserved_name(&v) {
+                    return None;
+                }
+                parts.push(v);
+            }
+            _ => return None,
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    let name = parts.join("/");
+    if base.is_empty() {
+        Some(name)
+    } else {
+        Some(format!("{base}/{name}"))
+    }
+}
+
+fn set_content_disposition(res: &mut Response, inline: bool, filename: &str) -> Result<()> {
+    res.headers_mut()
+        .insert(CONTENT_DISPOSITION, content_disposition(inline, filename)?);
     Ok(())
 }
 
+/// Staging files of chunked and `--upload-tmp-dir` uploads are always hidden
 fn is_hidden(hidden: &[String], file_name: &str, is_dir_type: bool) -> bool {
+    if file_name.starts_with(CHUNKED_UPLOAD_PREFIX) {
+        return true;
+    }
     hidden.iter().any(|v| {
         if is_dir_type {
             if let Some(x) = v.strip_suffix('/') {
@@ -1720,6 +6436,21 @@ fn is_hidden(hidden: &[String], file_name: &str, is_dir_type: bool) -> bool {
     })
 }
 
+/// `X-OC-Mtime` in seconds since the epoch, possibly fractional, as milliseconds
+fn parse_oc_mtime(headers: &HeaderMap<HeaderValue>) -> Option<u64> {
+    let value: f64 = headers
+        .get("x-oc-mtime")?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    if !value.is_finite() || value < 0.0 {
+        return None;
+    }
+    Some((value * 1000.0) as u64)
+}
+
 fn set_webdav_headers(res: &mut Response) {
     res.headers_mut().insert(
         "Allow",
@@ -1731,23 +6462,43 @@ fn set_webdav_headers(res: &mut Response) {
         .insert("DAV", HeaderValue::from_static("1, 2, 3"));
 }
 
-async fn get_content_type(path: &Path) -> Result<String> {
+async fn get_content_type(
+    path: &Path,
+    charset: Option<&'static Encoding>,
+    sniff: bool,
+    no_atime: bool,
+) -> Result<String> {
     let mut buffer: Vec<u8> = vec![];
-    fs::File::open(path)
+    open_read(path, no_atime)
         .await?
         .take(1024)
         .read_to_end(&mut buffer)
         .await?;
     let mime = mime_guess::from_path(path).first();
+    if mime.is_none() && sniff {
+        if let Some(mime) = sniff_mime(&buffer) {
+            return Ok(mime.to_string());
+        }
+    }
     let is_text = content_inspector::inspect(&buffer).is_text();
     let content_type = if is_text {
-        let mut detector = chardetng::EncodingDetector::new();
-        detector.feed(&buffer, buffer.len() < 1024);
-        let (enc, confident) = detector.guess_assess(None, true);
-        let charset = if confident {
-            format!("; charset={}", enc.name())
+        let truncated = buffer.len() == 1024;
+        let enc = if let Some((enc, _)) = Encoding::for_bom(&buffer) {
+            Some(enc)
+        } else if let Some(charset) = charset {
+            match is_utf8(&buffer, truncated) && !buffer.is_ascii() {
+                true => Some(UTF_8),
+                false => Some(charset),
+            }
         } else {
-            "".into()
+            let mut detector = chardetng::EncodingDetector::new();
+            detector.feed(&buffer, !truncated);
+            let (enc, confident) = detector.guess_assess(None, true);
+            confident.then_some(enc)
+        };
+        let charset = match enc {
+            Some(enc) => format!("; charset={}", enc.name()),
+            None => "".into(),
         };
         match mime {
             Some(m) => format!("{m}{charset}"),
@@ -1762,6 +6513,128 @@ async fn get_content_type(path: &Path) -> Result<String> {
     Ok(content_type)
 }
 
+/// Guesses a mime type from well-known magic bytes, leaving out types that
+/// browsers could execute like html or svg
+fn sniff_mime(buffer: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(usize, &[u8], &str)] = &[
+        (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+        (0, b"\xff\xd8\xff", "image/jpeg"),
+        (0, b"GIF87a", "image/gif"),
+        (0, b"GIF89a", "image/gif"),
+        (8, b"WEBP", "image/webp"),
+        (0, b"%PDF-", "application/pdf"),
+        (4, b"ftyp", "video/mp4"),
+        (0, b"\x1a\x45\xdf\xa3", "video/webm"),
+        (0, b"ID3", "audio/mpeg"),
+        (0, b"OggS", "audio/ogg"),
+        (0, b"fLaC", "audio/flac"),
+        (0, b"PK\x03\x04", "application/zip"),
+        (0, b"\x1f\x8b", "application/gzip"),
+    ];
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| buffer.get(*offset..*offset + magic.len()) == Some(*magic))
+        .map(|(_, _, mime)| *mime)
+}
+
+/// Magic bytes checked by `--allow-upload-types` and `--deny-upload-types`, with the
+/// extensions files of that type may be uploaded as. Files with a required extension
+/// must start with the magic bytes.
+const UPLOAD_SIGNATURES: &[(usize, &[u8], &[&str], bool)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", &["png", "apng"], true),
+    (0, b"\xff\xd8\xff", &["jpg", "jpeg", "jpe", "jfif"], true),
+    (0, b"GIF8", &["gif"], true),
+    (8, b"WEBP", &["webp"], true),
+    (0, b"%PDF-", &["pdf"], true),
+    (
+        4,
+        b"ftyp",
+        &["mp4", "m4a", "m4v", "mov", "3gp", "heic", "heif", "avif"],
+        true,
+    ),
+    (0, b"\x1a\x45\xdf\xa3", &["webm", "mkv", "mka"], true),
+    (0, b"ID3", &["mp3"], false),
+    (0, b"OggS", &["ogg", "oga", "ogv", "opus"], true),
+    (0, b"fLaC", &["flac"], true),
+    (
+        0,
+        b"PK\x03\x04",
+        &[
+            "zip", "jar", "apk", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub",
+        ],
+        true,
+    ),
+    (0, b"\x1f\x8b", &["gz", "tgz"], true),
+    (0, b"\x7fELF", &["elf", "so", "o"], false),
+    (0, b"MZ", &["exe", "dll", "sys", "scr", "com"], false),
+    (0, b"\xcf\xfa\xed\xfe", &["dylib"], false),
+    (0, b"\xca\xfe\xba\xbe", &["class", "dylib"], false),
+    (
+        0,
+        b"#!",
+        &["sh", "bash", "zsh", "py", "pl", "rb", "php", "js"],
+        false,
+    ),
+];
+
+/// Number of leading bytes needed to match `UPLOAD_SIGNATURES`
+const UPLOAD_SNIFF_SIZE: u64 = 16;
+
+fn sniff_upload_type(buffer: &[u8]) -> Option<&'static [&'static str]> {
+    UPLOAD_SIGNATURES
+        .iter()
+        .find(|(offset, magic, _, _)| buffer.get(*offset..*offset + magic.len()) == Some(*magic))
+        .map(|(_, _, exts, _)| *exts)
+}
+
+/// Checks for valid UTF-8, allowing a character cut off at the end of a truncated buffer
+fn is_utf8(buffer: &[u8], truncated: bool) -> bool {
+    match std::str::from_utf8(buffer) {
+        Ok(_) => true,
+        Err(err) => truncated && err.error_len().is_none(),
+    }
+}
+
+async fn find_precompressed(
+    path: &Path,
+    headers: &HeaderMap<HeaderValue>,
+) -> Option<(PathBuf, &'static str)> {
+    let accept_encoding = headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+    let accepted: Vec<&str> = accept_encoding
+        .split(',')
+        .filter_map(|v| {
+            let mut parts = v.split(';').map(|v| v.trim());
+            let name = parts.next()?;
+            let rejected = parts.any(|v| {
+                v.strip_prefix("q=")
+                    .and_then(|v| v.parse::<f32>().ok())
+                    .map(|v| v == 0.0)
+                    .unwrap_or_default()
+            });
+            if rejected {
+                None
+            } else {
+                Some(name)
+            }
+        })
+        .collect();
+    let file_name = get_file_name(path);
+    for (encoding, ext) in [("br", "br"), ("gzip", "gz")] {
+        if !accepted.iter().any(|v| *v == encoding || *v == "*") {
+            continue;
+        }
+        let variant_path = path.with_file_name(format!("{file_name}.{ext}"));
+        if fs::metadata(&variant_path)
+            .await
+            .map(|v| v.is_file())
+            .unwrap_or_default()
+        {
+            return Some((variant_path, encoding));
+        }
+    }
+    None
+}
+
 fn parse_upload_offset(headers: &HeaderMap<HeaderValue>, size: u64) -> Result<Option<u64>> {
     let value = match headers.get("x-update-range") {
         Some(v) => v,
@@ -1776,21 +6649,50 @@ fn parse_upload_offset(headers: &HeaderMap<HeaderValue>, size: u64) -> Result<Op
     Ok(Some(start))
 }
 
-async fn sha256_file(path: &Path) -> Result<String> {
-    let mut file = fs::File::open(path).await?;
-    let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 8192];
+fn checksum_sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(CHECKSUM_SIDECAR_EXT);
+    PathBuf::from(name)
+}
+
+fn is_checksum_sidecar(path: &Path) -> bool {
+    get_file_name(path).ends_with(CHECKSUM_SIDECAR_EXT)
+}
+
+/// Copies are best effort, a filesystem without extended attributes drops them
+async fn copy_dead_props(from: &Path, to: &Path) {
+    let (from, to) = (from.to_path_buf(), to.to_path_buf());
+    let _ = tokio::task::spawn_blocking(move || dav_props::copy(&from, &to)).await;
+}
+
+/// Hashes on the blocking pool so large files don't stall request handling.
+async fn sha256_file(path: &Path, no_atime: bool) -> Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        use std::io::Read;
+        let mut file = open_read_blocking(&path, no_atime)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; BUF_SIZE];
 
-    loop {
-        let bytes_read = file.read(&mut buffer).await?;
-        if bytes_read == 0 {
-            break;
+        loop {
+            let bytes_read = file.read(&mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..bytes_read]);
         }
-        hasher.update(&buffer[..bytes_read]);
-    }
 
-    let result = hasher.finalize();
-    Ok(format!("{:x}", result))
+        let result = hasher.finalize();
+        Ok(format!("{:x}", result))
+    })
+    .await?
+}
+
+/// Fuzzy search results are ranked by score unless an explicit sort is requested.
+fn is_ranked_search(query_params: &HashMap<String, String>) -> bool {
+    !query_params.contains_key("sort")
+        && query_params.get("q").is_some_and(|v| !v.is_empty())
+        && SearchMode::parse(query_params.get("mode")) == Some(SearchMode::Fuzzy)
 }
 
 fn has_query_flag(query_params: &HashMap<String, String>, name: &str) -> bool {
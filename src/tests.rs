@@ -0,0 +1,152 @@
+//! In-process tests against real temp directories, see `Server::spawn_for_tests`.
+
+use crate::args::{build_cli, Args};
+use crate::server::{Server, TestShutdown};
+
+use assert_fs::prelude::*;
+use assert_fs::TempDir;
+use serde_json::Value;
+use std::net::SocketAddr;
+
+struct TestServer {
+    dir: TempDir,
+    addr: SocketAddr,
+    shutdown: TestShutdown,
+}
+
+impl TestServer {
+    async fn spawn(flags: &[&str]) -> Self {
+        let dir = TempDir::new().unwrap();
+        for file in [
+            "index.html",
+            "test.txt",
+            "dir1/test.txt",
+            "dir1/sub/test.md",
+        ] {
+            dir.child(file)
+                .write_str(&format!("This is {file}"))
+                .unwrap();
+        }
+        dir.child(".git/config").write_str("").unwrap();
+        let path = dir.path().to_string_lossy().to_string();
+        let matches = build_cli()
+            .try_get_matches_from(
+                ["dufs", path.as_str(), "--log-format", ""]
+                    .iter()
+                    .chain(flags),
+            )
+            .unwrap();
+        let args = Args::parse(matches).unwrap();
+        let (addr, shutdown) = Server::spawn_for_tests(args).await.unwrap();
+        Self {
+            dir,
+            addr,
+            shutdown,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}/{}", self.addr, path)
+    }
+
+    async fn json(&self, path: &str) -> Value {
+        let resp = reqwest::get(self.url(path)).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        serde_json::from_slice(&resp.bytes().await.unwrap()).unwrap()
+    }
+}
+
+fn names(data: &Value) -> Vec<&str> {
+    let mut names: Vec<&str> = data["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["name"].as_str().unwrap())
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+#[tokio::test]
+async fn list_dir() {
+    let server = TestServer::spawn(&["--hidden", ".git"]).await;
+    let data = server.json("?json").await;
+    assert_eq!(names(&data), ["dir1", "index.html", "test.txt"]);
+    let data = server.json("dir1/?json").await;
+    assert_eq!(names(&data), ["sub", "test.txt"]);
+    server.shutdown.shutdown().await;
+}
+
+#[tokio::test]
+async fn upload_file() {
+    let server = TestServer::spawn(&["--allow-upload"]).await;
+    let client = reqwest::Client::new();
+    let resp = client
+        .put(server.url("new/file.txt"))
+        .body("abc")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    let content = std::fs::read_to_string(server.dir.path().join("new/file.txt")).unwrap();
+    assert_eq!(content, "abc");
+    let resp = client
+        .delete(server.url("new/file.txt"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 403);
+    server.shutdown.shutdown().await;
+}
+
+#[tokio::test]
+async fn auth_required_for_writes() {
+    let server = TestServer::spawn(&["--allow-upload", "-a", "admin:pass@/:rw", "-a", "@/"]).await;
+    let client = reqwest::Client::new();
+    let resp = client.get(server.url("test.txt")).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let resp = client
+        .put(server.url("file.txt"))
+        .body("abc")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+    let resp = client
+        .put(server.url("file.txt"))
+        .basic_auth("admin", Some("pass"))
+        .body("abc")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    server.shutdown.shutdown().await;
+}
+
+#[tokio::test]
+async fn search_dir() {
+    let server = TestServer::spawn(&["--allow-search"]).await;
+    let data = server.json("?q=test&json").await;
+    assert_eq!(
+        names(&data),
+        ["dir1/sub/test.md", "dir1/test.txt", "test.txt"]
+    );
+    server.shutdown.shutdown().await;
+}
+
+#[tokio::test]
+async fn zip_dir() {
+    let server = TestServer::spawn(&["--allow-archive"]).await;
+    let resp = reqwest::get(server.url("dir1/?zip")).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/zip"
+    );
+    let data = resp.bytes().await.unwrap();
+    assert!(data.starts_with(b"PK"));
+    for name in ["test.txt", "sub/test.md"] {
+        assert!(data.windows(name.len()).any(|v| v == name.as_bytes()));
+    }
+    server.shutdown.shutdown().await;
+}
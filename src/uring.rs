@@ -0,0 +1,203 @@
+use bytes::{Bytes, BytesMut};
+use futures_util::Stream;
+use std::{
+    fs::File,
+    io,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::{mpsc, oneshot},
+};
+
+const URING_THREADS: usize = 2;
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+enum Job {
+    Read {
+        file: File,
+        offset: u64,
+        len: u64,
+        chunk_size: usize,
+        chunks: mpsc::Sender<io::Result<Bytes>>,
+    },
+    Write {
+        file: File,
+        offset: u64,
+        chunks: mpsc::Receiver<Bytes>,
+        done: oneshot::Sender<io::Result<u64>>,
+    },
+}
+
+/// Runs file reads and writes on io_uring instead of the blocking pool.
+///
+/// Files are opened by the caller and handed over, so the io threads never
+/// resolve paths themselves.
+pub struct Uring {
+    threads: Vec<mpsc::UnboundedSender<Job>>,
+    next: AtomicUsize,
+}
+
+impl Uring {
+    /// Starts the io threads, fails if the kernel can't set up a ring.
+    pub fn start() -> io::Result<Self> {
+        let mut threads = vec![];
+        for i in 0..URING_THREADS {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+            thread::Builder::new()
+                .name(format!("dufs-uring-{i}"))
+                .spawn(move || {
+                    let runtime = match tokio_uring::Runtime::new(&tokio_uring::builder()) {
+                        Ok(runtime) => runtime,
+                        Err(err) => {
+                            let _ = ready_tx.send(Err(err));
+                            return;
+                        }
+                    };
+                    let _ = ready_tx.send(Ok(()));
+                    runtime.block_on(async move {
+                        while let Some(job) = rx.recv().await {
+                            tokio_uring::spawn(run(job));
+                        }
+                    })
+                })?;
+            ready_rx.recv().map_err(|_| io_thread_gone())??;
+            threads.push(tx);
+        }
+        Ok(Self {
+            threads,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Streams `len` bytes of `file` from `offset`, stopping early at the end of the file.
+    pub fn read(
+        &self,
+        file: File,
+        offset: u64,
+        len: u64,
+        chunk_size: usize,
+    ) -> impl Stream<Item = io::Result<Bytes>> {
+        let (tx, mut rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+        self.submit(Job::Read {
+            file,
+            offset,
+            len,
+            chunk_size,
+            chunks: tx,
+        });
+        async_stream::stream! {
+            while let Some(chunk) = rx.recv().await {
+                yield chunk;
+            }
+        }
+    }
+
+    /// Copies `reader` into `file` from `offset`, returns the bytes written.
+    pub async fn copy<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        file: File,
+        offset: u64,
+        chunk_size: usize,
+    ) -> io::Result<u64> {
+        let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+        let (done_tx, done_rx) = oneshot::channel();
+        self.submit(Job::Write {
+            file,
+            offset,
+            chunks: rx,
+            done: done_tx,
+        });
+        loop {
+            let mut buf = BytesMut::with_capacity(chunk_size);
+            if reader.read_buf(&mut buf).await? == 0 {
+                break;
+            }
+            // A closed channel means the write failed, the error comes from `done`
+            if tx.send(buf.freeze()).await.is_err() {
+                break;
+            }
+        }
+        drop(tx);
+        done_rx.await.map_err(|_| io_thread_gone())?
+    }
+
+    fn submit(&self, job: Job) {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.threads.len();
+        // The threads live as long as the server, a failed send drops the job's
+        // channels and the caller sees `io_thread_gone`
+        let _ = self.threads[i].send(job);
+    }
+}
+
+async fn run(job: Job) {
+    match job {
+        Job::Read {
+            file,
+            offset,
+            len,
+            chunk_size,
+            chunks,
+        } => {
+            let file = tokio_uring::fs::File::from_std(file);
+            let mut pos = offset;
+            let end = offset.saturating_add(len);
+            while pos < end {
+                let size = chunk_size.min((end - pos) as usize);
+                let (ret, buf) = file.read_at(BytesMut::with_capacity(size), pos).await;
+                let chunk = match ret {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        pos += n as u64;
+                        Ok(buf.freeze())
+                    }
+                    Err(err) => Err(err),
+                };
+                let failed = chunk.is_err();
+                // The response was dropped
+                if chunks.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        }
+        Job::Write {
+            file,
+            offset,
+            mut chunks,
+            done,
+        } => {
+            let file = tokio_uring::fs::File::from_std(file);
+            let mut pos = offset;
+            let mut ret = Ok(0);
+            'chunks: while let Some(mut chunk) = chunks.recv().await {
+                while !chunk.is_empty() {
+                    let (res, buf) = file.write_at(chunk, pos).await;
+                    chunk = buf;
+                    match res {
+                        Ok(0) => {
+                            ret = Err(io::ErrorKind::WriteZero.into());
+                            break 'chunks;
+                        }
+                        Ok(n) => {
+                            let _ = chunk.split_to(n);
+                            pos += n as u64;
+                            ret = Ok(pos - offset);
+                        }
+                        Err(err) => {
+                            ret = Err(err);
+                            break 'chunks;
+                        }
+                    }
+                }
+            }
+            let _ = done.send(ret);
+        }
+    }
+}
+
+fn io_thread_gone() -> io::Error {
+    io::Error::other("io_uring thread stopped")
+}
@@ -2,6 +2,7 @@ use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
 #[cfg(feature = "tls")]
 use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use sha2::{Digest, Sha256};
 use std::{
     borrow::Cow,
     path::Path,
@@ -14,12 +15,32 @@ pub fn unix_now() -> Result<Duration> {
         .with_context(|| "Invalid system time")
 }
 
+/// HMAC-SHA256 (RFC 2104), for the session cookies and S3 signatures
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|v| v ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
 pub fn encode_uri(v: &str) -> String {
     let parts: Vec<_> = v.split('/').map(urlencoding::encode).collect();
     parts.join("/")
 }
 
-pub fn decode_uri(v: &str) -> Option<Cow<str>> {
+pub fn decode_uri(v: &str) -> Option<Cow<'_, str>> {
     percent_encoding::percent_decode(v.as_bytes())
         .decode_utf8()
         .ok()
@@ -43,7 +64,217 @@ pub async fn get_file_mtime_and_mode(path: &Path) -> Result<(DateTime<Utc>, u16)
 pub async fn get_file_mtime_and_mode(path: &Path) -> Result<(DateTime<Utc>, u16)> {
     let meta = tokio::fs::metadata(&path).await?;
     let datetime: DateTime<Utc> = meta.modified()?.into();
-    Ok((datetime, 0o644))
+    // A regular file with rw-r--r--, as unix extractors expect the file type bits
+    Ok((datetime, 0o100644))
+}
+
+/// Returns `(total, used, available)` bytes of the filesystem containing `path`.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn get_disk_usage(path: &Path) -> Result<(u64, u64, u64)> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to stat filesystem of `{}`", path.display()));
+    }
+    let block_size = stat.f_frsize as u64;
+    let total = stat.f_blocks as u64 * block_size;
+    let free = stat.f_bfree as u64 * block_size;
+    let available = stat.f_bavail as u64 * block_size;
+    Ok((total, total.saturating_sub(free), available))
+}
+
+#[cfg(not(unix))]
+pub fn get_disk_usage(_path: &Path) -> Result<(u64, u64, u64)> {
+    Err(anyhow!("Disk usage is not supported on this platform"))
+}
+
+/// Flushes the directory entry of `path` to disk.
+#[cfg(unix)]
+pub async fn sync_parent_dir(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::File::open(parent).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Opens `path` for reading, with `no_atime` without updating its access time
+/// where `O_NOATIME` exists. Only the owner of a file may ask for that, other
+/// files are opened normally.
+pub fn open_read_blocking(path: &Path, no_atime: bool) -> std::io::Result<std::fs::File> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if no_atime {
+        use std::os::unix::fs::OpenOptionsExt;
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOATIME)
+            .open(path)
+        {
+            Err(err) if err.raw_os_error() == Some(libc::EPERM) => {}
+            ret => return ret,
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = no_atime;
+    std::fs::File::open(path)
+}
+
+pub async fn open_read(path: &Path, no_atime: bool) -> std::io::Result<tokio::fs::File> {
+    let path = path.to_path_buf();
+    let file = tokio::task::spawn_blocking(move || open_read_blocking(&path, no_atime)).await??;
+    Ok(tokio::fs::File::from_std(file))
+}
+
+/// Whether both paths are on one filesystem, so a rename between them is atomic.
+#[cfg(unix)]
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => false,
+    }
+}
+
+/// Whether both paths are on one drive, their canonical forms share the prefix.
+#[cfg(not(unix))]
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a.components().next() == b.components().next(),
+        _ => false,
+    }
+}
+
+/// Looks up the ids of the user and group to switch to, names or numeric ids
+/// are accepted. Done before the sandbox hides the user database.
+#[cfg(unix)]
+pub fn lookup_ids(user: Option<&str>, group: Option<&str>) -> Result<(Option<u32>, Option<u32>)> {
+    use std::ffi::CString;
+    let (uid, user_gid) = match user {
+        Some(user) => match user.parse::<libc::uid_t>() {
+            Ok(uid) => (Some(uid), None),
+            Err(_) => {
+                let name = CString::new(user)?;
+                let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+                if passwd.is_null() {
+                    return Err(anyhow!("Unknown user `{user}`"));
+                }
+                let passwd = unsafe { &*passwd };
+                (Some(passwd.pw_uid), Some(passwd.pw_gid))
+            }
+        },
+        None => (None, None),
+    };
+    let gid = match group {
+        Some(group) => match group.parse::<libc::gid_t>() {
+            Ok(gid) => Some(gid),
+            Err(_) => {
+                let name = CString::new(group)?;
+                let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+                if entry.is_null() {
+                    return Err(anyhow!("Unknown group `{group}`"));
+                }
+                Some(unsafe { (*entry).gr_gid })
+            }
+        },
+        None => user_gid,
+    };
+    Ok((uid, gid))
+}
+
+/// Switches to an unprivileged user and group, e.g. after binding to ports
+/// below 1024 as root.
+#[cfg(unix)]
+pub fn drop_privileges(uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+    if let Some(gid) = gid {
+        if unsafe { libc::setgroups(1, &gid) } != 0 || unsafe { libc::setgid(gid) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to switch to group `{gid}`"));
+        }
+    }
+    if let Some(uid) = uid {
+        if unsafe { libc::setuid(uid) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to switch to user `{uid}`"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lookup_ids(_user: Option<&str>, _group: Option<&str>) -> Result<(Option<u32>, Option<u32>)> {
+    Err(anyhow!("Switching user is not supported on this platform"))
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(_uid: Option<u32>, _gid: Option<u32>) -> Result<()> {
+    Err(anyhow!("Switching user is not supported on this platform"))
+}
+
+/// Name of the user with `uid`, or the id itself if it has no name
+#[cfg(unix)]
+pub fn user_name(uid: u32) -> String {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result = std::ptr::null_mut();
+    let ret =
+        unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if ret != 0 || result.is_null() {
+        return uid.to_string();
+    }
+    unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Name of the group with `gid`, or the id itself if it has no name
+#[cfg(unix)]
+pub fn group_name(gid: u32) -> String {
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result = std::ptr::null_mut();
+    let ret =
+        unsafe { libc::getgrgid_r(gid, &mut group, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if ret != 0 || result.is_null() {
+        return gid.to_string();
+    }
+    unsafe { std::ffi::CStr::from_ptr(group.gr_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Formats unix permissions like `ls -l`, e.g. `drwxr-xr-x`
+#[cfg(unix)]
+pub fn format_mode(mode: u32) -> String {
+    let kind = match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        _ => '-',
+    };
+    let mut output = String::from(kind);
+    // (read, write, execute, special bit, special char)
+    let triplets = [
+        (0o400, 0o200, 0o100, 0o4000, 's'),
+        (0o040, 0o020, 0o010, 0o2000, 's'),
+        (0o004, 0o002, 0o001, 0o1000, 't'),
+    ];
+    for (read, write, execute, special, special_char) in triplets {
+        output.push(if mode & read != 0 { 'r' } else { '-' });
+        output.push(if mode & write != 0 { 'w' } else { '-' });
+        output.push(match (mode & execute != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    output
 }
 
 pub fn try_get_file_name(path: &Path) -> Result<&str> {
@@ -100,35 +331,184 @@ pub fn load_private_key<T: AsRef<Path>>(filename: T) -> Result<PrivateKeyDer<'st
     anyhow::bail!("No supported private key in file");
 }
 
+/// Whether Windows would not treat `name` as a plain file name: a device such
+/// as `CON` or `aux.txt`, a drive such as `C:`, an alternate data stream such
+/// as `file.txt::$DATA`, or a name it silently trims such as `file.txt.`.
+pub fn is_windows_reserved_name(name: &str) -> bool {
+    if name.contains(':') || name.ends_with(['.', ' ']) {
+        return true;
+    }
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    let stem = stem.to_ascii_uppercase();
+    if matches!(
+        stem.as_str(),
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$"
+    ) {
+        return true;
+    }
+    match stem
+        .strip_prefix("COM")
+        .or_else(|| stem.strip_prefix("LPT"))
+    {
+        Some(v) => {
+            let mut chars = v.chars();
+            matches!(
+                (chars.next(), chars.next()),
+                (Some('0'..='9' | '¹' | '²' | '³'), None)
+            )
+        }
+        None => false,
+    }
+}
+
+/// `report.pdf` as its `n`th copy `report (n).pdf`, the number goes before the
+/// last extension unless the name only has a leading dot like `.bashrc`.
+pub fn numbered_name(name: &str, n: usize) -> String {
+    match name.rfind('.').filter(|&i| i > 0) {
+        Some(i) => format!("{} ({n}){}", &name[..i], &name[i..]),
+        None => format!("{name} ({n})"),
+    }
+}
+
+/// A download name chosen by the client, reduced to its last path component
+/// and given the `ext` extension if missing.
+pub fn download_name(name: &str, ext: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    let suffix = format!(".{ext}");
+    if name.to_ascii_lowercase().ends_with(&suffix) {
+        Some(name.to_string())
+    } else {
+        Some(format!("{name}{suffix}"))
+    }
+}
+
 pub fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let ranges = parse_ranges(range, size)?;
+    if ranges.len() != 1 {
+        return None;
+    }
+    Some(ranges[0])
+}
+
+/// Most ranges one request may ask for, a long list of tiny ranges costs far
+/// more to serve than the bytes it returns
+const MAX_RANGES: usize = 16;
+
+/// Parses a `Range` header into sorted ranges, overlapping and adjacent ones
+/// merged. Unsatisfiable ranges are skipped as long as one is left.
+pub fn parse_ranges(range: &str, size: u64) -> Option<Vec<(u64, u64)>> {
     let (unit, range) = range.split_once('=')?;
-    if unit != "bytes" || range.contains(',') {
+    if unit != "bytes" {
         return None;
     }
+    let specs: Vec<_> = range.split(',').collect();
+    if specs.len() > MAX_RANGES {
+        return None;
+    }
+    let mut ranges = vec![];
+    for spec in specs {
+        if let Some(range) = parse_range_spec(spec.trim(), size)? {
+            ranges.push(range);
+        }
+    }
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = vec![];
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    if merged.is_empty() {
+        return None;
+    }
+    Some(merged)
+}
+
+/// `None` for an invalid range, `Some(None)` for a valid one outside the file
+fn parse_range_spec(range: &str, size: u64) -> Option<Option<(u64, u64)>> {
     let (start, end) = range.split_once('-')?;
     if start.is_empty() {
+        // A zero suffix selects nothing, which also covers empty files
         let offset = end.parse::<u64>().ok()?;
-        if offset <= size {
-            Some((size - offset, size - 1))
-        } else {
-            None
-        }
+        Some((offset > 0 && offset <= size).then(|| (size - offset, size - 1)))
     } else {
         let start = start.parse::<u64>().ok()?;
-        if start < size {
-            if end.is_empty() {
-                Some((start, size - 1))
-            } else {
-                let end = end.parse::<u64>().ok()?;
-                if end < size {
-                    Some((start, end))
-                } else {
-                    None
-                }
+        let end = match end.is_empty() {
+            true => None,
+            false => Some(end.parse::<u64>().ok()?),
+        };
+        match end {
+            Some(end) if end < start => None,
+            Some(end) => Some((end < size).then_some((start, end))),
+            None => Some((start < size).then(|| (start, size - 1))),
+        }
+    }
+}
+
+/// Scores how well `pattern` matches `text`, higher is better.
+///
+/// Substring matches rank first, then in-order subsequences (`img2024` matches `IMG_2024`),
+/// then near-substrings with up to one typo per four pattern characters.
+pub fn fuzzy_score(text: &str, pattern: &str) -> Option<u32> {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let extra = text.len().saturating_sub(pattern.len()) as u32;
+    if let Some(pos) = text.windows(pattern.len()).position(|v| v == pattern) {
+        return Some(3000u32.saturating_sub(pos as u32 + extra).max(2000));
+    }
+    let mut matched = 0;
+    let mut bonus = 0u32;
+    let mut prev_matched = false;
+    for (i, c) in text.iter().enumerate() {
+        if matched < pattern.len() && *c == pattern[matched] {
+            bonus += 10;
+            if prev_matched {
+                bonus += 15;
+            }
+            if i == 0 || !text[i - 1].is_alphanumeric() {
+                bonus += 10;
             }
+            matched += 1;
+            prev_matched = true;
         } else {
-            None
+            prev_matched = false;
+        }
+    }
+    if matched == pattern.len() {
+        return Some((1000 + bonus).saturating_sub(extra).clamp(1000, 1999));
+    }
+    let max_typos = pattern.len() / 4;
+    if max_typos == 0 {
+        return None;
+    }
+    // Smallest edit distance (with transpositions) between the pattern and any substring of the text
+    let mut prev2: Vec<usize> = vec![0; text.len() + 1];
+    let mut prev: Vec<usize> = vec![0; text.len() + 1];
+    for (i, p) in pattern.iter().enumerate() {
+        let mut cur = vec![i + 1; text.len() + 1];
+        for (j, t) in text.iter().enumerate() {
+            let cost = usize::from(p != t);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+            if i > 0 && j > 0 && *p == text[j - 1] && pattern[i - 1] == *t {
+                cur[j + 1] = cur[j + 1].min(prev2[j - 1] + 1);
+            }
         }
+        prev2 = std::mem::replace(&mut prev, cur);
+    }
+    let distance = prev.into_iter().min().unwrap_or(usize::MAX);
+    if distance <= max_typos {
+        Some(900u32.saturating_sub(distance as u32 * 100 + extra).max(1))
+    } else {
+        None
     }
 }
 
@@ -160,6 +540,17 @@ mod tests {
         assert!(!glob("*/", "abc"));
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_format_mode() {
+        assert_eq!(format_mode(0o040755), "drwxr-xr-x");
+        assert_eq!(format_mode(0o100644), "-rw-r--r--");
+        assert_eq!(format_mode(0o120777), "lrwxrwxrwx");
+        assert_eq!(format_mode(0o104755), "-rwsr-xr-x");
+        assert_eq!(format_mode(0o041777), "drwxrwxrwt");
+        assert_eq!(format_mode(0o102644), "-rw-r-Sr--");
+    }
+
     #[test]
     fn test_parse_range() {
         assert_eq!(parse_range("bytes=0-499", 500), Some((0, 499)));
@@ -170,5 +561,129 @@ mod tests {
         assert_eq!(parse_range("bytes=500-", 500), None);
         assert_eq!(parse_range("bytes=-501", 500), None);
         assert_eq!(parse_range("bytes=0-500", 500), None);
+        assert_eq!(parse_range("bytes=0-1,3-4", 500), None);
+        assert_eq!(parse_range("bytes=-0", 500), None);
+        assert_eq!(parse_range("bytes=5-2", 500), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+        assert_eq!(parse_range("bytes=-0", 0), None);
+        assert_eq!(parse_range("bytes=-1", 0), None);
+        let size = 5 << 30;
+        assert_eq!(
+            parse_range("bytes=4294967296-", size),
+            Some((4294967296, size - 1))
+        );
+        assert_eq!(parse_range("bytes=-1", size), Some((size - 1, size - 1)));
+    }
+
+    #[test]
+    fn test_is_windows_reserved_name() {
+        for name in [
+            "CON",
+            "con",
+            "aux.txt",
+            "Nul.tar.gz",
+            "COM1",
+            "lpt9.log",
+            "com¹",
+            "CON .txt",
+            "C:",
+            "file.txt::$DATA",
+            "file.txt:stream",
+            "file.",
+            "file ",
+        ] {
+            assert!(is_windows_reserved_name(name), "{name}");
+        }
+        for name in [
+            "console",
+            "auxiliary.txt",
+            "COM",
+            "COM10",
+            "LPT",
+            "file.txt",
+            ".git",
+            "a.con",
+        ] {
+            assert!(!is_windows_reserved_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_numbered_name() {
+        assert_eq!(numbered_name("report.pdf", 1), "report (1).pdf");
+        assert_eq!(numbered_name("a.tar.gz", 2), "a.tar (2).gz");
+        assert_eq!(numbered_name("Makefile", 3), "Makefile (3)");
+        assert_eq!(numbered_name(".bashrc", 1), ".bashrc (1)");
+    }
+
+    #[test]
+    fn test_download_name() {
+        assert_eq!(download_name("photos", "zip"), Some("photos.zip".into()));
+        assert_eq!(
+            download_name("Photos.ZIP", "zip"),
+            Some("Photos.ZIP".into())
+        );
+        assert_eq!(
+            download_name("../etc/passwd", "zip"),
+            Some("passwd.zip".into())
+        );
+        assert_eq!(download_name("a\\b\n", "zip"), Some("b.zip".into()));
+        assert_eq!(download_name(" / ", "zip"), None);
+        assert_eq!(download_name("..", "zip"), None);
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test case 2
+        let hex: String = hmac_sha256(b"Jefe", b"what do ya want for nothing?")
+            .iter()
+            .map(|v| format!("{v:02x}"))
+            .collect();
+        assert_eq!(
+            hex,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(parse_ranges("bytes=0-1", 500), Some(vec![(0, 1)]));
+        assert_eq!(
+            parse_ranges("bytes=0-1, 3-4,-10", 500),
+            Some(vec![(0, 1), (3, 4), (490, 499)])
+        );
+        assert_eq!(parse_ranges("bytes=0-1,500-", 500), Some(vec![(0, 1)]));
+        assert_eq!(parse_ranges("bytes=500-,0-500", 500), None);
+        assert_eq!(parse_ranges("bytes=0-1,5-2", 500), None);
+        assert_eq!(parse_ranges("items=0-1", 500), None);
+        assert_eq!(
+            parse_ranges("bytes=10-20,0-4,5-9,15-30,-1", 500),
+            Some(vec![(0, 30), (499, 499)])
+        );
+        let many = vec!["0-0"; 17].join(",");
+        assert_eq!(parse_ranges(&format!("bytes={many}"), 500), None);
+        let many = vec!["0-0"; 16].join(",");
+        assert_eq!(
+            parse_ranges(&format!("bytes={many}"), 500),
+            Some(vec![(0, 0)])
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score("abc", ""), Some(0));
+        assert!(fuzzy_score("img_2024.jpg", "img_2024").unwrap() >= 2000);
+        assert!(fuzzy_score("img_2024.jpg", "img2024").unwrap() >= 1000);
+        assert!(fuzzy_score("receive.txt", "recieve").unwrap() < 1000);
+        assert_eq!(fuzzy_score("abc.txt", "xyz"), None);
+        assert_eq!(fuzzy_score("abc.txt", "abd"), None);
+        assert!(
+            fuzzy_score("test.html", "test").unwrap()
+                > fuzzy_score("my-test.html", "test").unwrap()
+        );
+        assert!(
+            fuzzy_score("img_2024.jpg", "img2024").unwrap()
+                > fuzzy_score("i_m_g_2_0_2_4.jpg", "img2024").unwrap()
+        );
     }
 }
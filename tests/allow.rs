@@ -1,8 +1,11 @@
 mod fixtures;
 mod utils;
 
-use fixtures::{server, Error, TestServer};
+use assert_cmd::prelude::*;
+use assert_fs::fixture::TempDir;
+use fixtures::{port, server, tmpdir, wait_for_port, Error, TestServer};
 use rstest::rstest;
+use std::process::{Command, Stdio};
 
 #[rstest]
 fn default_not_allow_upload(server: TestServer) -> Result<(), Error> {
@@ -90,3 +93,310 @@ fn allow_archive(#[with(&["--allow-archive"])] server: TestServer) -> Result<(),
     assert!(resp.headers().contains_key("content-disposition"));
     Ok(())
 }
+
+#[rstest]
+fn no_flags_override_allow_all(
+    #[with(&["-A", "--no-archive", "--no-search", "--no-delete"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    let resp = reqwest::blocking::get(format!("{}?q={}", server.url(), "test.html"))?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"DELETE", format!("{}test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn patch_overwrite_not_allow_delete(
+    #[with(&["--allow-upload"])] server: TestServer,
+) -> Result<(), Error> {
+    let before = std::fs::read(server.path().join("index.html"))?;
+    let resp = fetch!(b"PATCH", format!("{}index.html", server.url()))
+        .header("X-Update-Range", "bytes=0-")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(std::fs::read(server.path().join("index.html"))?, before);
+    Ok(())
+}
+
+#[rstest]
+fn write_once(#[with(&["-A", "--write-once"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", &url).body(b"def".to_vec()).send()?;
+    assert_eq!(resp.status(), 409);
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "append")
+        .body(b"def".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 409);
+    let resp = fetch!(b"COPY", &url)
+        .header("Destination", format!("{}test.html", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 409);
+    let resp = fetch!(b"DELETE", &url).send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"MOVE", &url)
+        .header("Destination", format!("{}file2", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "abc");
+    Ok(())
+}
+
+#[rstest]
+fn write_once_failed_upload_id(
+    #[with(&["-A", "--write-once"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file-new", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("X-Upload-Id", "id1")
+        .header("X-Checksum-Sha256", "0".repeat(64))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    // The id of the failed attempt doesn't allow replacing the file
+    let resp = fetch!(b"PUT", &url)
+        .header("X-Upload-Id", "id1")
+        .body(b"def".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 409);
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "abc");
+    Ok(())
+}
+
+#[rstest]
+fn upload_path(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path().join("dir1"))
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--allow-upload")
+        .arg("--upload-path")
+        .arg(tmpdir.path().join("dir2"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let url = format!("http://localhost:{port}/new/file1");
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        std::fs::read_to_string(tmpdir.path().join("dir2/new/file1"))?,
+        "abc"
+    );
+    assert!(!tmpdir.path().join("dir1/new").exists());
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 404);
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/index.html"))?;
+    assert_eq!(resp.status(), 200);
+
+    // The access files of the upload path apply to uploads
+    std::fs::write(tmpdir.path().join("dir2/new/.dufs-access"), "readonly\n")?;
+    let url = format!("http://localhost:{port}/new/file2");
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(!tmpdir.path().join("dir2/new/file2").exists());
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn upload_path_writes(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path().join("dir1"))
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("-A")
+        .arg("--upload-path")
+        .arg(tmpdir.path().join("dir2"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let url = |name: &str| format!("http://localhost:{port}/{name}");
+    let resp = fetch!(b"COPY", url("index.html"))
+        .header("Destination", url("copied.html"))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(tmpdir.path().join("dir2/copied.html").is_file());
+    assert!(!tmpdir.path().join("dir1/copied.html").exists());
+    let resp = fetch!(b"MOVE", url("copied.html"))
+        .header("Destination", url("moved.html"))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(tmpdir.path().join("dir2/moved.html").is_file());
+    let resp = fetch!(b"DELETE", url("moved.html")).send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(!tmpdir.path().join("dir2/moved.html").exists());
+
+    // Served files are never changed
+    std::fs::remove_file(tmpdir.path().join("dir2/test.html"))?;
+    let resp = fetch!(b"DELETE", url("test.html")).send()?;
+    assert_eq!(resp.status(), 404);
+    let resp = fetch!(b"MOVE", url("test.html"))
+        .header("Destination", url("test2.html"))
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    let body = r#"[
+        {"op": "copy", "from": "index.html", "to": "index2.html"},
+        {"op": "move", "from": "test.html", "to": "test2.html"},
+        {"op": "delete", "path": "test.html"}
+    ]"#;
+    let resp = fetch!(b"POST", format!("{}?batch", url("")))
+        .body(body)
+        .send()?;
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json[0]["status"], 204);
+    assert_eq!(json[1]["status"], 404);
+    assert_eq!(json[2]["status"], 404);
+    assert!(tmpdir.path().join("dir2/index2.html").is_file());
+    assert!(tmpdir.path().join("dir1/test.html").is_file());
+
+    child.kill()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn upload_path_chmod(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path().join("dir1"))
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("-A")
+        .arg("--allow-chmod")
+        .arg("--upload-path")
+        .arg(tmpdir.path().join("dir2"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    // The served file is changed, not the one of the same name under the upload path
+    let url = format!("http://localhost:{port}/index.html?chmod=751");
+    let resp = fetch!(b"PATCH", &url).send()?;
+    assert_eq!(resp.status(), 204);
+    let mode = |path: &str| {
+        std::fs::metadata(tmpdir.path().join(path))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777
+    };
+    assert_eq!(mode("dir1/index.html"), 0o751);
+    assert_ne!(mode("dir2/index.html"), 0o751);
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn upload_path_windows_lock(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path().join("dir1"))
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--allow-upload")
+        .arg("--upload-path")
+        .arg(tmpdir.path().join("dir2"))
+        .arg("--webdav-quirks")
+        .arg("windows")
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let resp = fetch!(b"LOCK", format!("http://localhost:{port}/new.docx")).send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(tmpdir.path().join("dir2/new.docx").exists());
+    assert!(!tmpdir.path().join("dir1/new.docx").exists());
+    // Already uploaded, the file is only locked
+    let resp = fetch!(b"LOCK", format!("http://localhost:{port}/new.docx")).send()?;
+    assert_eq!(resp.status(), 200);
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn upload_tmp_dir(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path().join("dir1"))
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--allow-upload")
+        .arg("--upload-tmp-dir")
+        .arg(tmpdir.path().join("dir2"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let tmp_files = || {
+        std::fs::read_dir(tmpdir.path().join("dir2"))
+            .unwrap()
+            .count()
+    };
+    let before = tmp_files();
+    let url = format!("http://localhost:{port}/new/file1");
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        std::fs::read_to_string(tmpdir.path().join("dir1/new/file1"))?,
+        "abc"
+    );
+    // A rejected upload leaves nothing behind, in either directory
+    let url = format!("http://localhost:{port}/file2");
+    let resp = fetch!(b"PUT", &url)
+        .header("x-checksum-sha256", "0".repeat(64))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    assert!(!tmpdir.path().join("dir1/file2").exists());
+    assert_eq!(tmp_files(), before);
+
+    child.kill()?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[rstest]
+fn upload_tmp_dir_sandbox(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path().join("dir1"))
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--allow-upload")
+        .arg("--sandbox")
+        .arg("--upload-tmp-dir")
+        .arg(tmpdir.path().join("dir2"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let url = format!("http://localhost:{port}/file1");
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        std::fs::read_to_string(tmpdir.path().join("dir1/file1"))?,
+        "abc"
+    );
+
+    child.kill()?;
+    Ok(())
+}
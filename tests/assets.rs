@@ -124,3 +124,110 @@ fn assets_override(tmpdir: TempDir, port: u16) -> Result<(), Error> {
     child.kill()?;
     Ok(())
 }
+
+#[rstest]
+fn builtin_robots_txt(#[with(&["-a", "user:pass@/:rw"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}robots.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "User-agent: *\nDisallow: /\n");
+    Ok(())
+}
+
+#[rstest]
+fn builtin_robots_txt_allow(
+    #[with(&["--robots", "allow"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}robots.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "User-agent: *\nDisallow:\n");
+    Ok(())
+}
+
+#[rstest]
+fn real_robots_txt_wins(server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("robots.txt"), "custom")?;
+    let resp = reqwest::blocking::get(format!("{}robots.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "custom");
+    Ok(())
+}
+
+#[rstest]
+fn builtin_favicon(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}favicon.ico", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "image/x-icon");
+    Ok(())
+}
+
+#[rstest]
+fn custom_favicon(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let favicon_path = tmpdir.path().join("dir1").join("test.txt");
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--favicon")
+        .arg(&favicon_path)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/favicon.ico"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is dir1/test.txt");
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn header_footer_files(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let header_path = tmpdir.path().join("header.html");
+    std::fs::write(&header_path, "<p class=\"notice\">Internal use only</p>")?;
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--header-file")
+        .arg(&header_path)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/dir1/"))?;
+    let text = resp.text()?;
+    assert!(text
+        .contains(r#"<div class="custom-header"><p class="notice">Internal use only</p></div>"#));
+    assert!(text.contains(r#"<div class="custom-footer"></div>"#));
+
+    child.kill()?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[rstest]
+fn header_file_sandbox(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let dir = assert_fs::TempDir::new()?;
+    let header_path = dir.path().join("header.html");
+    std::fs::write(&header_path, "<p>Internal use only</p>")?;
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--sandbox")
+        .arg("--header-file")
+        .arg(&header_path)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/dir1/"))?;
+    assert!(resp.text()?.contains("<p>Internal use only</p>"));
+
+    child.kill()?;
+    Ok(())
+}
@@ -26,6 +26,25 @@ fn no_auth(#[with(&["--auth", "user:pass@/:rw", "-A"])] server: TestServer) -> R
     Ok(())
 }
 
+#[rstest]
+fn auth_internal_endpoints(
+    #[with(&["--auth", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}__dufs__/df", server.url());
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 401);
+    let resp = send_with_digest_auth(fetch!(b"GET", &url), "user", "pass")?;
+    assert_eq!(resp.status(), 200);
+    let url = format!("{}__dufs__/info", server.url());
+    let resp = send_with_digest_auth(fetch!(b"GET", &url), "user", "pass")?;
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(
+        json["features"]["auth"],
+        serde_json::json!(["digest", "basic"])
+    );
+    Ok(())
+}
+
 #[rstest]
 #[case(server(&["--auth", "user:pass@/:rw", "-A"]), "user", "pass")]
 #[case(server(&["--auth", "user:pa:ss@1@/:rw", "-A"]), "user", "pa:ss@1")]
@@ -339,3 +358,453 @@ fn auth_precedence(
 
     Ok(())
 }
+
+#[rstest]
+fn auth_bulk_delete_per_item(
+    #[with(&["--auth", "user:pass@/:rw,/dir1:ro", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}?delete", server.url());
+    let resp = send_with_digest_auth(
+        fetch!(b"POST", &url).body(r#"["test.html", "dir1"]"#),
+        "user",
+        "pass",
+    )?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json[0]["status"], 204);
+    assert_eq!(json[1]["status"], 403);
+    assert!(server.path().join("dir1").exists());
+    Ok(())
+}
+
+fn create_guest_token(url: &str, body: &'static str) -> Result<serde_json::Value, Error> {
+    let resp = send_with_digest_auth(
+        fetch!(b"POST", format!("{url}?tokens")).body(body),
+        "user",
+        "pass",
+    )?;
+    assert_eq!(resp.status(), 201);
+    Ok(serde_json::from_str(&resp.text()?)?)
+}
+
+#[rstest]
+fn guest_read_token(
+    #[with(&["--auth", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let json = create_guest_token(&format!("{}dir1/", server.url()), "")?;
+    assert_eq!(json["path"], "dir1");
+    assert_eq!(json["perm"], "read");
+    let token = json["token"].as_str().unwrap();
+    let resp = fetch!(
+        b"GET",
+        format!("{}dir1/test.html?token={token}", server.url())
+    )
+    .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", format!("{}dir1/", server.url()))
+        .header("Authorization", format!("Bearer {token}"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(
+        b"GET",
+        format!("{}dir2/test.html?token={token}", server.url())
+    )
+    .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"PUT", format!("{}dir1/new?token={token}", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(
+        b"DELETE",
+        format!("{}dir1/test.html?token={token}", server.url())
+    )
+    .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn guest_token_cookie(
+    #[with(&["--auth", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let json = create_guest_token(&format!("{}dir1/", server.url()), "")?;
+    let token = json["token"].as_str().unwrap();
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let resp = client
+        .get(format!("{}dir1/?token={token}&sort=name", server.url()))
+        .header("accept", "text/html")
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/dir1/?sort=name");
+    let cookie = resp.headers().get("set-cookie").unwrap().to_str()?;
+    assert!(cookie.contains("HttpOnly"));
+    let cookie = cookie.split(';').next().unwrap().to_string();
+
+    let resp = client
+        .get(format!("{}dir1/test.html", server.url()))
+        .header("cookie", &cookie)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    // Outside the token's folder the cookie is ignored
+    let resp = client
+        .get(format!("{}dir2/test.html", server.url()))
+        .header("cookie", &cookie)
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn guest_upload_token(
+    #[with(&["--auth", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let json = create_guest_token(&format!("{}dir1/", server.url()), r#"{"perm":"upload"}"#)?;
+    let token = json["token"].as_str().unwrap();
+    let resp = fetch!(b"PUT", format!("{}dir1/new?token={token}", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(
+        b"PUT",
+        format!("{}dir1/test.html?token={token}", server.url())
+    )
+    .body(b"abc".to_vec())
+    .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{}dir1/new?token={token}", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn guest_token_expire_and_revoke(
+    #[with(&["--auth", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let json = create_guest_token(server.url().as_ref(), r#"{"expires_in":0}"#)?;
+    let token = json["token"].as_str().unwrap();
+    let resp = fetch!(b"GET", format!("{}test.html?token={token}", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+
+    let json = create_guest_token(server.url().as_ref(), r#"{"expires_in":3600}"#)?;
+    let token = json["token"].as_str().unwrap();
+    let resp = send_with_digest_auth(
+        fetch!(b"GET", format!("{}?tokens", server.url())),
+        "user",
+        "pass",
+    )?;
+    let list: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(list.as_array().unwrap().len(), 1);
+    let resp = send_with_digest_auth(
+        fetch!(b"POST", format!("{}?tokens", server.url()))
+            .body(format!(r#"{{"revoke":"{token}"}}"#)),
+        "user",
+        "pass",
+    )?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"GET", format!("{}test.html?token={token}", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn guest_token_requires_user(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?tokens", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn auth_user_root(
+    #[with(&["--auth", "alice:pass@/:rw", "--auth", "bob:pass@/:rw", "--user-root", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}?json", server.url());
+    let resp = send_with_digest_auth(fetch!(b"GET", &url), "alice", "pass")?;
+    assert_eq!(resp.status(), 200);
+    assert!(server.path().join("alice").is_dir());
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    let names: Vec<_> = json["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["alice"]);
+
+    let url = format!("{}alice/file1", server.url());
+    let resp = send_with_digest_auth(fetch!(b"PUT", &url).body(b"abc".to_vec()), "alice", "pass")?;
+    assert_eq!(resp.status(), 201);
+    let resp = send_with_digest_auth(fetch!(b"GET", &url), "bob", "pass")?;
+    assert_eq!(resp.status(), 403);
+    let url = format!("{}index.html", server.url());
+    let resp = send_with_digest_auth(fetch!(b"GET", &url), "alice", "pass")?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn auth_login_page(
+    #[with(&["--auth", "user:pass@/:rw", "--login-page", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let resp = client
+        .get(format!("{}dir1/", server.url()))
+        .header("accept", "text/html")
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    assert_eq!(
+        resp.headers().get("location").unwrap(),
+        "/__dufs__/login?next=/dir1/"
+    );
+    let resp = client.get(format!("{}dir1/", server.url())).send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = client
+        .get(format!("{}__dufs__/login?next=/dir1/", server.url()))
+        .send()?;
+    assert!(resp.text()?.contains(r#"name="next" value="/dir1/""#));
+
+    let login_url = format!("{}__dufs__/login", server.url());
+    let resp = client
+        .post(&login_url)
+        .form(&[("user", "user"), ("pass", "wrong"), ("next", "/dir1/")])
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    assert!(resp.headers().get("set-cookie").is_none());
+    let resp = client
+        .post(&login_url)
+        .form(&[("user", "user"), ("pass", "pass"), ("next", "/dir1/")])
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/dir1/");
+    let cookie = resp.headers().get("set-cookie").unwrap().to_str()?;
+    assert!(cookie.contains("HttpOnly"));
+    let cookie = cookie.split(';').next().unwrap().to_string();
+
+    let url = format!("{}file1", server.url());
+    let resp = client
+        .put(&url)
+        .header("cookie", &cookie)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = client
+        .get(&url)
+        .header("cookie", format!("{cookie}x"))
+        .send()?;
+    assert_eq!(resp.status(), 401);
+
+    let resp = client
+        .get(format!("{}__dufs__/logout", server.url()))
+        .header("cookie", &cookie)
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    let set_cookie = resp.headers().get("set-cookie").unwrap().to_str()?;
+    assert!(set_cookie.starts_with("dufs_session=;"));
+    assert!(set_cookie.contains("Max-Age=0"));
+    // The old cookie is rejected by the server too
+    let resp = client.get(&url).header("cookie", &cookie).send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn auth_login_page_next(
+    #[with(&["--auth", "user:pass@/:rw", "--login-page", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    for next in [
+        "/\\evil.com",
+        "/%5Cevil.com",
+        "//evil.com",
+        "/%0D%0Aevil.com",
+    ] {
+        let resp = client
+            .get(format!("{}__dufs__/login?next={next}", server.url()))
+            .send()?;
+        assert!(resp.text()?.contains(r#"name="next" value="/""#));
+    }
+    let resp = client
+        .post(format!("{}__dufs__/login", server.url()))
+        .form(&[("user", "user"), ("pass", "pass"), ("next", "/\\evil.com")])
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/");
+    Ok(())
+}
+
+#[rstest]
+fn auth_login_page_move_and_batch(
+    #[with(&["--auth", "user:pass@/:rw", "--login-page", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let resp = client
+        .post(format!("{}__dufs__/login", server.url()))
+        .form(&[("user", "user"), ("pass", "pass"), ("next", "/")])
+        .send()?;
+    let cookie = resp.headers().get("set-cookie").unwrap().to_str()?;
+    let cookie = cookie.split(';').next().unwrap().to_string();
+
+    let resp = client
+        .request(
+            reqwest::Method::from_bytes(b"MOVE")?,
+            format!("{}index.html", server.url()),
+        )
+        .header("cookie", &cookie)
+        .header("Destination", format!("{}index2.html", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(server.path().join("index2.html").exists());
+
+    let resp = client
+        .post(format!("{}?delete", server.url()))
+        .header("cookie", &cookie)
+        .body(r#"["index2.html", "dir1/"]"#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    let statuses: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, [204, 204]);
+    assert!(!server.path().join("index2.html").exists());
+    assert!(!server.path().join("dir1").exists());
+    Ok(())
+}
+
+#[rstest]
+fn auth_access_file(
+    #[with(&["-a", "user:pass@/:rw", "-a", "user2:pass2@/:rw", "-a", "@/", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(
+        server.path().join("dir1/.dufs-access"),
+        "require user user2\n",
+    )?;
+    std::fs::write(server.path().join("dir2/.dufs-access"), "readonly\n")?;
+
+    let url = format!("{}dir1/index.html", server.url());
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("user2", Some("pass2"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+
+    // Denied subtrees are left out of listings and searches
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(!paths.contains("dir1/"));
+    assert!(paths.contains("dir2/"));
+    let url = format!("{}?q=index.html", server.url());
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(!paths.contains("dir1/index.html"));
+    assert!(paths.contains("dir2/index.html"));
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("user2", Some("pass2"))
+        .send()?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("dir1/index.html"));
+
+    let url = format!("{}dir2/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"MOVE", format!("{}test.txt", server.url()))
+        .header("Destination", format!("{}dir2/test.txt", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+
+    // The access files themselves can't be read or changed over HTTP
+    let url = format!("{}dir1/.dufs-access", server.url());
+    let resp = fetch!(b"DELETE", &url)
+        .basic_auth("user2", Some("pass2"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("user2", Some("pass2"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{}dir2/", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(!paths.contains(".dufs-access"));
+
+    // A readonly subtree can't be deleted or moved with its parent
+    std::fs::create_dir_all(server.path().join("outer/inner"))?;
+    std::fs::write(server.path().join("outer/inner/.dufs-access"), "readonly\n")?;
+    let url = format!("{}outer/", server.url());
+    let resp = fetch!(b"DELETE", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"MOVE", &url)
+        .header("Destination", format!("{}outer2/", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"POST", format!("{}?delete", server.url()))
+        .body(r#"["outer/"]"#)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json[0]["status"], 403);
+    assert!(server.path().join("outer/inner").exists());
+    Ok(())
+}
+
+#[rstest]
+fn auth_vary(
+    #[with(&["--auth", "user:pass@/:rw", "--login-page", "--precompressed", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = send_with_digest_auth(fetch!(b"GET", &url), "user", "pass")?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("vary").unwrap(),
+        "Accept-Encoding, Authorization, Cookie, Accept"
+    );
+    let etag = resp.headers().get("etag").unwrap().clone();
+    let resp = send_with_digest_auth(
+        fetch!(b"GET", &url).header("if-none-match", etag),
+        "user",
+        "pass",
+    )?;
+    assert_eq!(resp.status(), 304);
+    assert_eq!(
+        resp.headers().get("vary").unwrap(),
+        "Accept-Encoding, Authorization, Cookie, Accept"
+    );
+    let resp = fetch!(b"GET", server.url()).send()?;
+    assert_eq!(resp.status(), 401);
+    assert_eq!(
+        resp.headers().get("vary").unwrap(),
+        "Authorization, Cookie, Accept"
+    );
+    Ok(())
+}
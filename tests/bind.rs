@@ -6,8 +6,10 @@ use assert_cmd::prelude::*;
 use assert_fs::fixture::TempDir;
 use regex::Regex;
 use rstest::rstest;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::net::TcpStream;
 use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
 #[rstest]
 #[case(&["-b", "20.205.243.166"])]
@@ -82,3 +84,100 @@ fn validate_printed_urls(tmpdir: TempDir, port: u16, #[case] args: &[&str]) -> R
 
     Ok(())
 }
+
+#[rstest]
+fn keep_alive_closes_idle_connections(
+    #[with(&["--keep-alive", "1"])] server: TestServer,
+) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port()))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    stream.write_all(b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+    let mut buf = [0; 4096];
+    let n = stream.read(&mut buf)?;
+    assert!(std::str::from_utf8(&buf[..n])?.starts_with("HTTP/1.1 200"));
+    let start = Instant::now();
+    assert_eq!(stream.read(&mut buf)?, 0);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    Ok(())
+}
+
+#[rstest]
+fn keep_alive_disabled(#[with(&["--keep-alive", "0"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("connection").unwrap(), "close");
+    Ok(())
+}
+
+#[rstest]
+fn socket_options(
+    #[with(&["--tcp-nodelay", "--no-reuse-addr", "--backlog", "16"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn reuse_port_shares_port(
+    #[with(&["--reuse-port"])] server: TestServer,
+    tmpdir: TempDir,
+) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-b")
+        .arg("127.0.0.1")
+        .arg("-p")
+        .arg(server.port().to_string())
+        .arg("--reuse-port")
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut buf = [0; 1000];
+    let n = child.stdout.as_mut().unwrap().read(&mut buf)?;
+    assert!(std::str::from_utf8(&buf[..n])?.contains("Listening on"));
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn bind_readonly(
+    tmpdir: TempDir,
+    port: u16,
+    #[from(port)] readonly_port: u16,
+) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-A")
+        .arg("-b")
+        .arg("127.0.0.1")
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--bind-readonly")
+        .arg(format!("127.0.0.1:{readonly_port}"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+    wait_for_port(readonly_port);
+
+    let client = reqwest::blocking::Client::new();
+    let url = format!("http://127.0.0.1:{port}/test.txt");
+    let readonly_url = format!("http://127.0.0.1:{readonly_port}/test.txt");
+    let resp = client.put(&readonly_url).body("abc").send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = client.put(&url).body("abc").send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = client.get(&readonly_url).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "abc");
+    let resp = client.delete(&readonly_url).send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = client
+        .get(format!("http://127.0.0.1:{readonly_port}/?json"))
+        .send()?;
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["allow_upload"], false);
+
+    child.kill()?;
+    Ok(())
+}
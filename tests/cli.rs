@@ -5,7 +5,13 @@ mod fixtures;
 use assert_cmd::prelude::*;
 use clap::ValueEnum;
 use clap_complete::Shell;
+#[cfg(unix)]
+use fixtures::port;
 use fixtures::Error;
+#[cfg(unix)]
+use predicates::str::contains;
+#[cfg(unix)]
+use rstest::rstest;
 use std::process::Command;
 
 #[test]
@@ -30,3 +36,90 @@ fn print_completions() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+/// Run the benchmark and clean up its files.
+fn bench_reports_workloads() -> Result<(), Error> {
+    let tmpdir = assert_fs::TempDir::new()?;
+    let output = Command::cargo_bin("dufs")?
+        .args(["bench", "--requests", "20", "--concurrency", "2"])
+        .arg(tmpdir.path())
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    for workload in ["small-file", "large-file", "ranged", "zip"] {
+        assert!(stdout.contains(workload), "{stdout}");
+    }
+    assert_eq!(std::fs::read_dir(tmpdir.path())?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+/// Refuse a blocking pool that archive jobs could exhaust.
+fn blocking_threads_exceed_archive_jobs() -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .args(["--blocking-threads", "4"])
+        .assert()
+        .failure();
+    Command::cargo_bin("dufs")?
+        .args(["--blocking-threads", "4", "--max-archive-jobs", "0"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+/// The sandbox can't run the preview programs or ffmpeg.
+fn sandbox_with_preview() -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .args(["--sandbox", "--preview", "html>txt=cp {input} {output}"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "--sandbox can't be used with --preview",
+        ));
+    Command::cargo_bin("dufs")?
+        .args(["--sandbox", "--ffmpeg", "cp"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "--sandbox can't be used with --ffmpeg",
+        ));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+/// Fail to start when the user to switch to doesn't exist.
+fn unknown_user_fails(port: u16) -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--user")
+        .arg("dufs-no-such-user")
+        .assert()
+        .failure()
+        .stderr(contains("Unknown user `dufs-no-such-user`"));
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[rstest]
+/// Look up the user before the sandbox hides the user database.
+fn sandbox_with_user(port: u16) -> Result<(), Error> {
+    // Switching to root only works as root, where the server keeps running
+    let output = assert_cmd::Command::cargo_bin("dufs")?
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--sandbox", "--user", "root"])
+        .timeout(std::time::Duration::from_secs(1))
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Unknown user"), "{stderr}");
+
+    Ok(())
+}
@@ -70,3 +70,58 @@ fn hidden_dir_only(
     assert_eq!(paths.len(), count);
     Ok(())
 }
+
+#[rstest]
+fn dufsignore(#[with(&["--allow-search"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join(".dufsignore"), "*.html\ndir2/\n")?;
+    std::fs::write(server.path().join("dir1/.dufsignore"), "!index.html\n")?;
+    let resp = reqwest::blocking::get(server.url())?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("dir1/"));
+    assert!(paths.contains("test.txt"));
+    assert!(!paths.contains("dir2/"));
+    assert!(!paths.contains("index.html"));
+
+    let resp = reqwest::blocking::get(format!("{}dir1/", server.url()))?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("index.html"));
+    assert!(!paths.contains("test.html"));
+
+    let resp = reqwest::blocking::get(format!("{}?q={}", server.url(), "html"))?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("dir1/index.html"));
+    assert!(paths.iter().all(|p| p == "dir1/index.html"));
+
+    let resp = reqwest::blocking::get(format!("{}?feed", server.url()))?;
+    let body = resp.text()?;
+    assert!(body.contains("test.txt"));
+    assert!(!body.contains("dir2/"));
+    assert!(!body.contains("test.html"));
+
+    // Edits are picked up without a restart
+    std::fs::write(server.path().join(".dufsignore"), "")?;
+    let resp = reqwest::blocking::get(server.url())?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("index.html"));
+    Ok(())
+}
+
+#[rstest]
+#[case(server(&["--allow-search"] as &[&str]), true)]
+#[case(server(&["--allow-search", "--gitignore"]), false)]
+fn gitignore(#[case] server: TestServer, #[case] exist: bool) -> Result<(), Error> {
+    std::fs::write(server.path().join(".gitignore"), "dir2/\n")?;
+    std::fs::write(server.path().join("dir1/.gitignore"), "test.txt\n")?;
+    let resp = reqwest::blocking::get(server.url())?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("dir1/"));
+    assert!(paths.contains("test.txt"));
+    assert_eq!(paths.contains("dir2/"), exist);
+
+    let resp = reqwest::blocking::get(format!("{}?q={}", server.url(), "test.txt"))?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("test.txt"));
+    assert_eq!(paths.contains("dir1/test.txt"), exist);
+    assert_eq!(paths.contains("dir2/test.txt"), exist);
+    Ok(())
+}
@@ -1,9 +1,12 @@
 mod fixtures;
 mod utils;
 
-use fixtures::{server, Error, TestServer, BIN_FILE};
+use assert_cmd::prelude::*;
+use assert_fs::fixture::TempDir;
+use fixtures::{port, server, tmpdir, wait_for_port, Error, TestServer, BIN_FILE};
 use rstest::rstest;
 use serde_json::Value;
+use std::process::{Command, Stdio};
 use utils::retrieve_edit_file;
 
 #[rstest]
@@ -56,6 +59,254 @@ fn get_dir_zip(#[case] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_zip_cjk_name(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::create_dir(server.path().join("文档"))?;
+    let resp = reqwest::blocking::get(format!("{}%E6%96%87%E6%A1%A3/?zip", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"__.zip\"; filename*=UTF-8''%E6%96%87%E6%A1%A3.zip"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_zip_name(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/?zip&name=..%2Fbackup", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"backup.zip\""
+    );
+    let resp = reqwest::blocking::get(format!("{}dir1/?zip&name=", server.url()))?;
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"dir1.zip\""
+    );
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_zip_total_bytes(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::create_dir_all(server.path().join("zipme/sub"))?;
+    std::fs::write(server.path().join("zipme/a.txt"), "abc")?;
+    std::fs::write(server.path().join("zipme/sub/b.txt"), "defgh")?;
+    let resp = fetch!(b"HEAD", format!("{}zipme/?zip", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("x-archive-total-bytes").unwrap(), "8");
+    let resp = reqwest::blocking::get(format!("{}zipme/?zip", server.url()))?;
+    assert_eq!(resp.headers().get("x-archive-total-bytes").unwrap(), "8");
+    assert!(!resp.bytes()?.is_empty());
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[rstest]
+fn get_file_no_atime_updates(
+    #[with(&["-A", "--no-atime-updates"])] server: TestServer,
+) -> Result<(), Error> {
+    use std::time::{Duration, UNIX_EPOCH};
+    let path = server.path().join("index.html");
+    // Older than the mtime, which even `relatime` mounts would update
+    let atime = UNIX_EPOCH + Duration::from_secs(981173106);
+    std::fs::File::options()
+        .write(true)
+        .open(&path)?
+        .set_times(
+            std::fs::FileTimes::new()
+                .set_accessed(atime)
+                .set_modified(atime + Duration::from_secs(60)),
+        )?;
+    for url in ["index.html", "index.html?hash", "?zip"] {
+        let resp = reqwest::blocking::get(format!("{}{url}", server.url()))?;
+        assert_eq!(resp.status(), 200);
+        assert!(!resp.bytes()?.is_empty());
+    }
+    assert_eq!(std::fs::metadata(&path)?.accessed()?, atime);
+    Ok(())
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[rstest]
+fn get_file_io_uring(#[with(&["-A", "--io-uring"])] server: TestServer) -> Result<(), Error> {
+    // Spans several read chunks, and ends in a partial one
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let url = format!("{}big.bin", server.url());
+    let resp = fetch!(b"PUT", &url).body(data.clone()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(std::fs::read(server.path().join("big.bin"))?, data);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.bytes()?.to_vec(), data);
+    let resp = fetch!(b"GET", &url)
+        .header("range", "bytes=70000-200000")
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.bytes()?.to_vec(), data[70000..=200000]);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_zip_max_jobs(
+    #[with(&["-A", "--max-archive-jobs", "1"])] server: TestServer,
+) -> Result<(), Error> {
+    // Large enough to keep the first archive job busy until its body is read
+    let data: Vec<u8> = (0..4_000_000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+    std::fs::write(server.path().join("large.bin"), data)?;
+    let resp1 = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp1.status(), 200);
+    let resp2 = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp2.status(), 429);
+    assert!(resp2.headers().contains_key("retry-after"));
+    let len = resp1.bytes()?.len();
+    assert!(len > 0);
+    let resp3 = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp3.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_zip_mtime(#[with(&["-A", "--utc"])] server: TestServer) -> Result<(), Error> {
+    // 2001-02-03T04:05:06Z
+    let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(981173106);
+    std::fs::File::options()
+        .write(true)
+        .open(server.path().join("dir1/index.html"))?
+        .set_modified(mtime)?;
+    let resp = reqwest::blocking::get(format!("{}dir1/?zip", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let data = resp.bytes()?;
+    // Finds the local file header of the entry
+    let name = b"index.html";
+    let header = data
+        .windows(4)
+        .enumerate()
+        .filter(|(_, v)| *v == b"PK\x03\x04")
+        .map(|(i, _)| &data[i..])
+        .find(|v| v.len() > 30 + name.len() && &v[30..30 + name.len()] == name)
+        .expect("Missing entry");
+    let time = u16::from_le_bytes([header[10], header[11]]);
+    let date = u16::from_le_bytes([header[12], header[13]]);
+    assert_eq!(time, (4 << 11) | (5 << 5) | (6 / 2));
+    assert_eq!(date, ((2001 - 1980) << 9) | (2 << 5) | 3);
+    Ok(())
+}
+
+#[rstest]
+fn get_max_requests(
+    #[with(&["-A", "--max-requests", "2"])] server: TestServer,
+) -> Result<(), Error> {
+    // Large enough to stay in flight until its body is read
+    let data: Vec<u8> = (0..16_000_000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+    std::fs::write(server.path().join("large.bin"), data)?;
+    let resp1 = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp1.status(), 200);
+    // Zip downloads are refused from half of the limit, plain ones still pass
+    let resp2 = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp2.status(), 503);
+    assert_eq!(resp2.headers().get("retry-after").unwrap(), "1");
+    let resp3 = reqwest::blocking::get(format!("{}large.bin", server.url()))?;
+    assert_eq!(resp3.status(), 200);
+    let resp4 = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp4.status(), 503);
+    assert!(!resp1.bytes()?.is_empty());
+    assert!(!resp3.bytes()?.is_empty());
+
+    let resp = reqwest::blocking::get(format!("{}__dufs__/metrics", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["load"]["max_requests"], 2);
+    assert_eq!(json["load"]["refused_requests"], 1);
+    assert_eq!(json["load"]["refused_archives"], 1);
+    Ok(())
+}
+
+#[rstest]
+fn admin_api(#[with(&["-A", "--admin-token", "secret"])] server: TestServer) -> Result<(), Error> {
+    let admin_url = |action: &str| format!("{}__dufs__/admin/{action}", server.url());
+    let resp = reqwest::blocking::get(admin_url("status"))?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"GET", admin_url("status"))
+        .bearer_auth("wrong")
+        .send()?;
+    assert_eq!(resp.status(), 401);
+
+    // Large enough to keep the archive job listed until its body is read
+    let data: Vec<u8> = (0..16_000_000u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+    std::fs::write(server.path().join("large.bin"), data)?;
+    let zip_resp = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(zip_resp.status(), 200);
+    let resp = fetch!(b"GET", admin_url("status"))
+        .bearer_auth("secret")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["readonly"], false);
+    assert_eq!(json["jobs"][0]["kind"], "archive");
+    assert_eq!(json["jobs"][0]["path"], "/");
+    assert!(!zip_resp.bytes()?.is_empty());
+
+    let resp = fetch!(b"POST", admin_url("readonly"))
+        .bearer_auth("secret")
+        .body(r#"{"readonly":true}"#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["readonly"], true);
+    let url = format!("{}test.txt", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"POST", admin_url("readonly"))
+        .bearer_auth("secret")
+        .body(r#"{"readonly":false}"#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+
+    let resp = fetch!(b"POST", admin_url("invalidate-caches"))
+        .bearer_auth("secret")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["previews"], 0);
+    Ok(())
+}
+
+#[rstest]
+fn admin_api_drain(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--admin-token")
+        .arg("secret")
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+
+    let url = format!("http://localhost:{port}/__dufs__/admin/drain");
+    let resp = fetch!(b"POST", &url).bearer_auth("secret").send()?;
+    assert_eq!(resp.status(), 202);
+    let start = std::time::Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        assert!(start.elapsed().as_secs() < 10, "server did not exit");
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+    assert!(status.success());
+    Ok(())
+}
+
 #[rstest]
 fn get_dir_json(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
@@ -69,16 +320,341 @@ fn get_dir_json(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_readme(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(
+        server.path().join("dir1/README.md"),
+        "# Usage\n\n[Files](test.txt) <b>raw</b>",
+    )?;
+    let resp = reqwest::blocking::get(format!("{}dir1?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    let readme = json["readme"].as_str().unwrap();
+    assert!(readme.contains("<h1>Usage</h1>"));
+    assert!(readme.contains(r#"<a href="/dir1/test.txt">Files</a>"#));
+    assert!(readme.contains("&lt;b&gt;raw&lt;/b&gt;"));
+
+    let resp = reqwest::blocking::get(format!("{}dir1/?q=test.txt&json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert!(json.get("readme").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_readme_custom(
+    #[with(&["--readme", "secret.md", "--hidden", "secret.md"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("dir1/secret.md"), "secret")?;
+    let resp = reqwest::blocking::get(format!("{}dir1/?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert!(json.get("readme").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_breadcrumb(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(
+        json["breadcrumb"],
+        serde_json::json!([
+            { "name": "", "href": "/" },
+            { "name": "dir1", "href": "/dir1/" },
+        ])
+    );
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_breadcrumb_path_prefix(
+    #[with(&["--path-prefix", "xyz"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(server.path().join("dir1/a b"))?;
+    let resp = reqwest::blocking::get(format!("{}xyz/dir1/a%20b/?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    let hrefs: Vec<&str> = json["breadcrumb"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["href"].as_str().unwrap())
+        .collect();
+    assert_eq!(hrefs, ["/xyz/", "/xyz/dir1/", "/xyz/dir1/a%20b/"]);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_etag(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}dir1/?json", server.url());
+    let resp = reqwest::blocking::get(&url)?;
+    let etag = resp.headers().get("etag").unwrap().clone();
+    assert!(etag.to_str()?.starts_with("W/"));
+    let resp = fetch!(b"GET", &url).header("if-none-match", &etag).send()?;
+    assert_eq!(resp.status(), 304);
+    assert_eq!(resp.headers().get("etag").unwrap(), &etag);
+    assert_eq!(resp.text()?, "");
+
+    let resp = fetch!(b"GET", format!("{}dir1/", server.url()))
+        .header("if-none-match", &etag)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_ne!(resp.headers().get("etag").unwrap(), &etag);
+
+    std::fs::write(server.path().join("dir1/new-file"), b"new")?;
+    let resp = fetch!(b"GET", &url).header("if-none-match", &etag).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_ne!(resp.headers().get("etag").unwrap(), &etag);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_json_depth_infinity(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}dir1/?json", server.url()))
+        .header("depth", "infinity")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    let names: Vec<&str> = json["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["name"].as_str().unwrap())
+        .collect();
+    assert!(names.contains(&"test.html"));
+    assert!(names.iter().all(|v| !v.starts_with('/')));
+
+    let resp = fetch!(b"GET", format!("{}?json", server.url()))
+        .header("depth", "infinity")
+        .send()?;
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    let names: Vec<&str> = json["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["name"].as_str().unwrap())
+        .collect();
+    assert!(names.contains(&"dir1"));
+    assert!(names.contains(&"dir1/test.html"));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_json_utc(#[with(&["--utc"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert_eq!(json["utc"], true);
+    for item in json["paths"].as_array().unwrap() {
+        let modified = item["modified"].as_str().unwrap();
+        assert!(modified.ends_with('Z'));
+        let datetime = chrono::DateTime::parse_from_rfc3339(modified).unwrap();
+        assert_eq!(datetime.timestamp(), item["mtime"].as_i64().unwrap() / 1000);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn get_dir_json_columns(
+    #[with(&["--show-columns", "size,type,owner,mode"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert_eq!(
+        json["columns"],
+        serde_json::json!(["size", "type", "owner", "mode"])
+    );
+    let paths = json["paths"].as_array().unwrap();
+    let find = |name: &str| paths.iter().find(|v| v["name"] == name).unwrap();
+    let file = find("index.html");
+    assert_eq!(file["type"], "text/html");
+    assert!(file["mode"].as_str().unwrap().starts_with("-rw"));
+    assert!(!file["owner"].as_str().unwrap().is_empty());
+    let dir = find("dir1");
+    assert!(dir.get("type").is_none());
+    assert!(dir["mode"].as_str().unwrap().starts_with('d'));
+    assert!(dir.get("group").is_none());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn get_dir_json_permissions(
+    #[with(&["--show-permissions"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert_eq!(
+        json["columns"],
+        serde_json::json!(["mtime", "size", "mode", "owner", "group"])
+    );
+    let paths = json["paths"].as_array().unwrap();
+    let file = paths.iter().find(|v| v["name"] == "index.html").unwrap();
+    assert!(file["mode"].as_str().unwrap().starts_with("-rw"));
+    assert!(!file["owner"].as_str().unwrap().is_empty());
+    assert!(!file["group"].as_str().unwrap().is_empty());
+    Ok(())
+}
+
+#[rstest]
+fn get_disk_usage(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}__dufs__/df", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    let mount = &json["mounts"][0];
+    assert_eq!(mount["path"], "/");
+    let total = mount["total"].as_u64().unwrap();
+    assert!(total > 0);
+    assert!(mount["used"].as_u64().unwrap() <= total);
+    assert!(mount["available"].as_u64().unwrap() <= total);
+    let resp = fetch!(b"PUT", format!("{}__dufs__/df", server.url())).send()?;
+    assert_eq!(resp.status(), 405);
+    Ok(())
+}
+
+#[rstest]
+fn get_server_info(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}__dufs__/info", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["features"]["webdav"], true);
+    assert_eq!(json["features"]["auth"].as_array().unwrap().len(), 0);
+    assert_eq!(json["mounts"][0]["path"], "/");
+    assert_eq!(json["permissions"]["allow_upload"], true);
+    assert_eq!(json["permissions"]["allow_delete"], false);
+    Ok(())
+}
+
+#[rstest]
+fn get_openapi(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}__dufs__/openapi.json", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/json"
+    );
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert!(json["openapi"].as_str().unwrap().starts_with("3."));
+    assert_eq!(json["info"]["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["servers"][0]["url"], "/");
+    assert!(json["paths"]["/{path}"]["put"].is_object());
+    Ok(())
+}
+
 #[rstest]
 fn get_dir_simple(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}?simple", server.url()))?;
     assert_eq!(resp.status(), 200);
     assert_eq!(
         resp.headers().get("content-type").unwrap(),
-        "text/html; charset=utf-8"
+        "text/plain; charset=utf-8"
     );
     let text = resp.text().unwrap();
     assert!(text.split('\n').any(|v| v == "index.html"));
+    assert!(text.split('\n').any(|v| v == "dir1/"));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_csv(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?csv", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    let text = resp.text().unwrap();
+    let mut lines = text.lines();
+    assert_eq!(lines.next(), Some("name,size,mtime"));
+    assert!(lines.clone().any(|v| v.starts_with("index.html,18,")));
+    assert!(lines.any(|v| v.starts_with("dir1/,")));
+    #[cfg(not(target_os = "windows"))]
+    assert!(text.contains("\"file\n1.txt\",18,"));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_tree(#[with(&["--hidden", ".git"])] server: TestServer) -> Result<(), Error> {
+    std::fs::create_dir_all(server.path().join("dir1/a/b/c"))?;
+    let resp = reqwest::blocking::get(format!("{}?tree", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    let dirs = json["dirs"].as_array().unwrap();
+    let find = |name: &str| dirs.iter().find(|v| v["name"] == name);
+    assert!(find(".git").is_none());
+    assert_eq!(
+        find("dir1").unwrap()["dirs"],
+        serde_json::json!([{ "name": "a" }])
+    );
+    assert_eq!(find("dir2").unwrap()["dirs"], serde_json::json!([]));
+
+    let resp = reqwest::blocking::get(format!("{}dir1/a/?tree&depth=1", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert_eq!(json["dirs"], serde_json::json!([{ "name": "b" }]));
+
+    let resp = reqwest::blocking::get(format!("{}?tree&depth=0", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_changes(#[with(&["--allow-all"])] server: TestServer) -> Result<(), Error> {
+    let since = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    std::fs::write(server.path().join("dir1/new.txt"), b"new")?;
+    let resp = fetch!(b"DELETE", format!("{}dir1/test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(format!("{}dir1/?changes&since={since}", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert_eq!(json["complete"], true);
+    assert!(json["until"].as_u64().unwrap() as u128 > since);
+    let changes = json["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0]["name"], "new.txt");
+    assert_eq!(changes[0]["size"], 3);
+    assert_eq!(changes[1]["name"], "test.html");
+    assert_eq!(changes[1]["change"], "deleted");
+
+    let resp = reqwest::blocking::get(format!("{}?changes", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_feed(server: TestServer) -> Result<(), Error> {
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    std::fs::write(server.path().join("dir1/latest.txt"), b"latest")?;
+    let resp = reqwest::blocking::get(format!("{}?feed", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/atom+xml; charset=utf-8"
+    );
+    let text = resp.text()?;
+    assert!(text.contains(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#));
+    let first_entry = text.split("<entry>").nth(1).unwrap();
+    assert!(first_entry.contains("<title>dir1/latest.txt</title>"));
+    assert!(first_entry.contains(&format!(
+        r#"<link href="{}dir1/latest.txt"/>"#,
+        server.url()
+    )));
+    let resp = fetch!(b"GET", format!("{}?feed", server.url()))
+        .header("host", "evil.com/x?")
+        .send()?;
+    let text = resp.text()?;
+    assert!(!text.contains("evil.com"));
+    assert!(text.contains(r#"<link href="/dir1/latest.txt"/>"#));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_feed_public_url(
+    #[with(&["--public-url", "https://files.example.com/"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?feed", server.url()))?;
+    let text = resp.text()?;
+    assert!(text.contains(r#"<link href="https://files.example.com/"/>"#));
     Ok(())
 }
 
@@ -137,6 +713,98 @@ fn get_dir_search4(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_search_ndjson(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?q={}&ndjson", server.url(), "test.html"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/x-ndjson"
+    );
+    let text = resp.text()?;
+    let names: Vec<String> = text
+        .lines()
+        .map(|line| {
+            let item: serde_json::Value = serde_json::from_str(line).unwrap();
+            item["name"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert!(names.len() > 1);
+    assert!(names.iter().all(|v| v.contains("test.html")));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_limit(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp =
+        reqwest::blocking::get(format!("{}?q={}&ndjson&limit=1", server.url(), "test.html"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?.lines().count(), 1);
+    let resp =
+        reqwest::blocking::get(format!("{}?q={}&simple&limit=2", server.url(), "test.html"))?;
+    assert_eq!(resp.text()?.lines().count(), 2);
+    let resp = reqwest::blocking::get(format!("{}?q={}&limit=0", server.url(), "test.html"))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_modes(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let search = |query: &str| -> Result<Vec<String>, Error> {
+        let resp = reqwest::blocking::get(format!("{}?{query}&simple", server.url()))?;
+        assert_eq!(resp.status(), 200);
+        Ok(resp.text()?.lines().map(|v| v.to_string()).collect())
+    };
+    let base_name = |v: &String| v.rsplit('/').next().unwrap().to_string();
+    let names = search("q=tes&mode=prefix")?;
+    assert!(!names.is_empty());
+    assert!(names.iter().all(|v| base_name(v).starts_with("tes")));
+    assert!(search("q=est&mode=prefix")?.is_empty());
+    let names = search("q=test.html&mode=exact")?;
+    assert!(!names.is_empty());
+    assert!(names.iter().all(|v| base_name(v) == "test.html"));
+    assert!(search("q=test.htm&mode=exact")?.is_empty());
+    let names = search("q=tets.html&mode=fuzzy")?;
+    assert!(names.iter().any(|v| base_name(v) == "test.html"));
+    let names = search("q=html&mode=fuzzy")?;
+    assert_eq!(base_name(&names[0]), "test.html");
+    let resp = reqwest::blocking::get(format!("{}?q=test&mode=regex", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_scope(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let search = |query: &str| -> Result<Vec<String>, Error> {
+        let resp = reqwest::blocking::get(format!("{}?{query}&simple", server.url()))?;
+        assert_eq!(resp.status(), 200);
+        Ok(resp.text()?.lines().map(|v| v.to_string()).collect())
+    };
+    let names = search("q=test.html&max-depth=1")?;
+    assert_eq!(names, ["test.html"]);
+    let names = search("q=dir&dirs-only")?;
+    assert!(!names.is_empty());
+    assert!(names.iter().all(|v| v.ends_with('/')));
+    let names = search("q=dir&files-only")?;
+    assert!(names.iter().all(|v| !v.ends_with('/')));
+    let resp = reqwest::blocking::get(format!("{}?q=dir&dirs-only&files-only", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    let resp = reqwest::blocking::get(format!("{}?q=dir&max-depth=x", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_max_results(
+    #[with(&["-A", "--search-max-results", "2"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?q={}&simple", server.url(), "test"))?;
+    assert_eq!(resp.text()?.lines().count(), 2);
+    let resp = reqwest::blocking::get(format!("{}?q={}&simple&limit=5", server.url(), "test"))?;
+    assert_eq!(resp.text()?.lines().count(), 2);
+    Ok(())
+}
+
 #[rstest]
 fn head_dir_search(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"HEAD", format!("{}?q={}", server.url(), "test.html")).send()?;
@@ -156,6 +824,89 @@ fn empty_search(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_file_route(
+    #[with(&["--route", "/latest=/dir1/index.html", "--route", "/d2/*=/dir2"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}latest", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is dir1/index.html");
+    let resp = reqwest::blocking::get(format!("{}d2/test.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is dir2/test.txt");
+    let resp = reqwest::blocking::get(format!("{}latest/index.html", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn get_file_custom_headers(
+    #[with(&[
+        "--header",
+        "/dir1/*=Cache-Control: public, max-age=31536000, immutable",
+        "--header",
+        "/test.txt=Content-Disposition: attachment",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/test.txt", server.url()))?;
+    assert_eq!(
+        resp.headers().get("cache-control").unwrap(),
+        "public, max-age=31536000, immutable"
+    );
+    let resp = reqwest::blocking::get(format!("{}test.txt", server.url()))?;
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment"
+    );
+    assert!(resp.headers().get("cache-control").is_none());
+    let resp = reqwest::blocking::get(format!("{}dir1/missing.txt", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    assert!(resp.headers().get("cache-control").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn get_file_charset(#[with(&["-A", "--charset", "gbk"])] server: TestServer) -> Result<(), Error> {
+    let cases: [(&str, &[u8], &str); 3] = [
+        // "中文" in GBK
+        ("gbk.txt", b"\xd6\xd0\xce\xc4", "text/plain; charset=GBK"),
+        ("utf8.txt", "中文".as_bytes(), "text/plain; charset=UTF-8"),
+        (
+            "utf16.txt",
+            b"\xff\xfeh\x00i\x00",
+            "text/plain; charset=UTF-16LE",
+        ),
+    ];
+    for (name, content, content_type) in cases {
+        let url = format!("{}{}", server.url(), name);
+        let resp = fetch!(b"PUT", &url).body(content.to_vec()).send()?;
+        assert_eq!(resp.status(), 201);
+        let resp = reqwest::blocking::get(url)?;
+        assert_eq!(resp.headers().get("content-type").unwrap(), content_type);
+    }
+    Ok(())
+}
+
+#[rstest]
+fn get_file_sniff_content(
+    #[with(&["-A", "--sniff-content"])] server: TestServer,
+) -> Result<(), Error> {
+    let cases: [(&str, &[u8], &str); 3] = [
+        ("image", b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR", "image/png"),
+        ("document", b"%PDF-1.7\n\x00\xff", "application/pdf"),
+        ("unknown", b"\x00\x01\x02\x03", "application/octet-stream"),
+    ];
+    for (name, content, content_type) in cases {
+        let url = format!("{}{}", server.url(), name);
+        let resp = fetch!(b"PUT", &url).body(content.to_vec()).send()?;
+        assert_eq!(resp.status(), 201);
+        let resp = reqwest::blocking::get(url)?;
+        assert_eq!(resp.headers().get("content-type").unwrap(), content_type);
+    }
+    Ok(())
+}
+
 #[rstest]
 fn get_file(server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
@@ -189,6 +940,22 @@ fn head_file(server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn limited_runtime_threads(
+    #[with(&["--allow-archive", "--worker-threads", "1", "--blocking-threads", "5"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.bytes()?.starts_with(b"PK"));
+    let resp = reqwest::blocking::get(format!("{}index.html?hash", server.url()))?;
+    assert_eq!(
+        resp.text()?,
+        "c8dd395e3202674b9512f7b7f956e0d96a8ba8f572e785b0d5413ab83766dbc4"
+    );
+    Ok(())
+}
+
 #[rstest]
 fn hash_file(server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}index.html?hash", server.url()))?;
@@ -208,8 +975,45 @@ fn hash_file(server: TestServer) -> Result<(), Error> {
 fn get_file_404(server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}404", server.url()))?;
     assert_eq!(resp.status(), 404);
-    Ok(())
-}
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/json"
+    );
+    let request_id = resp.headers().get("x-request-id").unwrap().to_str()?;
+    let request_id = request_id.to_string();
+    let body: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(
+        body,
+        serde_json::json!({"code": "not_found", "message": "Not Found", "path": "/404", "request_id": request_id})
+    );
+    Ok(())
+}
+
+#[rstest]
+fn request_id_from_trusted_proxy(
+    #[with(&["--trusted-proxies", "127.0.0.1,::1"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url())
+        .header("x-request-id", "abc-123")
+        .send()?;
+    assert_eq!(resp.headers().get("x-request-id").unwrap(), "abc-123");
+    let resp = fetch!(b"GET", server.url())
+        .header("x-request-id", "a b")
+        .send()?;
+    assert_ne!(resp.headers().get("x-request-id").unwrap(), "a b");
+    Ok(())
+}
+
+#[rstest]
+fn request_id_from_untrusted_client(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url())
+        .header("x-request-id", "abc-123")
+        .send()?;
+    let request_id = resp.headers().get("x-request-id").unwrap();
+    assert_ne!(request_id, "abc-123");
+    assert_eq!(request_id.len(), 36);
+    Ok(())
+}
 
 #[rstest]
 fn get_file_emoji_path(server: TestServer) -> Result<(), Error> {
@@ -217,7 +1021,7 @@ fn get_file_emoji_path(server: TestServer) -> Result<(), Error> {
     assert_eq!(resp.status(), 200);
     assert_eq!(
         resp.headers().get("content-disposition").unwrap(),
-        "inline; filename=\"😀.bin\"; filename*=UTF-8''%F0%9F%98%80.bin"
+        "inline; filename=\"_.bin\"; filename*=UTF-8''%F0%9F%98%80.bin"
     );
     Ok(())
 }
@@ -281,6 +1085,486 @@ fn put_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn put_empty_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(vec![]).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(std::fs::metadata(server.path().join("file1"))?.len(), 0);
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "append")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "abc");
+
+    std::fs::write(server.path().join("dir1/empty"), b"")?;
+    let resp = reqwest::blocking::get(format!("{}dir1/?zip", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let body = resp.bytes()?;
+    assert!(body.windows(5).any(|v| v == b"empty"));
+    Ok(())
+}
+
+#[rstest]
+fn put_file_fsync(#[with(&["-A", "--fsync"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}xyz/file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(reqwest::blocking::get(url)?.text()?, "abc");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_upload_types(
+    #[with(&["-A", "--allow-upload-types", "png,txt", "--deny-upload-types", "sh"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let png = b"\x89PNG\r\n\x1a\n0000".to_vec();
+    let cases: [(&str, Vec<u8>, u16); 6] = [
+        ("image.png", png.clone(), 201),
+        ("notes.txt", b"abc".to_vec(), 201),
+        ("image.PNG", png.clone(), 201),
+        ("image.gif", b"GIF89a".to_vec(), 415),
+        ("fake.png", b"abc".to_vec(), 415),
+        ("program.txt", b"\x7fELF0000".to_vec(), 415),
+    ];
+    for (name, body, status) in cases {
+        let url = format!("{}{}", server.url(), name);
+        let resp = fetch!(b"PUT", &url).body(body).send()?;
+        assert_eq!(resp.status(), status, "{name}");
+        assert_eq!(server.path().join(name).exists(), status == 201, "{name}");
+    }
+    let resp = fetch!(b"MOVE", format!("{}image.png", server.url()))
+        .header("Destination", format!("{}image.txt", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_checksum_sidecar(
+    #[with(&["-A", "--checksum-sidecar"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    let sidecar = server.path().join("file1.sha256");
+    assert_eq!(
+        std::fs::read_to_string(&sidecar)?,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  file1\n"
+    );
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "append")
+        .body(b"def".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(format!("{url}?hash"))?;
+    let hash = resp.text()?;
+    assert_eq!(
+        std::fs::read_to_string(&sidecar)?,
+        format!("{hash}  file1\n")
+    );
+    let resp = fetch!(b"DELETE", &url).send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(!sidecar.exists());
+
+    // Batch deletes take the sidecar along too
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(sidecar.exists());
+    let resp = fetch!(b"POST", format!("{}?batch", server.url()))
+        .body(r#"[{"op": "delete", "path": "file1"}]"#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert!(!sidecar.exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn put_file_dedup_hardlink(
+    #[with(&["-A", "--dedup-uploads", "hardlink"])] server: TestServer,
+) -> Result<(), Error> {
+    use std::os::unix::fs::MetadataExt;
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    for name in ["file1", "file2", "file3"] {
+        let body = match name {
+            "file3" => [&data[1..], b"x"].concat(),
+            _ => data.clone(),
+        };
+        let resp = fetch!(b"PUT", format!("{}{name}", server.url()))
+            .body(body)
+            .send()?;
+        assert_eq!(resp.status(), 201);
+    }
+    let ino = |name: &str| std::fs::metadata(server.path().join(name)).map(|v| v.ino());
+    assert_eq!(ino("file1")?, ino("file2")?);
+    assert_ne!(ino("file1")?, ino("file3")?);
+    let resp = fetch!(b"PATCH", format!("{}file2", server.url()))
+        .header("X-Update-Range", "append")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    assert_ne!(ino("file1")?, ino("file2")?);
+    assert_eq!(std::fs::read(server.path().join("file1"))?, data);
+    assert_eq!(
+        std::fs::read(server.path().join("file2"))?,
+        [&data[..], b"abc"].concat()
+    );
+    Ok(())
+}
+
+#[rstest]
+fn put_file_skip_unchanged(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let put = |size: &str| -> Result<reqwest::blocking::Response, Error> {
+        Ok(fetch!(b"PUT", &url)
+            .header("X-File-Size", size)
+            .header("X-File-Mtime", "1700000000123")
+            .body(b"abc".to_vec())
+            .send()?)
+    };
+    assert_eq!(put("3")?.status(), 201);
+    let mtime = std::fs::metadata(server.path().join("file1"))?.modified()?;
+    assert_eq!(
+        mtime.duration_since(std::time::UNIX_EPOCH)?.as_millis(),
+        1700000000123
+    );
+    assert_eq!(put("3")?.status(), 304);
+    assert_eq!(put("4")?.status(), 201);
+
+    let resp = fetch!(b"PUT", &url)
+        .header(
+            "X-File-Sha256",
+            "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD",
+        )
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 304);
+    let resp = fetch!(b"PUT", &url)
+        .header("X-File-Sha256", "abc")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn post_upload_manifest(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}dir1/file1", server.url()))
+        .header("X-File-Mtime", "1700000000123")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let manifest = serde_json::json!([
+        {"path": "dir1/file1", "size": 3, "mtime": 1700000000999u64},
+        {"path": "dir1/file1", "size": 4, "mtime": 1700000000123u64},
+        {"path": "dir1/file1", "sha256": "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD"},
+        {"path": "dir1/new/file", "size": 3},
+        {"path": "../escape", "size": 3},
+    ]);
+    let resp = fetch!(b"POST", format!("{}?manifest", server.url()))
+        .body(manifest.to_string())
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    let states: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["state"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        states,
+        ["unchanged", "changed", "unchanged", "missing", "invalid"]
+    );
+
+    let resp = fetch!(b"POST", format!("{}index.html?manifest", server.url()))
+        .body("[]")
+        .send()?;
+    assert_eq!(resp.status(), 405);
+    Ok(())
+}
+
+#[rstest]
+fn post_upload_manifest_not_allowed(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?manifest", server.url()))
+        .body("[]")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_chunked(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}xyz/file1", server.url());
+    let resp = fetch!(b"POST", format!("{url}?chunked&size=10&chunk-size=4")).send()?;
+    assert_eq!(resp.status(), 201);
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert_eq!(json["chunks"], 3);
+    let id = json["id"].as_str().unwrap();
+    let put_chunk = |index: usize, body: &[u8]| -> Result<u16, Error> {
+        let resp = fetch!(b"PUT", format!("{url}?upload={id}&chunk={index}"))
+            .body(body.to_vec())
+            .send()?;
+        Ok(resp.status().as_u16())
+    };
+    assert_eq!(put_chunk(2, b"ij")?, 204);
+    assert_eq!(put_chunk(0, b"abcd")?, 204);
+    assert_eq!(put_chunk(1, b"efghi")?, 400);
+    assert_eq!(put_chunk(3, b"x")?, 404);
+
+    let resp = reqwest::blocking::get(format!("{}xyz/?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    assert_eq!(json["paths"], serde_json::json!([]));
+    let resp = fetch!(b"POST", format!("{url}?upload={id}")).send()?;
+    assert_eq!(resp.status(), 409);
+
+    assert_eq!(put_chunk(1, b"efgh")?, 204);
+    let resp = fetch!(b"POST", format!("{url}?upload={id}")).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "abcdefghij");
+    assert_eq!(std::fs::read_dir(server.path().join("xyz"))?.count(), 1);
+    Ok(())
+}
+
+#[rstest]
+#[case::write_once(&["-A", "--write-once"], 409)]
+#[case::no_delete(&["--allow-upload"], 403)]
+fn put_file_chunked_target_created(
+    #[case] args: &[&str],
+    #[case] status: u16,
+    tmpdir: TempDir,
+    port: u16,
+) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+
+    let url = format!("http://localhost:{port}/file1");
+    let resp = fetch!(b"POST", format!("{url}?chunked&size=3&chunk-size=4")).send()?;
+    assert_eq!(resp.status(), 201);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    let id = json["id"].as_str().unwrap();
+    let resp = fetch!(b"PUT", format!("{url}?upload={id}&chunk=0"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    // Created by someone else while the chunks were sent
+    std::fs::write(tmpdir.path().join("file1"), "def")?;
+    let resp = fetch!(b"POST", format!("{url}?upload={id}")).send()?;
+    assert_eq!(resp.status(), status);
+    assert_eq!(std::fs::read_to_string(tmpdir.path().join("file1"))?, "def");
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn put_file_chunked_journal(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let journal = tmpdir.path().join("dir2").join("journal.json");
+    let spawn = || {
+        let child = Command::cargo_bin("dufs")?
+            .arg(tmpdir.path().join("dir1"))
+            .arg("-p")
+            .arg(port.to_string())
+            .arg("--allow-upload")
+            .arg("--upload-journal")
+            .arg(&journal)
+            .stdout(Stdio::piped())
+            .spawn()?;
+        wait_for_port(port);
+        Ok::<_, Error>(child)
+    };
+    let url = format!("http://localhost:{port}/file1");
+    let put_chunk = |id: &str, index: usize, body: &[u8]| -> Result<u16, Error> {
+        let resp = fetch!(b"PUT", format!("{url}?upload={id}&chunk={index}"))
+            .body(body.to_vec())
+            .send()?;
+        Ok(resp.status().as_u16())
+    };
+
+    let mut child = spawn()?;
+    let resp = fetch!(b"POST", format!("{url}?chunked&size=10&chunk-size=4")).send()?;
+    assert_eq!(resp.status(), 201);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    let id = json["id"].as_str().unwrap().to_string();
+    assert_eq!(put_chunk(&id, 0, b"abcd")?, 204);
+    child.kill()?;
+    child.wait()?;
+
+    // The upload carries on where it was after a restart
+    let mut child = spawn()?;
+    let resp = fetch!(b"POST", format!("{url}?upload={id}")).send()?;
+    assert_eq!(resp.status(), 409);
+    assert_eq!(put_chunk(&id, 1, b"efgh")?, 204);
+    assert_eq!(put_chunk(&id, 2, b"ij")?, 204);
+    let resp = fetch!(b"POST", format!("{url}?upload={id}")).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        std::fs::read_to_string(tmpdir.path().join("dir1/file1"))?,
+        "abcdefghij"
+    );
+    let json: Value = serde_json::from_str(&std::fs::read_to_string(&journal)?)?;
+    assert_eq!(json, serde_json::json!({}));
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn put_file_chunked_abort(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"POST", format!("{url}?chunked&size=10&chunk-size=0")).send()?;
+    assert_eq!(resp.status(), 400);
+    let resp = fetch!(b"POST", format!("{url}?chunked&size=10&chunk-size=5")).send()?;
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    let id = json["id"].as_str().unwrap();
+    let staging_path = server.path().join(format!(".dufs-upload-{id}"));
+    assert!(staging_path.exists());
+    let resp = fetch!(b"DELETE", format!("{url}?upload={id}")).send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(!staging_path.exists());
+    let resp = fetch!(b"POST", format!("{url}?upload={id}")).send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_checksum_trailer(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    use std::io::{Read, Write};
+    let put = |name: &str, checksum: &str| -> Result<String, Error> {
+        let mut stream = std::net::TcpStream::connect(("127.0.0.1", server.port()))?;
+        write!(
+            stream,
+            "PUT /{name} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Transfer-Encoding: chunked\r\nTrailer: X-Checksum-Sha256\r\n\r\n\
+             3\r\nabc\r\n0\r\nX-Checksum-Sha256: {checksum}\r\n\r\n"
+        )?;
+        let mut output = String::new();
+        stream.read_to_string(&mut output)?;
+        Ok(output)
+    };
+    let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert!(put("file1", sha256)?.starts_with("HTTP/1.1 201"));
+    assert_eq!(std::fs::read_to_string(server.path().join("file1"))?, "abc");
+    assert!(put("file2", &"0".repeat(64))?.starts_with("HTTP/1.1 400"));
+    assert!(!server.path().join("file2").exists());
+
+    let resp = fetch!(b"PUT", format!("{}file3", server.url()))
+        .header("X-Checksum-Sha256", &"0".repeat(64))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    assert!(!server.path().join("file3").exists());
+    Ok(())
+}
+
+#[rstest]
+fn put_file_preconditions(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?).unwrap();
+    let item = json["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|v| v["name"] == "index.html")
+        .unwrap();
+    let etag = item["etag"].as_str().unwrap().to_string();
+    let url = format!("{}index.html", server.url());
+    assert_eq!(
+        reqwest::blocking::get(&url)?.headers().get("etag").unwrap(),
+        etag.as_str()
+    );
+    let resp = fetch!(b"PUT", &url)
+        .header("If-Match", r#""0-0""#)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = fetch!(b"PUT", &url)
+        .header("If-None-Match", "*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = fetch!(b"DELETE", &url)
+        .header("If-Match", r#""0-0""#)
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = fetch!(b"PUT", &url)
+        .header("If-Match", &etag)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", format!("{}file-new", server.url()))
+        .header("If-Match", &etag)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = fetch!(b"PUT", format!("{}file-new", server.url()))
+        .header("If-None-Match", "*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_upload_id(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("X-Upload-Id", "id1")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let etag = resp.headers().get("etag").unwrap().clone();
+    // A retry of a finished upload returns its final state without writing again
+    let resp = fetch!(b"PUT", &url)
+        .header("X-Upload-Id", "id1")
+        .body(b"def".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("etag").unwrap(), &etag);
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "abc");
+    // Another upload id writes the file
+    let resp = fetch!(b"PUT", &url)
+        .header("X-Upload-Id", "id2")
+        .body(b"def".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "def");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_aborted_removes_partial(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    struct FailingReader {
+        sent: bool,
+    }
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.sent {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                return Err(std::io::Error::other("aborted"));
+            }
+            self.sent = true;
+            buf[..5].copy_from_slice(b"hello");
+            Ok(5)
+        }
+    }
+    let url = format!("{}file-aborted", server.url());
+    let body = reqwest::blocking::Body::sized(FailingReader { sent: false }, 1024);
+    let resp = fetch!(b"PUT", &url).body(body).send();
+    assert!(resp.is_err());
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(!server.path().join("file-aborted").exists());
+    Ok(())
+}
+
 #[rstest]
 fn put_file_create_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let url = format!("{}xyz/file1", server.url());
@@ -291,6 +1575,66 @@ fn put_file_create_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error>
     Ok(())
 }
 
+#[rstest]
+fn put_file_long_path(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    // Longer than MAX_PATH of Windows
+    let dir = vec!["d".repeat(60); 5].join("/");
+    let url = format!("{}{dir}/file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "abc");
+    let resp = reqwest::blocking::get(format!("{}{dir}/?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["paths"][0]["name"], "file1");
+    let resp = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let entry = format!("{dir}/file1");
+    let body = resp.bytes()?;
+    assert!(body.windows(entry.len()).any(|v| v == entry.as_bytes()));
+    Ok(())
+}
+
+#[cfg(windows)]
+#[rstest]
+#[case("CON")]
+#[case("dir1/aux.txt")]
+#[case("file1.txt::$DATA")]
+#[case("file1.txt.")]
+fn put_file_windows_reserved_name(
+    #[with(&["-A"])] server: TestServer,
+    #[case] name: &str,
+) -> Result<(), Error> {
+    let url = format!("{}{}", server.url(), name);
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_unicode_normalize(
+    #[with(&["-A", "--unicode-normalize", "nfc"])] server: TestServer,
+) -> Result<(), Error> {
+    // "é" decomposed (NFD) as sent by macOS clients
+    let nfd_url = format!("{}cafe%CC%81.txt", server.url());
+    let resp = fetch!(b"PUT", &nfd_url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("caf\u{e9}.txt").is_file());
+    let resp = reqwest::blocking::get(nfd_url)?;
+    assert_eq!(resp.status(), 200);
+    let resp = reqwest::blocking::get(format!("{}caf%C3%A9.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_create_nested_dirs(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}folder/sub%20dir/deep/file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("folder/sub dir/deep/file1").is_file());
+    Ok(())
+}
+
 #[rstest]
 fn put_file_conflict_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let url = format!("{}dir1", server.url());
@@ -316,6 +1660,122 @@ fn delete_file_404(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn bulk_delete(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?delete", server.url()))
+        .body(r#"["test.html", "dir1/", "file1", "../index.html"]"#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/json"
+    );
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    let statuses: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, [204, 204, 404, 400]);
+    assert!(!server.path().join("test.html").exists());
+    assert!(!server.path().join("dir1").exists());
+    Ok(())
+}
+
+#[rstest]
+fn bulk_delete_not_allow_delete(
+    #[with(&["--allow-upload"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?delete", server.url()))
+        .body(r#"["test.html"]"#)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(server.path().join("test.html").exists());
+    Ok(())
+}
+
+#[rstest]
+fn bulk_delete_invalid_body(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?delete", server.url()))
+        .body("test.html")
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn batch_ops(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let body = r#"[
+        {"op": "move", "from": "test.html", "to": "moved/test.html"},
+        {"op": "copy", "from": "index.html", "to": "index2.html"},
+        {"op": "copy", "from": "dir1", "to": "dir3"},
+        {"op": "move", "from": "404", "to": "405"},
+        {"op": "copy", "from": "index.html", "to": "index2.html"},
+        {"op": "delete", "path": "dir2"}
+    ]"#;
+    let resp = fetch!(b"POST", format!("{}?batch", server.url()))
+        .body(body)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    let statuses: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, [204, 204, 403, 404, 409, 204]);
+    assert!(!server.path().join("test.html").exists());
+    assert!(server.path().join("moved/test.html").is_file());
+    assert!(server.path().join("index2.html").is_file());
+    assert!(!server.path().join("dir2").exists());
+    Ok(())
+}
+
+#[rstest]
+fn batch_ops_write_once(
+    #[with(&["-A", "--write-once", "--deny-upload-types", "sh"])] server: TestServer,
+) -> Result<(), Error> {
+    let body = r#"[
+        {"op": "copy", "from": "index.html", "to": "index2.html"},
+        {"op": "copy", "from": "test.html", "to": "index2.html"},
+        {"op": "copy", "from": "index.html", "to": "index.sh"}
+    ]"#;
+    let resp = fetch!(b"POST", format!("{}?batch", server.url()))
+        .body(body)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json[0]["status"], 204);
+    assert_eq!(json[1]["status"], 409);
+    assert_eq!(json[2]["status"], 415);
+    assert_eq!(
+        std::fs::read(server.path().join("index2.html"))?,
+        std::fs::read(server.path().join("index.html"))?
+    );
+    assert!(!server.path().join("index.sh").exists());
+    Ok(())
+}
+
+#[rstest]
+fn batch_ops_per_permission(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let body = r#"[
+        {"op": "copy", "from": "index.html", "to": "index2.html"},
+        {"op": "move", "from": "test.html", "to": "test2.html"},
+        {"op": "delete", "path": "dir1"}
+    ]"#;
+    let resp = fetch!(b"POST", format!("{}?batch", server.url()))
+        .body(body)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json[0]["status"], 204);
+    assert_eq!(json[1]["status"], 403);
+    assert_eq!(json[2]["status"], 403);
+    Ok(())
+}
+
 #[rstest]
 fn get_file_content_type(server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}content-types/bin.tar", server.url()))?;
@@ -361,3 +1821,171 @@ fn resumable_upload(#[with(&["--allow-upload"])] server: TestServer) -> Result<(
     assert_eq!(resp.text().unwrap(), "abc123");
     Ok(())
 }
+
+#[cfg(unix)]
+#[rstest]
+fn patch_chmod(#[with(&["-A", "--allow-chmod"])] server: TestServer) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PATCH", format!("{url}?chmod=751")).send()?;
+    assert_eq!(resp.status(), 204);
+    let mode = std::fs::metadata(server.path().join("index.html"))?
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o7777, 0o751);
+    for mode in ["4755", "abc", "7", ""] {
+        let resp = fetch!(b"PATCH", format!("{url}?chmod={mode}")).send()?;
+        assert_eq!(resp.status(), 400);
+    }
+    let resp = fetch!(b"PATCH", format!("{}missing?chmod=644", server.url())).send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn patch_chmod_not_allowed(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PATCH", format!("{}index.html?chmod=644", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn patch_chmod_write_once(
+    #[with(&["-A", "--allow-chmod", "--write-once"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PATCH", format!("{}index.html?chmod=644", server.url())).send()?;
+    assert_eq!(resp.status(), 409);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_rename_on_conflict(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}dir1/report.pdf?rename-on-conflict", server.url());
+    for (body, name) in [
+        ("a", "report.pdf"),
+        ("b", "report (1).pdf"),
+        ("c", "report (2).pdf"),
+    ] {
+        let resp = fetch!(b"PUT", &url).body(body).send()?;
+        assert_eq!(resp.status(), 201);
+        let json: Value = serde_json::from_str(&resp.text()?)?;
+        assert_eq!(json["name"], name);
+        let path = server.path().join("dir1").join(name);
+        assert_eq!(std::fs::read_to_string(path)?, body);
+    }
+    Ok(())
+}
+
+#[rstest]
+fn put_file_rename_on_conflict_not_allowed(server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html?rename-on-conflict", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(!server.path().join("index (1).html").exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn get_file_preview(
+    #[with(&[
+        "--preview", "html>txt=cp {input} {output}",
+        "--preview", "bin>png=false {input} {output}",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    for _ in 0..2 {
+        let resp = reqwest::blocking::get(format!("{}dir1/index.html?preview", server.url()))?;
+        assert_eq!(resp.status(), 200);
+        assert!(resp
+            .headers()
+            .get("content-type")
+            .unwrap()
+            .to_str()?
+            .starts_with("text/plain"));
+        assert_eq!(
+            resp.headers().get("content-disposition").unwrap(),
+            r#"inline; filename="index.txt""#
+        );
+        assert_eq!(resp.text()?, "This is dir1/index.html");
+    }
+    let resp = reqwest::blocking::get(format!("{}test.txt?preview", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    let resp = reqwest::blocking::get(format!("{}%F0%9F%98%80.bin?preview", server.url()))?;
+    assert_eq!(resp.status(), 500);
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["previews"], serde_json::json!(["bin"]));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn get_file_thumb(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let bin = assert_fs::TempDir::new()?;
+    let ffmpeg = bin.path().join("ffmpeg");
+    // Writes "poster" to the last argument, where ffmpeg writes its output
+    std::fs::write(
+        &ffmpeg,
+        "#!/bin/sh\nfor arg; do out=$arg; done\nprintf poster > \"$out\"\n",
+    )?;
+    std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755))?;
+    std::fs::write(tmpdir.path().join("clip.mp4"), "video")?;
+    std::fs::write(tmpdir.path().join("photo.png"), "image")?;
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--ffmpeg")
+        .arg(&ffmpeg)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/clip.mp4?thumb"))?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp
+        .headers()
+        .get("content-type")
+        .unwrap()
+        .to_str()?
+        .starts_with("image/jpeg"));
+    assert_eq!(resp.text()?, "poster");
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/photo.png?thumb"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "image");
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/index.html?thumb"))?;
+    assert_eq!(resp.status(), 404);
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/?json"))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert!(json["video_posters"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!("mp4")));
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn ignore_metadata_files(
+    #[with(&["-A", "--ignore-metadata-files"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("dir1/Thumbs.db"), b"")?;
+    let resp = reqwest::blocking::get(format!("{}dir1/?json", server.url()))?;
+    assert!(!resp.text()?.contains("Thumbs.db"));
+    for name in ["._file1", ".DS_Store", "Thumbs.db"] {
+        let resp = fetch!(b"PUT", format!("{}{name}", server.url()))
+            .body(b"abc".to_vec())
+            .send()?;
+        assert_eq!(resp.status(), 201);
+        assert!(!server.path().join(name).exists());
+    }
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("file1").exists());
+    Ok(())
+}
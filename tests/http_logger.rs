@@ -8,8 +8,10 @@ use fixtures::{port, tmpdir, wait_for_port, Error};
 use assert_cmd::prelude::*;
 use assert_fs::fixture::TempDir;
 use rstest::rstest;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
 use std::process::{Command, Stdio};
+use std::time::Duration;
 
 #[rstest]
 #[case(&["-a", "user:pass@/:rw", "--log-format", "$remote_user"], false)]
@@ -79,3 +81,74 @@ fn no_log(tmpdir: TempDir, port: u16, #[case] args: &[&str]) -> Result<(), Error
     child.kill()?;
     Ok(())
 }
+
+#[rstest]
+fn log_redacts_token(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--log-format", "$request"])
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let stdout = child.stdout.as_mut().expect("Failed to get stdout");
+
+    let resp = fetch!(
+        b"GET",
+        &format!("http://localhost:{port}/?token=secret&json")
+    )
+    .send()?;
+    assert_eq!(resp.status(), 403);
+
+    let mut buf = [0; 2048];
+    let buf_len = stdout.read(&mut buf)?;
+    let output = std::str::from_utf8(&buf[0..buf_len])?;
+
+    assert!(output
+        .lines()
+        .last()
+        .unwrap()
+        .ends_with("GET /?token=REDACTED&json"));
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn log_client_abort(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("-A")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream
+        .write_all(b"PUT /file1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 100\r\n\r\nabc")?;
+    stream.shutdown(Shutdown::Write)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 400"));
+
+    let stderr = child.stderr.as_mut().expect("Failed to get stderr");
+    let mut buf = [0; 2048];
+    let buf_len = stderr.read(&mut buf)?;
+    let output = std::str::from_utf8(&buf[0..buf_len])?;
+
+    let line = output.lines().last().unwrap();
+    assert!(line.contains(" WARN - "), "{line}");
+    assert!(line.contains(r#""PUT /file1" 499"#), "{line}");
+    assert!(!tmpdir.path().join("file1").exists());
+
+    child.kill()?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+#[rstest]
+fn get_file_precompressed(#[with(&["--precompressed"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("test.txt.gz"), b"gzip-content")?;
+    std::fs::write(server.path().join("test.txt.br"), b"br-content")?;
+    let url = format!("{}test.txt", server.url());
+
+    let resp = fetch!(b"GET", &url)
+        .header("accept-encoding", "gzip, deflate, br")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-encoding").unwrap(), "br");
+    assert_eq!(resp.headers().get("vary").unwrap(), "Accept-Encoding");
+    assert!(resp
+        .headers()
+        .get("content-type")
+        .unwrap()
+        .to_str()?
+        .starts_with("text/plain"));
+    assert_eq!(resp.text()?, "br-content");
+
+    let resp = fetch!(b"GET", &url)
+        .header("accept-encoding", "gzip, br;q=0")
+        .send()?;
+    assert_eq!(resp.headers().get("content-encoding").unwrap(), "gzip");
+    assert_eq!(resp.text()?, "gzip-content");
+
+    let resp = fetch!(b"GET", &url).send()?;
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert_eq!(resp.text()?, "This is test.txt");
+    Ok(())
+}
+
+#[rstest]
+fn get_file_precompressed_disabled(server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("test.txt.gz"), b"gzip-content")?;
+    let resp = fetch!(b"GET", format!("{}test.txt", server.url()))
+        .header("accept-encoding", "gzip")
+        .send()?;
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert_eq!(resp.text()?, "This is test.txt");
+    Ok(())
+}
@@ -4,6 +4,7 @@ mod utils;
 use fixtures::{server, Error, TestServer};
 use reqwest::header::HeaderValue;
 use rstest::rstest;
+use std::io::{Seek, SeekFrom, Write};
 
 #[rstest]
 fn get_file_range(server: TestServer) -> Result<(), Error> {
@@ -39,3 +40,149 @@ fn get_file_range_invalid(server: TestServer) -> Result<(), Error> {
     assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */18");
     Ok(())
 }
+
+#[rstest]
+fn get_file_multiple_ranges(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=0-3, 8-9"))
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    let content_type = resp.headers().get("content-type").unwrap().to_str()?;
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap()
+        .to_string();
+    let content_length: usize = resp
+        .headers()
+        .get("content-length")
+        .unwrap()
+        .to_str()?
+        .parse()?;
+    let body = resp.text()?;
+    assert_eq!(body.len(), content_length);
+    let expected = format!(
+        "\r\n--{boundary}\r\nContent-Type: text/html; charset=UTF-8\r\nContent-Range: bytes 0-3/18\r\n\r\nThis\r\n--{boundary}\r\nContent-Type: text/html; charset=UTF-8\r\nContent-Range: bytes 8-9/18\r\n\r\nin\r\n--{boundary}--\r\n"
+    );
+    assert_eq!(body, expected);
+    Ok(())
+}
+
+#[rstest]
+fn get_file_multiple_ranges_unsatisfiable(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=0-3, 20-"))
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes 0-3/18");
+    assert_eq!(resp.text()?, "This");
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=18-, 20-"))
+        .send()?;
+    assert_eq!(resp.status(), 416);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */18");
+    Ok(())
+}
+
+#[rstest]
+fn get_empty_file_range(server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("empty"), b"")?;
+    let url = format!("{}empty", server.url());
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-length").unwrap(), "0");
+    assert!(resp.bytes()?.is_empty());
+    for range in ["bytes=0-", "bytes=-0", "bytes=-1"] {
+        let resp = fetch!(b"GET", &url).header("range", range).send()?;
+        assert_eq!(resp.status(), 416, "{range}");
+        assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */0");
+    }
+    Ok(())
+}
+
+#[rstest]
+fn get_file_range_reversed(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=6-2"))
+        .send()?;
+    assert_eq!(resp.status(), 416);
+    Ok(())
+}
+
+#[rstest]
+fn get_sparse_file_range_beyond_4g(server: TestServer) -> Result<(), Error> {
+    let size: u64 = 5 << 30;
+    let mut file = std::fs::File::create(server.path().join("sparse"))?;
+    file.set_len(size)?;
+    file.seek(SeekFrom::Start(1 << 32))?;
+    file.write_all(b"dufs")?;
+    drop(file);
+
+    let url = format!("{}sparse", server.url());
+    let resp = fetch!(b"HEAD", &url).send()?;
+    assert_eq!(resp.headers().get("content-length").unwrap(), "5368709120");
+    let resp = fetch!(b"GET", &url)
+        .header("range", "bytes=4294967296-4294967299")
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(
+        resp.headers().get("content-range").unwrap(),
+        "bytes 4294967296-4294967299/5368709120"
+    );
+    assert_eq!(resp.headers().get("content-length").unwrap(), "4");
+    assert_eq!(resp.text()?, "dufs");
+    let resp = fetch!(b"GET", &url).header("range", "bytes=-2").send()?;
+    assert_eq!(
+        resp.headers().get("content-range").unwrap(),
+        "bytes 5368709118-5368709119/5368709120"
+    );
+    assert_eq!(resp.bytes()?.as_ref(), b"\0\0");
+    Ok(())
+}
+
+#[rstest]
+fn get_file_range_if_range_etag(server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"HEAD", &url).send()?;
+    let etag = resp.headers().get("etag").unwrap().clone();
+    let resp = fetch!(b"GET", &url)
+        .header("range", "bytes=0-6")
+        .header("if-range", etag.clone())
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.text()?, "This is");
+    for stale in [r#""0-18""#, &format!("W/{}", etag.to_str()?), "garbage"] {
+        let resp = fetch!(b"GET", &url)
+            .header("range", "bytes=0-6")
+            .header("if-range", stale)
+            .send()?;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get("content-range").is_none());
+        assert_eq!(resp.text()?.len(), 18);
+    }
+    Ok(())
+}
+
+#[rstest]
+fn get_file_range_if_range_date(server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"HEAD", &url).send()?;
+    let last_modified = resp.headers().get("last-modified").unwrap().clone();
+    let resp = fetch!(b"GET", &url)
+        .header("range", "bytes=0-6")
+        .header("if-range", last_modified)
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.text()?, "This is");
+    for stale in [
+        "Thu, 01 Jan 1970 00:00:00 GMT",
+        "Fri, 31 Dec 9999 23:59:59 GMT",
+    ] {
+        let resp = fetch!(b"GET", &url)
+            .header("range", "bytes=0-6")
+            .header("if-range", stale)
+            .send()?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.text()?.len(), 18);
+    }
+    Ok(())
+}
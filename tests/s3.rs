@@ -0,0 +1,220 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use reqwest::blocking::RequestBuilder;
+use rstest::rstest;
+use sha2::{Digest, Sha256};
+
+const ACCESS_KEY: &str = "user";
+const SECRET_KEY: &str = "pass";
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut block = [0u8; 64];
+    block[..key.len()].copy_from_slice(key);
+    let pad = |byte: u8| block.iter().map(|v| v ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(data)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{v:02x}")).collect()
+}
+
+/// Sends a request signed like an S3 client, `path_query` must be encoded
+/// and have its query parameters sorted
+fn s3(
+    method: &str,
+    server: &TestServer,
+    path_query: &str,
+    payload_hash: &str,
+    secret: &str,
+) -> RequestBuilder {
+    let url = format!("{}{}", server.url(), path_query.trim_start_matches('/'));
+    let host = format!("localhost:{}", server.port());
+    let (path, query) = path_query.split_once('?').unwrap_or((path_query, ""));
+    let query = query
+        .split('&')
+        .filter(|v| !v.is_empty())
+        .map(|v| match v.contains('=') {
+            true => v.to_string(),
+            false => format!("{v}="),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let date = &amz_date[..8];
+    let canonical_request = format!(
+        "{method}\n{path}\n{query}\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\nhost;x-amz-content-sha256;x-amz-date\n{payload_hash}"
+    );
+    let scope = format!("{date}/us-east-1/s3/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex(&Sha256::digest(canonical_request))
+    );
+    let mut key = hmac(format!("AWS4{secret}").as_bytes(), date);
+    for part in ["us-east-1", "s3", "aws4_request"] {
+        key = hmac(&key, part);
+    }
+    let signature = hex(&hmac(&key, &string_to_sign));
+    let method = reqwest::Method::from_bytes(method.as_bytes()).unwrap();
+    reqwest::blocking::Client::new()
+        .request(method, url)
+        .header("x-amz-content-sha256", payload_hash)
+        .header("x-amz-date", amz_date)
+        .header(
+            "authorization",
+            format!("AWS4-HMAC-SHA256 Credential={ACCESS_KEY}/{scope}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={signature}"),
+        )
+}
+
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+#[rstest]
+fn s3_list_objects(
+    #[with(&["--s3-bucket", "bkt", "-a", "user:pass@/:rw"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = s3(
+        "GET",
+        &server,
+        "/bkt?delimiter=%2F&list-type=2",
+        EMPTY_SHA256,
+        SECRET_KEY,
+    )
+    .send()?;
+    assert_eq!(resp.status(), 200);
+    let body = resp.text()?;
+    assert!(body.contains("<CommonPrefixes><Prefix>dir1/</Prefix></CommonPrefixes>"));
+    assert!(body.contains("<Key>test.txt</Key>"));
+    assert!(!body.contains("dir1/test.txt"));
+
+    let resp = s3(
+        "GET",
+        &server,
+        "/bkt?list-type=2&max-keys=2&prefix=dir1%2F",
+        EMPTY_SHA256,
+        SECRET_KEY,
+    )
+    .send()?;
+    let body = resp.text()?;
+    assert!(body.contains("<KeyCount>2</KeyCount>"));
+    assert!(body.contains("<IsTruncated>true</IsTruncated>"));
+    assert!(body.contains("<Key>dir1/index.html</Key>"));
+    let token = body
+        .split("<NextContinuationToken>")
+        .nth(1)
+        .and_then(|v| v.split('<').next())
+        .unwrap();
+    let resp = s3(
+        "GET",
+        &server,
+        &format!(
+            "/bkt?continuation-token={}&list-type=2&prefix=dir1%2F",
+            token
+                .bytes()
+                .map(|v| match v.is_ascii_alphanumeric() {
+                    true => (v as char).to_string(),
+                    false => format!("%{v:02X}"),
+                })
+                .collect::<String>()
+        ),
+        EMPTY_SHA256,
+        SECRET_KEY,
+    )
+    .send()?;
+    let body = resp.text()?;
+    assert!(!body.contains("<Key>dir1/index.html</Key>"));
+    assert!(body.contains("<Key>dir1/test.txt</Key>"));
+    Ok(())
+}
+
+#[rstest]
+fn s3_object_ops(
+    #[with(&["--s3-bucket", "bkt", "-a", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = s3(
+        "GET",
+        &server,
+        "/bkt/dir1/test.txt",
+        EMPTY_SHA256,
+        SECRET_KEY,
+    )
+    .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is dir1/test.txt");
+
+    let resp = s3("HEAD", &server, "/bkt/missing", EMPTY_SHA256, SECRET_KEY).send()?;
+    assert_eq!(resp.status(), 404);
+    let resp = s3("GET", &server, "/bkt/missing", EMPTY_SHA256, SECRET_KEY).send()?;
+    assert_eq!(resp.status(), 404);
+    assert!(resp.text()?.contains("<Code>NoSuchKey</Code>"));
+
+    let hash = hex(&Sha256::digest(b"abc"));
+    let resp = s3("PUT", &server, "/bkt/new/file1", &hash, SECRET_KEY)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().contains_key("etag"));
+    assert_eq!(std::fs::read(server.path().join("new/file1"))?, b"abc");
+
+    // The body doesn't match the signed hash
+    let resp = s3("PUT", &server, "/bkt/new/file2", &hash, SECRET_KEY)
+        .body(b"abd".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    assert!(!server.path().join("new/file2").exists());
+
+    let resp = s3(
+        "PUT",
+        &server,
+        "/bkt/new/file3",
+        "STREAMING-UNSIGNED-PAYLOAD-TRAILER",
+        SECRET_KEY,
+    )
+    .header("content-encoding", "aws-chunked")
+    .body(b"3\r\nabc\r\n2\r\nde\r\n0\r\nx-amz-checksum-crc32:AAAAAA==\r\n\r\n".to_vec())
+    .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(std::fs::read(server.path().join("new/file3"))?, b"abcde");
+
+    let resp = s3(
+        "DELETE",
+        &server,
+        "/bkt/new/file1",
+        EMPTY_SHA256,
+        SECRET_KEY,
+    )
+    .send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(!server.path().join("new/file1").exists());
+    Ok(())
+}
+
+#[rstest]
+fn s3_auth(
+    #[with(&["--s3-bucket", "bkt", "-a", "user:pass@/:rw", "-a", "@/dir1", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = s3("GET", &server, "/bkt/test.txt", EMPTY_SHA256, "wrong").send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text()?.contains("<Code>SignatureDoesNotMatch</Code>"));
+
+    // Unsigned requests get the anonymous rules
+    let resp = fetch!(b"GET", format!("{}bkt/dir1/test.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", format!("{}bkt/test.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text()?.contains("<Code>AccessDenied</Code>"));
+    let resp = fetch!(b"PUT", format!("{}bkt/dir1/file1", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
@@ -58,3 +58,38 @@ fn path_prefix_single_file(tmpdir: TempDir, port: u16, #[case] file: &str) -> Re
     child.kill()?;
     Ok(())
 }
+
+#[rstest]
+fn pipe_stdin(port: u16) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut child = Command::cargo_bin("dufs")?
+        .arg("--pipe")
+        .arg("data.txt")
+        .arg("-p")
+        .arg(port.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"hello ")?;
+    stdin.flush()?;
+    let handle = std::thread::spawn(move || {
+        reqwest::blocking::get(format!("http://localhost:{port}/data.txt"))
+            .and_then(|resp| resp.text())
+    });
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    stdin.write_all(b"world")?;
+    drop(stdin);
+    assert_eq!(handle.join().unwrap()?, "hello world");
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/"))?;
+    assert_eq!(resp.content_length(), Some(11));
+    assert_eq!(resp.text()?, "hello world");
+
+    child.kill()?;
+    Ok(())
+}
@@ -1,9 +1,12 @@
 mod fixtures;
 mod utils;
 
+use assert_cmd::prelude::*;
 use assert_fs::fixture::TempDir;
-use fixtures::{server, tmpdir, Error, TestServer};
+use fixtures::{port, server, tmpdir, wait_for_port, Error, TestServer};
 use rstest::rstest;
+use serde_json::Value;
+use std::process::{Command, Stdio};
 
 #[cfg(unix)]
 use std::os::unix::fs::symlink as symlink_dir;
@@ -44,3 +47,104 @@ fn allow_symlink(
     assert!(paths.contains(&format!("{dir}/")));
     Ok(())
 }
+
+#[rstest]
+fn upload_through_symlink_not_allowed(
+    #[with(&["--allow-upload", "--allow-delete"])] server: TestServer,
+    tmpdir: TempDir,
+) -> Result<(), Error> {
+    let dir = "foo";
+    symlink_dir(tmpdir.path(), server.path().join(dir)).expect("Couldn't create symlink");
+    let resp = fetch!(b"PUT", format!("{}{}/new.txt", server.url(), dir))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    let resp = fetch!(b"PUT", format!("{}{}/sub/new.txt", server.url(), dir))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    let resp = fetch!(b"MOVE", format!("{}test.html", server.url()))
+        .header("Destination", format!("{}{}/test2.html", server.url(), dir))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(!tmpdir.path().join("new.txt").exists());
+    assert!(!tmpdir.path().join("sub").exists());
+    assert!(!tmpdir.path().join("test2.html").exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn upload_through_dangling_symlink_not_allowed(
+    #[with(&["--allow-upload", "--allow-delete"])] server: TestServer,
+    tmpdir: TempDir,
+) -> Result<(), Error> {
+    let target = tmpdir.path().join("missing.txt");
+    std::os::unix::fs::symlink(&target, server.path().join("bar"))
+        .expect("Couldn't create symlink");
+    let resp = fetch!(b"PUT", format!("{}bar", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    assert!(!target.exists());
+    Ok(())
+}
+
+#[rstest]
+#[case("%2e%2e/%2e%2e/etc/passwd")]
+#[case("dir1/..%2f..%2fetc/passwd")]
+#[case("dir1/%2e%2e%5c%2e%2e%5cetc%5cpasswd")]
+#[case("%5c%5c%3f%5cC:%5cWindows%5cwin.ini")]
+#[case("C:%5cWindows%5cwin.ini")]
+fn path_traversal_rejected(server: TestServer, #[case] path: &str) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}{}", server.url(), path))?;
+    assert!(matches!(resp.status().as_u16(), 400 | 404), "{path}");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[rstest]
+fn sandbox_blocks_symlink(
+    #[with(&["--allow-symlink", "--allow-upload", "--sandbox"])] server: TestServer,
+    tmpdir: TempDir,
+) -> Result<(), Error> {
+    let dir = "foo";
+    symlink_dir(tmpdir.path(), server.path().join(dir)).expect("Couldn't create symlink");
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let resp = reqwest::blocking::get(format!("{}{}/index.html", server.url(), dir))?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"PUT", format!("{}{}/new.txt", server.url(), dir))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(!tmpdir.path().join("new.txt").exists());
+    Ok(())
+}
+
+#[rstest]
+fn symlinked_root(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let parent = assert_fs::TempDir::new()?;
+    symlink_dir(tmpdir.path(), parent.path().join("root"))?;
+    let mut child = Command::cargo_bin("dufs")?
+        .current_dir(parent.path())
+        .arg("./root")
+        .arg("-A")
+        .arg("-p")
+        .arg(port.to_string())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/dir1/?json"))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["href"], "/dir1");
+    let paths = json["paths"].as_array().unwrap();
+    assert!(paths.iter().any(|v| v["name"] == "index.html"));
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/dir1/index.html"))?;
+    assert_eq!(resp.text()?, "This is dir1/index.html");
+
+    child.kill()?;
+    Ok(())
+}
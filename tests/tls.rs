@@ -1,11 +1,15 @@
 mod fixtures;
 mod utils;
 
+use assert_cmd::prelude::CommandCargoExt;
 use assert_cmd::Command;
-use fixtures::{server, Error, TestServer};
+use fixtures::{server, tmpdir, wait_for_port, Error, TestServer};
 use predicates::str::contains;
 use reqwest::blocking::ClientBuilder;
 use rstest::rstest;
+use serde_json::Value;
+use std::io::Read;
+use std::process::Stdio;
 
 use crate::fixtures::port;
 
@@ -71,3 +75,64 @@ fn wrong_path_key() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Negotiates HTTP/2 through ALPN, links still use the `:authority` host.
+#[rstest]
+fn tls_http2(
+    #[with(&[
+        "--tls-cert", "tests/data/cert.pem",
+        "--tls-key", "tests/data/key_pkcs8.pem",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resp = client.get(server.url()).send()?.error_for_status()?;
+    assert_eq!(resp.version(), reqwest::Version::HTTP_2);
+    assert_resp_paths!(resp);
+    let resp = client
+        .get(format!("{}?feed", server.url()))
+        .send()?
+        .error_for_status()?;
+    assert_eq!(resp.version(), reqwest::Version::HTTP_2);
+    let host = format!("https://localhost:{}/", server.port());
+    assert!(resp.text()?.contains(&host));
+    Ok(())
+}
+
+/// Logs the negotiated TLS parameters and counts the handshakes.
+#[rstest]
+fn tls_session_info(tmpdir: assert_fs::TempDir, port: u16) -> Result<(), Error> {
+    let mut child = std::process::Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--tls-cert", "tests/data/cert.pem"])
+        .args(["--tls-key", "tests/data/key_pkcs8.pem"])
+        .args(["--log-format", "$server_protocol $ssl_protocol $ssl_cipher"])
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+    let stdout = child.stdout.as_mut().expect("Failed to get stdout");
+
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resp = client
+        .get(format!("https://localhost:{port}/__dufs__/metrics"))
+        .send()?
+        .error_for_status()?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["tls"]["versions"]["TLSv1.3"], 1);
+    assert_eq!(json["tls"]["alpn"]["h2"], 1);
+
+    let mut buf = [0; 4096];
+    let buf_len = stdout.read(&mut buf)?;
+    let output = std::str::from_utf8(&buf[0..buf_len])?;
+    let line = output.lines().last().unwrap();
+    assert!(line.contains("HTTP/2.0 TLSv1.3 TLS13_"), "{line}");
+
+    child.kill()?;
+    Ok(())
+}
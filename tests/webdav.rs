@@ -46,8 +46,43 @@ fn propfind_dir_depth2(server: TestServer) -> Result<(), Error> {
         .header("depth", "2")
         .send()?;
     assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(
+        body["message"],
+        "Invalid depth: only 0, 1 and infinity are allowed."
+    );
+    Ok(())
+}
+
+#[rstest]
+fn propfind_dir_depth_infinity(
+    #[with(&["--hidden", ".git"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", server.url())
+        .header("depth", "infinity")
+        .send()?;
+    assert_eq!(resp.status(), 207);
     let body = resp.text()?;
-    assert_eq!(body, "Invalid depth: only 0 and 1 are allowed.");
+    assert!(body.contains("<D:href>/dir1/</D:href>"));
+    assert!(body.contains("<D:href>/dir1/test.html</D:href>"));
+    assert!(body.contains("<D:href>/test.html</D:href>"));
+    assert!(!body.contains("/.git/"));
+    Ok(())
+}
+
+#[rstest]
+fn propfind_dir_depth_infinity_limit(
+    #[with(&["--depth-max-entries", "3"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", server.url())
+        .header("depth", "infinity")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text()?.contains("<D:propfind-finite-depth/>"));
+    let resp = fetch!(b"PROPFIND", server.url())
+        .header("depth", "1")
+        .send()?;
+    assert_eq!(resp.status(), 207);
     Ok(())
 }
 
@@ -90,6 +125,70 @@ fn proppatch_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn proppatch_dead_props(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}test.html", server.url());
+    let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:schemas-microsoft-com:">
+  <D:set><D:prop><Z:Win32FileAttributes>00000020</Z:Win32FileAttributes></D:prop></D:set>
+</D:propertyupdate>"#;
+    let resp = fetch!(b"PROPPATCH", &url).body(body).send()?;
+    assert_eq!(resp.status(), 207);
+    let text = resp.text()?;
+    assert!(text.contains(r#"<Win32FileAttributes xmlns="urn:schemas-microsoft-com:"/>"#));
+    assert!(text.contains("HTTP/1.1 200 OK"));
+
+    let resp = fetch!(b"PROPFIND", &url).send()?;
+    assert!(resp.text()?.contains(
+        r#"<Win32FileAttributes xmlns="urn:schemas-microsoft-com:">00000020</Win32FileAttributes>"#
+    ));
+    let resp = fetch!(b"PROPFIND", server.url()).send()?;
+    assert!(resp.text()?.contains(">00000020</Win32FileAttributes>"));
+
+    let resp = fetch!(b"COPY", &url)
+        .header("Destination", format!("{}test2.html", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"PROPFIND", format!("{}test2.html", server.url())).send()?;
+    assert!(resp.text()?.contains(">00000020</Win32FileAttributes>"));
+
+    // Live properties can't be changed and fail the whole update
+    let body = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:example">
+  <D:set><D:prop><D:getcontentlength>1</D:getcontentlength><Z:color>red</Z:color></D:prop></D:set>
+  <D:remove><D:prop><Z:Win32FileAttributes xmlns:Z="urn:schemas-microsoft-com:"/></D:prop></D:remove>
+</D:propertyupdate>"#;
+    let resp = fetch!(b"PROPPATCH", &url).body(body).send()?;
+    let text = resp.text()?;
+    assert!(text.contains("HTTP/1.1 403 Forbidden"));
+    assert!(text.contains("HTTP/1.1 424 Failed Dependency"));
+    let resp = fetch!(b"PROPFIND", &url).send()?;
+    let text = resp.text()?;
+    assert!(text.contains(">00000020</Win32FileAttributes>"));
+    assert!(!text.contains("urn:example"));
+
+    let body = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:schemas-microsoft-com:">
+  <D:remove><D:prop><Z:Win32FileAttributes/></D:prop></D:remove>
+</D:propertyupdate>"#;
+    let resp = fetch!(b"PROPPATCH", &url).body(body).send()?;
+    assert!(resp.text()?.contains("HTTP/1.1 200 OK"));
+    let resp = fetch!(b"PROPFIND", &url).send()?;
+    assert!(!resp.text()?.contains("Win32FileAttributes"));
+    Ok(())
+}
+
+#[rstest]
+fn proppatch_readonly(server: TestServer) -> Result<(), Error> {
+    let body = r#"<D:propertyupdate xmlns:D="DAV:">
+  <D:set><D:prop><color xmlns="urn:example">red</color></D:prop></D:set>
+</D:propertyupdate>"#;
+    let resp = fetch!(b"PROPPATCH", format!("{}test.html", server.url()))
+        .body(body)
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    assert!(resp.text()?.contains("HTTP/1.1 403 Forbidden"));
+    Ok(())
+}
+
 #[rstest]
 fn proppatch_404(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"PROPPATCH", format!("{}404", server.url())).send()?;
@@ -104,6 +203,14 @@ fn mkcol_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn mkcol_nested_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"MKCOL", format!("{}newdir/sub/empty", server.url())).send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("newdir/sub/empty").is_dir());
+    Ok(())
+}
+
 #[rstest]
 fn mkcol_not_allow_upload(server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"MKCOL", format!("{}newdir", server.url())).send()?;
@@ -226,3 +333,72 @@ fn unlock_file_404(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     assert_eq!(resp.status(), 404);
     Ok(())
 }
+
+#[rstest]
+fn quirks_windows(
+    #[with(&["-A", "--webdav-quirks", "windows"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"OPTIONS", server.url()).send()?;
+    assert_eq!(resp.headers().get("ms-author-via").unwrap(), "DAV");
+    let resp = fetch!(b"LOCK", format!("{}new.docx", server.url())).send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(resp.headers().contains_key("lock-token"));
+    assert_eq!(std::fs::read(server.path().join("new.docx"))?, b"");
+    let resp = fetch!(b"LOCK", format!("{}missing/new.docx", server.url())).send()?;
+    assert_eq!(resp.status(), 409);
+    Ok(())
+}
+
+#[rstest]
+fn quirks_windows_upload_checks(
+    #[with(&["-A", "--webdav-quirks", "windows", "--write-once", "--deny-upload-types", "sh"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"LOCK", format!("{}new.sh", server.url())).send()?;
+    assert_eq!(resp.status(), 415);
+    assert!(!server.path().join("new.sh").exists());
+    // No placeholder is created, so the PUT that follows isn't a conflict
+    let url = format!("{}new.txt", server.url());
+    let resp = fetch!(b"LOCK", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().contains_key("lock-token"));
+    assert!(!server.path().join("new.txt").exists());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn quirks_rclone(
+    #[with(&["-A", "--webdav-quirks", "rclone"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .header("x-oc-mtime", "1700000000.250000000")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("x-oc-mtime").unwrap(), "accepted");
+    let mtime = std::fs::metadata(server.path().join("file1"))?.modified()?;
+    let mtime = mtime.duration_since(std::time::UNIX_EPOCH)?;
+    assert_eq!(mtime.as_millis(), 1700000000250);
+    Ok(())
+}
+
+#[rstest]
+fn quirks_finder(
+    #[with(&["-A", "--webdav-quirks", "finder"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join(".DS_Store"), b"")?;
+    let resp = fetch!(b"PUT", format!("{}._test.html", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(!server.path().join("._test.html").exists());
+    let resp = fetch!(b"LOCK", format!("{}._test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"UNLOCK", format!("{}._test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"PROPFIND", server.url()).send()?;
+    assert!(!resp.text()?.contains(".DS_Store"));
+    Ok(())
+}